@@ -1,9 +1,10 @@
-from .floky import LshL2, LshSrp, LshSrpMem, LshL2Mem, sort_by_distances
+from .floky import LshL2, LshSrp, LshMips, LshMinHash, sort_by_distances
 from tqdm import tqdm
 import numpy as np
 import os
+import warnings
 from collections import namedtuple
-from typing import Union, List
+from typing import Union, List, Optional
 
 
 QueryResult = namedtuple(
@@ -11,16 +12,30 @@ QueryResult = namedtuple(
 )
 
 
+def _backend(backend: str, in_mem: Optional[bool]) -> str:
+    """
+    Map the deprecated `in_mem` keyword to a backend.
+    """
+    if in_mem is None:
+        return backend
+    warnings.warn(
+        "in_mem is deprecated, use backend='mem' or backend='sqlite'",
+        DeprecationWarning,
+        stacklevel=3,
+    )
+    return "mem" if in_mem else "sqlite"
+
+
 class Base:
     def __init__(
         self,
-        lsh: Union[LshL2, LshL2Mem, LshSrp, LshSrpMem],
+        lsh: Union[LshL2, LshSrp, LshMips, LshMinHash],
         n_projections: int,
         n_hash_tables: int,
         dim: int,
         db_path: str,
         seed: int,
-        in_mem: bool,
+        backend: str,
         log: bool,
     ):
         self.n_projection = n_projections
@@ -30,7 +45,9 @@ class Base:
         self.seed = seed
         self.db_path = db_path
         self.data = None
-        self.in_mem = in_mem
+        self.backend = backend
+        # the memory backend has no transactions or index.
+        self.in_mem = backend == "mem"
         self.log = log
 
     def base(self):
@@ -192,6 +209,40 @@ class Base:
         self.lsh.increase_storage(len(X))
         self.store_vecs(self.data, chunk_size)
 
+    def fit_frame(
+        self,
+        df: Union["pd.DataFrame", str, os.PathLike],
+        columns: Optional[List[str]] = None,
+        chunk_size: int = 250,
+    ):
+        """
+        One shot store and hash the rows of a pandas DataFrame or parquet file.
+        The rows are converted to a contiguous float32 array and stored in chunks, see `fit`.
+
+        Parameters
+        ----------
+        df
+            DataFrame with float columns, or the path to a parquet file.
+        columns
+            Columns that make up the data points. Defaults to all columns.
+        chunk_size
+            How many chunks will be written to the backend at once.
+            If an in memory backend is used this can be significantly higher compared to the SQLite backend.
+        """
+        import pandas as pd
+
+        if isinstance(df, (str, os.PathLike)):
+            df = pd.read_parquet(df, columns=columns)
+        elif columns is not None:
+            df = df[columns]
+
+        not_float = [
+            c for c, dtype in df.dtypes.items() if not np.issubdtype(dtype, np.floating)
+        ]
+        if not_float:
+            raise ValueError(f"columns {not_float} are not float columns")
+        self.fit(df.to_numpy(dtype=np.float32), chunk_size)
+
     def _predict(
         self,
         x: Union[np.ndarray, List[List[float]]],
@@ -210,6 +261,7 @@ class Base:
         distance_f
             - "cosine"
             - "euclidean"
+            - "jaccard"
         only_index
             Only return indexes and not the data points.
         top_k
@@ -253,7 +305,7 @@ class Base:
         """
         Remove database file
         """
-        if not self.in_mem:
+        if self.backend == "sqlite" and os.path.exists(self.db_path):
             os.remove(self.db_path)
 
     def to_mem(self, pages_per_step: int = 100):
@@ -265,7 +317,7 @@ class Base:
         pages_per_step
             Number of pages per step
         """
-        if not self.in_mem:
+        if self.backend == "sqlite":
             self.lsh.to_mem(pages_per_step)
 
 
@@ -278,8 +330,9 @@ class L2(Base):
         r: float = 4.0,
         seed: int = 0,
         db_path: str = "./lsh.db3",
-        in_mem: bool = True,
+        backend: str = "mem",
         log: bool = True,
+        in_mem: Optional[bool] = None,
     ):
         """
         L2 LSH. Used to find data points with minimal euclidean distance.
@@ -298,20 +351,21 @@ class L2(Base):
             Seed for the hashing functions. If set to zero, the hashing functions are randomly generated.
         db_path
             Path to SQLite database file. Only needed for SQLite backend.
-        in_mem
-            In memory backend or SQLite backend
+        backend
+            - "mem": in memory backend
+            - "sqlite": SQLite database file at `db_path`
+            - "sqlite_mem": in memory SQLite database
         log
             Print fit information to screen
+        in_mem
+            Deprecated, use `backend`. In memory backend or SQLite backend.
         """
-        if in_mem:
-            self.lsh_builder = LshL2Mem
-        else:
-            self.lsh_builder = LshL2
-
-        lsh = self.lsh_builder(n_projections, n_hash_tables, dim, r, seed, db_path)
+        backend = _backend(backend, in_mem)
+        self.lsh_builder = LshL2
+        lsh = self.lsh_builder(n_projections, n_hash_tables, dim, r, seed, db_path, backend)
         self.r = r
         super().__init__(
-            lsh, n_projections, n_hash_tables, dim, db_path, seed, in_mem, log
+            lsh, n_projections, n_hash_tables, dim, db_path, seed, backend, log
         )
 
     def reset(self, dim: int):
@@ -324,6 +378,7 @@ class L2(Base):
             self.r,
             self.seed,
             self.db_path,
+            self.backend,
         )
 
     def predict(
@@ -364,8 +419,9 @@ class SRP(Base):
         dim: int = 10,
         seed: int = 0,
         db_path: str = "./lsh.db3",
-        in_mem: bool = True,
+        backend: str = "mem",
         log: bool = True,
+        in_mem: Optional[bool] = None,
     ):
         """
         Signed Random Projections. Used to for cosine similarity.
@@ -382,25 +438,32 @@ class SRP(Base):
             Seed for the hashing functions. If set to zero, the hashing functions are randomly generated.
         db_path
             Path to SQLite database file. Only needed for SQLite backend.
-        in_mem
-            In memory backend or SQLite backend
+        backend
+            - "mem": in memory backend
+            - "sqlite": SQLite database file at `db_path`
+            - "sqlite_mem": in memory SQLite database
         log
             Print fit information to screen
+        in_mem
+            Deprecated, use `backend`. In memory backend or SQLite backend.
         """
-        if in_mem:
-            self.lsh_builder = LshSrpMem
-        else:
-            self.lsh_builder = LshSrp
-        lsh = self.lsh_builder(n_projections, n_hash_tables, dim, seed, db_path)
+        backend = _backend(backend, in_mem)
+        self.lsh_builder = LshSrp
+        lsh = self.lsh_builder(n_projections, n_hash_tables, dim, seed, db_path, backend)
         super().__init__(
-            lsh, n_projections, n_hash_tables, dim, db_path, seed, in_mem, log
+            lsh, n_projections, n_hash_tables, dim, db_path, seed, backend, log
         )
 
     def reset(self, dim: int):
         self.clean()
         self.dim = dim
         self.lsh = self.lsh_builder(
-            self.n_projection, self.n_hash_tables, self.dim, self.seed, self.db_path
+            self.n_projection,
+            self.n_hash_tables,
+            self.dim,
+            self.seed,
+            self.db_path,
+            self.backend,
         )
 
     def predict(
@@ -430,3 +493,84 @@ class SRP(Base):
         Named tuples List[QueryResult]
         """
         return self._predict(x, "cosine", only_index, top_k, bound)
+
+
+class MinHash(Base):
+    def __init__(
+        self,
+        n_projections: int,
+        n_hash_tables: int,
+        dim: int = 10,
+        seed: int = 0,
+        db_path: str = "./lsh.db3",
+        backend: str = "mem",
+        log: bool = True,
+    ):
+        """
+        MinHash. Used to find data points with a minimal jaccard distance.
+        The data points are shingles vectors, their values are cast to u16.
+
+        Parameters
+        ----------
+        n_projections
+            Number of values in the hash; `K` in literature.
+        n_hash_tables
+            Number of hash tables; `L` in literature.
+        dim
+            Dimension of the data points.
+        seed
+            Seed for the hashing functions. If set to zero, the hashing functions are randomly generated.
+        db_path
+            Path to SQLite database file. Only needed for SQLite backend.
+        backend
+            - "mem": in memory backend
+            - "sqlite": SQLite database file at `db_path`
+            - "sqlite_mem": in memory SQLite database
+        log
+            Print fit information to screen
+        """
+        self.lsh_builder = LshMinHash
+        lsh = self.lsh_builder(n_projections, n_hash_tables, dim, seed, db_path, backend)
+        super().__init__(
+            lsh, n_projections, n_hash_tables, dim, db_path, seed, backend, log
+        )
+
+    def reset(self, dim: int):
+        self.clean()
+        self.dim = dim
+        self.lsh = self.lsh_builder(
+            self.n_projection,
+            self.n_hash_tables,
+            self.dim,
+            self.seed,
+            self.db_path,
+            self.backend,
+        )
+
+    def predict(
+        self,
+        x: Union[np.ndarray, List[List[float]]],
+        only_index: bool = False,
+        top_k: int = 5,
+        bound: Union[None, int] = None
+    ):
+        """
+        Query data points.
+
+        Parameters
+        ----------
+        x
+            Shape: (n, dim)
+            Query data points
+        only_index
+            Only return indexes and not the data points.
+        top_k
+            Take the k closest
+        bound
+            Only take the first 0..bound slice
+
+        Returns
+        -------
+        Named tuples List[QueryResult]
+        """
+        return self._predict(x, "jaccard", only_index, top_k, bound)
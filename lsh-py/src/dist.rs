@@ -14,10 +14,24 @@ fn cosine_sim(a: ArrayView1<f32>, b: ArrayView1<f32>) -> f32 {
     &a.dot(&b) / (l2_norm(a) * l2_norm(b))
 }
 
+/// Weighted jaccard distance of two shingles vectors.
+fn jaccard_dist(a: ArrayView1<f32>, b: ArrayView1<f32>) -> f32 {
+    let (min, max) = a
+        .iter()
+        .zip(b)
+        .fold((0., 0.), |(min, max), (&x, &y)| (min + x.min(y), max + x.max(y)));
+    if max == 0. {
+        0.
+    } else {
+        1. - min / max
+    }
+}
+
 pub fn cdist(q: ArrayView1<f32>, vs: &[ArrayView1<f32>], distance_f: &str) -> Vec<f32> {
     let dist_fn = match distance_f {
         "l2" | "euclidean" => l2_dist,
         "cosine" => cosine_sim,
+        "jaccard" => jaccard_dist,
         _ => panic!("distance function not defined"),
     };
     vs.into_iter().map(|&v| dist_fn(q, v)).collect()
@@ -32,7 +46,7 @@ pub fn sort_by_distance(
     // similarities should be reversed
     // distances not.
     let reverse = match distance_f {
-        "l2" | "euclidean" => false,
+        "l2" | "euclidean" | "jaccard" => false,
         "cosine" => true,
         _ => panic!("distance function not defined"),
     };
@@ -1,5 +1,6 @@
 mod dist;
 use crate::dist::sort_by_distance;
+use lsh_rs::data::Numeric;
 use lsh_rs::{prelude::Error as LshError, prelude::*};
 use pyo3::exceptions::{RuntimeError, ValueError};
 use pyo3::prelude::*;
@@ -8,6 +9,8 @@ use thiserror::Error;
 
 use ndarray::parallel::prelude::*;
 use ndarray::prelude::*;
+use ndarray::CowArray;
+use std::borrow::Cow;
 use numpy::PyArray2;
 use pyo3::prelude::*;
 
@@ -26,6 +29,7 @@ pub fn sort_by_distances(
     let distance_f = match distance_f {
         "cosine" => "cosine",
         "l2" | "euclidean" => "l2",
+        "jaccard" => "jaccard",
         _ => return Err(PyErr::new::<ValueError, _>("distance function not correct")),
     };
 
@@ -63,6 +67,10 @@ enum PyLshErr {
     Err(#[from] LshError),
     #[error("array memory order is not contiguous")]
     NonContiguous,
+    #[error("base not initialized")]
+    NotInitialized,
+    #[error("{0} is not supported by the memory backend")]
+    NotSupported(&'static str),
 }
 
 impl std::convert::From<PyLshErr> for PyErr {
@@ -76,42 +84,127 @@ fn floky(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<LshL2>()?;
     m.add_class::<LshMips>()?;
     m.add_class::<LshSrp>()?;
-    m.add_class::<LshL2Mem>()?;
-    m.add_class::<LshSrpMem>()?;
+    m.add_class::<LshMinHash>()?;
     m.add_wrapped(wrap_pyfunction!(sort_by_distances)).unwrap();
     Ok(())
 }
 
+/// Python passes data points as f32. MinHash takes shingles vectors, these are cast to u16.
+trait FromF32: Numeric {
+    fn from_f32s(v: &[f32]) -> Cow<'_, [Self]>;
+    fn from_f32_array(vs: ArrayView2<'_, f32>) -> CowArray<'_, Self, Ix2>;
+}
+
+impl FromF32 for f32 {
+    fn from_f32s(v: &[f32]) -> Cow<'_, [Self]> {
+        Cow::Borrowed(v)
+    }
+    fn from_f32_array(vs: ArrayView2<'_, f32>) -> CowArray<'_, Self, Ix2> {
+        CowArray::from(vs)
+    }
+}
+
+impl FromF32 for u16 {
+    fn from_f32s(v: &[f32]) -> Cow<'_, [Self]> {
+        Cow::Owned(v.iter().map(|&x| x as u16).collect())
+    }
+    fn from_f32_array(vs: ArrayView2<'_, f32>) -> CowArray<'_, Self, Ix2> {
+        CowArray::from(vs.mapv(|x| x as u16))
+    }
+}
+
+fn cast<N: FromF32>(v: &[f32]) -> Cow<'_, [N]> {
+    N::from_f32s(v)
+}
+
+fn cast_array<N: FromF32>(vs: ArrayView2<'_, f32>) -> CowArray<'_, N, Ix2> {
+    N::from_f32_array(vs)
+}
+
+fn to_f32s<N: Numeric>(v: &[N]) -> Vec<f32> {
+    v.iter().map(|x| x.to_f32().unwrap()).collect()
+}
+
+fn bucket_to_f32s<N: Numeric>(bucket: Vec<&[N]>) -> Vec<Vec<f32>> {
+    bucket.into_iter().map(to_f32s).collect()
+}
+
+/// Every hasher × backend combination.
 enum LshTypes {
-    L2(LshSql<L2<f32, i32>, f32, i32>),
+    L2Sql(LshSql<L2<f32, i32>, f32, i32>),
+    L2SqlMem(LshSqlMem<L2<f32, i32>, f32, i32>),
     L2Mem(LshMem<L2<f32, i32>, f32, i32>),
+    MipsSql(LshSql<MIPS<f32, i32>, f32, i32>),
+    MipsSqlMem(LshSqlMem<MIPS<f32, i32>, f32, i32>),
     MipsMem(LshMem<MIPS<f32, i32>, f32, i32>),
-    Srp(LshSql<SignRandomProjections<f32>, f32, i8>),
+    SrpSql(LshSql<SignRandomProjections<f32>, f32, i8>),
+    SrpSqlMem(LshSqlMem<SignRandomProjections<f32>, f32, i8>),
     SrpMem(LshMem<SignRandomProjections<f32>, f32, i8>),
+    MinHashSql(LshSql<MinHash<u16, i32>, u16, i32>),
+    MinHashSqlMem(LshSqlMem<MinHash<u16, i32>, u16, i32>),
+    MinHashMem(LshMem<MinHash<u16, i32>, u16, i32>),
     Empty,
 }
 
-macro_rules! call_lsh_types {
-    ($lsh:expr, $method_call:ident, $value:expr, $($optional:tt),*) => {
+/// Dispatch over the backends. Evaluates the expression of the backend of `$lsh`, with the
+/// LSH bound to `$x`.
+macro_rules! dispatch_backend {
+    ($lsh:expr, sql: $x:ident => $sql:expr, sql_mem: $y:ident => $sql_mem:expr, mem: $z:ident => $mem:expr) => {
         match $lsh {
-            LshTypes::L2(lsh) => {lsh.$method_call($value) $($optional),*},
-            LshTypes::L2Mem(lsh) => {lsh.$method_call($value)$($optional),*},
-            LshTypes::MipsMem(lsh) => {lsh.$method_call($value)$($optional),*},
-            LshTypes::Srp(lsh) => {lsh.$method_call($value)$($optional),*},
-            LshTypes::SrpMem(lsh) => {lsh.$method_call($value)$($optional),*},
-            LshTypes::Empty => panic!("base not initialized"),
-        };
+            LshTypes::L2Sql($x) => $sql,
+            LshTypes::MipsSql($x) => $sql,
+            LshTypes::SrpSql($x) => $sql,
+            LshTypes::MinHashSql($x) => $sql,
+            LshTypes::L2SqlMem($y) => $sql_mem,
+            LshTypes::MipsSqlMem($y) => $sql_mem,
+            LshTypes::SrpSqlMem($y) => $sql_mem,
+            LshTypes::MinHashSqlMem($y) => $sql_mem,
+            LshTypes::L2Mem($z) => $mem,
+            LshTypes::MipsMem($z) => $mem,
+            LshTypes::SrpMem($z) => $mem,
+            LshTypes::MinHashMem($z) => $mem,
+            LshTypes::Empty => return Err(PyLshErr::NotInitialized),
+        }
     };
+}
 
-    ($lsh:expr, $method_call:ident, $($optional:tt),*) => {
-        match $lsh {
-            LshTypes::L2(lsh) => {lsh.$method_call() $($optional),*},
-            LshTypes::L2Mem(lsh) => {lsh.$method_call() $($optional),*},
-            LshTypes::MipsMem(lsh) => {lsh.$method_call() $($optional),*},
-            LshTypes::Srp(lsh) => {lsh.$method_call() $($optional),*},
-            LshTypes::SrpMem(lsh) => {lsh.$method_call() $($optional),*},
-            LshTypes::Empty => panic!("base not initialized"),
-        };
+/// Dispatch over all combinations with the same expression.
+macro_rules! dispatch {
+    ($lsh:expr, $x:ident => $body:expr) => {
+        dispatch_backend!($lsh, sql: $x => $body, sql_mem: $x => $body, mem: $x => $body)
+    };
+}
+
+/// Build the LSH of a hasher with the backend given by name.
+macro_rules! build_lsh {
+    ($backend:expr, $n_projections:expr, $n_hash_tables:expr, $dim:expr, $seed:expr, $db_path:expr,
+     $hasher:ident($($arg:expr),*), $sql:ident, $sql_mem:ident, $mem:ident) => {
+        match $backend {
+            "sqlite" => LshSql::new($n_projections, $n_hash_tables, $dim)
+                .seed($seed)
+                .only_index()
+                .set_database_file(&$db_path)
+                .$hasher($($arg),*)
+                .map(LshTypes::$sql),
+            "sqlite_mem" => LshSqlMem::new($n_projections, $n_hash_tables, $dim)
+                .seed($seed)
+                .only_index()
+                .$hasher($($arg),*)
+                .map(LshTypes::$sql_mem),
+            "mem" => LshMem::new($n_projections, $n_hash_tables, $dim)
+                .seed($seed)
+                .only_index()
+                .$hasher($($arg),*)
+                .map(LshTypes::$mem),
+            backend => {
+                return Err(ValueError::py_err(format!(
+                    "backend should be one of 'mem', 'sqlite' or 'sqlite_mem', got '{}'",
+                    backend
+                )))
+            }
+        }
+        .map(|lsh| Base { lsh })
+        .map_err(|e| RuntimeError::py_err(format!("{}", e)))
     };
 }
 
@@ -122,28 +215,39 @@ struct Base {
 
 impl Base {
     fn _store_vec(&mut self, v: Vec<f32>) -> IntResult<()> {
-        call_lsh_types!(&mut self.lsh, store_vec, &v,)?;
+        dispatch!(&mut self.lsh, lsh => lsh.store_vec(&*cast(&v)).map(|_| ()))?;
         Ok(())
     }
 
-    fn _store_vecs(&mut self, vs: &PyArray2<f32>) -> IntResult<()> {
-        let vs = vs.as_array();
-        call_lsh_types!(&mut self.lsh, store_array, vs,)?;
-        Ok(())
+    /// The data points are hashed without the GIL, so other python threads can run. Those
+    /// threads could write to the numpy array, so it is copied while the GIL is held.
+    fn _store_vecs(&mut self, py: Python, vs: &PyArray2<f32>) -> IntResult<()> {
+        let vs = vs.as_array().to_owned();
+        let vs = vs.view();
+        let lsh = &mut self.lsh;
+        py.allow_threads(move || -> IntResult<()> {
+            dispatch!(lsh, lsh => lsh.store_array(cast_array(vs).view()).map(|_| ()))?;
+            Ok(())
+        })
     }
-    fn _query_bucket_idx(&self, v: Vec<f32>) -> IntResult<Vec<u32>> {
-        let q = call_lsh_types!(&self.lsh, query_bucket_ids, &v,)?;
+
+    /// SQLite connections aren't `Sync`, so the SQLite backends query with the GIL held, which
+    /// keeps other python threads off the connection. The memory backend releases the GIL.
+    fn _query_bucket_idx(&self, py: Python, v: Vec<f32>) -> IntResult<Vec<u32>> {
+        let q = dispatch_backend!(&self.lsh,
+            sql: lsh => lsh.query_bucket_ids(&*cast(&v))?,
+            sql_mem: lsh => lsh.query_bucket_ids(&*cast(&v))?,
+            mem: lsh => py.allow_threads(|| lsh.query_bucket_ids(&*cast(&v)))?
+        );
         Ok(q)
     }
 
     fn _increase_storage(&mut self, upper_bound: usize) -> IntResult<()> {
-        call_lsh_types!(&mut self.lsh, increase_storage, upper_bound, ;);
+        dispatch!(&mut self.lsh, lsh => lsh.increase_storage(upper_bound).map(|_| ()))?;
         Ok(())
     }
 
-    fn _query_batch(&self, vs: &PyArray2<f32>) -> IntResult<Vec<Vec<u32>>> {
-        let gil_guard = Python::acquire_gil();
-        let py = gil_guard.python();
+    fn _query_batch(&self, py: Python, vs: &PyArray2<f32>) -> IntResult<Vec<Vec<u32>>> {
         // allow threads doesn't make a difference on the rust side. But allows other python
         // code to run.
         // https://github.com/PyO3/pyo3/issues/649#issuecomment-546656381
@@ -152,92 +256,92 @@ impl Base {
         if !vs.is_standard_layout() {
             return Err(PyLshErr::NonContiguous);
         }
-        let q = match &self.lsh {
-            LshTypes::L2(lsh) => lsh.query_bucket_ids_batch_arr(vs),
-            LshTypes::L2Mem(lsh) => lsh.query_bucket_ids_batch_arr_par(vs),
-            LshTypes::MipsMem(lsh) => lsh.query_bucket_ids_batch_arr(vs),
-            LshTypes::Srp(lsh) => lsh.query_bucket_ids_batch_arr(vs),
-            LshTypes::SrpMem(lsh) => lsh.query_bucket_ids_batch_arr_par(vs),
-            _ => panic!("base not initialized"),
-        }?;
+        // SQLite connections cannot be shared between threads, so only the memory backend
+        // queries in parallel, and without the GIL. Then the queries are copied, as other
+        // python threads could write to the numpy array.
+        let q = dispatch_backend!(&self.lsh,
+            sql: lsh => lsh.query_bucket_ids_batch_arr(cast_array(vs).view()),
+            sql_mem: lsh => lsh.query_bucket_ids_batch_arr(cast_array(vs).view()),
+            mem: lsh => {
+                let vs = vs.to_owned();
+                py.allow_threads(|| lsh.query_bucket_ids_batch_arr_par(cast_array(vs.view()).view()))
+            }
+        )?;
         Ok(q)
     }
 
-    fn _query_bucket(&self, v: Vec<f32>) -> IntResult<Vec<Vec<f32>>> {
-        let q = match &self.lsh {
-            LshTypes::L2(lsh) => lsh
-                .query_bucket(&v)?
-                .into_iter()
-                .map(|dp| dp.clone())
-                .collect(),
-            LshTypes::L2Mem(lsh) => lsh
-                .query_bucket(&v)?
-                .into_iter()
-                .map(|dp| dp.clone())
-                .collect(),
-            LshTypes::MipsMem(lsh) => lsh
-                .query_bucket(&v)?
-                .into_iter()
-                .map(|dp| dp.clone())
-                .collect(),
-            LshTypes::Srp(lsh) => lsh
-                .query_bucket(&v)?
-                .into_iter()
-                .map(|dp| dp.clone())
-                .collect(),
-            LshTypes::SrpMem(lsh) => lsh
-                .query_bucket(&v)?
-                .into_iter()
-                .map(|dp| dp.clone())
-                .collect(),
-            LshTypes::Empty => panic!("base not initialized"),
-        };
+    /// Like [_query_bucket_idx](#method._query_bucket_idx), only the memory backend releases
+    /// the GIL.
+    fn _query_bucket(&self, py: Python, v: Vec<f32>) -> IntResult<Vec<Vec<f32>>> {
+        let q = dispatch_backend!(&self.lsh,
+            sql: lsh => bucket_to_f32s(lsh.query_bucket(&*cast(&v))?),
+            sql_mem: lsh => bucket_to_f32s(lsh.query_bucket(&*cast(&v))?),
+            mem: lsh => py.allow_threads(|| lsh.query_bucket(&*cast(&v)).map(bucket_to_f32s))?
+        );
         Ok(q)
     }
 
     fn _delete_vec(&mut self, v: Vec<f32>) -> IntResult<()> {
-        call_lsh_types!(&mut self.lsh, delete_vec, &v,)?;
+        dispatch!(&mut self.lsh, lsh => lsh.delete_vec(&*cast(&v)))?;
         Ok(())
     }
 
-    fn _describe(&mut self) -> IntResult<String> {
-        let s = call_lsh_types!(&mut self.lsh, describe,)?;
-        Ok(s)
+    /// Describing scans all buckets, so it runs without the GIL.
+    fn _describe(&mut self, py: Python) -> IntResult<String> {
+        let lsh = &mut self.lsh;
+        py.allow_threads(move || -> IntResult<String> {
+            Ok(dispatch!(lsh, lsh => lsh.describe())?)
+        })
+    }
+
+    fn _multi_probe(&mut self, budget: usize) -> IntResult<()> {
+        dispatch!(&mut self.lsh, lsh => {
+            lsh.multi_probe(budget);
+        });
+        Ok(())
+    }
+
+    fn _base(&mut self) -> IntResult<()> {
+        dispatch!(&mut self.lsh, lsh => {
+            lsh.base();
+        });
+        Ok(())
     }
 
     fn _commit(&mut self) -> IntResult<()> {
-        match &mut self.lsh {
-            LshTypes::L2(lsh) => lsh.commit()?,
-            LshTypes::Srp(lsh) => lsh.commit()?,
-            _ => panic!("base not initialized"),
-        };
+        dispatch_backend!(&mut self.lsh,
+            sql: lsh => lsh.commit()?,
+            sql_mem: lsh => lsh.hash_tables.as_ref().unwrap().commit()?,
+            mem: _lsh => return Err(PyLshErr::NotSupported("commit"))
+        );
         Ok(())
     }
 
     fn _init_transaction(&mut self) -> IntResult<()> {
-        match &mut self.lsh {
-            LshTypes::L2(lsh) => lsh.init_transaction()?,
-            LshTypes::Srp(lsh) => lsh.init_transaction()?,
-            _ => panic!("base not initialized"),
-        };
+        dispatch_backend!(&mut self.lsh,
+            sql: lsh => lsh.init_transaction()?,
+            sql_mem: lsh => lsh.hash_tables.as_ref().unwrap().init_transaction()?,
+            mem: _lsh => return Err(PyLshErr::NotSupported("init_transaction"))
+        );
         Ok(())
     }
 
     fn _index(&self) -> IntResult<()> {
-        match &self.lsh {
-            LshTypes::L2(lsh) => lsh.hash_tables.as_ref().unwrap().index_hash()?,
-            LshTypes::Srp(lsh) => lsh.hash_tables.as_ref().unwrap().index_hash()?,
-            _ => panic!("base not initialized"),
-        };
+        dispatch_backend!(&self.lsh,
+            sql: lsh => lsh.hash_tables.as_ref().unwrap().index_hash()?,
+            sql_mem: lsh => lsh.hash_tables.as_ref().unwrap().index_hash()?,
+            mem: _lsh => return Err(PyLshErr::NotSupported("index"))
+        );
         Ok(())
     }
 
     fn _to_mem(&mut self) -> IntResult<()> {
-        match &mut self.lsh {
-            LshTypes::L2(lsh) => lsh.hash_tables.as_mut().unwrap().to_mem()?,
-            LshTypes::Srp(lsh) => lsh.hash_tables.as_mut().unwrap().to_mem()?,
-            _ => panic!("base not initialized"),
-        };
+        dispatch_backend!(&mut self.lsh,
+            sql: lsh => lsh.hash_tables.as_mut().unwrap().to_mem()?,
+            // already in memory
+            sql_mem: _lsh => {},
+            mem: _lsh => return Err(PyLshErr::NotSupported("to_mem"))
+        );
         Ok(())
     }
 }
@@ -256,23 +360,23 @@ impl Base {
         Ok(())
     }
 
-    fn store_vecs(&mut self, vs: &PyArray2<f32>) -> PyResult<()> {
-        self._store_vecs(vs);
+    fn store_vecs(&mut self, py: Python, vs: &PyArray2<f32>) -> PyResult<()> {
+        self._store_vecs(py, vs)?;
         Ok(())
     }
 
-    fn query_bucket(&self, v: Vec<f32>) -> PyResult<Vec<Vec<f32>>> {
-        let q = self._query_bucket(v)?;
+    fn query_bucket(&self, py: Python, v: Vec<f32>) -> PyResult<Vec<Vec<f32>>> {
+        let q = self._query_bucket(py, v)?;
         Ok(q)
     }
 
-    fn query_bucket_idx(&self, v: Vec<f32>) -> PyResult<Vec<u32>> {
-        let q = self._query_bucket_idx(v)?;
+    fn query_bucket_idx(&self, py: Python, v: Vec<f32>) -> PyResult<Vec<u32>> {
+        let q = self._query_bucket_idx(py, v)?;
         Ok(q)
     }
 
-    fn query_bucket_idx_batch(&self, vs: &PyArray2<f32>) -> PyResult<Vec<Vec<u32>>> {
-        let q = self._query_batch(vs)?;
+    fn query_bucket_idx_batch(&self, py: Python, vs: &PyArray2<f32>) -> PyResult<Vec<Vec<u32>>> {
+        let q = self._query_batch(py, vs)?;
         Ok(q)
     }
 
@@ -281,8 +385,8 @@ impl Base {
         Ok(())
     }
 
-    fn describe(&mut self) -> PyResult<String> {
-        let s = self._describe()?;
+    fn describe(&mut self, py: Python) -> PyResult<String> {
+        let s = self._describe(py)?;
         Ok(s)
     }
 
@@ -312,12 +416,12 @@ impl Base {
     }
 
     fn multi_probe(&mut self, budget: usize) -> PyResult<()> {
-        call_lsh_types!(&mut self.lsh, multi_probe, budget, ;);
+        self._multi_probe(budget)?;
         Ok(())
     }
 
     fn base(&mut self) -> PyResult<()> {
-        call_lsh_types!(&mut self.lsh, base, ;);
+        self._base()?;
         Ok(())
     }
 }
@@ -327,6 +431,7 @@ struct LshL2 {}
 
 #[pymethods]
 impl LshL2 {
+    /// `backend` is one of "mem", "sqlite" or "sqlite_mem".
     #[new]
     fn new(
         n_projections: usize,
@@ -335,56 +440,21 @@ impl LshL2 {
         r: f32,
         seed: u64,
         db_path: String,
+        backend: &str,
     ) -> PyResult<(Self, Base)> {
-        let r = LshSql::new(n_projections, n_hash_tables, dim)
-            .seed(seed)
-            .only_index()
-            .set_database_file(&db_path)
-            .l2(r);
-
-        let lsh = match r {
-            Ok(lsh) => lsh,
-            Err(e) => return Err(RuntimeError::py_err(format!("{}", e))),
-        };
-        Ok((
-            LshL2 {},
-            Base {
-                lsh: LshTypes::L2(lsh),
-            },
-        ))
-    }
-}
-
-#[pyclass(extends=Base)]
-struct LshL2Mem {}
-
-#[pymethods]
-impl LshL2Mem {
-    #[new]
-    fn new(
-        n_projections: usize,
-        n_hash_tables: usize,
-        dim: usize,
-        r: f32,
-        seed: u64,
-        db_path: String,
-    ) -> PyResult<(Self, Base)> {
-        let r = LshMem::new(n_projections, n_hash_tables, dim)
-            .seed(seed)
-            .only_index()
-            .set_database_file(&db_path)
-            .l2(r);
-
-        let lsh = match r {
-            Ok(lsh) => lsh,
-            Err(e) => return Err(RuntimeError::py_err(format!("{}", e))),
-        };
-        Ok((
-            LshL2Mem {},
-            Base {
-                lsh: LshTypes::L2Mem(lsh),
-            },
-        ))
+        let base = build_lsh!(
+            backend,
+            n_projections,
+            n_hash_tables,
+            dim,
+            seed,
+            db_path,
+            l2(r),
+            L2Sql,
+            L2SqlMem,
+            L2Mem
+        )?;
+        Ok((LshL2 {}, base))
     }
 }
 
@@ -393,6 +463,7 @@ struct LshMips {}
 
 #[pymethods]
 impl LshMips {
+    /// `backend` is one of "mem", "sqlite" or "sqlite_mem".
     #[new]
     fn new(
         n_projections: usize,
@@ -403,30 +474,30 @@ impl LshMips {
         m: usize,
         seed: u64,
         db_path: String,
+        backend: &str,
     ) -> PyResult<(Self, Base)> {
-        let r = LshMem::new(n_projections, n_hash_tables, dim)
-            .seed(seed)
-            .only_index()
-            .set_database_file(&db_path)
-            .mips(r, U, m);
-        let lsh = match r {
-            Ok(lsh) => lsh,
-            Err(e) => return Err(RuntimeError::py_err(format!("{}", e))),
-        };
-
-        Ok((
-            LshMips {},
-            Base {
-                lsh: LshTypes::MipsMem(lsh),
-            },
-        ))
+        let base = build_lsh!(
+            backend,
+            n_projections,
+            n_hash_tables,
+            dim,
+            seed,
+            db_path,
+            mips(r, U, m),
+            MipsSql,
+            MipsSqlMem,
+            MipsMem
+        )?;
+        Ok((LshMips {}, base))
     }
 }
+
 #[pyclass(extends=Base)]
 struct LshSrp {}
 
 #[pymethods]
 impl LshSrp {
+    /// `backend` is one of "mem", "sqlite" or "sqlite_mem".
     #[new]
     fn new(
         n_projections: usize,
@@ -434,30 +505,31 @@ impl LshSrp {
         dim: usize,
         seed: u64,
         db_path: String,
+        backend: &str,
     ) -> PyResult<(Self, Base)> {
-        let r = LshSql::new(n_projections, n_hash_tables, dim)
-            .seed(seed)
-            .only_index()
-            .set_database_file(&db_path)
-            .srp();
-        let lsh = match r {
-            Ok(lsh) => lsh,
-            Err(e) => return Err(RuntimeError::py_err(format!("{}", e))),
-        };
-        Ok((
-            LshSrp {},
-            Base {
-                lsh: LshTypes::Srp(lsh),
-            },
-        ))
+        let base = build_lsh!(
+            backend,
+            n_projections,
+            n_hash_tables,
+            dim,
+            seed,
+            db_path,
+            srp(),
+            SrpSql,
+            SrpSqlMem,
+            SrpMem
+        )?;
+        Ok((LshSrp {}, base))
     }
 }
 
 #[pyclass(extends=Base)]
-struct LshSrpMem {}
+struct LshMinHash {}
 
 #[pymethods]
-impl LshSrpMem {
+impl LshMinHash {
+    /// `backend` is one of "mem", "sqlite" or "sqlite_mem". The data points are shingles
+    /// vectors, their values are cast to u16.
     #[new]
     fn new(
         n_projections: usize,
@@ -465,21 +537,20 @@ impl LshSrpMem {
         dim: usize,
         seed: u64,
         db_path: String,
+        backend: &str,
     ) -> PyResult<(Self, Base)> {
-        let r = LshMem::new(n_projections, n_hash_tables, dim)
-            .seed(seed)
-            .only_index()
-            .set_database_file(&db_path)
-            .srp();
-        let lsh = match r {
-            Ok(lsh) => lsh,
-            Err(e) => return Err(RuntimeError::py_err(format!("{}", e))),
-        };
-        Ok((
-            LshSrpMem {},
-            Base {
-                lsh: LshTypes::SrpMem(lsh),
-            },
-        ))
+        let base = build_lsh!(
+            backend,
+            n_projections,
+            n_hash_tables,
+            dim,
+            seed,
+            db_path,
+            minhash(),
+            MinHashSql,
+            MinHashSqlMem,
+            MinHashMem
+        )?;
+        Ok((LshMinHash {}, base))
     }
 }
@@ -1,7 +1,10 @@
-from floky import L2, SRP, QueryResult
+from floky import L2, SRP, MinHash, QueryResult
 import numpy as np
 from scipy.spatial.distance import cdist
 from typing import List
+import threading
+import time
+import pytest
 
 
 def get_mean_collisions(results: List[QueryResult]):
@@ -52,3 +55,71 @@ def test_srp():
     query = np.random.randn(n, dim)
     results = lsh.predict(query)
     print(get_mean_collisions(results))
+
+
+def test_in_mem_deprecated(tmp_path):
+    db_path = str(tmp_path / "lsh.db3")
+    for cls in (L2, SRP):
+        for in_mem, backend in ((False, "sqlite"), (True, "mem")):
+            with pytest.warns(DeprecationWarning):
+                lsh = cls(5, 3, log=False, db_path=db_path, in_mem=in_mem)
+            assert lsh.backend == backend
+            lsh.clean()
+
+
+def test_minhash():
+    np.random.seed(1)
+    # 64 random bits per row, so every row is unique.
+    arr = (np.random.rand(1000, 64) > 0.5).astype(np.float32)
+    lsh = MinHash(n_projections=5, n_hash_tables=10, dim=64, log=False, seed=1)
+    lsh.fit(arr)
+    results = lsh.predict(arr[:10], only_index=True, top_k=1)
+    for i, qr in enumerate(results):
+        assert qr.index[0] == i
+        assert qr.distances[0] == 0.0
+
+
+def test_gil_released():
+    # the main thread keeps running while another thread stores and queries in rust.
+    np.random.seed(1)
+    dim = 50
+    arr = np.random.randn(50000, dim).astype(np.float32)
+    lsh = L2(n_projections=10, n_hash_tables=20, dim=dim, log=False, seed=1)
+    window = []
+
+    def work():
+        start = time.perf_counter()
+        lsh.lsh.store_vecs(arr)
+        lsh.lsh.query_bucket_idx_batch(arr[:10000])
+        window.extend([start, time.perf_counter()])
+
+    worker = threading.Thread(target=work)
+    ticks = []
+    worker.start()
+    while worker.is_alive():
+        ticks.append(time.perf_counter())
+    worker.join()
+    start, end = window
+    ticks = [start] + [t for t in ticks if start < t < end] + [end]
+    # with the GIL held, the main thread would stall for the whole store and query.
+    assert max(b - a for a, b in zip(ticks, ticks[1:])) < (end - start) / 2
+
+
+def test_fit_frame(tmp_path):
+    pd = pytest.importorskip("pandas")
+    np.random.seed(1)
+    arr = np.random.randn(100, 4).astype(np.float32)
+    df = pd.DataFrame(arr, columns=["a", "b", "c", "d"])
+    df["label"] = "x"
+
+    lsh = SRP(n_projections=5, n_hash_tables=3, log=False, seed=1)
+    with pytest.raises(ValueError):
+        lsh.fit_frame(df)
+    lsh.fit_frame(df, columns=["a", "b", "c", "d"])
+    np.testing.assert_array_equal(lsh.data, arr)
+
+    pytest.importorskip("pyarrow")
+    path = tmp_path / "data.parquet"
+    df.to_parquet(path)
+    lsh.fit_frame(path, columns=["a", "b", "c", "d"])
+    np.testing.assert_array_equal(lsh.data, arr)
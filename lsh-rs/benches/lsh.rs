@@ -0,0 +1,547 @@
+//! Benchmarks of hashing, storing and querying, for every backend and every hasher. Runs on
+//! stable Rust:
+//!
+//! `cargo bench`
+//!
+//! The queries run against an index of `LSH_BENCH_N` (default 1M) vectors, with multi probe
+//! budgets of 0 (no multi probing), 16 and 64. Hashers that don't support multi probing, L1,
+//! MIPS and the MinHash and bit sampling families, are only queried with a budget of 0. The query benchmark
+//! ids are `query/{single,batch,batch_par}/backend/hasher/budget`, so a subset can be selected
+//! with a filter, e.g. `cargo bench -- query/single/mem/`. A smaller index, e.g.
+//! `LSH_BENCH_N=100000`, makes a quick run.
+//!
+//! To detect regressions, save a baseline before a change and compare against it afterwards:
+//!
+//! `cargo bench -- --save-baseline main`
+//! `cargo bench -- --baseline main`
+//!
+//! The benchmarks need the (default) "sqlite" feature. The memory-mapped backend is benchmarked
+//! with the "mmap" feature, `cargo bench --features mmap`. BLAS needs a backend of `blas-src`,
+//! e.g. `cargo bench --features "blas blas-src/openblas"`, the hand-vectorized projections
+//! `cargo bench --features simd`.
+use criterion::{
+    black_box, criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, BenchmarkId,
+    Criterion, Throughput,
+};
+use lsh_rs::data::{Integer, Numeric};
+use lsh_rs::prelude::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+const DIM: usize = 100;
+const N_PROJECTIONS: usize = 20;
+const N_HASH_TABLES: usize = 7;
+const N_STORE: usize = 10_000;
+const N_QUERIES: usize = 1000;
+const BUDGETS: [usize; 3] = [0, 16, 64];
+/// Quantization scale of the int8 data points.
+const SCALE: f32 = 1. / 32.;
+/// Number of `u64` words of the bit vectors of BitSampling.
+const BIT_WORDS: usize = 4;
+/// Dimension of the binary data points of the MinHash hashers, with about 1 in 50 ones.
+const SET_DIM: usize = 1000;
+const B_BITS: u32 = 4;
+
+fn n_index() -> usize {
+    std::env::var("LSH_BENCH_N")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1_000_000)
+}
+
+fn prep_vecs(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| (&mut rng).sample_iter(StandardNormal).take(dim).collect())
+        .collect()
+}
+
+/// Int8 quantized `vs`, see [SCALE].
+fn quantize(vs: &[Vec<f32>]) -> Vec<Vec<i8>> {
+    vs.iter()
+        .map(|v| {
+            v.iter()
+                .map(|&x| (x / SCALE).round().clamp(-128., 127.) as i8)
+                .collect()
+        })
+        .collect()
+}
+
+fn prep_bits(n: usize, seed: u64) -> Vec<Vec<u64>> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| (0..BIT_WORDS).map(|_| rng.gen()).collect())
+        .collect()
+}
+
+fn prep_sets(n: usize, seed: u64) -> Vec<Vec<u16>> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| (0..SET_DIM).map(|_| rng.gen_bool(0.02) as u16).collect())
+        .collect()
+}
+
+/// A database file in the temporary directory, removed if it exists.
+fn tmp_db(name: &str) -> String {
+    let mut path = std::env::temp_dir();
+    path.push(name);
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+/// An in memory index that only stores the ids, so the hashers are compared without the copies of
+/// the data points.
+fn mem_index_only<H, N, K>(dim: usize) -> LshMem<H, N, K>
+where
+    H: VecHash<N, K>,
+    N: Numeric,
+    K: Integer,
+{
+    let mut lsh = LshMem::new(N_PROJECTIONS, N_HASH_TABLES, dim);
+    lsh.only_index();
+    lsh
+}
+
+/// Set the seed and the multi probe `budget` of an index that is being built.
+fn configure<H, N, T, K>(lsh: &mut LSH<H, N, T, K>, budget: usize) -> &mut LSH<H, N, T, K>
+where
+    H: VecHash<N, K>,
+    N: Numeric,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    lsh.seed(1);
+    if budget > 0 {
+        lsh.multi_probe(budget);
+    }
+    lsh
+}
+
+/// Single, batch and, with the method `$par`, parallel batch queries of `$lsh`. Backends that
+/// can't be queried in parallel omit `$par`.
+macro_rules! bench_queries {
+    ($group:expr, $id:expr, $lsh:expr, $queries:expr $(, $par:ident)?) => {{
+        let group: &mut BenchmarkGroup<WallTime> = $group;
+        let id: &str = &$id;
+        let lsh = &$lsh;
+        let queries = &$queries[..];
+
+        group.throughput(Throughput::Elements(1));
+        let mut i = 0;
+        group.bench_function(BenchmarkId::new("single", id), |b| {
+            b.iter(|| {
+                i = (i + 1) % queries.len();
+                lsh.query_bucket_ids(&queries[i]).unwrap()
+            })
+        });
+
+        group.throughput(Throughput::Elements(queries.len() as u64));
+        group.bench_function(BenchmarkId::new("batch", id), |b| {
+            b.iter(|| lsh.query_bucket_ids_batch(queries).unwrap())
+        });
+        $(
+            group.bench_function(BenchmarkId::new("batch_par", id), |b| {
+                b.iter(|| lsh.$par(queries).unwrap())
+            });
+        )?
+    }};
+}
+
+/// Store `$vs` in a fresh index, built from `$lsh` with the terminal builder method `$build`.
+/// Hashers that need to be fitted, i.e. MIPS, pass `fit` after the builder method. The fit on
+/// the stored vectors is part of the benchmark.
+macro_rules! bench_store {
+    ($group:expr, $id:expr, $lsh:expr, $vs:expr, $build:ident($($arg:expr),*) $(, $fit:ident)?) => {{
+        let group: &mut BenchmarkGroup<WallTime> = $group;
+        let vs = &$vs;
+        group.bench_function($id, |b| {
+            b.iter(|| {
+                let mut lsh = configure(&mut $lsh, 0).$build($($arg),*).unwrap();
+                $(lsh.$fit(vs).unwrap();)?
+                lsh.store_vecs(vs).unwrap();
+                lsh
+            })
+        });
+    }};
+}
+
+fn bench_hash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash");
+    let v = [1.; DIM];
+    let sparse = 1. / (DIM as f32).sqrt();
+
+    let srp = SignRandomProjections::<f32>::new(N_PROJECTIONS, DIM, 0);
+    group.bench_function("srp", |b| b.iter(|| srp.hash_vec_query(black_box(&v))));
+
+    let srp_sparse =
+        SignRandomProjections::<f32>::new_sparse(N_PROJECTIONS, DIM, sparse, 0).unwrap();
+    group.bench_function("srp_sparse", |b| {
+        b.iter(|| srp_sparse.hash_vec_query(black_box(&v)))
+    });
+
+    let srp_packed = PackedSignRandomProjections::<f32>::new(N_PROJECTIONS, DIM, 0);
+    group.bench_function("srp_packed", |b| {
+        b.iter(|| srp_packed.hash_vec_query(black_box(&v)))
+    });
+
+    let srp_quantized =
+        QuantizedSignRandomProjections::new(N_PROJECTIONS, DIM, &[SCALE], 0).unwrap();
+    let vq = [32; DIM];
+    group.bench_function("srp_quantized", |b| {
+        b.iter(|| srp_quantized.hash_vec_query(black_box(&vq)))
+    });
+
+    let l2: L2<f32, i32> = L2::new(DIM, 4., N_PROJECTIONS, 0);
+    group.bench_function("l2", |b| b.iter(|| l2.hash_vec_query(black_box(&v))));
+
+    let l2_sparse: L2<f32, i32> = L2::new_sparse(DIM, 4., N_PROJECTIONS, sparse, 0).unwrap();
+    group.bench_function("l2_sparse", |b| {
+        b.iter(|| l2_sparse.hash_vec_query(black_box(&v)))
+    });
+
+    let l1: L1<f32, i32> = L1::new(DIM, 4., N_PROJECTIONS, 0);
+    group.bench_function("l1", |b| b.iter(|| l1.hash_vec_query(black_box(&v))));
+
+    let mips: MIPS<f32, i32> = MIPS::new(DIM, 4., 0.83, 3, N_PROJECTIONS, 0);
+    group.bench_function("mips", |b| b.iter(|| mips.hash_vec_query(black_box(&v))));
+
+    let bits = [0x5555_5555_5555_5555u64; BIT_WORDS];
+    let bit_sampling: BitSampling<u64, i8> = BitSampling::new(N_PROJECTIONS, BIT_WORDS, 0);
+    group.bench_function("bit_sampling", |b| {
+        b.iter(|| bit_sampling.hash_vec_query(black_box(&bits)))
+    });
+
+    let dim = 100_000;
+    let v = (0..dim).map(|i| (i % 100 == 0) as u32).collect::<Vec<_>>();
+    let h: MinHash<u32, i32> = MinHash::new(128, dim, 0);
+    group.bench_function("minhash", |b| b.iter(|| h.hash_vec_query(black_box(&v))));
+    let h: BMinHash<u32, i32> = BMinHash::new(128, dim, B_BITS, 0);
+    group.bench_function("b_minhash", |b| b.iter(|| h.hash_vec_query(black_box(&v))));
+    group.finish();
+}
+
+fn bench_store(c: &mut Criterion) {
+    let mut group = c.benchmark_group("store");
+    group.sample_size(10);
+    let vs = prep_vecs(N_STORE, DIM, 0);
+    let vq = quantize(&vs);
+    let bits = prep_bits(N_STORE, 0);
+    let sets = prep_sets(N_STORE, 0);
+    let sparse = 1. / (DIM as f32).sqrt();
+    group.throughput(Throughput::Elements(N_STORE as u64));
+
+    let mem = || LshMem::new(N_PROJECTIONS, N_HASH_TABLES, DIM);
+    bench_store!(&mut group, "mem/srp", mem(), vs, srp());
+    let mem = || hi32::LshMem::new(N_PROJECTIONS, N_HASH_TABLES, DIM);
+    bench_store!(&mut group, "mem/l2", mem(), vs, l2(4.));
+
+    let sparse_srp = || {
+        let mut lsh = mem_index_only(DIM);
+        lsh.projection_density(sparse);
+        lsh
+    };
+    let scale = [SCALE];
+    bench_store!(
+        &mut group,
+        "mem_index_only/srp",
+        mem_index_only(DIM),
+        vs,
+        srp()
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/srp_sparse",
+        sparse_srp(),
+        vs,
+        srp()
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/srp_packed",
+        mem_index_only(DIM),
+        vs,
+        srp_packed()
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/srp_quantized",
+        mem_index_only(DIM),
+        vq,
+        srp_quantized(&scale)
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/l2",
+        mem_index_only::<_, _, i32>(DIM),
+        vs,
+        l2(4.)
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/l1",
+        mem_index_only::<_, _, i32>(DIM),
+        vs,
+        l1(4.)
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/mips",
+        mem_index_only::<_, _, i32>(DIM),
+        vs,
+        mips(4., 0.83, 3),
+        fit
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/bit_sampling",
+        mem_index_only::<_, _, i8>(BIT_WORDS),
+        bits,
+        bit_sampling()
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/minhash",
+        mem_index_only::<_, _, i32>(SET_DIM),
+        sets,
+        minhash()
+    );
+    bench_store!(
+        &mut group,
+        "mem_index_only/b_minhash",
+        mem_index_only::<_, _, i32>(SET_DIM),
+        sets,
+        b_minhash(B_BITS)
+    );
+
+    let shared = || LshShared::new(N_PROJECTIONS, N_HASH_TABLES, DIM);
+    bench_store!(&mut group, "shared/srp", shared(), vs, srp());
+    let sql = || LshSqlMem::new(N_PROJECTIONS, N_HASH_TABLES, DIM);
+    bench_store!(&mut group, "sqlite_mem/srp", sql(), vs, srp());
+    group.bench_function("sqlite/srp", |b| {
+        b.iter(|| {
+            let path = tmp_db("lsh_bench_store.db3");
+            let mut lsh = configure(&mut LshSql::new(N_PROJECTIONS, N_HASH_TABLES, DIM), 0)
+                .set_database_file(&path)
+                .srp()
+                .unwrap();
+            lsh.store_vecs(&vs).unwrap();
+            lsh.commit().unwrap();
+            lsh
+        })
+    });
+    #[cfg(feature = "mmap")]
+    group.bench_function("mmap/srp", |b| {
+        b.iter(|| {
+            let path = tmp_db("lsh_bench_store.mmap");
+            let mut lsh = configure(&mut LshMmap::new(N_PROJECTIONS, N_HASH_TABLES, DIM), 0)
+                .set_database_file(&path)
+                .srp()
+                .unwrap();
+            lsh.store_vecs(&vs).unwrap();
+            lsh.flush().unwrap();
+            lsh
+        })
+    });
+    group.finish();
+}
+
+/// Query the in memory index `$lsh`, built with the terminal builder method `$build` and multi
+/// probe `$budget`. The id is `mem/$name/budget`.
+macro_rules! bench_mem_query {
+    ($group:expr, $name:expr, $budget:expr, $vs:expr, $queries:expr, $lsh:expr, $build:ident($($arg:expr),*) $(, $fit:ident)?) => {{
+        let mut lsh = configure(&mut $lsh, $budget).$build($($arg),*).unwrap();
+        $(lsh.$fit(&$vs).unwrap();)?
+        lsh.store_vecs(&$vs).unwrap();
+        let id = format!("mem/{}/{}", $name, $budget);
+        bench_queries!($group, id, lsh, $queries, query_bucket_ids_batch_par);
+    }};
+}
+
+/// Query every backend with SignRandomProjections, and the in memory backend with every hasher.
+fn bench_query(c: &mut Criterion) {
+    let n = n_index();
+    let vs = prep_vecs(n, DIM, 0);
+    let queries = prep_vecs(N_QUERIES, DIM, n as u64);
+    let vq = quantize(&vs);
+    let queries_q = quantize(&queries);
+
+    let mut group = c.benchmark_group("query");
+    for &budget in &BUDGETS {
+        let mut lsh = configure(&mut LshMem::new(N_PROJECTIONS, N_HASH_TABLES, DIM), budget)
+            .only_index()
+            .srp()
+            .unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let id = format!("mem/srp/{}", budget);
+        bench_queries!(&mut group, id, lsh, queries, query_bucket_ids_batch_par);
+
+        let lsh = configure(
+            &mut LshShared::new(N_PROJECTIONS, N_HASH_TABLES, DIM),
+            budget,
+        )
+        .only_index()
+        .srp()
+        .unwrap();
+        lsh.store_vecs_shared(&vs).unwrap();
+        let id = format!("shared/srp/{}", budget);
+        bench_queries!(&mut group, id, lsh, queries, query_bucket_ids_batch_par);
+
+        let mut lsh = configure(
+            &mut LshSqlMem::new(N_PROJECTIONS, N_HASH_TABLES, DIM),
+            budget,
+        )
+        .only_index()
+        .srp()
+        .unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let id = format!("sqlite_mem/srp/{}", budget);
+        bench_queries!(&mut group, id, lsh, queries);
+
+        // parallel SQLite queries need a database file that is opened read-only.
+        let path = tmp_db("lsh_bench_query.db3");
+        let mut lsh = configure(&mut LshSql::new(N_PROJECTIONS, N_HASH_TABLES, DIM), budget)
+            .only_index()
+            .set_database_file(&path)
+            .srp()
+            .unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        lsh.commit().unwrap();
+        drop(lsh);
+        let lsh = configure(
+            &mut LshSql::<SignRandomProjections<f32>>::new(N_PROJECTIONS, N_HASH_TABLES, DIM),
+            budget,
+        )
+        .open_readonly(&path)
+        .unwrap();
+        let id = format!("sqlite/srp/{}", budget);
+        bench_queries!(&mut group, id, lsh, queries, query_bucket_ids_batch_par_sql);
+
+        #[cfg(feature = "mmap")]
+        {
+            let path = tmp_db("lsh_bench_query.mmap");
+            let mut lsh = configure(&mut LshMmap::new(N_PROJECTIONS, N_HASH_TABLES, DIM), budget)
+                .only_index()
+                .set_database_file(&path)
+                .srp()
+                .unwrap();
+            lsh.store_vecs(&vs).unwrap();
+            let id = format!("mmap/srp/{}", budget);
+            bench_queries!(&mut group, id, lsh, queries, query_bucket_ids_batch_par);
+        }
+
+        let sparse_srp = || {
+            let mut lsh = mem_index_only(DIM);
+            lsh.projection_density(1. / (DIM as f32).sqrt());
+            lsh
+        };
+        let scale = [SCALE];
+        bench_mem_query!(
+            &mut group,
+            "srp_sparse",
+            budget,
+            vs,
+            queries,
+            sparse_srp(),
+            srp()
+        );
+        bench_mem_query!(
+            &mut group,
+            "srp_packed",
+            budget,
+            vs,
+            queries,
+            mem_index_only(DIM),
+            srp_packed()
+        );
+        bench_mem_query!(
+            &mut group,
+            "srp_quantized",
+            budget,
+            vq,
+            queries_q,
+            mem_index_only(DIM),
+            srp_quantized(&scale)
+        );
+        bench_mem_query!(
+            &mut group,
+            "l2",
+            budget,
+            vs,
+            queries,
+            mem_index_only::<_, _, i32>(DIM),
+            l2(4.)
+        );
+    }
+
+    // these hashers don't support multi probing.
+    bench_mem_query!(
+        &mut group,
+        "mips",
+        0,
+        vs,
+        queries,
+        mem_index_only::<_, _, i32>(DIM),
+        mips(4., 0.83, 3),
+        fit
+    );
+    bench_mem_query!(
+        &mut group,
+        "l1",
+        0,
+        vs,
+        queries,
+        mem_index_only::<_, _, i32>(DIM),
+        l1(4.)
+    );
+    let bits = prep_bits(n, 0);
+    let queries_bits = prep_bits(N_QUERIES, n as u64);
+    let sets = prep_sets(n, 0);
+    let queries_sets = prep_sets(N_QUERIES, n as u64);
+    bench_mem_query!(
+        &mut group,
+        "bit_sampling",
+        0,
+        bits,
+        queries_bits,
+        mem_index_only::<_, _, i8>(BIT_WORDS),
+        bit_sampling()
+    );
+    bench_mem_query!(
+        &mut group,
+        "minhash",
+        0,
+        sets,
+        queries_sets,
+        mem_index_only::<_, _, i32>(SET_DIM),
+        minhash()
+    );
+    bench_mem_query!(
+        &mut group,
+        "b_minhash",
+        0,
+        sets,
+        queries_sets,
+        mem_index_only::<_, _, i32>(SET_DIM),
+        b_minhash(B_BITS)
+    );
+    group.finish();
+}
+
+fn bench_sqlite_put(c: &mut Criterion) {
+    let mut sql = SqlTableMem::<f32, i8>::new(1, true, ".").unwrap();
+    let v = vec![1., 2.];
+    let hash = vec![1, 2];
+    c.bench_function("sqlite_put", |b| {
+        b.iter(|| sql.put(hash.clone(), &v, 0).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_hash,
+    bench_store,
+    bench_query,
+    bench_sqlite_put
+);
+criterion_main!(benches);
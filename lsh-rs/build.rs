@@ -0,0 +1,10 @@
+fn main() {
+    #[cfg(feature = "serve")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_build::configure()
+            .build_transport(false)
+            .compile_protos(&["proto/lsh.proto"], &["proto"])
+            .unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Flag near duplicates in a stream of embeddings. Every line holds one comma separated
+//! embedding. An embedding is a duplicate if a stored embedding lies within the distance
+//! threshold. Embeddings expire after `ttl` newer embeddings and are deleted from the index.
+//! Every `checkpoint` embeddings, the live embeddings are moved to a fresh index, which frees
+//! the vectors of the expired ones, and the index is dumped. At the end, the last checkpoint is
+//! reloaded as a restarted service would do. Run with:
+//!
+//! `cargo run --example dedup_stream -- [FILE|-] [threshold] [ttl] [checkpoint]`
+//!
+//! Without a file, the embeddings are read from stdin. `tests/dedup_stream.rs` runs the same
+//! path on a generated stream.
+use lsh_rs::prelude::{LshMem, Result as LshResult, L2};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+type Index = LshMem<L2<f32, i32>, f32, i32>;
+
+fn parse(line: &str) -> Result<Vec<f32>, std::num::ParseFloatError> {
+    line.split(',').map(|x| x.trim().parse()).collect()
+}
+
+fn new_index(dim: usize, threshold: f64) -> LshResult<Index> {
+    LshMem::new(8, 16, dim)
+        .seed(1)
+        .multi_probe(8)
+        .l2(4. * threshold as f32)
+}
+
+/// Rolling deduplication index. The user id of a stored embedding is its number in the stream.
+pub struct Dedup {
+    threshold: f64,
+    ttl: usize,
+    // the index is created once the first embedding sets the dimension.
+    index: Option<Index>,
+    // ids of the stored embeddings, oldest first.
+    live: VecDeque<u32>,
+    n: usize,
+    n_duplicates: usize,
+}
+
+impl Dedup {
+    pub fn new(threshold: f64, ttl: usize) -> Self {
+        Dedup {
+            threshold,
+            ttl,
+            index: None,
+            live: VecDeque::new(),
+            n: 0,
+            n_duplicates: 0,
+        }
+    }
+
+    /// Add the next embedding of the stream. Returns the number of the embedding it duplicates
+    /// and their distance, or `None` if it is stored.
+    pub fn push(&mut self, v: &[f32]) -> LshResult<Option<(u64, f64)>> {
+        if self.index.is_none() {
+            self.index = Some(new_index(v.len(), self.threshold)?);
+        }
+        let lsh = self.index.as_mut().unwrap();
+        let n = self.n as u64;
+        self.n += 1;
+
+        let duplicate = match lsh.query_top_k(v, 1)?.first() {
+            Some(&(idx, dist)) if dist <= self.threshold => Some((lsh.user_id(idx), dist)),
+            _ => None,
+        };
+        match duplicate {
+            Some(_) => self.n_duplicates += 1,
+            None => self.live.push_back(lsh.store_vec_with_id(v, n)?),
+        }
+        // expire the embeddings that are older than the ttl.
+        while self.live.len() > self.ttl {
+            let idx = self.live.pop_front().unwrap();
+            lsh.delete_by_idx(idx)?;
+        }
+        Ok(duplicate)
+    }
+
+    /// Move the live embeddings, oldest first, to a fresh index and dump it to `path`. The
+    /// deleted embeddings stay in the vector store of an index, so dropping the old index
+    /// frees them.
+    pub fn checkpoint(&mut self, path: &Path) -> LshResult<()> {
+        let lsh = match self.index.as_mut() {
+            Some(lsh) => lsh,
+            None => return Ok(()),
+        };
+        let mut fresh = new_index(lsh.dim, self.threshold)?;
+        let old = lsh.hash_tables.as_ref().unwrap();
+        let mut moved = VecDeque::with_capacity(self.live.len());
+        for &idx in self.live.iter() {
+            let v = old.vec_store.get(idx as usize);
+            moved.push_back(fresh.store_vec_with_id(v, lsh.user_id(idx))?);
+        }
+        *lsh = fresh;
+        self.live = moved;
+        lsh.dump(path)
+    }
+
+    /// Reload the checkpoint at `path` and count the live embeddings that the reloaded index
+    /// finds under their own number. Call it right after [checkpoint](#method.checkpoint).
+    pub fn reload(&self, path: &Path) -> LshResult<usize> {
+        let lsh = match self.index.as_ref() {
+            Some(lsh) => lsh,
+            None => return Ok(0),
+        };
+        let mut reloaded = new_index(lsh.dim, self.threshold)?;
+        reloaded.load(path)?;
+        let vec_store = &lsh.hash_tables.as_ref().unwrap().vec_store;
+        let mut found = 0;
+        for &idx in self.live.iter() {
+            let v = vec_store.get(idx as usize);
+            if let Some(&(r_idx, _)) = reloaded.query_top_k(v, 1)?.first() {
+                if reloaded.user_id(r_idx) == lsh.user_id(idx) {
+                    found += 1;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Number of embeddings in the stream so far.
+    pub fn n_embeddings(&self) -> usize {
+        self.n
+    }
+
+    pub fn n_duplicates(&self) -> usize {
+        self.n_duplicates
+    }
+
+    /// Number of embeddings that haven't expired.
+    pub fn n_live(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Number of vectors in the vector store, including the expired ones since the last
+    /// checkpoint.
+    pub fn n_stored(&self) -> usize {
+        self.index
+            .as_ref()
+            .map_or(0, |lsh| lsh.hash_tables.as_ref().unwrap().vec_store.len())
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let input: Box<dyn BufRead> = match args.first().map(String::as_str) {
+        None | Some("-") => Box::new(BufReader::new(io::stdin())),
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+    };
+    let threshold: f64 = args.get(1).map_or(Ok(0.5), |s| s.parse())?;
+    let ttl: usize = args.get(2).map_or(Ok(10_000), |s| s.parse())?;
+    let checkpoint: usize = args.get(3).map_or(Ok(1_000), |s| s.parse())?;
+    let checkpoint_path = std::env::temp_dir().join("lsh_dedup_stream.bincode");
+
+    let mut dedup = Dedup::new(threshold, ttl);
+    let mut until_checkpoint = checkpoint;
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let v = parse(&line)?;
+        if let Some((original, dist)) = dedup.push(&v)? {
+            println!(
+                "{}\tduplicate of {}\tdistance {:.4}",
+                dedup.n_embeddings() - 1,
+                original,
+                dist
+            );
+        }
+        until_checkpoint -= 1;
+        if until_checkpoint == 0 {
+            until_checkpoint = checkpoint;
+            dedup.checkpoint(&checkpoint_path)?;
+            eprintln!(
+                "checkpoint at {} embeddings: {} live, {} duplicates",
+                dedup.n_embeddings(),
+                dedup.n_live(),
+                dedup.n_duplicates()
+            );
+        }
+    }
+    eprintln!(
+        "{} embeddings, {} duplicates, {} live, {} stored",
+        dedup.n_embeddings(),
+        dedup.n_duplicates(),
+        dedup.n_live(),
+        dedup.n_stored()
+    );
+    dedup.checkpoint(&checkpoint_path)?;
+    let found = dedup.reload(&checkpoint_path)?;
+    eprintln!(
+        "reloaded checkpoint {}: {} of {} live embeddings found",
+        checkpoint_path.display(),
+        found,
+        dedup.n_live()
+    );
+    Ok(())
+}
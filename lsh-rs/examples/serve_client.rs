@@ -0,0 +1,49 @@
+//! Store and query vectors on a running `serve_server` example. Run with:
+//!
+//! `cargo run --example serve_client --features serve`
+use lsh_rs::serve::proto::{
+    lsh_client::LshClient, QueryRequest, StoreRequest, TopKRequest, Vector,
+};
+use tonic::transport::Endpoint;
+
+fn vector(values: &[f32]) -> Vector {
+    Vector {
+        values: values.to_vec(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let channel = Endpoint::from_static("http://[::1]:50051")
+        .connect()
+        .await?;
+    let mut client = LshClient::new(channel);
+
+    let stored = client
+        .store(StoreRequest {
+            vectors: vec![
+                vector(&[2., 3., 4.]),
+                vector(&[2.1, 3., 4.]),
+                vector(&[-20., 30., -4.]),
+            ],
+        })
+        .await?;
+    println!("stored: {:?}", stored.into_inner().ids);
+
+    let q = vector(&[2., 3.1, 4.]);
+    let candidates = client
+        .query(QueryRequest {
+            vector: Some(q.clone()),
+        })
+        .await?;
+    println!("candidates: {:?}", candidates.into_inner().ids);
+
+    let top_k = client
+        .top_k(TopKRequest {
+            vector: Some(q),
+            k: 1,
+        })
+        .await?;
+    println!("nearest: {:?}", top_k.into_inner().neighbors);
+    Ok(())
+}
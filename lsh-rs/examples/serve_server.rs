@@ -0,0 +1,19 @@
+//! Serve an in memory L2 index over gRPC. Run with:
+//!
+//! `cargo run --example serve_server --features serve`
+use lsh_rs::prelude::LshMem;
+use lsh_rs::serve::LshService;
+use tonic::transport::Server;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let lsh = LshMem::<_, f32, i32>::new(9, 20, 3).seed(1).l2(4.)?;
+    let addr = "[::1]:50051".parse()?;
+    println!("serving on {}", addr);
+
+    Server::builder()
+        .add_service(LshService::new(lsh).into_server())
+        .serve(addr)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,447 @@
+//! Serializable configuration of an [LSH](../struct.LSH.html) index, so that experiments can be
+//! captured and replayed from a file.
+use crate::constants::AUTO_INDEX_THRESHOLD;
+use crate::data::{Integer, MaybeDeserialize, Numeric};
+use crate::prelude::*;
+use num::{Float, PrimInt};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Hash family and the parameters of its hash functions.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "family", rename_all = "snake_case"))]
+pub enum HashFamily {
+    /// [SignRandomProjections](../struct.SignRandomProjections.html)
+    Srp {
+        #[cfg_attr(feature = "serde", serde(default))]
+        sign: SignConvention,
+    },
+    /// [QuantizedSignRandomProjections](../struct.QuantizedSignRandomProjections.html) with the
+    /// quantization `scale`.
+    SrpQuantized {
+        #[cfg_attr(feature = "serde", serde(default))]
+        sign: SignConvention,
+        scale: Vec<f32>,
+    },
+    /// [PackedSignRandomProjections](../struct.PackedSignRandomProjections.html)
+    SrpPacked,
+    /// [L2](../struct.L2.html) with bucket width `r`.
+    L2 { r: f32 },
+    /// [L1](../struct.L1.html) with bucket width `r`.
+    L1 { r: f32 },
+    /// [MIPS](../struct.MIPS.html)
+    Mips { r: f32, U: f32, m: usize },
+    /// [MinHash](../struct.MinHash.html)
+    MinHash,
+    /// [BMinHash](../struct.BMinHash.html) that keeps `b` bits per MinHash value.
+    BMinHash { b: u32 },
+    /// [BitSampling](../struct.BitSampling.html)
+    BitSampling,
+}
+
+/// All hyperparameters of an [LSH](../struct.LSH.html) index. Build an index with
+/// [LSH::from_config](../struct.LSH.html#method.from_config) and get the configuration of an
+/// index with [LSH::config](../struct.LSH.html#method.config).
+///
+/// The backend is chosen by the type of the index, the configuration holds its settings.
+/// Optional settings default to the defaults of the builder.
+///
+/// # Examples
+#[cfg_attr(feature = "serde", doc = "```")]
+#[cfg_attr(not(feature = "serde"), doc = "```ignore")]
+/// use lsh_rs::prelude::*;
+/// let cfg: LshConfig = serde_json::from_str(
+///     r#"{"hasher": {"family": "l2", "r": 4.0}, "n_projections": 5, "n_hash_tables": 10,
+///         "dim": 3, "seed": 12}"#,
+/// )
+/// .unwrap();
+/// let lsh = LshMem::<L2<f32, i32>, f32, i32>::from_config(&cfg).unwrap();
+/// assert_eq!(lsh.config(), cfg);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LshConfig {
+    pub hasher: HashFamily,
+    /// Hash length. `K` in literature.
+    pub n_projections: usize,
+    /// Number of hash tables. `L` in literature.
+    pub n_hash_tables: usize,
+    pub dim: usize,
+    /// If 0, randomness is seeded from the os and the hashers can't be replayed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub only_index: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub multi_probe: bool,
+    #[cfg_attr(feature = "serde", serde(default = "default_multi_probe_budget"))]
+    pub multi_probe_budget: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub flat_storage: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub packed_keys: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_bucket_size: usize,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub max_bucket_size: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub boundary_eps: f64,
+    #[cfg_attr(feature = "serde", serde(default = "default_db_path"))]
+    pub db_path: String,
+    #[cfg_attr(feature = "serde", serde(default = "default_auto_index"))]
+    pub auto_index: Option<u32>,
+    /// Hash length per hash table, overrides `n_projections`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub projections_per_table: Option<Vec<usize>>,
+    /// Rows of the projection matrix that is shared by the L2 hash tables.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub shared_projections: Option<usize>,
+    /// Fraction of non-zero entries of sparse random projections.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub projection_density: Option<f32>,
+    /// Open the existing database without writing to it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub read_only: bool,
+}
+
+fn default_multi_probe_budget() -> usize {
+    16
+}
+
+fn default_db_path() -> String {
+    "./lsh.db3".to_string()
+}
+
+fn default_auto_index() -> Option<u32> {
+    Some(AUTO_INDEX_THRESHOLD)
+}
+
+impl LshConfig {
+    /// Configuration with the defaults of the builder.
+    pub fn new(hasher: HashFamily, n_projections: usize, n_hash_tables: usize, dim: usize) -> Self {
+        LshConfig {
+            hasher,
+            n_projections,
+            n_hash_tables,
+            dim,
+            seed: 0,
+            only_index: false,
+            multi_probe: false,
+            multi_probe_budget: default_multi_probe_budget(),
+            flat_storage: false,
+            packed_keys: false,
+            min_bucket_size: 0,
+            max_bucket_size: None,
+            boundary_eps: 0.,
+            db_path: default_db_path(),
+            auto_index: default_auto_index(),
+            projections_per_table: None,
+            shared_projections: None,
+            projection_density: None,
+            read_only: false,
+        }
+    }
+
+    /// Read a configuration from a JSON file.
+    #[cfg(feature = "serde")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let f = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    /// Write the configuration to a JSON file.
+    #[cfg(feature = "serde")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut f, self)?;
+        f.flush()?;
+        Ok(())
+    }
+}
+
+/// Hashers that can be created from a [HashFamily](enum.HashFamily.html).
+pub trait FromHashFamily<N, K>: VecHash<N, K> + Sized
+where
+    N: Numeric,
+    K: Integer,
+{
+    /// The family and parameters of this hasher.
+    fn family(&self) -> HashFamily;
+
+    /// Finish the builder with hashers of `family`.
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>>;
+}
+
+fn family_mismatch<H>(family: &HashFamily) -> Error {
+    Error::Failed(format!(
+        "configuration holds hash family {:?}, expected hasher {}",
+        family,
+        std::any::type_name::<H>()
+    ))
+}
+
+impl<N> FromHashFamily<N, i8> for SignRandomProjections<N>
+where
+    N: Numeric + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::Srp {
+            sign: self.sign_convention(),
+        }
+    }
+
+    fn build<T: HashTables<N, i8>>(
+        builder: &mut LSH<Self, N, T, i8>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, i8>> {
+        match family {
+            HashFamily::Srp { sign } => builder.srp_with_sign(*sign),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl FromHashFamily<i8, i8> for QuantizedSignRandomProjections {
+    fn family(&self) -> HashFamily {
+        HashFamily::SrpQuantized {
+            sign: self.sign_convention(),
+            scale: self.scale().to_vec(),
+        }
+    }
+
+    fn build<T: HashTables<i8, i8>>(
+        builder: &mut LSH<Self, i8, T, i8>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, i8, T, i8>> {
+        match family {
+            HashFamily::SrpQuantized { sign, scale } => {
+                builder.srp_quantized_with_sign(scale, *sign)
+            }
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N> FromHashFamily<N, u64> for PackedSignRandomProjections<N>
+where
+    N: Numeric + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::SrpPacked
+    }
+
+    fn build<T: HashTables<N, u64>>(
+        builder: &mut LSH<Self, N, T, u64>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, u64>> {
+        match family {
+            HashFamily::SrpPacked => builder.srp_packed(),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N, K> FromHashFamily<N, K> for L2<N, K>
+where
+    N: Numeric + Float + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::L2 {
+            r: self.r.to_f32().unwrap(),
+        }
+    }
+
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>> {
+        match family {
+            HashFamily::L2 { r } => builder.l2(*r),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N, K> FromHashFamily<N, K> for L1<N, K>
+where
+    N: Numeric + Float + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::L1 {
+            r: self.r.to_f32().unwrap(),
+        }
+    }
+
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>> {
+        match family {
+            HashFamily::L1 { r } => builder.l1(*r),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N, K> FromHashFamily<N, K> for MIPS<N, K>
+where
+    N: Numeric + Float + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+{
+    /// The fitted state (see [fit](../struct.LSH.html#method.fit)) is not part of the family.
+    fn family(&self) -> HashFamily {
+        let (r, U, m) = self.params();
+        HashFamily::Mips {
+            r: r.to_f32().unwrap(),
+            U: U.to_f32().unwrap(),
+            m,
+        }
+    }
+
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>> {
+        match family {
+            HashFamily::Mips { r, U, m } => builder.mips(*r, N::from_f32(*U).unwrap(), *m),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N, K> FromHashFamily<N, K> for MinHash<N, K>
+where
+    N: Integer + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::MinHash
+    }
+
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>> {
+        match family {
+            HashFamily::MinHash => builder.minhash(),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N, K> FromHashFamily<N, K> for BMinHash<N, K>
+where
+    N: Integer + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::BMinHash { b: self.b() }
+    }
+
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>> {
+        match family {
+            HashFamily::BMinHash { b } => builder.b_minhash(*b),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+impl<N, K> FromHashFamily<N, K> for BitSampling<N, K>
+where
+    N: Integer + PrimInt + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+{
+    fn family(&self) -> HashFamily {
+        HashFamily::BitSampling
+    }
+
+    fn build<T: HashTables<N, K>>(
+        builder: &mut LSH<Self, N, T, K>,
+        family: &HashFamily,
+    ) -> Result<LSH<Self, N, T, K>> {
+        match family {
+            HashFamily::BitSampling => builder.bit_sampling(),
+            _ => Err(family_mismatch::<Self>(family)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_roundtrip() {
+        let mut cfg = LshConfig::new(
+            HashFamily::Srp {
+                sign: SignConvention::Bipolar,
+            },
+            5,
+            3,
+            4,
+        );
+        cfg.seed = 2;
+        cfg.only_index = true;
+        cfg.multi_probe = true;
+        cfg.multi_probe_budget = 4;
+        cfg.min_bucket_size = 1;
+
+        let path = std::env::temp_dir().join("lsh_test_config.json");
+        cfg.save(&path).unwrap();
+        let loaded = LshConfig::load(&path).unwrap();
+        assert_eq!(loaded, cfg);
+
+        let v = [1., -2., 0.5, 3.];
+        let mut a = LshMem::<SignRandomProjections<f32>, f32>::from_config(&loaded).unwrap();
+        let mut b = LshMem::<SignRandomProjections<f32>, f32>::from_config(&loaded).unwrap();
+        assert_eq!(a.config(), cfg);
+        a.store_vec(&v).unwrap();
+        b.store_vec(&v).unwrap();
+        assert_eq!(
+            a.hashers[1].hash_vec_query(&v),
+            b.hashers[1].hash_vec_query(&v)
+        );
+
+        let cfg = LshConfig::new(
+            HashFamily::Mips {
+                r: 4.,
+                U: 0.83,
+                m: 3,
+            },
+            5,
+            3,
+            4,
+        );
+        let lsh = LshMem::<MIPS<f32, i32>, f32, i32>::from_config(&cfg).unwrap();
+        assert_eq!(lsh.config(), cfg);
+        // the hasher type has to match the family.
+        assert!(LshMem::<L2<f32, i32>, f32, i32>::from_config(&cfg).is_err());
+    }
+}
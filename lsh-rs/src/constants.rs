@@ -1 +1,36 @@
 pub const DESCRIBE_MAX: u32 = 5000;
+/// Maximum number of parameters of a SQLite statement, in SQLite versions before 3.32.
+pub const SQL_MAX_VARIABLES: usize = 999;
+/// Number of rows in a SQLite hash table after which the hash index is created on commit.
+pub const AUTO_INDEX_THRESHOLD: u32 = 10000;
+/// Magic bytes at the start of a dump file.
+pub const DUMP_MAGIC: &[u8; 4] = b"LSHD";
+/// Version of the layout of the serialized hashers in dumps. Bumped when the fields of a
+/// hasher change, as bincode can't read the hashers of another layout.
+pub const HASHERS_VERSION: u32 = 1;
+/// Format name and version of the portable (JSON) export.
+pub const PORTABLE_FORMAT: &str = "lsh-rs-portable";
+pub const PORTABLE_VERSION: u32 = 2;
+/// Number of data points that are hashed at once by `store_vecs_par`. Bounds the memory of the
+/// computed hashes.
+pub const STORE_PAR_CHUNK_SIZE: usize = 10000;
+/// Number of data points that are stored at once by `extend` and `with_vecs`.
+pub const EXTEND_CHUNK_SIZE: usize = 1000;
+/// Maximum number of pairs of data points whose distance is computed by `estimate_r`.
+pub const ESTIMATE_R_PAIRS: usize = 10000;
+/// Number of locked shards per hash table of the
+/// [ShardedMemoryTable](struct.ShardedMemoryTable.html).
+pub const N_SHARDS: usize = 16;
+/// Initial size of the file of a [MmapTable](struct.MmapTable.html). The file doubles when
+/// it is full.
+pub const MMAP_INITIAL_LEN: usize = 1 << 20;
+/// Summation of the projection dot products in this build, recorded in the index metadata.
+/// Builds with another summation can round differently, which changes the hashes.
+#[cfg(feature = "stable-hash")]
+pub const HASH_FLAVOR: &str = "stable";
+#[cfg(all(feature = "blas", not(feature = "stable-hash")))]
+pub const HASH_FLAVOR: &str = "blas";
+#[cfg(all(feature = "simd", not(any(feature = "blas", feature = "stable-hash"))))]
+pub const HASH_FLAVOR: &str = "simd";
+#[cfg(not(any(feature = "blas", feature = "stable-hash", feature = "simd")))]
+pub const HASH_FLAVOR: &str = "ndarray";
@@ -1,15 +1,42 @@
 //! Generic traits for numeric input and hash outputs.
-use ndarray::{LinalgScalar, ScalarOperand};
-use num::{FromPrimitive, NumCast, ToPrimitive};
-use serde::Serialize;
-use std::fmt::{Debug, Display};
+use ndarray::{Array1, ArrayView1, ArrayView2, Axis, LinalgScalar};
+use num::{Bounded, FromPrimitive, NumCast, ToPrimitive};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
 use std::cmp::{Ord, PartialEq, PartialOrd};
+use std::fmt::{Debug, Display};
 use std::hash::Hash;
 use std::ops::AddAssign;
 
+/// `Serialize` with the "serde" feature. Without it, every type implements it, so that the
+/// generic types don't need serde.
+#[cfg(feature = "serde")]
+pub trait MaybeSerialize: Serialize {}
+#[cfg(feature = "serde")]
+impl<T: Serialize> MaybeSerialize for T {}
+#[cfg(not(feature = "serde"))]
+pub trait MaybeSerialize {}
+#[cfg(not(feature = "serde"))]
+impl<T> MaybeSerialize for T {}
+
+/// `DeserializeOwned` with the "serde" feature, see [MaybeSerialize](trait.MaybeSerialize.html).
+#[cfg(feature = "serde")]
+pub trait MaybeDeserialize: DeserializeOwned {}
+#[cfg(feature = "serde")]
+impl<T: DeserializeOwned> MaybeDeserialize for T {}
+#[cfg(not(feature = "serde"))]
+pub trait MaybeDeserialize {}
+#[cfg(not(feature = "serde"))]
+impl<T> MaybeDeserialize for T {}
+
+/// Both [MaybeSerialize](trait.MaybeSerialize.html) and
+/// [MaybeDeserialize](trait.MaybeDeserialize.html), e.g. for the hashers that are stored in
+/// the indexes.
+pub trait MaybeSerde: MaybeSerialize + MaybeDeserialize {}
+impl<T: MaybeSerialize + MaybeDeserialize> MaybeSerde for T {}
+
 pub trait Numeric:
     LinalgScalar
-    + ScalarOperand
     + NumCast
     + ToPrimitive
     + Send
@@ -18,10 +45,123 @@ pub trait Numeric:
     + PartialOrd
     + FromPrimitive
     + AddAssign
-    + Serialize
+    + MaybeSerialize
     + Debug
     + Display
 {
+    /// Dot product of `a` and `b`.
+    fn dot(a: ArrayView1<Self>, b: ArrayView1<Self>) -> Self {
+        if cfg!(feature = "stable-hash") {
+            pairwise_dot(a, b)
+        } else if cfg!(all(feature = "simd", not(feature = "blas"))) {
+            match (a.as_slice(), b.as_slice()) {
+                (Some(a), Some(b)) => lanes_dot(a, b),
+                _ => a.dot(&b),
+            }
+        } else {
+            a.dot(&b)
+        }
+    }
+
+    /// Dot products of the rows of `a` with `v`, i.e. the projections of `v` in the hashers.
+    fn project(a: ArrayView2<Self>, v: ArrayView1<Self>) -> Array1<Self> {
+        if cfg!(feature = "stable-hash") {
+            a.outer_iter().map(|row| pairwise_dot(row, v)).collect()
+        } else if cfg!(all(feature = "simd", not(feature = "blas"))) {
+            match (a.as_slice(), v.as_slice()) {
+                (Some(data), Some(v)) => lanes_project(data, v),
+                _ => a.dot(&v),
+            }
+        } else {
+            a.dot(&v)
+        }
+    }
+}
+
+/// Number of accumulators of `lanes_dot`, which the compiler keeps in SIMD registers.
+const LANES: usize = 8;
+/// Number of rows that `lanes_project` multiplies at once, so that the chunks of the vector
+/// are loaded once per block of rows.
+const ROWS: usize = 4;
+
+/// Sum of the accumulators and the products of the elements that don't fill a chunk.
+fn reduce_lanes<N: Numeric>(acc: &[N; LANES], a: &[N], b: &[N]) -> N {
+    let mut sum = N::zero();
+    for &x in acc {
+        sum += x;
+    }
+    for (&x, &y) in a.iter().zip(b) {
+        sum += x * y;
+    }
+    sum
+}
+
+/// Dot product in `LANES` independent accumulators, which vectorizes without BLAS. The
+/// summation order differs from `ndarray` and BLAS, so the results can round differently.
+pub(crate) fn lanes_dot<N: Numeric>(a: &[N], b: &[N]) -> N {
+    let mut acc = [N::zero(); LANES];
+    let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+    let (a_rem, b_rem) = (a_chunks.remainder(), b_chunks.remainder());
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for ((acc, &x), &y) in acc.iter_mut().zip(x).zip(y) {
+            *acc += x * y;
+        }
+    }
+    reduce_lanes(&acc, a_rem, b_rem)
+}
+
+/// Same as `lanes_dot` for every row of the row-major matrix `data` with `v`, in blocks of
+/// `ROWS` rows. The results are equal to `lanes_dot` of the rows.
+pub(crate) fn lanes_project<N: Numeric>(data: &[N], v: &[N]) -> Array1<N> {
+    let dim = v.len();
+    if dim == 0 {
+        return Array1::zeros(0);
+    }
+    let mut out = Vec::with_capacity(data.len() / dim);
+    let mut blocks = data.chunks_exact(dim * ROWS);
+    let tail = dim - dim % LANES;
+    for block in &mut blocks {
+        let mut acc = [[N::zero(); LANES]; ROWS];
+        for (c, y) in v.chunks_exact(LANES).enumerate() {
+            for (r, acc) in acc.iter_mut().enumerate() {
+                let x = &block[r * dim + c * LANES..r * dim + (c + 1) * LANES];
+                for ((acc, &x), &y) in acc.iter_mut().zip(x).zip(y) {
+                    *acc += x * y;
+                }
+            }
+        }
+        for (r, acc) in acc.iter().enumerate() {
+            out.push(reduce_lanes(
+                acc,
+                &block[r * dim + tail..(r + 1) * dim],
+                &v[tail..],
+            ));
+        }
+    }
+    out.extend(
+        blocks
+            .remainder()
+            .chunks_exact(dim)
+            .map(|row| lanes_dot(row, v)),
+    );
+    Array1::from(out)
+}
+
+/// Dot product with pairwise summation in a fixed order. Unlike `ndarray` and BLAS, the
+/// result doesn't depend on the build, e.g. on the vectorization or the BLAS implementation.
+pub(crate) fn pairwise_dot<N: Numeric>(a: ArrayView1<N>, b: ArrayView1<N>) -> N {
+    const BLOCK: usize = 32;
+    if a.len() <= BLOCK {
+        let mut sum = N::zero();
+        for (&x, &y) in a.iter().zip(b) {
+            sum += x * y;
+        }
+        return sum;
+    }
+    let mid = a.len() / 2;
+    let (a1, a2) = a.split_at(Axis(0), mid);
+    let (b1, b2) = b.split_at(Axis(0), mid);
+    pairwise_dot(a1, b1) + pairwise_dot(a2, b2)
 }
 
 impl Numeric for f32 {}
@@ -34,8 +174,26 @@ impl Numeric for u8 {}
 impl Numeric for u16 {}
 impl Numeric for u32 {}
 impl Numeric for u64 {}
+/// Half precision data points halve the memory of the stored data points. The sums of the dot
+/// products are accumulated in f32, as half precision sums lose precision quickly. Only the
+/// results are rounded to half precision, so they overflow above 65504.
+#[cfg(feature = "f16")]
+impl Numeric for half::f16 {
+    fn dot(a: ArrayView1<Self>, b: ArrayView1<Self>) -> Self {
+        half::f16::from_f32(
+            a.iter()
+                .zip(b)
+                .map(|(&x, &y)| x.to_f32() * y.to_f32())
+                .sum(),
+        )
+    }
 
-pub trait Integer: Numeric + Ord + Eq + Hash {}
+    fn project(a: ArrayView2<Self>, v: ArrayView1<Self>) -> Array1<Self> {
+        a.outer_iter().map(|row| Self::dot(row, v)).collect()
+    }
+}
+
+pub trait Integer: Numeric + Ord + Eq + Hash + Bounded {}
 impl Integer for u8 {}
 impl Integer for u16 {}
 impl Integer for u32 {}
@@ -45,3 +203,33 @@ impl Integer for i8 {}
 impl Integer for i16 {}
 impl Integer for i32 {}
 impl Integer for i64 {}
+
+/// A hash word as `u64`, for the unique hash values in the statistics. Negative words keep
+/// their two's complement bits, so distinct words of one type stay distinct.
+pub(crate) fn hash_word<K: Integer>(k: K) -> u64 {
+    k.to_i64()
+        .map(|k| k as u64)
+        .or_else(|| k.to_u64())
+        .unwrap_or_default()
+}
+
+/// Id of a data point in the hash tables. `u32` ids address about 4.29 billion data points,
+/// `u64` ids more, at the cost of the memory of the buckets.
+pub trait Idx: Integer + MaybeDeserialize + Default {
+    /// Id of the `n`th data point. Panics if `n` doesn't fit in the id type.
+    fn from_index(n: usize) -> Self {
+        Self::from_usize(n).expect("id doesn't fit in the id type")
+    }
+
+    /// Position of the data point in the storage.
+    fn index(self) -> usize {
+        self.to_usize().unwrap()
+    }
+
+    /// The next id.
+    fn succ(self) -> Self {
+        self + Self::one()
+    }
+}
+impl Idx for u32 {}
+impl Idx for u64 {}
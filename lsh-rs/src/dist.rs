@@ -1,7 +1,10 @@
 //! Distance/ similarity functions.
 use crate::data::Numeric;
+use crate::error::{Error, Result};
 use ndarray::prelude::*;
 use num::Float;
+use rayon::prelude::*;
+use std::cmp::Ordering;
 
 /// L2 norm of a single vector.
 ///
@@ -19,7 +22,7 @@ use num::Float;
 /// ```
 pub fn l2_norm<N: Numeric + Float>(x: &[N]) -> N {
     let x = aview1(x);
-    x.dot(&x).sqrt()
+    N::dot(x, x).sqrt()
 }
 
 /// Dot product between two vectors.
@@ -37,7 +40,7 @@ pub fn l2_norm<N: Numeric + Float>(x: &[N]) -> N {
 /// let prod = inner_prod(&a, &b);
 /// ```
 pub fn inner_prod<N: Numeric + Float>(a: &[N], b: &[N]) -> N {
-    aview1(a).dot(&aview1(b))
+    N::dot(aview1(a), aview1(b))
 }
 
 /// Cosine similarity between two vectors.
@@ -57,3 +60,151 @@ pub fn inner_prod<N: Numeric + Float>(a: &[N], b: &[N]) -> N {
 pub fn cosine_sim<N: Numeric + Float>(a: &[N], b: &[N]) -> N {
     inner_prod(a, b) / (l2_norm(a) * l2_norm(b))
 }
+
+/// Distance between data points, e.g. to rank the candidates of a query, see
+/// [rerank_candidates](../rerank/fn.rerank_candidates.html). A smaller distance is closer for
+/// every metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Euclidean distance.
+    L2,
+    /// Cosine distance, `1 - cosine similarity`. A zero vector has distance 1 to any vector.
+    Cosine,
+    /// Negative inner product, for maximum inner product search.
+    InnerProduct,
+    /// Jaccard distance of the non-zero entries, e.g. of shingles vectors.
+    Jaccard,
+}
+
+impl Metric {
+    /// Distance between `a` and `b`, computed in `f64`.
+    ///
+    /// # Errors
+    ///
+    /// `Error::DimensionMismatch` if `a` and `b` have different lengths, `Error::Failed` if a
+    /// value can't be represented as `f64`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lsh_rs::dist::Metric;
+    /// assert_eq!(Metric::L2.distance(&[0., 3.], &[4., 0.]).unwrap(), 5.);
+    /// assert_eq!(Metric::Jaccard.distance(&[1, 1, 0], &[1, 0, 1]).unwrap(), 1. - 1. / 3.);
+    /// assert!(Metric::L2.distance(&[0., 3.], &[4.]).is_err());
+    /// ```
+    pub fn distance<N: Numeric>(self, a: &[N], b: &[N]) -> Result<f64> {
+        if a.len() != b.len() {
+            return Err(Error::DimensionMismatch {
+                expected: a.len(),
+                actual: b.len(),
+            });
+        }
+        let to_f64 = |x: &N| {
+            x.to_f64()
+                .ok_or_else(|| Error::Failed("value can't be represented as f64".to_string()))
+        };
+        let mut pairs = Vec::with_capacity(a.len());
+        for (x, y) in a.iter().zip(b) {
+            pairs.push((to_f64(x)?, to_f64(y)?));
+        }
+        let pairs = pairs.into_iter();
+        Ok(match self {
+            Metric::L2 => pairs.map(|(x, y)| (x - y) * (x - y)).sum::<f64>().sqrt(),
+            Metric::Cosine => {
+                let (mut ab, mut aa, mut bb) = (0., 0., 0.);
+                for (x, y) in pairs {
+                    ab += x * y;
+                    aa += x * x;
+                    bb += y * y;
+                }
+                if aa == 0. || bb == 0. {
+                    return Ok(1.);
+                }
+                1. - ab / (aa.sqrt() * bb.sqrt())
+            }
+            Metric::InnerProduct => -pairs.map(|(x, y)| x * y).sum::<f64>(),
+            Metric::Jaccard => {
+                let (mut intersection, mut union) = (0, 0);
+                for (x, y) in pairs {
+                    let (x, y) = (x > 0., y > 0.);
+                    intersection += (x && y) as usize;
+                    union += (x || y) as usize;
+                }
+                if union == 0 {
+                    return Ok(1.);
+                }
+                1. - intersection as f64 / union as f64
+            }
+        })
+    }
+}
+
+/// Jaccard similarity between two sets, given as sorted index lists without duplicates
+/// (e.g. shingle ids). Two empty sets have a similarity of 0.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::dist::jaccard_sorted;
+/// let a = vec![1, 3, 5, 7];
+/// let b = vec![3, 4, 5];
+/// assert_eq!(jaccard_sorted(&a, &b), 2. / 5.);
+/// ```
+pub fn jaccard_sorted<T: Ord>(a: &[T], b: &[T]) -> f64 {
+    let (mut i, mut j, mut intersection) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                intersection += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        return 0.;
+    }
+    intersection as f64 / union as f64
+}
+
+/// Exact Jaccard verification of candidate pairs, e.g. generated by MinHash LSH. The pairs are
+/// verified in parallel and only the pairs with a similarity of at least `threshold` are
+/// returned as `(a, b, similarity)`, in the order of `candidates`.
+///
+/// # Arguments
+/// * `sets` - Sorted index lists without duplicates, see [jaccard_sorted](fn.jaccard_sorted.html).
+/// * `candidates` - Pairs of indexes into `sets`.
+/// * `threshold` - Minimal Jaccard similarity.
+///
+/// # Panics
+///
+/// Panics if a candidate index is out of bounds of `sets`.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::dist::verify_jaccard;
+/// let sets = vec![vec![1, 2, 3], vec![1, 2, 4], vec![7, 8]];
+/// let pairs = verify_jaccard(&sets, &[(0, 1), (0, 2)], 0.5);
+/// assert_eq!(pairs, vec![(0, 1, 0.5)]);
+/// ```
+pub fn verify_jaccard<T: Ord + Sync>(
+    sets: &[Vec<T>],
+    candidates: &[(u32, u32)],
+    threshold: f64,
+) -> Vec<(u32, u32, f64)> {
+    candidates
+        .par_iter()
+        .filter_map(|&(a, b)| {
+            let sim = jaccard_sorted(&sets[a as usize], &sets[b as usize]);
+            if sim >= threshold {
+                Some((a, b, sim))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
@@ -10,15 +10,69 @@ pub enum Error {
     TableNotExist,
     #[error("Not implemented")]
     NotImplemented,
+    #[error("Hashers are not fitted, call fit or store the first data points with store_vecs")]
+    NotFitted,
+    #[error("Data point has dimension {actual}, expected {expected}")]
+    DimensionMismatch { expected: usize, actual: usize },
+    #[error(
+        "Array has shape ({rows}, {cols}), expected {expected} columns, one data point per row"
+    )]
+    ShapeMismatch {
+        rows: usize,
+        cols: usize,
+        expected: usize,
+    },
+    #[error("Dimension {dim} doesn't fit in the integer type, the maximum is {max}")]
+    DimensionOverflow { dim: usize, max: usize },
+    #[error("Index file is corrupt, {what} mismatch: expected {expected}, found {found}")]
+    CorruptIndex {
+        what: &'static str,
+        expected: u64,
+        found: u64,
+    },
+    #[error("Index was hashed with {found} summation, this build uses {expected} summation")]
+    HashFlavorMismatch {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("Id {0} is already in use")]
+    DuplicateId(u64),
+    #[error("Index is opened in read-only mode")]
+    ReadOnly,
+    #[error("Database is locked by another connection")]
+    Busy,
+    #[error("Row {row} of batch failed: {source}")]
+    Batch { row: usize, source: Box<Error> },
     #[error(transparent)]
+    #[cfg(feature = "serde")]
     SerializationFailed(#[from] std::boxed::Box<bincode::ErrorKind>),
     #[error(transparent)]
+    #[cfg(feature = "serde")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
     #[cfg(feature = "sqlite")]
-    SqlFailure(#[from] rusqlite::Error),
+    SqlFailure(rusqlite::Error),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
 
+/// A database that is locked by another connection is reported as `Error::Busy`, so it can be
+/// told apart from other failures.
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode::{DatabaseBusy, DatabaseLocked};
+        match e {
+            rusqlite::Error::SqliteFailure(ref err, _)
+                if err.code == DatabaseBusy || err.code == DatabaseLocked =>
+            {
+                Error::Busy
+            }
+            e => Error::SqlFailure(e),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,169 @@
+//! Feature hashing (the hashing trick) for categorical data.
+//!
+//! Converts feature maps like `[("user=alice", 1.), ("level=error", 1.)]` into fixed dimension
+//! vectors that can be hashed by [SignRandomProjections](../struct.SignRandomProjections.html),
+//! [L2](../struct.L2.html) or [MinHash](../struct.MinHash.html).
+//!
+//! # Examples
+//!
+//! ```
+//! use lsh_rs::prelude::*;
+//! use lsh_rs::feature_hash::{char_ngrams, FeatureHasher};
+//!
+//! let hasher = FeatureHasher::new(64);
+//! let v = hasher.transform(&[("level=error", 1.), ("service=auth", 1.), ("latency", 0.3)]);
+//! let mut lsh = LshMem::<_, f32>::new(8, 10, 64).srp().unwrap();
+//! lsh.store_vec(&v).unwrap();
+//!
+//! // shingles for MinHash
+//! let ngrams = char_ngrams("connection refused", 3);
+//! let features = ngrams.iter().map(|s| (s.as_str(), 1.)).collect::<Vec<_>>();
+//! let shingles: Vec<u16> = FeatureHasher::new(256).transform_binary(&features);
+//! ```
+use crate::data::Integer;
+use fnv::FnvHasher;
+use std::hash::Hasher;
+
+/// Maps named features to the indexes of a fixed dimension vector by hashing the names.
+///
+/// Colliding features are summed. With `signed` hashing (the default) a second hash decides the
+/// sign of every feature, so that collisions cancel out in expectation and inner products are
+/// preserved.
+#[derive(Debug, Clone)]
+pub struct FeatureHasher {
+    dim: usize,
+    signed: bool,
+    seed: u64,
+}
+
+impl FeatureHasher {
+    /// # Arguments
+    /// * `dim` - Dimension of the output vectors.
+    pub fn new(dim: usize) -> Self {
+        assert!(dim > 0, "dim should be larger than 0");
+        FeatureHasher {
+            dim,
+            signed: true,
+            seed: 0,
+        }
+    }
+
+    /// Enable or disable signed hashing. Disable it for count like data.
+    pub fn signed(&mut self, signed: bool) -> &mut Self {
+        self.signed = signed;
+        self
+    }
+
+    /// Seed of the hash function. Vectors created with different seeds are not comparable.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Index and sign of a feature.
+    fn index(&self, feature: &str) -> (usize, f32) {
+        let mut hasher = FnvHasher::with_key(0xcbf2_9ce4_8422_2325 ^ self.seed);
+        hasher.write(feature.as_bytes());
+        let h = hasher.finish();
+        let sign = if self.signed && (h >> 63) == 1 {
+            -1.
+        } else {
+            1.
+        };
+        ((h % self.dim as u64) as usize, sign)
+    }
+
+    /// Dense vector of length `dim`.
+    pub fn transform(&self, features: &[(&str, f32)]) -> Vec<f32> {
+        let mut v = vec![0.; self.dim];
+        for &(feature, value) in features {
+            let (idx, sign) = self.index(feature);
+            v[idx] += sign * value;
+        }
+        v
+    }
+
+    /// Sparse vector as `(index, value)` pairs, sorted by index. Collisions are merged.
+    pub fn transform_sparse(&self, features: &[(&str, f32)]) -> Vec<(usize, f32)> {
+        let mut v = features
+            .iter()
+            .map(|&(feature, value)| {
+                let (idx, sign) = self.index(feature);
+                (idx, sign * value)
+            })
+            .collect::<Vec<_>>();
+        v.sort_unstable_by_key(|&(idx, _)| idx);
+        v.dedup_by(|(idx_b, b), (idx_a, a)| {
+            if idx_a == idx_b {
+                *a += *b;
+                true
+            } else {
+                false
+            }
+        });
+        v
+    }
+
+    /// Binary (shingle) vector of length `dim` for [MinHash](../struct.MinHash.html). An index is
+    /// set if any of its features has a non zero value.
+    pub fn transform_binary<N: Integer>(&self, features: &[(&str, f32)]) -> Vec<N> {
+        let mut v = vec![N::zero(); self.dim];
+        for &(feature, value) in features {
+            if value != 0. {
+                v[self.index(feature).0] = N::one();
+            }
+        }
+        v
+    }
+}
+
+/// Character n-grams of a string, e.g. to use log lines or names as features.
+/// Strings shorter than `n` result in a single n-gram.
+pub fn char_ngrams(s: &str, n: usize) -> Vec<String> {
+    assert!(n > 0, "n should be larger than 0");
+    let chars = s.chars().collect::<Vec<_>>();
+    if chars.len() <= n {
+        return vec![s.to_string()];
+    }
+    chars.windows(n).map(|w| w.iter().collect()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_feature_hasher() {
+        let features = [("a", 1.), ("b", 2.), ("c", -1.)];
+        let hasher = FeatureHasher::new(16);
+        let dense = hasher.transform(&features);
+        assert_eq!(dense.len(), 16);
+        assert_eq!(dense, FeatureHasher::new(16).transform(&features));
+
+        let sparse = hasher.transform_sparse(&features);
+        let mut from_sparse = vec![0.; 16];
+        sparse.iter().for_each(|&(idx, v)| from_sparse[idx] += v);
+        assert_eq!(dense, from_sparse);
+        assert!(sparse.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let binary: Vec<u8> = hasher.transform_binary(&features);
+        assert_eq!(
+            binary.iter().map(|&x| x as usize).sum::<usize>(),
+            sparse.len()
+        );
+
+        // collisions are summed
+        let dense = FeatureHasher::new(1).signed(false).transform(&features);
+        assert_eq!(dense, vec![2.]);
+    }
+
+    #[test]
+    fn test_char_ngrams() {
+        assert_eq!(char_ngrams("abcd", 3), vec!["abc", "bcd"]);
+        assert_eq!(char_ngrams("ab", 3), vec!["ab"]);
+    }
+}
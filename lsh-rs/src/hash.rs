@@ -1,12 +1,17 @@
 use crate::data::Integer;
+use crate::dist::{l2_norm, Metric};
+use crate::error::{Error, Result};
 use crate::multi_probe::StepWiseProbe;
-use crate::{data::Numeric, dist::l2_norm, multi_probe::QueryDirectedProbe, utils::create_rng};
+use crate::{data::Numeric, multi_probe::QueryDirectedProbe, utils::create_rng};
 use ndarray::prelude::*;
-use ndarray_rand::rand_distr::{StandardNormal, Uniform};
+use ndarray::ArcArray2;
+use ndarray_rand::rand_distr::{Cauchy, StandardNormal, Uniform};
 use ndarray_rand::RandomExt;
-use num::{traits::NumCast, Float, Zero};
-use std::marker::PhantomData;
+use num::{traits::NumCast, Float, PrimInt, Zero};
+use rand::Rng;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
 use std::ops::Deref;
 
 /// Implement this trait to create your own custom hashers.
@@ -19,6 +24,21 @@ pub trait VecHash<N, K> {
         self.hash_vec_query(v)
     }
 
+    /// Create the query hash, plus the hashes of the neighboring cells of the projections that
+    /// lie within `eps` of a cell boundary. Hashers without cell boundaries only return the
+    /// query hash.
+    fn hash_vec_query_boundary(&self, v: &[N], _eps: f64) -> Vec<Vec<K>> {
+        vec![self.hash_vec_query(v)]
+    }
+
+    /// Distance between two data points in the metric that the hash family approximates.
+    /// Lower is closer. Used to rank the candidates of
+    /// [query_top_k](struct.LSH.html#method.query_top_k). `None` if the hasher doesn't define a
+    /// distance.
+    fn distance(&self, _a: &[N], _b: &[N]) -> Option<f64> {
+        None
+    }
+
     /// If the hasher implements the QueryDirectedProbe trait it should return Some(self)
     fn as_query_directed_probe(&self) -> Option<&dyn QueryDirectedProbe<N, K>> {
         None
@@ -27,13 +47,154 @@ pub trait VecHash<N, K> {
     fn as_step_wise_probe(&self) -> Option<&dyn StepWiseProbe<N, K>> {
         None
     }
+    /// If the hasher implements the VecHashFit trait it should return Some(self)
+    fn as_fit(&self) -> Option<&dyn VecHashFit<N, K>> {
+        None
+    }
+    /// Same as `as_fit`, to fit the hasher.
+    fn as_fit_mut(&mut self) -> Option<&mut dyn VecHashFit<N, K>> {
+        None
+    }
+}
+
+/// Hashers that have to be fitted to the data before data points can be stored, e.g.
+/// [MIPS](struct.MIPS.html). See [fit](struct.LSH.html#method.fit).
+pub trait VecHashFit<N, K>: VecHash<N, K> {
+    /// Fit the hasher to a sample of the data points.
+    fn fit(&mut self, vs: &[Vec<N>]) -> Result<()>;
+    /// Whether the hasher is fitted.
+    fn is_fitted(&self) -> bool;
+    /// Check that the fitted hasher can store data point `v`, e.g. that it lies within the
+    /// range of the sample the hasher was fitted on.
+    fn check_put(&self, _v: &[N]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Hashers of sets, that hash the indexes of the present shingles directly instead of a dense
+/// binary vector. See [store_set](struct.LSH.html#method.store_set).
+pub trait SetHash<K> {
+    /// Hash the set of shingle indexes `set`. The hash is equal to the hash of the binary vector
+    /// with ones at the indexes in `set`. The indexes should be smaller than the dimension.
+    fn hash_set(&self, set: &[usize]) -> Vec<K>;
+}
+
+/// Values of the bits in a [SignRandomProjections](struct.SignRandomProjections.html) hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SignConvention {
+    /// A data point below a hyperplane is hashed to 0, above to 1.
+    #[default]
+    Binary,
+    /// A data point below a hyperplane is hashed to -1, above to 1.
+    Bipolar,
+}
+
+impl SignConvention {
+    /// The bit of a data point above (`true`) or below (`false`) a hyperplane.
+    pub fn bit(self, above: bool) -> i8 {
+        match (self, above) {
+            (_, true) => 1,
+            (SignConvention::Binary, false) => 0,
+            (SignConvention::Bipolar, false) => -1,
+        }
+    }
+
+    /// Flip a bit to the other side of the hyperplane.
+    pub fn flip(self, bit: i8) -> i8 {
+        match self {
+            SignConvention::Binary => 1 - bit,
+            SignConvention::Bipolar => -bit,
+        }
+    }
+}
+
+/// Very sparse random projections. An entry is `1 / sqrt(density)` or `-1 / sqrt(density)`,
+/// both with probability `density / 2`, and 0 otherwise. The entries have unit variance, like
+/// Gaussian projections, but a projection only sums the non-zero entries.
+/// [Read more.](https://web.stanford.edu/~hastie/Papers/Ping/KDD06_rp.pdf)
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct SparseProjections {
+    /// Columns of the positive and the negative entries per row.
+    rows: Vec<(Vec<u32>, Vec<u32>)>,
+    density: f32,
+}
+
+impl SparseProjections {
+    fn new<R: Rng>(n_rows: usize, dim: usize, density: f32, rng: &mut R) -> Result<Self> {
+        if !(density > 0. && density <= 1.) {
+            return Err(Error::Failed(format!(
+                "projection density should be in (0, 1], got {}",
+                density
+            )));
+        }
+        let rows = (0..n_rows)
+            .map(|_| {
+                let (mut plus, mut minus) = (vec![], vec![]);
+                for j in 0..dim as u32 {
+                    let u: f32 = rng.gen();
+                    if u < density / 2. {
+                        plus.push(j)
+                    } else if u < density {
+                        minus.push(j)
+                    }
+                }
+                (plus, minus)
+            })
+            .collect();
+        Ok(SparseProjections { rows, density })
+    }
+
+    fn scale(&self) -> f32 {
+        1. / self.density.sqrt()
+    }
+
+    /// The projections as a dense matrix.
+    #[cfg(test)]
+    fn to_dense<N: Numeric>(&self, dim: usize) -> Array2<N> {
+        let scale = N::from_f32(self.scale()).unwrap();
+        let mut a = Array2::zeros((self.rows.len(), dim));
+        for (i, (plus, minus)) in self.rows.iter().enumerate() {
+            for &j in plus {
+                a[[i, j as usize]] = scale;
+            }
+            for &j in minus {
+                a[[i, j as usize]] = N::zero() - scale;
+            }
+        }
+        a
+    }
+
+    /// Projections of `v`, without the scale `1 / sqrt(density)`.
+    fn project_unscaled<N: Numeric>(&self, v: &[N]) -> Array1<N> {
+        self.rows
+            .iter()
+            .map(|(plus, minus)| {
+                let mut sum = N::zero();
+                for &j in plus {
+                    sum += v[j as usize];
+                }
+                for &j in minus {
+                    sum = sum - v[j as usize];
+                }
+                sum
+            })
+            .collect()
+    }
 }
 
 /// A family of hashers for the cosine similarity.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SignRandomProjections<N: Numeric> {
-    ///  Random unit vectors that will lead to the bits of the hash.
+    ///  Random unit vectors that will lead to the bits of the hash. Empty if the hyperplanes
+    ///  are sparse.
     hyperplanes: Array2<N>,
+    sign: SignConvention,
+    /// Sparse hyperplanes, see [new_sparse](#method.new_sparse).
+    #[cfg_attr(feature = "serde", serde(default))]
+    sparse: Option<SparseProjections>,
 }
 
 impl<N: Numeric> SignRandomProjections<N> {
@@ -47,34 +208,252 @@ impl<N: Numeric> SignRandomProjections<N> {
         let hp: Array2<f32> = Array::random_using((k, dim), StandardNormal, &mut rng);
         let hp = hp.mapv(|v| N::from_f32(v).unwrap());
 
-        SignRandomProjections { hyperplanes: hp }
+        SignRandomProjections {
+            hyperplanes: hp,
+            sign: SignConvention::default(),
+            sparse: None,
+        }
+    }
+
+    /// Same as [new](#method.new), with very sparse random hyperplanes. A fraction `density`
+    /// of the entries is non-zero, so hashing sums about `k * dim * density` elements instead
+    /// of computing `k` dot products. A density of `1 / sqrt(dim)` is recommended for high
+    /// dimensional data.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Number of hyperplanes used for determining the hash.
+    /// * `density` - Fraction of non-zero entries, in `(0, 1]`.
+    pub fn new_sparse(k: usize, dim: usize, density: f32, seed: u64) -> Result<Self> {
+        let mut rng = create_rng(seed);
+        let sparse = SparseProjections::new(k, dim, density, &mut rng)?;
+        Ok(SignRandomProjections {
+            hyperplanes: Array2::zeros((0, dim)),
+            sign: SignConvention::default(),
+            sparse: Some(sparse),
+        })
+    }
+
+    /// Set the values of the hash bits. Defaults to [Binary](enum.SignConvention.html#variant.Binary).
+    pub fn with_sign_convention(mut self, sign: SignConvention) -> Self {
+        self.sign = sign;
+        self
+    }
+
+    pub fn sign_convention(&self) -> SignConvention {
+        self.sign
+    }
+
+    fn project(&self, v: &[N]) -> Array1<N> {
+        // the sign of a projection doesn't depend on the scale of the sparse hyperplanes.
+        match &self.sparse {
+            Some(sparse) => sparse.project_unscaled(v),
+            None => N::project(self.hyperplanes.view(), aview1(v)),
+        }
     }
 
     fn hash_vec(&self, v: &[N]) -> Vec<i8> {
-        let v = aview1(v);
-        self.hyperplanes
-            .dot(&v)
-            .mapv(|ai| if ai > Zero::zero() { 1 } else { 0 })
+        let sign = self.sign;
+        self.project(v)
+            .mapv(|ai| sign.bit(ai > Zero::zero()))
             .to_vec()
     }
+
+    /// The hash bits packed in `u64` words, see
+    /// [PackedSignRandomProjections](struct.PackedSignRandomProjections.html).
+    fn hash_vec_packed(&self, v: &[N]) -> Vec<u64> {
+        let projections = self.project(v);
+        let mut hash = vec![0; projections.len().div_ceil(64)];
+        for (i, &ai) in projections.iter().enumerate() {
+            if ai > Zero::zero() {
+                hash[i / 64] |= 1 << (i % 64);
+            }
+        }
+        hash
+    }
 }
 
 impl<N: Numeric> VecHash<N, i8> for SignRandomProjections<N> {
     fn hash_vec_query(&self, v: &[N]) -> Vec<i8> {
         self.hash_vec(v)
     }
+    /// Cosine distance.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        Metric::Cosine.distance(a, b).ok()
+    }
     fn as_step_wise_probe(&self) -> Option<&dyn StepWiseProbe<N, i8>> {
         Some(self)
     }
 }
 
+/// [SignRandomProjections](struct.SignRandomProjections.html) with the hash bits packed in
+/// `u64` words, 64 projections per word. The hashes take 8 times less memory than the `i8` hashes.
+/// Hashing isn't faster, the projections are the same as those of SignRandomProjections (see the
+/// `hash/srp` and `hash/srp_packed` benchmarks). Use the `u64` hash primitive of
+/// [hi_packed](prelude/hi_packed/index.html).
+///
+/// Projection `i` is bit `i % 64` of word `i / 64`. The bit is 1 if a data point lies above the
+/// hyperplane, there is no [SignConvention](enum.SignConvention.html).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PackedSignRandomProjections<N: Numeric = f32> {
+    srp: SignRandomProjections<N>,
+}
+
+impl<N: Numeric> PackedSignRandomProjections<N> {
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Number of hyperplanes used for determining the hash.
+    ///   The hash holds `ceil(k / 64)` words.
+    pub fn new(k: usize, dim: usize, seed: u64) -> Self {
+        SignRandomProjections::new(k, dim, seed).into()
+    }
+
+    /// Same as [new](#method.new), with very sparse random hyperplanes. See
+    /// [SignRandomProjections::new_sparse](struct.SignRandomProjections.html#method.new_sparse).
+    pub fn new_sparse(k: usize, dim: usize, density: f32, seed: u64) -> Result<Self> {
+        Ok(SignRandomProjections::new_sparse(k, dim, density, seed)?.into())
+    }
+}
+
+impl<N: Numeric> From<SignRandomProjections<N>> for PackedSignRandomProjections<N> {
+    /// Pack the hashes of `srp`. The hash bits are those of `srp` with the
+    /// [Binary](enum.SignConvention.html#variant.Binary) sign convention, the sign convention of
+    /// `srp` is converted to it. A `-1` of the
+    /// [Bipolar](enum.SignConvention.html#variant.Bipolar) convention is a 0 bit.
+    fn from(srp: SignRandomProjections<N>) -> Self {
+        PackedSignRandomProjections {
+            srp: srp.with_sign_convention(SignConvention::Binary),
+        }
+    }
+}
+
+impl<N: Numeric> VecHash<N, u64> for PackedSignRandomProjections<N> {
+    fn hash_vec_query(&self, v: &[N]) -> Vec<u64> {
+        self.srp.hash_vec_packed(v)
+    }
+    /// Cosine distance.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        Metric::Cosine.distance(a, b).ok()
+    }
+    fn as_step_wise_probe(&self) -> Option<&dyn StepWiseProbe<N, u64>> {
+        Some(self)
+    }
+}
+
+/// Sign random projections for int8 quantized data points. The data points are widened to `f32`
+/// while hashing, so they don't have to be dequantized before they are stored.
+///
+/// A data point `v` represents the embedding `v * scale`. The cosine similarity doesn't depend on
+/// a single scale for all dimensions (or per data point), only a scale per dimension changes the
+/// hashes and distances.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuantizedSignRandomProjections {
+    /// Random unit vectors, multiplied with the scale of the dimensions.
+    hyperplanes: Array2<f32>,
+    scale: Vec<f32>,
+    sign: SignConvention,
+}
+
+impl QuantizedSignRandomProjections {
+    /// # Arguments
+    ///
+    /// * `k` - Number of hyperplanes used for determining the hash.
+    ///   This will also be the hash length.
+    /// * `scale` - One scale for all dimensions, or one scale per dimension.
+    pub fn new(k: usize, dim: usize, scale: &[f32], seed: u64) -> Result<Self> {
+        if scale.len() != 1 && scale.len() != dim {
+            return Err(Error::Failed(format!(
+                "expected 1 or {} quantization scales, got {}",
+                dim,
+                scale.len()
+            )));
+        }
+        if scale.iter().any(|&s| !(s.is_finite() && s > 0.)) {
+            return Err(Error::Failed(
+                "quantization scales should be finite and positive".to_string(),
+            ));
+        }
+        let mut rng = create_rng(seed);
+        let mut hp: Array2<f32> = Array::random_using((k, dim), StandardNormal, &mut rng);
+        if scale.len() == dim {
+            hp *= &aview1(scale);
+        }
+
+        Ok(QuantizedSignRandomProjections {
+            hyperplanes: hp,
+            scale: scale.to_vec(),
+            sign: SignConvention::default(),
+        })
+    }
+
+    /// Set the values of the hash bits. Defaults to [Binary](enum.SignConvention.html#variant.Binary).
+    pub fn with_sign_convention(mut self, sign: SignConvention) -> Self {
+        self.sign = sign;
+        self
+    }
+
+    pub fn sign_convention(&self) -> SignConvention {
+        self.sign
+    }
+
+    /// Quantization scale of the dimensions.
+    pub fn scale(&self) -> &[f32] {
+        &self.scale
+    }
+
+    /// Dequantized value of dimension `i`.
+    fn dequantize(&self, i: usize, x: i8) -> f64 {
+        let s = if self.scale.len() == 1 {
+            self.scale[0]
+        } else {
+            self.scale[i]
+        };
+        x as f64 * s as f64
+    }
+}
+
+impl VecHash<i8, i8> for QuantizedSignRandomProjections {
+    fn hash_vec_query(&self, v: &[i8]) -> Vec<i8> {
+        let v = v.iter().map(|&x| x as f32).collect::<Array1<f32>>();
+        let sign = self.sign;
+        f32::project(self.hyperplanes.view(), v.view())
+            .mapv(|ai| sign.bit(ai > 0.))
+            .to_vec()
+    }
+
+    /// Cosine distance between the dequantized data points.
+    fn distance(&self, a: &[i8], b: &[i8]) -> Option<f64> {
+        let (mut ab, mut aa, mut bb) = (0., 0., 0.);
+        for (i, (&x, &y)) in a.iter().zip(b).enumerate() {
+            let (x, y) = (self.dequantize(i, x), self.dequantize(i, y));
+            ab += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        Some(1. - ab / (aa.sqrt() * bb.sqrt()))
+    }
+
+    fn as_step_wise_probe(&self) -> Option<&dyn StepWiseProbe<i8, i8>> {
+        Some(self)
+    }
+}
+
 /// L2 Hasher family. [Read more.](https://arxiv.org/pdf/1411.3787.pdf)
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct L2<N = f32, K = i32> {
-    pub a: Array2<N>,
+    /// Projections, clones of the hasher share them until they are written to. Empty if the
+    /// projections are sparse, see [new_sparse](#method.new_sparse).
+    pub a: ArcArray2<N>,
     pub r: N,
     pub b: Array1<N>,
     n_projections: usize,
+    /// Sparse projections, used for hashing while `a` is empty.
+    #[cfg_attr(feature = "serde", serde(default))]
+    sparse: Option<SparseProjections>,
     phantom: PhantomData<K>,
 }
 
@@ -90,7 +469,7 @@ where
         let b = Array::random_using(n_projections, uniform_dist, &mut rng);
 
         // cast to generic
-        let a = a.mapv(|v| N::from_f32(v).unwrap());
+        let a = a.mapv(|v| N::from_f32(v).unwrap()).into_shared();
         let b = b.mapv(|v| N::from_f32(v).unwrap());
         let r = N::from_f32(r).unwrap();
 
@@ -99,20 +478,114 @@ where
             r,
             b,
             n_projections,
+            sparse: None,
             phantom: PhantomData,
         }
     }
 
+    /// Same as [new](#method.new), with very sparse random projections. A fraction `density`
+    /// of the entries of `a` is non-zero, so hashing sums about `n_projections * dim * density`
+    /// elements instead of computing `n_projections` dot products. A density of `1 / sqrt(dim)`
+    /// is recommended for high dimensional data.
+    ///
+    /// Only the non-zero entries are kept, `a` is empty. Assigning dense projections to `a`
+    /// replaces the sparse projections.
+    ///
+    /// # Arguments
+    ///
+    /// * `density` - Fraction of non-zero entries, in `(0, 1]`.
+    pub fn new_sparse(
+        dim: usize,
+        r: f32,
+        n_projections: usize,
+        density: f32,
+        seed: u64,
+    ) -> Result<Self> {
+        let mut rng = create_rng(seed);
+        let sparse = SparseProjections::new(n_projections, dim, density, &mut rng)?;
+        let b = Array::random_using(n_projections, Uniform::new(0., r), &mut rng);
+        Ok(L2 {
+            a: Array2::zeros((0, dim)).into_shared(),
+            r: N::from_f32(r).unwrap(),
+            b: b.mapv(|v| N::from_f32(v).unwrap()),
+            n_projections,
+            sparse: Some(sparse),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Gaussian projection matrix of `n_rows` x `dim` that can be shared by multiple hashers,
+    /// see [from_shared](#method.from_shared).
+    pub fn projection_pool(n_rows: usize, dim: usize, seed: u64) -> ArcArray2<N> {
+        let mut rng = create_rng(seed);
+        let a = Array::random_using((n_rows, dim), StandardNormal, &mut rng);
+        a.mapv(|v| N::from_f32(v).unwrap()).into_shared()
+    }
+
+    /// Create a hasher that projects with the rows `offset, offset + step, ...` of a shared
+    /// projection matrix, `n_projections` rows in total. The rows aren't copied, all hashers
+    /// created from the same matrix share its memory. Only the offsets `b` are drawn per hasher.
+    ///
+    /// # Arguments
+    /// * `pool` - Shared projection matrix, e.g. from [projection_pool](#method.projection_pool).
+    /// * `offset` - First row of the projections of this hasher.
+    /// * `step` - Distance between the rows of the projections, at least 1.
+    /// * `r` - Bucket width.
+    /// * `n_projections` - Hash length.
+    /// * `seed` - Seed for the offsets `b`.
+    pub fn from_shared(
+        pool: &ArcArray2<N>,
+        offset: usize,
+        step: usize,
+        r: f32,
+        n_projections: usize,
+        seed: u64,
+    ) -> Result<Self> {
+        let end = offset + step * n_projections.saturating_sub(1) + 1;
+        if step == 0 || n_projections == 0 || end > pool.nrows() {
+            return Err(Error::Failed(format!(
+                "{} rows from {} with step {} are out of bounds for a projection pool of {} rows",
+                n_projections,
+                offset,
+                step,
+                pool.nrows()
+            )));
+        }
+        let mut rng = create_rng(seed);
+        let b = Array::random_using(n_projections, Uniform::new(0., r), &mut rng);
+        let a = pool.clone().slice_move(s![offset..end;step as isize, ..]);
+        Ok(L2 {
+            a,
+            r: N::from_f32(r).unwrap(),
+            b: b.mapv(|v| N::from_f32(v).unwrap()),
+            n_projections,
+            sparse: None,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Projections `a^Tv + b`.
+    pub(crate) fn project(&self, v: &[N]) -> Array1<N> {
+        match &self.sparse {
+            Some(sparse) if self.a.is_empty() => {
+                let scale = N::from_f32(sparse.scale()).unwrap();
+                sparse.project_unscaled(v).mapv(|x| x * scale) + &self.b
+            }
+            _ => N::project(self.a.view(), aview1(v)) + &self.b,
+        }
+    }
+
     pub(crate) fn hash_vec(&self, v: &[N]) -> Array1<N> {
-        ((self.a.dot(&aview1(v)) + &self.b) / self.r).mapv(|x| x.floor())
+        let r = self.r;
+        self.project(v).mapv(|x| (x / r).floor())
     }
 
     fn hash_and_cast_vec(&self, v: &[N]) -> Vec<K> {
         let div_r = N::from_i8(1).unwrap() / self.r;
         // not DRY. we don't call hash_vec to save function call.
-        ((self.a.dot(&aview1(v)) + &self.b) * div_r)
+        self.project(v)
             .mapv(|x| {
-                let hp = NumCast::from(x.floor())
+                let hp = NumCast::from((x * div_r).floor())
                     .expect("Hash value doesnt fit in the Hash primitive type");
                 hp
             })
@@ -128,17 +601,153 @@ where
     fn hash_vec_query(&self, v: &[N]) -> Vec<K> {
         self.hash_and_cast_vec(v)
     }
+    /// Euclidean distance.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        Metric::L2.distance(a, b).ok()
+    }
+
+    /// `eps` is relative to the bucket width `r`. Every projection within `eps` of a cell
+    /// boundary adds one hash, with that projection in the neighboring cell.
+    fn hash_vec_query_boundary(&self, v: &[N], eps: f64) -> Vec<Vec<K>> {
+        let r = self.r;
+        let x = self.project(v).mapv(|x| x / r);
+        let hash: Vec<K> = x
+            .iter()
+            .map(|x| {
+                NumCast::from(x.floor()).expect("Hash value doesnt fit in the Hash primitive type")
+            })
+            .collect();
+        let eps = N::from_f64(eps).unwrap();
+        let mut hashes = Vec::with_capacity(1);
+        for (i, &xi) in x.iter().enumerate() {
+            let frac = xi - xi.floor();
+            let shift = if frac < eps {
+                -1
+            } else if N::one() - frac < eps {
+                1
+            } else {
+                continue;
+            };
+            if let Some(neighbor) = hash[i].to_i64().and_then(|h| K::from_i64(h + shift)) {
+                let mut h = hash.clone();
+                h[i] = neighbor;
+                hashes.push(h);
+            }
+        }
+        hashes.insert(0, hash);
+        hashes
+    }
 
     fn as_query_directed_probe(&self) -> Option<&dyn QueryDirectedProbe<N, K>> {
         Some(self)
     }
 }
 
+/// L1 Hasher family. Uses Cauchy (1-stable) projections, so that the collision probability
+/// decreases with the Manhattan distance. [Read more.](https://www.cs.princeton.edu/courses/archive/spring05/cos598E/bib/p253-datar.pdf)
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct L1<N = f32, K = i32> {
+    pub a: Array2<N>,
+    pub r: N,
+    pub b: Array1<N>,
+    n_projections: usize,
+    phantom: PhantomData<K>,
+}
+
+impl<N, K> L1<N, K>
+where
+    N: Numeric + Float,
+    K: Integer,
+{
+    pub fn new(dim: usize, r: f32, n_projections: usize, seed: u64) -> Self {
+        let mut rng = create_rng(seed);
+        let cauchy = Cauchy::new(0., 1.).unwrap();
+        let a = Array::random_using((n_projections, dim), cauchy, &mut rng);
+        let uniform_dist = Uniform::new(0., r);
+        let b = Array::random_using(n_projections, uniform_dist, &mut rng);
+
+        // cast to generic
+        let a = a.mapv(|v: f32| N::from_f32(v).unwrap());
+        let b = b.mapv(|v| N::from_f32(v).unwrap());
+        let r = N::from_f32(r).unwrap();
+
+        L1 {
+            a,
+            r,
+            b,
+            n_projections,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<N, K> VecHash<N, K> for L1<N, K>
+where
+    N: Numeric + Float,
+    K: Integer,
+{
+    fn hash_vec_query(&self, v: &[N]) -> Vec<K> {
+        let r = self.r;
+        (N::project(self.a.view(), aview1(v)) + &self.b)
+            .mapv(|x| {
+                NumCast::from((x / r).floor())
+                    .expect("Hash value doesnt fit in the Hash primitive type")
+            })
+            .to_vec()
+    }
+
+    /// Manhattan distance.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        a.iter()
+            .zip(b)
+            .fold(N::zero(), |acc, (&x, &y)| acc + (x - y).abs())
+            .to_f64()
+    }
+}
+
+/// `M` of [MIPS](struct.MIPS.html) is serialized as a plain value that is 0 while the hasher
+/// isn't fitted, the layout of the dumps from before the fitted state was explicit.
+#[cfg(feature = "serde")]
+mod fitted_norm {
+    use num::Zero;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<N, S>(m: &Option<N>, s: S) -> Result<S::Ok, S::Error>
+    where
+        N: Serialize + Zero + Copy,
+        S: Serializer,
+    {
+        m.unwrap_or_else(N::zero).serialize(s)
+    }
+
+    pub fn deserialize<'de, N, D>(d: D) -> Result<Option<N>, D::Error>
+    where
+        N: Deserialize<'de> + Zero,
+        D: Deserializer<'de>,
+    {
+        let m = N::deserialize(d)?;
+        Ok(if m.is_zero() { None } else { Some(m) })
+    }
+}
+
 /// Maximum Inner Product Search. [Read more.](https://papers.nips.cc/paper/5329-asymmetric-lsh-alsh-for-sublinear-time-maximum-inner-product-search-mips.pdf)
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MIPS<N, K = i32> {
     U: N,
-    M: N,
+    /// Maximum l2 norm of the data points, `None` until the hasher is fitted.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            with = "fitted_norm",
+            bound(
+                serialize = "N: Serialize + Numeric",
+                deserialize = "N: Deserialize<'de> + Numeric"
+            )
+        )
+    )]
+    M: Option<N>,
     m: usize,
     dim: usize,
     hasher: L2<N, K>,
@@ -153,35 +762,25 @@ where
         let l2 = L2::new(dim + m, r, n_projections, seed);
         MIPS {
             U,
-            M: Zero::zero(),
+            M: None,
             m,
             dim,
             hasher: l2,
         }
     }
 
-    pub fn fit(&mut self, v: &[Vec<N>]) {
-        // TODO: add fit to vechash trait?
-        let mut max_l2 = Zero::zero();
-        for x in v.iter() {
-            let l2 = l2_norm(x);
-            if l2 > max_l2 {
-                max_l2 = l2
-            }
-        }
-        self.M = max_l2
+    /// The `(r, U, m)` parameters of the hasher.
+    pub(crate) fn params(&self) -> (N, N, usize) {
+        (self.hasher.r, self.U, self.m)
     }
 
     pub fn tranform_put(&self, x: &[N]) -> Vec<N> {
         let mut x_new = Vec::with_capacity(x.len() + self.m);
-
-        if self.M == Zero::zero() {
-            panic!("MIPS is not fitted")
-        }
+        let M = self.M.expect("MIPS is not fitted");
 
         // shrink norm such that l2 norm < U < 1.
         for x_i in x.iter().cloned() {
-            x_new.push(x_i / self.M * self.U)
+            x_new.push(x_i / M * self.U)
         }
 
         let norm_sq = l2_norm(&x_new).powf(N::from_f32(2.).unwrap());
@@ -222,6 +821,63 @@ where
         let p = self.tranform_put(v);
         self.hasher.hash_vec_query(&p)
     }
+
+    /// Negative inner product, so that the maximum inner product is the closest.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        Metric::InnerProduct.distance(a, b).ok()
+    }
+
+    fn as_fit(&self) -> Option<&dyn VecHashFit<N, K>> {
+        Some(self)
+    }
+
+    fn as_fit_mut(&mut self) -> Option<&mut dyn VecHashFit<N, K>> {
+        Some(self)
+    }
+}
+
+impl<N, K> VecHashFit<N, K> for MIPS<N, K>
+where
+    N: Numeric + Float,
+    K: Integer,
+{
+    /// Fit `M`, the maximum l2 norm of the data points. The sample should include the data
+    /// points with the largest norms, as larger data points can't be stored afterwards.
+    fn fit(&mut self, v: &[Vec<N>]) -> Result<()> {
+        let mut max_l2 = Zero::zero();
+        for x in v.iter() {
+            let l2 = l2_norm(x);
+            if l2 > max_l2 {
+                max_l2 = l2
+            }
+        }
+        if !(max_l2.is_finite() && max_l2 > Zero::zero()) {
+            return Err(Error::Failed(format!(
+                "cannot fit MIPS to data points with a maximum norm of {:?}",
+                max_l2
+            )));
+        }
+        self.M = Some(max_l2);
+        Ok(())
+    }
+
+    fn is_fitted(&self) -> bool {
+        self.M.is_some()
+    }
+
+    /// The norm of `v` shouldn't exceed the fitted `M`, otherwise the transformed data point
+    /// doesn't meet the conditions of the asymmetric transformation.
+    fn check_put(&self, v: &[N]) -> Result<()> {
+        let M = self.M.ok_or(Error::NotFitted)?;
+        let l2 = l2_norm(v);
+        if l2 > M {
+            return Err(Error::Failed(format!(
+                "data point with norm {:?} exceeds the maximum norm {:?} that MIPS was fitted to",
+                l2, M
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl<N, K> Deref for MIPS<N, K>
@@ -237,9 +893,10 @@ where
 }
 
 /// A hash family for the [Jaccard Index](https://en.wikipedia.org/wiki/Jaccard_index)
-/// /// The generic integer N, needs to be able to hold the number of dimensions.
-/// so a `u8` with a vector of > 255 dimensions will cause a `panic`.
-#[derive(Serialize, Deserialize, Clone)]
+/// The generic integer N, needs to be able to hold the number of dimensions.
+/// So a `u8` with a vector of > 255 dimensions is rejected by [try_new](#method.try_new).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MinHash<N = u8, K = i32> {
     pub pi: Array2<N>,
     n_projections: usize,
@@ -251,7 +908,21 @@ where
     N: Integer,
     K: Integer,
 {
+    /// # Panics
+    ///
+    /// Panics if `dim` doesn't fit in `N`. Use [try_new](#method.try_new) to get an
+    /// error instead.
     pub fn new(n_projections: usize, dim: usize, seed: u64) -> Self {
+        Self::try_new(n_projections, dim, seed).expect("could not create MinHash")
+    }
+
+    /// Create a new MinHash hasher. Returns [Error::DimensionOverflow](enum.Error.html) if the
+    /// permutation indexes `1..=dim` don't fit in `N`.
+    pub fn try_new(n_projections: usize, dim: usize, seed: u64) -> Result<Self> {
+        if N::from_usize(dim).is_none() {
+            let max = N::max_value().to_usize().unwrap_or(usize::MAX);
+            return Err(Error::DimensionOverflow { dim, max });
+        }
         let mut pi = Array::zeros((n_projections, dim));
         let mut rng = create_rng(seed);
 
@@ -268,11 +939,50 @@ where
             let mut slice = pi.slice_mut(s![row, ..]);
             slice += &aview1(&permutation_idx);
         }
-        MinHash {
+        Ok(MinHash {
             pi,
             n_projections,
             phantom: PhantomData,
-        }
+        })
+    }
+
+    /// The lowest permutation index of the non zero entries of `v`, per permutation. `None` if
+    /// `v` has no non zero entries.
+    fn permutation_minima(&self, v: &[N]) -> Vec<Option<N>> {
+        // Only the non zero entries of the (sparse) shingles vector can lead to a hash value.
+        // Select those columns of the permutations once, instead of folding over all dims.
+        let nz = v
+            .iter()
+            .enumerate()
+            .filter(|(_, &x)| x > Zero::zero())
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let v_nz = aview1(v).select(Axis(0), &nz);
+        let a = self.pi.select(Axis(1), &nz) * &v_nz;
+        a.map_axis(Axis(1), |view| view.iter().min().copied())
+            .to_vec()
+    }
+
+    /// The lowest permutation index of the shingles in `set`, per permutation.
+    fn set_minima(&self, set: &[usize]) -> Vec<Option<N>> {
+        self.pi
+            .outer_iter()
+            .map(|pi| set.iter().map(|&i| pi[i]).min())
+            .collect()
+    }
+
+    fn hash_minima(&self, minima: Vec<Option<N>>) -> Vec<K> {
+        // The hash is capped at n_projections before casting, so K doesn't need to hold dim.
+        let init = K::from_usize(self.n_projections).expect("could not cast to K");
+        minima
+            .into_iter()
+            .map(|min| match min {
+                Some(min) if min.to_usize().is_some_and(|m| m < self.n_projections) => {
+                    K::from(min).expect("could not cast N to K")
+                }
+                _ => init,
+            })
+            .collect()
     }
 }
 
@@ -282,23 +992,196 @@ where
     K: Integer,
 {
     fn hash_vec_query(&self, v: &[N]) -> Vec<K> {
-        let a = &self.pi * &aview1(v);
-        let init = K::from_usize(self.n_projections).expect("could not cast to K");
-        let hash = a.map_axis(Axis(1), |view| {
-            view.into_iter().fold(init, |acc, v| {
-                if *v > Zero::zero() {
-                    let v = K::from(*v).expect("could not cast N to K");
-                    if v < acc {
-                        v
-                    } else {
-                        acc
-                    }
+        self.hash_minima(self.permutation_minima(v))
+    }
+
+    /// Jaccard distance between the sets of non zero shingles.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        Metric::Jaccard.distance(a, b).ok()
+    }
+}
+
+impl<N, K> SetHash<K> for MinHash<N, K>
+where
+    N: Integer,
+    K: Integer,
+{
+    fn hash_set(&self, set: &[usize]) -> Vec<K> {
+        self.hash_minima(self.set_minima(set))
+    }
+}
+
+/// b-bit MinHash. A [MinHash](struct.MinHash.html) that only keeps the lowest `b` bits of every
+/// permutation minimum, which reduces the storage of the hashes. Unrelated sets collide with
+/// probability `1 / 2^b` per projection, so the collision probability is approximately
+/// `J + (1 - J) / 2^b` for Jaccard index `J`. Compensate a small `b` with more projections.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BMinHash<N = u8, K = i32> {
+    minhash: MinHash<N, K>,
+    b: u32,
+}
+
+impl<N, K> BMinHash<N, K>
+where
+    N: Integer,
+    K: Integer,
+{
+    /// # Panics
+    ///
+    /// Panics if `dim` doesn't fit in `N` or `b` bits don't fit in `K`. Use
+    /// [try_new](#method.try_new) to get an error instead.
+    pub fn new(n_projections: usize, dim: usize, b: u32, seed: u64) -> Self {
+        Self::try_new(n_projections, dim, b, seed).expect("could not create BMinHash")
+    }
+
+    /// Create a new b-bit MinHash hasher. Returns an error if `b` is 0 or the `b` bit values
+    /// don't fit in `K`, and [Error::DimensionOverflow](enum.Error.html) if `dim` doesn't fit
+    /// in `N`.
+    pub fn try_new(n_projections: usize, dim: usize, b: u32, seed: u64) -> Result<Self> {
+        if b == 0 || b > 32 || K::from_u64((1 << b) - 1).is_none() {
+            return Err(Error::Failed(format!(
+                "b-bit MinHash values of {} bits don't fit in the hash type",
+                b
+            )));
+        }
+        Ok(BMinHash {
+            minhash: MinHash::try_new(n_projections, dim, seed)?,
+            b,
+        })
+    }
+
+    /// Number of bits that are kept per permutation.
+    pub fn b(&self) -> u32 {
+        self.b
+    }
+
+    fn hash_minima(&self, minima: Vec<Option<N>>) -> Vec<K> {
+        let mask = (1u64 << self.b) - 1;
+        minima
+            .into_iter()
+            .map(|min| {
+                // an empty set has no minimum, it is hashed to the mask (all b bits set).
+                let min = min.map_or(mask, |min| min.to_u64().expect("could not cast N to u64"));
+                K::from_u64(min & mask).expect("could not cast to K")
+            })
+            .collect()
+    }
+}
+
+impl<N, K> VecHash<N, K> for BMinHash<N, K>
+where
+    N: Integer,
+    K: Integer,
+{
+    fn hash_vec_query(&self, v: &[N]) -> Vec<K> {
+        self.hash_minima(self.minhash.permutation_minima(v))
+    }
+
+    /// Jaccard distance between the sets of non zero shingles.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        self.minhash.distance(a, b)
+    }
+}
+
+impl<N, K> SetHash<K> for BMinHash<N, K>
+where
+    N: Integer,
+    K: Integer,
+{
+    fn hash_set(&self, set: &[usize]) -> Vec<K> {
+        self.hash_minima(self.minhash.set_minima(set))
+    }
+}
+
+/// Bit sampling, a hash family for the
+/// [Hamming distance](https://en.wikipedia.org/wiki/Hamming_distance) between binary vectors,
+/// e.g. perceptual hashes. The bits of a data point are packed in its values, so a data point
+/// of dimension `dim` of `u64` values holds `64 * dim` bits. Every projection samples one of
+/// these bits.
+///
+/// Two data points at Hamming distance `d` out of `D` bits agree on a sampled bit with
+/// probability `1 - d / D`. The `k` projections of a hash table sample distinct bits (without
+/// replacement), so the points collide in a hash table with probability
+/// `p = C(D - d, k) / C(D, k)`, the product of `(D - d - i) / (D - i)` for `i` in `0..k`. This
+/// is at most `(1 - d / D)^k` and close to it if `k` is much smaller than `D`. They collide in
+/// at least one of `L` hash tables with probability `1 - (1 - p)^L`.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BitSampling<N = u64, K = i8> {
+    /// Sampled bits, counted from the lowest bit of the first value.
+    positions: Vec<usize>,
+    phantom: PhantomData<(N, K)>,
+}
+
+impl<N, K> BitSampling<N, K>
+where
+    N: Integer + PrimInt,
+    K: Integer,
+{
+    /// # Panics
+    ///
+    /// Panics if there are fewer than `n_projections` bits. Use [try_new](#method.try_new) to
+    /// get an error instead.
+    pub fn new(n_projections: usize, dim: usize, seed: u64) -> Self {
+        Self::try_new(n_projections, dim, seed).expect("could not create BitSampling")
+    }
+
+    /// Create a new bit sampling hasher. The bits are sampled without replacement, so it
+    /// returns an error if the data points have fewer than `n_projections` bits.
+    pub fn try_new(n_projections: usize, dim: usize, seed: u64) -> Result<Self> {
+        let n_bits = dim * Self::value_bits();
+        if n_projections > n_bits {
+            return Err(Error::Failed(format!(
+                "cannot sample {} bits from data points of {} bits",
+                n_projections, n_bits
+            )));
+        }
+        let mut rng = create_rng(seed);
+        let positions = rand::seq::index::sample(&mut rng, n_bits, n_projections).into_vec();
+        Ok(BitSampling {
+            positions,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Sampled bits, counted from the lowest bit of the first value.
+    pub fn positions(&self) -> &[usize] {
+        &self.positions
+    }
+
+    fn value_bits() -> usize {
+        std::mem::size_of::<N>() * 8
+    }
+}
+
+impl<N, K> VecHash<N, K> for BitSampling<N, K>
+where
+    N: Integer + PrimInt,
+    K: Integer,
+{
+    fn hash_vec_query(&self, v: &[N]) -> Vec<K> {
+        let bits = Self::value_bits();
+        self.positions
+            .iter()
+            .map(|&p| {
+                if (v[p / bits] >> (p % bits)) & N::one() == N::zero() {
+                    K::zero()
                 } else {
-                    acc
+                    K::one()
                 }
             })
-        });
-        hash.to_vec()
+            .collect()
+    }
+
+    /// Number of bits that differ.
+    fn distance(&self, a: &[N], b: &[N]) -> Option<f64> {
+        Some(
+            a.iter()
+                .zip(b)
+                .map(|(&x, &y)| (x ^ y).count_ones())
+                .sum::<u32>() as f64,
+        )
     }
 }
 
@@ -306,6 +1189,31 @@ where
 mod test {
     use super::*;
 
+    #[test]
+    fn test_l2_boundary() {
+        // a single projection a = 1, b = 0, r = 1. So x is hashed to floor(x).
+        let mut l2 = L2::<f32, i32>::new(1, 1., 1, 1);
+        l2.a = array![[1.]].into_shared();
+        l2.b = array![0.];
+        // 0.9 / 0.3 is 2.9999998 in f32, while it should be exactly on the boundary at 3.
+        let x = 0.9_f32 / 0.3;
+        assert_eq!(l2.hash_vec_query(&[x]), vec![2]);
+        assert_eq!(l2.hash_vec_query(&[3.]), vec![3]);
+
+        // with a margin both cells are probed.
+        assert_eq!(
+            l2.hash_vec_query_boundary(&[x], 1e-3),
+            vec![vec![2], vec![3]]
+        );
+        assert_eq!(
+            l2.hash_vec_query_boundary(&[3.], 1e-3),
+            vec![vec![3], vec![2]]
+        );
+        // far from a boundary only the cell itself.
+        assert_eq!(l2.hash_vec_query_boundary(&[3.5], 1e-3), vec![vec![3]]);
+        assert_eq!(l2.hash_vec_query_boundary(&[x], 0.), vec![vec![2]]);
+    }
+
     #[test]
     fn test_l2() {
         // Only test if it runs
@@ -322,11 +1230,207 @@ mod test {
         assert_ne!(h1, h3);
     }
 
+    #[test]
+    fn test_sparse_projections() {
+        let dim = 400;
+        let mut l2 = L2::<f64, i32>::new_sparse(dim, 2.2, 8, 0.1, 1).unwrap();
+        assert!(l2.a.is_empty());
+        let a = l2.sparse.as_ref().unwrap().to_dense::<f64>(dim);
+        let nnz = a.iter().filter(|&&x| x != 0.).count();
+        assert!(nnz > 160 && nnz < 480, "{} non-zero entries", nnz);
+        assert!(a
+            .iter()
+            .all(|&x| x == 0. || (x.abs() - 10f64.sqrt()).abs() < 1e-6));
+        // the sparse projections equal the dense projections.
+        let v: Vec<f64> = (0..dim).map(|i| (i as f64).sin()).collect();
+        let dense = a.dot(&aview1(&v)) + &l2.b;
+        for (x, y) in l2.project(&v).iter().zip(dense.iter()) {
+            assert!((x - y).abs() < 1e-9);
+        }
+        // dense projections replace the sparse projections.
+        l2.a = (-a).into_shared();
+        let negated = l2.project(&v);
+        for i in 0..8 {
+            assert!((negated[i] - (2. * l2.b[i] - dense[i])).abs() < 1e-9);
+        }
+
+        let srp = SignRandomProjections::<f64>::new_sparse(8, dim, 0.1, 1).unwrap();
+        assert!(srp.hyperplanes.is_empty());
+        let expected: Vec<i8> = srp
+            .sparse
+            .as_ref()
+            .unwrap()
+            .to_dense::<f64>(dim)
+            .dot(&aview1(&v))
+            .iter()
+            .map(|&x| (x > 0.) as i8)
+            .collect();
+        assert_eq!(srp.hash_vec_query(&v), expected);
+
+        assert!(L2::<f32, i32>::new_sparse(dim, 2.2, 8, 0., 1).is_err());
+        assert!(SignRandomProjections::<f32>::new_sparse(8, dim, 1.5, 1).is_err());
+    }
+
     #[test]
     fn test_minhash() {
         let n_projections = 3;
         let h = <MinHash>::new(n_projections, 5, 0);
         let hash = h.hash_vec_query(&[1, 0, 1, 0, 1]);
-        assert_eq!(hash.len(), n_projections)
+        assert_eq!(hash.len(), n_projections);
+
+        // compare with a scalar fold over all dimensions.
+        let h = MinHash::<u16, i32>::new(8, 100, 1);
+        let v = (0..100).map(|i| (i % 7 == 0) as u16).collect::<Vec<_>>();
+        let expected = (0..8)
+            .map(|row| {
+                h.pi.row(row)
+                    .iter()
+                    .zip(&v)
+                    .map(|(&p, &x)| p * x)
+                    .filter(|&x| x > 0)
+                    .fold(8, |acc, x| std::cmp::min(acc, x as i32))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(h.hash_vec_query(&v), expected);
+        assert_eq!(h.hash_vec_query(&[0; 100]), vec![8; 8]);
+    }
+
+    #[test]
+    fn test_minhash_dim_overflow() {
+        assert!(MinHash::<u8, i32>::try_new(3, 255, 0).is_ok());
+        match MinHash::<u8, i32>::try_new(3, 256, 0) {
+            Err(Error::DimensionOverflow { dim: 256, max: 255 }) => {}
+            _ => panic!("expected a dimension overflow"),
+        }
+
+        // hashes are capped at n_projections, so K doesn't need to hold dim.
+        let h = MinHash::<u16, i8>::try_new(3, 1000, 0).unwrap();
+        let v = (0..1000).map(|i| (i == 999) as u16).collect::<Vec<_>>();
+        assert!(h.hash_vec_query(&v).iter().all(|&x| x > 0 && x <= 3));
+    }
+
+    #[test]
+    fn test_b_minhash() {
+        let h = BMinHash::<u16, i8>::new(16, 1000, 2, 3);
+        let v = (0..1000).map(|i| (i % 3 == 0) as u16).collect::<Vec<_>>();
+        let hash = h.hash_vec_query(&v);
+        assert_eq!(hash.len(), 16);
+        assert!(hash.iter().all(|&x| (0..4).contains(&x)));
+
+        // the lowest b bits of the uncapped permutation minima.
+        let expected = (0..16)
+            .map(|row| {
+                let min = h
+                    .minhash
+                    .pi
+                    .row(row)
+                    .iter()
+                    .zip(&v)
+                    .filter(|(_, &x)| x > 0)
+                    .map(|(&p, _)| p)
+                    .min()
+                    .unwrap();
+                (min & 3) as i8
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(hash, expected);
+
+        assert!(BMinHash::<u16, i8>::try_new(3, 100, 7, 0).is_ok());
+        assert!(BMinHash::<u16, i8>::try_new(3, 100, 8, 0).is_err());
+        assert!(BMinHash::<u16, i8>::try_new(3, 100, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_hash_set() {
+        let set = (0..1000).filter(|i| i % 7 == 3).collect::<Vec<_>>();
+        let v = (0..1000)
+            .map(|i| set.contains(&i) as u16)
+            .collect::<Vec<_>>();
+        let h = MinHash::<u16, i32>::new(16, 1000, 1);
+        assert_eq!(h.hash_set(&set), h.hash_vec_query(&v));
+        assert_eq!(h.hash_set(&[]), h.hash_vec_query(&[0; 1000]));
+        // duplicates don't change the minimum.
+        assert_eq!(h.hash_set(&[5, 5, 9]), h.hash_set(&[9, 5]));
+
+        let h = BMinHash::<u16, i8>::new(16, 1000, 2, 3);
+        assert_eq!(h.hash_set(&set), h.hash_vec_query(&v));
+        assert_eq!(h.hash_set(&[]), h.hash_vec_query(&[0; 1000]));
+    }
+
+    #[test]
+    fn test_quantized_srp() {
+        // a single scale doesn't change the hashes of the widened data point.
+        let q = QuantizedSignRandomProjections::new(8, 4, &[0.02], 3).unwrap();
+        let srp = SignRandomProjections::<f32>::new(8, 4, 3);
+        let v = [12i8, -100, 7, 55];
+        let vf = v.iter().map(|&x| x as f32 * 0.02).collect::<Vec<_>>();
+        assert_eq!(q.hash_vec_query(&v), srp.hash_vec_query(&vf));
+        let d = q.distance(&v, &[12, -100, 7, 56]).unwrap();
+        assert!(d > 0. && d < 1e-3);
+
+        // a scale per dimension is applied before hashing and in the distance.
+        let q = QuantizedSignRandomProjections::new(8, 4, &[1., 1., 100., 1.], 3).unwrap();
+        let vf = [12., -100., 700., 55.];
+        assert_eq!(q.hash_vec_query(&v), srp.hash_vec_query(&vf));
+        assert!((q.distance(&[1, 0, 0, 0], &[0, 0, 1, 0]).unwrap() - 1.).abs() < 1e-9);
+        assert!(q.distance(&[0, 0, 1, 0], &[1, 0, 1, 0]).unwrap() < 1e-4);
+
+        assert!(QuantizedSignRandomProjections::new(8, 4, &[1., 1.], 3).is_err());
+        assert!(QuantizedSignRandomProjections::new(8, 4, &[0.], 3).is_err());
+    }
+
+    #[test]
+    fn test_srp_packed_sign_convention() {
+        let v = [2., -3., 4., 0.5];
+        let srp = SignRandomProjections::<f32>::new(8, 4, 3);
+        let bits = srp.hash_vec_query(&v);
+        let bipolar = srp.clone().with_sign_convention(SignConvention::Bipolar);
+        assert_ne!(bipolar.hash_vec_query(&v), bits);
+
+        let packed = PackedSignRandomProjections::from(bipolar);
+        assert_eq!(packed.srp.sign_convention(), SignConvention::Binary);
+        let word = bits
+            .iter()
+            .enumerate()
+            .fold(0u64, |word, (i, &bit)| word | (bit as u64) << i);
+        assert_eq!(packed.hash_vec_query(&v), vec![word]);
+    }
+
+    #[test]
+    fn test_l1() {
+        let l1 = <L1>::new(5, 4., 7, 1);
+        let h1 = l1.hash_vec_query(&[1., 2., 3., 1., 3.]);
+        let h2 = l1.hash_vec_query(&[1., 2., 3., 1., 3.]);
+        let h3 = l1.hash_vec_query(&[100., -100., 100., -100., 100.]);
+        assert_eq!(h1.len(), 7);
+        assert_eq!(h1, h2);
+        assert_ne!(h1, h3);
+        assert_eq!(l1.distance(&[1., 2.], &[0., 4.]), Some(3.));
+    }
+
+    #[test]
+    fn test_bit_sampling() {
+        let h = BitSampling::<u8, i8>::new(16, 2, 1);
+        // all 16 bits are sampled, so the hash holds every bit once.
+        let hash = h.hash_vec_query(&[0b1000_0001, 0b0000_0100]);
+        assert_eq!(hash.iter().filter(|&&b| b == 1).count(), 3);
+        for (&p, &b) in h.positions().iter().zip(&hash) {
+            assert_eq!(b == 1, [0, 7, 10].contains(&p));
+        }
+        assert_eq!(h.distance(&[0b1000_0001, 0], &[0b0000_0011, 1]), Some(3.));
+        assert!(BitSampling::<u8, i8>::try_new(17, 2, 1).is_err());
+
+        // data points that differ in 16 of 64 bits collide on 4 sampled bits with probability
+        // (1 - 16 / 64)^4.
+        let (a, b) = ([u64::MAX], [u64::MAX ^ 0xffff]);
+        let n = 2000;
+        let collisions = (0..n)
+            .filter(|&seed| {
+                let h = BitSampling::<u64, i8>::new(4, 1, seed + 1);
+                h.hash_vec_query(&a) == h.hash_vec_query(&b)
+            })
+            .count();
+        let p = collisions as f64 / n as f64;
+        assert!((p - 0.75f64.powi(4)).abs() < 0.05, "{}", p);
     }
 }
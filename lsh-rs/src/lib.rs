@@ -13,20 +13,46 @@
 //!
 //! * **Base LSH**
 //!     - Signed Random Projections *(Cosine similarity)*
+//!     - Signed Random Projections on int8 quantized data points *(Cosine similarity)*
+//!     - Signed Random Projections with packed `u64` hashes *(Cosine similarity)*
 //!     - L2 distance
+//!     - L1 distance *(Manhattan)*
 //!     - MIPS *(Dot products/ Maximum Inner Product Search)*
 //!     - MinHash *(Jaccard Similarity)*
+//!     - b-bit MinHash *(Jaccard Similarity)*
+//!     - Bit sampling *(Hamming distance)*
 //! * **Multi Probe LSH**
 //!     - **Step wise probing**
 //!         - SRP (only bit shifts)
 //!     - **Query directed probing**
 //!         - L2
 //!         - MIPS
+//! * **LSH Forest** *(no fixed number of projections, see [LshForest](struct.LshForest.html))*
 //! * Generic numeric types
 //!
 //! ## Features
 //! * "blas"
 //! * "sqlite"
+//! * "serve" (gRPC serving layer, see [serve](serve/index.html))
+//! * "tracing" ([tracing](https://docs.rs/tracing) spans for storing, querying, probing and
+//!   committing. The buckets that are hit are recorded as `TRACE` events with the hash table
+//!   index and the bucket size.)
+//! * "f16" (half precision `half::f16` data points, e.g. `LshMem<_, f16, i32>`, which halves
+//!   the memory of the stored data points. The hashers accumulate in f32.)
+//! * "stable-hash" (sum the projections of the hashers in a fixed order. Without it, the
+//!   hashes of a "blas" build can differ from the hashes of a build without BLAS. The
+//!   summation is recorded in dumps and SQLite databases, and loading an index that was
+//!   hashed with another summation fails with `Error::HashFlavorMismatch`.)
+//! * "simd" (sum the projections of the hashers in hand-vectorized loops, without installing a
+//!   BLAS backend. Hashing a 100 dimensional vector to 20 projections (`cargo bench -- hash/`)
+//!   takes 504 ns instead of 936 ns for SignRandomProjections and 633 ns instead of 887 ns for
+//!   L2, the other hashers change less than 15%. It is a summation of its own, like
+//!   "stable-hash". Ignored with "blas" or "stable-hash".)
+//! * "mmap" (append-only memory-mapped backend [LshMmap](type.LshMmap.html))
+//! * "serde" (enabled by default. Serialization with serde, bincode and serde_json: dumps,
+//!   the write-ahead log, configuration files and the portable export. "sqlite" and "mmap"
+//!   store the hashers and enable it. Without it, the in memory backends compile without these
+//!   dependencies.)
 //!
 //! ## Getting started
 //!
@@ -139,6 +165,18 @@
 //!     .unwrap();
 //! ```
 //!
+//! The ids are `u32` by default, which limits an index to about 4.29 billion data points.
+//! [MemoryTable](struct.MemoryTable.html), [SqlTable](struct.SqlTable.html) and
+//! [SqlTableMem](struct.SqlTableMem.html) take the id type as last generic parameter, so larger
+//! indexes can use `u64` ids.
+//! ```rust
+//! # use lsh_rs::prelude::*;
+//! let mut lsh = LSH::<_, f32, MemoryTable<f32, i8, u64>>::new(9, 10, 3)
+//!     .srp()
+//!     .unwrap();
+//! let id: u64 = lsh.store_vec(&[2., 3., 4.]).unwrap();
+//! ```
+//!
 //! ## Builder pattern methods
 //! The following methods can be used to change internal state during object initialization:
 //! * [only_index](struct.LSH.html#method.only_index)
@@ -146,22 +184,53 @@
 //! * [set_database_file](struct.LSH.html#method.set_database_file)
 //! * [multi_probe](struct.LSH.html#method.multi_probe)
 //! * [increase_storage](struct.LSH.html#method.increase_storage)
-//! * [fit (only for MIPS)](struct.MIPS.html#method.fit)
+//! * [flat_storage](struct.LSH.html#method.flat_storage)
+//! * [packed_keys](struct.LSH.html#method.packed_keys)
+//! * [auto_index](struct.LSH.html#method.auto_index)
+//! * [min_bucket_size](struct.LSH.html#method.min_bucket_size)
+//! * [max_bucket_size](struct.LSH.html#method.max_bucket_size)
+//! * [boundary_eps](struct.LSH.html#method.boundary_eps)
+//! * [projections_per_table](struct.LSH.html#method.projections_per_table)
+//! * [shared_projections](struct.LSH.html#method.shared_projections)
+//! * [projection_density](struct.LSH.html#method.projection_density)
+//! * [read_only](struct.LSH.html#method.read_only)
+//! * [query_observer](struct.LSH.html#method.query_observer)
+//! * [fit (only for hashers that implement VecHashFit, e.g. MIPS)](struct.LSH.html#method.fit)
 //!
 //! ## Backends
 //! The [LSH struct](struct.LSH.html) is exposed with multiple backends that store the hashes.
 //! * in memory (fastest / can save state with serialization) [LshMem](type.LshMem.html)
 //! * SQLite (slower due to disk io, but automatic state preservation between sessions) [LshSql](type.LshSql.html)
 //! * in memory SQLite (can backup to SQLite when processing is done) [LshSqlMem](type.LshSqlMem.html)
+//! * in memory with locked shards (concurrent queries and writes) [LshShared](type.LshShared.html)
+//! * memory-mapped log file (in memory speed and state preservation between sessions, feature
+//!   "mmap") [LshMmap](type.LshMmap.html)
+//!
+//! ## Thread safety
+//! All hashers are `Send + Sync`. The thread safety of an [LSH](struct.LSH.html) object is
+//! determined by its backend:
+//! * [LshMem](type.LshMem.html) is `Send + Sync`. It can be shared between threads behind an `Arc`
+//!   for concurrent queries.
+//! * [LshShared](type.LshShared.html) is `Send + Sync` and can also be written behind an `Arc`,
+//!   e.g. by an ingesting thread while other threads query. See
+//!   [store_vec_shared](struct.LSH.html#method.store_vec_shared).
+//! * [LshSql](type.LshSql.html) and [LshSqlMem](type.LshSqlMem.html) are `Send`, but not `Sync`.
+//!   A SQLite connection may be moved to another thread, but not be used from multiple threads at
+//!   once. Wrap them in a `Mutex` to share them, or open one connection per thread.
+//!   A read-only [LshSql](type.LshSql.html) runs batched queries in parallel with
+//!   [query_bucket_ids_batch_par_sql](struct.LSH.html#method.query_bucket_ids_batch_par_sql),
+//!   which opens one connection per worker thread.
+//! * [LshMmap](type.LshMmap.html) is `Send + Sync` for concurrent queries.
 //!
 //! ## Hash primitives
 //! The hashers in this crate will produces hashes of type `Vec<T>`. Where `T` should be one of `i8`,
-//! `i16`, `i32` or `i64`. This concrete primitive value can be set by choosing on of the utillity types
+//! `i16`, `i32`, `i64` or `u64`. This concrete primitive value can be set by choosing on of the utillity types
 //! in the following sub-modules:
 //! * [hi8](prelude/hi8/index.html)
 //! * [hi16](prelude/hi16/index.html)
 //! * [hi32](prelude/hi32/index.html)
 //! * [hi64](prelude/hi64/index.html)
+//! * [hi_packed](prelude/hi_packed/index.html) (`u64` words of packed hash bits)
 //!
 //! Using smaller primitives for the hash values, will result in less space requirements and greater
 //! performance. However this may lead to panics if the hash value doesn't fit the chosen primitive
@@ -169,7 +238,9 @@
 //!
 //! *Note: the hash primitive cannot be set for every Hash family that has implemented
 //! [VecHash](trait.VecHash.html). For instance, [SignRandomProjections](struct.SignRandomProjections.html)
-//! will allways use `i8` as hash primitive.*
+//! will allways use `i8` as hash primitive. Its packed variant
+//! [PackedSignRandomProjections](struct.PackedSignRandomProjections.html) uses `u64` words of 64
+//! hash bits, which takes 8 times less memory.*
 //!
 //! ```rust
 //! # use lsh_rs::prelude::*;
@@ -184,6 +255,10 @@
 //! let lhs_i8 = hi64::LshMem::<_, u8>::new(n_projections, n_hash_tables, dim)
 //!     .minhash()
 //!     .unwrap();
+//! // pack the bits of SignRandomProjections in u64 words:
+//! let lsh_packed = hi_packed::LshMem::<_, f32>::new(n_projections, n_hash_tables, dim)
+//!     .srp_packed()
+//!     .unwrap();
 //! ```
 //!
 //! ## BLAS support
@@ -210,16 +285,25 @@
 #[cfg(feature = "blas")]
 extern crate blas_src;
 extern crate ndarray;
+pub mod config;
 mod hash;
 mod lsh {
+    pub mod dedup;
+    pub mod forest;
     pub mod lsh;
+    pub mod multi_metric;
+    pub mod observer;
     mod test;
+    mod wal;
 }
 pub mod dist;
+pub mod feature_hash;
 mod multi_probe;
 mod table {
     pub mod general;
     pub mod mem;
+    pub mod mmap;
+    pub mod shared;
     pub mod sqlite;
     pub mod sqlite_mem;
 }
@@ -230,11 +314,55 @@ mod error;
 pub mod utils;
 #[cfg(not(feature = "workspace"))]
 mod utils;
-pub use hash::VecHash;
-pub use multi_probe::{QueryDirectedProbe, StepWiseProbe};
-pub use table::{general::HashTables, mem::MemoryTable};
+pub use hash::{SetHash, VecHash};
+pub use multi_probe::{
+    adaptive_probe_budget, Probe, ProbeFn, ProbingSequence, QueryDirectedProbe, QueryScratch,
+    StepWiseProbe,
+};
+#[cfg(feature = "mmap")]
+pub use table::mmap::MmapTable;
+pub use table::{
+    general::HashTables,
+    mem::{FlatVecStore, MemoryTable, VecStore},
+    shared::ShardedMemoryTable,
+};
 #[cfg(feature = "sqlite")]
-pub use table::{sqlite::SqlTable, sqlite_mem::SqlTableMem};
+pub use table::{
+    sqlite::{FragmentationReport, SqlDistance, SqlIdx, SqlTable},
+    sqlite_mem::SqlTableMem,
+};
 pub mod data;
 pub mod prelude;
+pub mod rerank;
+#[cfg(feature = "serve")]
+pub mod serve;
 pub mod stats;
+
+// Compile time checks of the thread safety guarantees documented above.
+mod assert_thread_safety {
+    use crate::prelude::*;
+    use static_assertions::assert_impl_all;
+    #[cfg(feature = "sqlite")]
+    use static_assertions::assert_not_impl_any;
+
+    assert_impl_all!(SignRandomProjections<f32>: Send, Sync);
+    assert_impl_all!(QuantizedSignRandomProjections: Send, Sync);
+    assert_impl_all!(L2<f32>: Send, Sync);
+    assert_impl_all!(L1<f32>: Send, Sync);
+    assert_impl_all!(MIPS<f32>: Send, Sync);
+    assert_impl_all!(MinHash<u8>: Send, Sync);
+    assert_impl_all!(BMinHash<u8>: Send, Sync);
+    assert_impl_all!(BitSampling<u64>: Send, Sync);
+    assert_impl_all!(LshMem<L2<f32>, f32, i32>: Send, Sync);
+    assert_impl_all!(LshShared<L2<f32>, f32, i32>: Send, Sync);
+    assert_impl_all!(MultiMetricLSH<f32>: Send, Sync);
+    assert_impl_all!(LshForest<SignRandomProjections<f32>, f32>: Send, Sync);
+    #[cfg(feature = "sqlite")]
+    assert_impl_all!(LshSql<L2<f32>, f32, i32>: Send);
+    #[cfg(feature = "sqlite")]
+    assert_impl_all!(LshSqlMem<L2<f32>, f32, i32>: Send);
+    #[cfg(feature = "sqlite")]
+    assert_not_impl_any!(LshSql<L2<f32>, f32, i32>: Sync);
+    #[cfg(feature = "mmap")]
+    assert_impl_all!(LshMmap<L2<f32>, f32, i32>: Send, Sync);
+}
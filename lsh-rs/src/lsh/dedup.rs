@@ -0,0 +1,124 @@
+//! Deduplication of query results across queries, see
+//! [query_bucket_ids_dedup](struct.LSH.html#method.query_bucket_ids_dedup).
+use crate::prelude::*;
+use fnv::FnvHasher;
+use std::hash::{Hash, Hasher};
+
+/// Bloom filter of the ids or pairs that a [QuerySession](struct.QuerySession.html) emitted.
+#[derive(Debug, Clone)]
+struct BloomFilter {
+    bits: Vec<u64>,
+    n_bits: u64,
+    n_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Size the filter for `capacity` items at a false positive rate of `fp_rate`.
+    fn new(capacity: usize, fp_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let n_bits = (-(capacity as f64) * fp_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.) as u64;
+        let n_hashes = (n_bits as f64 / capacity as f64 * ln2).round().max(1.) as u32;
+        BloomFilter {
+            bits: vec![0; n_bits.div_ceil(64) as usize],
+            n_bits,
+            n_hashes,
+        }
+    }
+
+    /// Add `item`. Returns `false` if it was (probably) added before.
+    fn insert<T: Hash>(&mut self, item: &T) -> bool {
+        let mut hasher = FnvHasher::default();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+        // second hash for double hashing, a mix of the first.
+        let h2 = (h1 ^ (h1 >> 31)).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let mut new = false;
+        for i in 0..self.n_hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.n_bits;
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            new |= self.bits[word] & mask == 0;
+            self.bits[word] |= mask;
+        }
+        new
+    }
+
+    fn clear(&mut self) {
+        self.bits.iter_mut().for_each(|w| *w = 0);
+    }
+}
+
+/// Remembers the ids or pairs that queries emitted, so that repeated queries, e.g. of a
+/// near-duplicate search over a batch, only return new candidates. See
+/// [query_bucket_ids_dedup](struct.LSH.html#method.query_bucket_ids_dedup) and
+/// [query_bucket_pairs_dedup](struct.LSH.html#method.query_bucket_pairs_dedup).
+///
+/// The emitted items are kept in a bloom filter, so the memory doesn't grow with the number of
+/// queries. The price is that a fraction of the new items (about `fp_rate`, until more than
+/// `capacity` items are emitted) is taken for an emitted item and dropped.
+#[derive(Debug, Clone)]
+pub struct QuerySession {
+    filter: BloomFilter,
+    n_emitted: usize,
+}
+
+impl QuerySession {
+    /// # Arguments
+    /// * `capacity` - Expected number of distinct ids or pairs.
+    /// * `fp_rate` - Fraction of the new items that may be dropped, between 0 and 1.
+    pub fn new(capacity: usize, fp_rate: f64) -> Result<Self> {
+        if capacity == 0 {
+            return Err(Error::Failed(
+                "capacity should be larger than 0".to_string(),
+            ));
+        }
+        if !(fp_rate > 0. && fp_rate < 1.) {
+            return Err(Error::Failed(format!(
+                "fp_rate should be between 0 and 1, got {}",
+                fp_rate
+            )));
+        }
+        Ok(QuerySession {
+            filter: BloomFilter::new(capacity, fp_rate),
+            n_emitted: 0,
+        })
+    }
+
+    /// Number of ids or pairs that were emitted.
+    pub fn n_emitted(&self) -> usize {
+        self.n_emitted
+    }
+
+    /// Forget the emitted ids and pairs.
+    pub fn clear(&mut self) {
+        self.filter.clear();
+        self.n_emitted = 0;
+    }
+
+    /// Keep the items of `items` that weren't emitted before.
+    pub(crate) fn retain_new<T: Hash>(&mut self, items: &mut Vec<T>) {
+        let filter = &mut self.filter;
+        items.retain(|item| filter.insert(item));
+        self.n_emitted += items.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        let new = (0..1000u32).filter(|i| filter.insert(i)).count();
+        assert!(new > 980, "{}", new);
+        assert!((0..1000u32).all(|i| !filter.insert(&i)));
+        let false_positives = (1000..11000u32)
+            .filter(|i| !filter.clone().insert(i))
+            .count();
+        assert!(false_positives < 300, "{}", false_positives);
+        filter.clear();
+        assert!(filter.insert(&0u32));
+    }
+}
@@ -0,0 +1,218 @@
+//! LSH Forest, see [LshForest](struct.LshForest.html).
+use super::lsh::batch_err;
+use crate::data::{Integer, Numeric};
+use crate::prelude::*;
+use crate::utils::create_rng;
+use fnv::FnvHashSet;
+use num::Float;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+/// Prefix tree of one hasher. The keys are the full hashes, sorted lexicographically, so the
+/// hashes that share a prefix are a contiguous range.
+type PrefixTree<K> = BTreeMap<Vec<K>, Vec<u32>>;
+
+/// LSH Forest ([Bawa et al.](http://infolab.stanford.edu/~bawa/Pub/similarity.pdf)), an
+/// alternative to [LSH](struct.LSH.html) that doesn't need a fixed number of projections K.
+///
+/// Every hasher hashes to `max_depth` values, but the buckets are the prefixes of the hashes
+/// in a prefix tree. A query descends every tree to the longest prefix it shares with a stored
+/// hash, and then ascends all trees synchronously to shorter prefixes until `m` candidates are
+/// collected. Closer data points share longer prefixes, so the prefix length adapts to the
+/// density of the data around the query.
+///
+/// Only the ids are stored. Rank the candidates by their exact distance with
+/// [rerank_candidates](rerank/fn.rerank_candidates.html).
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::prelude::*;
+/// let mut forest = LshForest::srp(16, 10, 3, 1).unwrap();
+/// let idx = forest.store_vec(&[2., 3., 4.]).unwrap();
+/// forest.store_vec(&[-1., -1., 1.]).unwrap();
+/// assert_eq!(forest.query_bucket_ids(&[2., 3., 4.], 1).unwrap(), vec![idx]);
+/// assert_eq!(forest.query_bucket_ids(&[2., 3., 4.], 2).unwrap().len(), 2);
+/// ```
+pub struct LshForest<H, N, K = i8> {
+    hashers: Vec<H>,
+    trees: Vec<PrefixTree<K>>,
+    dim: usize,
+    counter: u32,
+    phantom: PhantomData<N>,
+}
+
+impl<H, N, K> LshForest<H, N, K>
+where
+    H: VecHash<N, K>,
+    N: Numeric,
+    K: Integer,
+{
+    /// Create a forest with a prefix tree per hasher. The length of the hashes is the maximum
+    /// prefix length.
+    ///
+    /// # Arguments
+    /// * `hashers` - Hashers, e.g. the hashers of an [LSH](struct.LSH.html) with
+    ///   `n_projections` as the maximum depth.
+    /// * `dim` - Dimension of the data points.
+    pub fn new(hashers: Vec<H>, dim: usize) -> Result<Self> {
+        if hashers.is_empty() {
+            return Err(Error::Failed(
+                "a forest needs at least one hasher".to_string(),
+            ));
+        }
+        Ok(LshForest {
+            trees: vec![PrefixTree::new(); hashers.len()],
+            hashers,
+            dim,
+            counter: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Number of prefix trees.
+    pub fn n_trees(&self) -> usize {
+        self.trees.len()
+    }
+
+    /// Number of stored data points.
+    pub fn len(&self) -> usize {
+        self.counter as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counter == 0
+    }
+
+    /// The hashers of the prefix trees.
+    pub fn hashers(&self) -> &[H] {
+        &self.hashers
+    }
+
+    fn validate_vec(&self, v: &[N]) -> Result<()> {
+        if v.len() != self.dim {
+            return Err(Error::DimensionMismatch {
+                expected: self.dim,
+                actual: v.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Store a data point in all prefix trees. Returns its id.
+    pub fn store_vec(&mut self, v: &[N]) -> Result<u32> {
+        self.validate_vec(v)?;
+        let idx = self.counter;
+        for (hasher, tree) in self.hashers.iter().zip(self.trees.iter_mut()) {
+            tree.entry(hasher.hash_vec_put(v)).or_default().push(idx);
+        }
+        self.counter += 1;
+        Ok(idx)
+    }
+
+    /// Store multiple data points. Returns their ids. All rows are validated first, so an
+    /// invalid row fails with `Error::Batch` and none of the data points are stored.
+    pub fn store_vecs(&mut self, vs: &[Vec<N>]) -> Result<Vec<u32>> {
+        vs.iter()
+            .enumerate()
+            .try_for_each(|(row, v)| self.validate_vec(v).map_err(batch_err(row)))?;
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Query at least `m` candidates of `v`, or all data points if fewer are stored. Returns
+    /// the ids sorted ascending.
+    ///
+    /// Every tree is descended to the longest prefix `x_i` of the query hash that is stored.
+    /// From `x = max(x_i)`, the ids under prefix length `x` are collected of all trees with
+    /// `x_i >= x`, lowering `x` until `m` candidates are collected.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `m` - Minimum number of candidates.
+    pub fn query_bucket_ids(&self, v: &[N], m: usize) -> Result<Vec<u32>> {
+        self.validate_vec(v)?;
+        let hashes: Vec<Vec<K>> = self.hashers.iter().map(|h| h.hash_vec_query(v)).collect();
+        let depths: Vec<usize> = self
+            .trees
+            .iter()
+            .zip(&hashes)
+            .map(|(tree, hash)| descend(tree, hash))
+            .collect();
+
+        let mut candidates = FnvHashSet::default();
+        let mut x = depths.iter().copied().max().unwrap_or(0);
+        loop {
+            for ((tree, hash), &depth) in self.trees.iter().zip(&hashes).zip(&depths) {
+                if depth >= x {
+                    collect_prefix(tree, &hash[..x], &mut candidates);
+                }
+            }
+            if candidates.len() >= m || x == 0 {
+                break;
+            }
+            x -= 1;
+        }
+        let mut ids: Vec<u32> = candidates.into_iter().collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+}
+
+/// Length of the longest prefix of `hash` that is shared with a stored hash. That is shared
+/// with one of the neighbors of `hash` in the sort order.
+fn descend<K: Integer>(tree: &PrefixTree<K>, hash: &[K]) -> usize {
+    let common = |key: &Vec<K>| key.iter().zip(hash).take_while(|(a, b)| a == b).count();
+    let below = tree
+        .range::<[K], _>((Bound::Unbounded, Bound::Included(hash)))
+        .next_back()
+        .map(|(key, _)| common(key));
+    let above = tree
+        .range::<[K], _>((Bound::Excluded(hash), Bound::Unbounded))
+        .next()
+        .map(|(key, _)| common(key));
+    below.max(above).unwrap_or(0)
+}
+
+/// Add the ids of all hashes that start with `prefix`.
+fn collect_prefix<K: Integer>(tree: &PrefixTree<K>, prefix: &[K], ids: &mut FnvHashSet<u32>) {
+    tree.range::<[K], _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(|(key, _)| key.starts_with(prefix))
+        .for_each(|(_, bucket)| ids.extend(bucket));
+}
+
+impl<N: Numeric> LshForest<SignRandomProjections<N>, N, i8> {
+    /// Create a forest of SignRandomProjections prefix trees.
+    ///
+    /// # Arguments
+    /// * `max_depth` - Number of projections per tree, the maximum prefix length.
+    /// * `n_trees` - Number of prefix trees.
+    /// * `dim` - Dimension of the data points.
+    /// * `seed` - Seed for the hashers. If 0, randomness is seeded from the os.
+    pub fn srp(max_depth: usize, n_trees: usize, dim: usize, seed: u64) -> Result<Self> {
+        let mut rng = create_rng(seed);
+        let hashers = (0..n_trees)
+            .map(|_| SignRandomProjections::new(max_depth, dim, rng.gen()))
+            .collect();
+        LshForest::new(hashers, dim)
+    }
+}
+
+impl<N: Numeric + Float> LshForest<L2<N, i32>, N, i32> {
+    /// Create a forest of L2 prefix trees.
+    ///
+    /// # Arguments
+    /// * `max_depth` - Number of projections per tree, the maximum prefix length.
+    /// * `n_trees` - Number of prefix trees.
+    /// * `dim` - Dimension of the data points.
+    /// * `r` - Bucket width of the projections.
+    /// * `seed` - Seed for the hashers. If 0, randomness is seeded from the os.
+    pub fn l2(max_depth: usize, n_trees: usize, dim: usize, r: f32, seed: u64) -> Result<Self> {
+        let mut rng = create_rng(seed);
+        let hashers = (0..n_trees)
+            .map(|_| L2::new(dim, r, max_depth, rng.gen()))
+            .collect();
+        LshForest::new(hashers, dim)
+    }
+}
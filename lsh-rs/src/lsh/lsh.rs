@@ -1,17 +1,37 @@
-use crate::data::Integer;
-use crate::table::general::Bucket;
+use super::dedup::QuerySession;
+use super::observer::{QueryEvent, QueryObserver, TableEvent};
+#[cfg(feature = "serde")]
+use super::wal;
+use super::wal::{Record, WalWriter};
+use crate::config::{FromHashFamily, LshConfig};
+use crate::constants::{
+    AUTO_INDEX_THRESHOLD, DESCRIBE_MAX, EXTEND_CHUNK_SIZE, HASH_FLAVOR, STORE_PAR_CHUNK_SIZE,
+};
+#[cfg(feature = "serde")]
+use crate::constants::{DUMP_MAGIC, HASHERS_VERSION, PORTABLE_FORMAT, PORTABLE_VERSION};
+use crate::data::{Idx, Integer, MaybeDeserialize, MaybeSerde};
+use crate::multi_probe::{resolve_probe_budget, validate_multi_probe};
+use crate::stats::{write_stats, BucketStats, HashTableStats, StatsFormat, TableStats};
+use crate::table::general::{Bucket, Payload};
+#[cfg(feature = "sqlite")]
+use crate::table::sqlite::read_meta;
 use crate::{data::Numeric, prelude::*, utils::create_rng};
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use ndarray::prelude::*;
-use num::Float;
+use num::{Bounded, Float, PrimInt, ToPrimitive, Zero};
+use rand::seq::SliceRandom;
 use rand::Rng;
 use rayon::prelude::*;
-use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::marker::PhantomData;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 /// Wrapper for LSH functionality.
 /// Can be initialized following the Builder pattern.
@@ -35,6 +55,18 @@ use std::path::Path;
 /// * [set_database_file](struct.LSH.html#method.set_database_file)
 /// * [multi_probe](struct.LSH.html#method.multi_probe)
 /// * [increase_storage](struct.LSH.html#method.increase_storage)
+/// * [flat_storage](struct.LSH.html#method.flat_storage)
+/// * [packed_keys](struct.LSH.html#method.packed_keys)
+/// * [auto_index](struct.LSH.html#method.auto_index)
+/// * [min_bucket_size](struct.LSH.html#method.min_bucket_size)
+/// * [max_bucket_size](struct.LSH.html#method.max_bucket_size)
+/// * [boundary_eps](struct.LSH.html#method.boundary_eps)
+/// * [projections_per_table](struct.LSH.html#method.projections_per_table)
+/// * [shared_projections](struct.LSH.html#method.shared_projections)
+/// * [projection_density](struct.LSH.html#method.projection_density)
+/// * [read_only](struct.LSH.html#method.read_only)
+/// * [query_observer](struct.LSH.html#method.query_observer)
+#[derive(Clone)]
 pub struct LSH<H, N, T, K = i8>
 where
     N: Numeric,          // data type
@@ -60,28 +92,150 @@ where
     /// multi probe budget
     pub(crate) _multi_probe_budget: usize,
     _db_path: String,
+    /// store data points in a flat aligned layout.
+    _flat_storage: bool,
+    /// pack the hashes of the bucket keys.
+    _packed_keys: bool,
+    /// row count threshold for automatic hash indexing.
+    _auto_index: Option<u32>,
+    /// buckets smaller than this are skipped during queries.
+    _min_bucket_size: usize,
+    /// buckets larger than this are skipped during queries.
+    _max_bucket_size: Option<usize>,
+    /// number of buckets that were skipped because of `_max_bucket_size`.
+    skipped_buckets: SkipCounter,
+    /// query margin around L2 cell boundaries, relative to the bucket width.
+    _boundary_eps: f64,
+    /// hash length per hash table, overrides `n_projections`.
+    _projections_per_table: Option<Vec<usize>>,
+    /// rows of the projection matrix that is shared by the L2 hash tables.
+    _shared_projections: Option<usize>,
+    /// fraction of non-zero entries of sparse random projections.
+    _projection_density: Option<f32>,
+    /// open the existing database without writing to it.
+    _read_only: bool,
+    /// user provided ids of the data points.
+    user_ids: UserIds<T::Id>,
+    /// write-ahead log of the changes, see `enable_wal`.
+    wal: WalWriter,
+    /// receives the events of the queries.
+    _query_observer: Option<Arc<dyn QueryObserver>>,
     phantom: PhantomData<(N, K)>,
 }
 
+/// Attach the row of a batch to an error.
+pub(crate) fn batch_err(row: usize) -> impl Fn(Error) -> Error {
+    move |e| Error::Batch {
+        row,
+        source: Box::new(e),
+    }
+}
+
+/// All `(offset, step)` pairs that select `k` evenly spaced rows of a matrix of `n_rows` rows.
+fn pool_row_ranges(n_rows: usize, k: usize) -> Vec<(usize, usize)> {
+    if k <= 1 {
+        return (0..n_rows * k).map(|offset| (offset, 1)).collect();
+    }
+    (1..=(n_rows.saturating_sub(1)) / (k - 1))
+        .flat_map(|step| (0..=n_rows - step * (k - 1) - 1).map(move |offset| (offset, step)))
+        .collect()
+}
+
+/// Borrow the elements of a view. Only views that aren't contiguous are copied.
+fn view_slice<'a, N: Clone>(v: &'a ArrayView1<N>) -> Cow<'a, [N]> {
+    match v.as_slice() {
+        Some(s) => Cow::Borrowed(s),
+        None => Cow::Owned(v.to_vec()),
+    }
+}
+
+/// Parse a `table_idx,hash,id` line of a hash import. The hash is hex encoded.
+fn parse_hash_line<K: Integer, I: Idx>(
+    line: &str,
+    projections: &[usize],
+) -> Result<(usize, Vec<K>, I)> {
+    let fail = |msg: &str| Error::Failed(format!("{}: {:?}", msg, line));
+    let mut fields = line.split(',').map(str::trim);
+    let (table_idx, hash, idx) = match (fields.next(), fields.next(), fields.next(), fields.next())
+    {
+        (Some(t), Some(h), Some(i), None) => (t, h, i),
+        _ => return Err(fail("expected 3 fields")),
+    };
+    let table_idx: usize = table_idx.parse().map_err(|_| fail("invalid table_idx"))?;
+    let n_projections = match projections.get(table_idx) {
+        Some(&k) => k,
+        None => return Err(Error::TableNotExist),
+    };
+    let idx = idx
+        .parse()
+        .ok()
+        .and_then(I::from_u64)
+        .ok_or_else(|| fail("invalid id"))?;
+
+    // the hex digits are sliced per byte.
+    if !hash.is_ascii() {
+        return Err(fail("invalid hex in hash"));
+    }
+    let size = std::mem::size_of::<K>();
+    if hash.len() != 2 * size * n_projections {
+        return Err(fail(&format!(
+            "expected a hash of {} bytes ({} projections of {} bytes)",
+            size * n_projections,
+            n_projections,
+            size
+        )));
+    }
+    let bytes = (0..hash.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hash[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| fail("invalid hex in hash"))?;
+    let hash = bytes
+        .chunks_exact(size)
+        .map(|c| unsafe { std::ptr::read_unaligned(c.as_ptr() as *const K) })
+        .collect();
+    Ok((table_idx, hash, idx))
+}
+
+/// Sort neighbors by ascending distance, ties by id.
+fn sort_by_distance<I: Ord>(neighbors: &mut [(I, f64)]) {
+    neighbors.sort_unstable_by(|a, b| {
+        a.1.partial_cmp(&b.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.0.cmp(&b.0))
+    });
+}
+
 /// Create a new LSH instance. Used in the builder pattern
-fn lsh_from_lsh<
-    N: Numeric,
-    T: HashTables<N, K>,
-    H: VecHash<N, K> + Serialize + DeserializeOwned,
-    K: Integer,
->(
+fn lsh_from_lsh<N: Numeric, T: HashTables<N, K>, H: VecHash<N, K> + MaybeSerde, K: Integer>(
     lsh: &mut LSH<H, N, T, K>,
     hashers: Vec<H>,
 ) -> Result<LSH<H, N, T, K>> {
+    if let Some(ks) = &lsh._projections_per_table {
+        if ks.len() != lsh.n_hash_tables || ks.contains(&0) {
+            return Err(Error::Failed(format!(
+                "expected {} non zero hash lengths, one per hash table, got {:?}",
+                lsh.n_hash_tables, ks
+            )));
+        }
+    }
+    if lsh._read_only {
+        return lsh_read_only(lsh, None);
+    }
+    validate_hashers(lsh, &hashers)?;
     let mut ht = *T::new(lsh.n_hash_tables, lsh.only_index_storage, &lsh._db_path)?;
+    if lsh._flat_storage {
+        ht.flat_storage(lsh.dim)?;
+    }
+    if lsh._packed_keys {
+        ht.packed_keys()?;
+    }
+    ht.auto_index(lsh._auto_index);
 
     // Load hashers if store hashers fails. (i.e. exists)
     let hashers = match ht.store_hashers(&hashers) {
         Ok(_) => hashers,
-        Err(_) => match ht.load_hashers() {
-            Err(e) => panic!("could not load hashers: {}", e),
-            Ok(hashers) => hashers,
-        },
+        Err(_) => ht.load_hashers()?,
     };
     let lsh = LSH {
         n_hash_tables: lsh.n_hash_tables,
@@ -94,24 +248,200 @@ fn lsh_from_lsh<
         _multi_probe: lsh._multi_probe,
         _multi_probe_budget: lsh._multi_probe_budget,
         _db_path: lsh._db_path.clone(),
+        _flat_storage: lsh._flat_storage,
+        _packed_keys: lsh._packed_keys,
+        _auto_index: lsh._auto_index,
+        _min_bucket_size: lsh._min_bucket_size,
+        _max_bucket_size: lsh._max_bucket_size,
+        skipped_buckets: SkipCounter::default(),
+        _boundary_eps: lsh._boundary_eps,
+        _projections_per_table: lsh._projections_per_table.clone(),
+        _shared_projections: lsh._shared_projections,
+        _projection_density: lsh._projection_density,
+        _read_only: false,
+        user_ids: UserIds::default(),
+        wal: WalWriter::default(),
+        _query_observer: lsh._query_observer.clone(),
         phantom: PhantomData,
     };
     Ok(lsh)
 }
 
+/// Open the database of the builder `lsh` in read-only mode, with `hashers` or else the
+/// hashers of the database. Used by the [read_only](struct.LSH.html#method.read_only) builder
+/// and by `open_readonly`.
+fn lsh_read_only<N: Numeric, T: HashTables<N, K>, H: VecHash<N, K> + MaybeSerde, K: Integer>(
+    lsh: &LSH<H, N, T, K>,
+    hashers: Option<Vec<H>>,
+) -> Result<LSH<H, N, T, K>> {
+    let ht = *T::open_read_only(lsh.n_hash_tables, &lsh._db_path)?;
+    lsh_with_read_only_table(lsh, ht, hashers)
+}
+
+/// Same as `lsh_read_only`, with the already opened read-only table `ht`.
+fn lsh_with_read_only_table<
+    N: Numeric,
+    T: HashTables<N, K>,
+    H: VecHash<N, K> + MaybeSerde,
+    K: Integer,
+>(
+    lsh: &LSH<H, N, T, K>,
+    ht: T,
+    hashers: Option<Vec<H>>,
+) -> Result<LSH<H, N, T, K>> {
+    let hashers: Vec<H> = match hashers {
+        Some(hashers) => hashers,
+        None => ht.load_hashers()?,
+    };
+    if hashers.len() != lsh.n_hash_tables {
+        return Err(Error::Failed(format!(
+            "database holds {} hashers, expected {}",
+            hashers.len(),
+            lsh.n_hash_tables
+        )));
+    }
+    validate_hashers(lsh, &hashers)?;
+    Ok(LSH {
+        n_hash_tables: lsh.n_hash_tables,
+        n_projections: lsh.n_projections,
+        hashers,
+        dim: lsh.dim,
+        hash_tables: Some(ht),
+        _seed: lsh._seed,
+        // a read-only database holds no data points.
+        only_index_storage: true,
+        _multi_probe: lsh._multi_probe,
+        _multi_probe_budget: lsh._multi_probe_budget,
+        _db_path: lsh._db_path.clone(),
+        _flat_storage: false,
+        _packed_keys: false,
+        _auto_index: None,
+        _min_bucket_size: lsh._min_bucket_size,
+        _max_bucket_size: lsh._max_bucket_size,
+        skipped_buckets: SkipCounter::default(),
+        _boundary_eps: lsh._boundary_eps,
+        _projections_per_table: lsh._projections_per_table.clone(),
+        _shared_projections: lsh._shared_projections,
+        _projection_density: lsh._projection_density,
+        _read_only: true,
+        user_ids: UserIds::default(),
+        wal: WalWriter::default(),
+        _query_observer: lsh._query_observer.clone(),
+        phantom: PhantomData,
+    })
+}
+
+/// Check the builder options that depend on the hashers.
+fn validate_hashers<N: Numeric, T: HashTables<N, K>, H: VecHash<N, K>, K: Integer>(
+    lsh: &LSH<H, N, T, K>,
+    hashers: &[H],
+) -> Result<()> {
+    if lsh._multi_probe {
+        let budget = lsh.resolve_probe_budget(hashers);
+        for (i, hasher) in hashers.iter().enumerate() {
+            validate_multi_probe(hasher, lsh.table_projections(i), budget)?;
+        }
+    }
+    Ok(())
+}
+
 impl<N, T> LSH<SignRandomProjections<N>, N, T, i8>
 where
-    N: Numeric + DeserializeOwned,
+    N: Numeric + MaybeDeserialize,
     T: HashTables<N, i8>,
 {
     /// Create a new SignRandomProjections LSH
     pub fn srp(&mut self) -> Result<Self> {
+        self.srp_with_sign(SignConvention::default())
+    }
+
+    /// Create a new SignRandomProjections LSH with hash bits following `sign`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sign` - Hash bits are 0/1 ([Binary](enum.SignConvention.html#variant.Binary)) or -1/1
+    ///   ([Bipolar](enum.SignConvention.html#variant.Bipolar)).
+    pub fn srp_with_sign(&mut self, sign: SignConvention) -> Result<Self> {
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+
+        for i in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let k = self.table_projections(i);
+            let hasher = match self._projection_density {
+                Some(density) => SignRandomProjections::new_sparse(k, self.dim, density, seed)?,
+                None => SignRandomProjections::new(k, self.dim, seed),
+            };
+            hashers.push(hasher.with_sign_convention(sign));
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<N, T> LSH<PackedSignRandomProjections<N>, N, T, u64>
+where
+    N: Numeric + MaybeDeserialize,
+    T: HashTables<N, u64>,
+{
+    /// Create a new SignRandomProjections LSH with the hash bits packed in `u64` words.
+    /// See [PackedSignRandomProjections](struct.PackedSignRandomProjections.html).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = hi_packed::LshMem::new(96, 10, 3).seed(1).srp_packed().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![0]);
+    /// ```
+    pub fn srp_packed(&mut self) -> Result<Self> {
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+
+        for i in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let k = self.table_projections(i);
+            let hasher = match self._projection_density {
+                Some(density) => {
+                    PackedSignRandomProjections::new_sparse(k, self.dim, density, seed)?
+                }
+                None => PackedSignRandomProjections::new(k, self.dim, seed),
+            };
+            hashers.push(hasher);
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<T> LSH<QuantizedSignRandomProjections, i8, T, i8>
+where
+    T: HashTables<i8, i8>,
+{
+    /// Create a new SignRandomProjections LSH for int8 quantized data points.
+    /// See [QuantizedSignRandomProjections](struct.QuantizedSignRandomProjections.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `scale` - Quantization scale. One scale for all dimensions, or one per dimension.
+    pub fn srp_quantized(&mut self, scale: &[f32]) -> Result<Self> {
+        self.srp_quantized_with_sign(scale, SignConvention::default())
+    }
+
+    /// Create a new SignRandomProjections LSH for int8 quantized data points, with hash bits
+    /// following `sign`.
+    pub fn srp_quantized_with_sign(&mut self, scale: &[f32], sign: SignConvention) -> Result<Self> {
         let mut rng = create_rng(self._seed);
         let mut hashers = Vec::with_capacity(self.n_hash_tables);
 
-        for _ in 0..self.n_hash_tables {
+        for i in 0..self.n_hash_tables {
             let seed = rng.gen();
-            let hasher = SignRandomProjections::new(self.n_projections, self.dim, seed);
+            let hasher = QuantizedSignRandomProjections::new(
+                self.table_projections(i),
+                self.dim,
+                scale,
+                seed,
+            )?
+            .with_sign_convention(sign);
             hashers.push(hasher);
         }
         lsh_from_lsh(self, hashers)
@@ -120,8 +450,8 @@ where
 
 impl<N, T, K> LSH<L2<N, K>, N, T, K>
 where
-    N: Numeric + Float + DeserializeOwned,
-    K: Integer + DeserializeOwned,
+    N: Numeric + Float + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
     T: HashTables<N, K>,
 {
     /// Create a new L2 LSH
@@ -136,11 +466,75 @@ where
     ///
     /// * `r` - Parameter of hash function.
     pub fn l2(&mut self, r: f32) -> Result<Self> {
+        if self._shared_projections.is_some() && self._projection_density.is_some() {
+            return Err(Error::Failed(
+                "shared_projections and projection_density can't be combined".to_string(),
+            ));
+        }
+        let mut rng = create_rng(self._seed);
+        let pool = self
+            ._shared_projections
+            .map(|n_rows| L2::<N, K>::projection_pool(n_rows, self.dim, rng.gen()));
+        // unused row ranges of the shared matrix by hash length, in random order.
+        let mut ranges: FnvHashMap<usize, Vec<(usize, usize)>> = FnvHashMap::default();
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for i in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let k = self.table_projections(i);
+            let hasher = match &pool {
+                Some(pool) => {
+                    let (offset, step) = ranges
+                        .entry(k)
+                        .or_insert_with(|| {
+                            let mut ranges = pool_row_ranges(pool.nrows(), k);
+                            ranges.shuffle(&mut rng);
+                            ranges
+                        })
+                        .pop()
+                        .ok_or_else(|| {
+                            Error::Failed(format!(
+                                "a shared projection matrix of {} rows has too few distinct \
+                                 row ranges for {} hash tables of {} projections",
+                                pool.nrows(),
+                                self.n_hash_tables,
+                                k
+                            ))
+                        })?;
+                    L2::from_shared(pool, offset, step, r, k, seed)?
+                }
+                None => match self._projection_density {
+                    Some(density) => L2::new_sparse(self.dim, r, k, density, seed)?,
+                    None => L2::new(self.dim, r, k, seed),
+                },
+            };
+            hashers.push(hasher);
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<N, T, K> LSH<L1<N, K>, N, T, K>
+where
+    N: Numeric + Float + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+    T: HashTables<N, K>,
+{
+    /// Create a new L1 LSH, for the Manhattan distance.
+    ///
+    /// Same hash function as [l2](#method.l2), with Cauchy instead of Gaussian distributed
+    /// projections `a`.
+    ///
+    /// h(v) = floor(a^Tv + b / r)
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Bucket width of the hash function.
+    pub fn l1(&mut self, r: f32) -> Result<Self> {
         let mut rng = create_rng(self._seed);
         let mut hashers = Vec::with_capacity(self.n_hash_tables);
-        for _ in 0..self.n_hash_tables {
+        for i in 0..self.n_hash_tables {
             let seed = rng.gen();
-            let hasher = L2::new(self.dim, r, self.n_projections, seed);
+            let hasher = L1::new(self.dim, r, self.table_projections(i), seed);
             hashers.push(hasher);
         }
         lsh_from_lsh(self, hashers)
@@ -149,8 +543,8 @@ where
 
 impl<N, T, K> LSH<MIPS<N, K>, N, T, K>
 where
-    N: Numeric + Float + DeserializeOwned,
-    K: Integer + DeserializeOwned,
+    N: Numeric + Float + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
     T: HashTables<N, K>,
 {
     /// Create a new MIPS LSH
@@ -169,34 +563,78 @@ where
         let mut rng = create_rng(self._seed);
         let mut hashers = Vec::with_capacity(self.n_hash_tables);
 
-        for _ in 0..self.n_hash_tables {
+        for i in 0..self.n_hash_tables {
             let seed = rng.gen();
-            let hasher = MIPS::new(self.dim, r, U, m, self.n_projections, seed);
+            let hasher = MIPS::new(self.dim, r, U, m, self.table_projections(i), seed);
             hashers.push(hasher);
         }
         lsh_from_lsh(self, hashers)
     }
-
-    /// Fit M parameter of the MIPS hasher. This needs to be done before the hasher can be used.
-    pub fn fit(&mut self, vs: &[Vec<N>]) -> Result<()> {
-        self.hashers.iter_mut().for_each(|h| h.fit(vs));
-        Ok(())
-    }
 }
 
 impl<N, T, K> LSH<MinHash<N, K>, N, T, K>
 where
-    N: Integer + DeserializeOwned,
-    K: Integer + DeserializeOwned,
+    N: Integer + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
     T: HashTables<N, K>,
 {
     pub fn minhash(&mut self) -> Result<Self> {
         let mut rng = create_rng(self._seed);
         let mut hashers = Vec::with_capacity(self.n_hash_tables);
 
-        for _ in 0..self.n_hash_tables {
+        for i in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let hasher = MinHash::try_new(self.table_projections(i), self.dim, seed)?;
+            hashers.push(hasher);
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<N, T, K> LSH<BMinHash<N, K>, N, T, K>
+where
+    N: Integer + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+    T: HashTables<N, K>,
+{
+    /// Create a new b-bit MinHash LSH, that keeps the lowest `b` bits of every MinHash value.
+    /// See [BMinHash](struct.BMinHash.html).
+    pub fn b_minhash(&mut self, b: u32) -> Result<Self> {
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+
+        for i in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let hasher = BMinHash::try_new(self.table_projections(i), self.dim, b, seed)?;
+            hashers.push(hasher);
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<N, T, K> LSH<BitSampling<N, K>, N, T, K>
+where
+    N: Integer + PrimInt + MaybeDeserialize,
+    K: Integer + MaybeDeserialize,
+    T: HashTables<N, K>,
+{
+    /// Create a new bit sampling LSH for the Hamming distance between binary vectors, of which
+    /// the bits are packed in the values. See [BitSampling](struct.BitSampling.html).
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::<_, u64>::new(16, 4, 1).seed(1).bit_sampling().unwrap();
+    /// lsh.store_vec(&[0b1011_0110]).unwrap();
+    /// assert_eq!(lsh.query_top_k(&[0b1011_0111], 1).unwrap(), vec![(0, 1.)]);
+    /// ```
+    pub fn bit_sampling(&mut self) -> Result<Self> {
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+
+        for i in 0..self.n_hash_tables {
             let seed = rng.gen();
-            let hasher = MinHash::new(self.n_projections, self.dim, seed);
+            let hasher = BitSampling::try_new(self.table_projections(i), self.dim, seed)?;
             hashers.push(hasher);
         }
         lsh_from_lsh(self, hashers)
@@ -206,127 +644,653 @@ where
 impl<H, N, T, K> LSH<H, N, T, K>
 where
     N: Numeric,
-    H: VecHash<N, K> + Sync,
-    T: HashTables<N, K> + Sync,
+    H: VecHash<N, K> + SetHash<K>,
+    T: HashTables<N, K>,
     K: Integer,
 {
-    /// Query bucket collision for a batch of data points in parallel.
+    /// Store a set, given by the indexes of its present shingles. The set is hashed directly,
+    /// which is much faster than hashing the dense binary vector with
+    /// [store_vec](#method.store_vec) when only few shingles are present, e.g. for text
+    /// deduplication with [MinHash](struct.MinHash.html). The binary vector is the stored data
+    /// point.
     ///
     /// # Arguments
-    /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch_par(&self, vs: &[Vec<N>]) -> Result<Vec<Vec<u32>>> {
-        vs.into_par_iter()
-            .map(|v| self.query_bucket_ids(v))
-            .collect()
+    /// * `set` - Shingle indexes, smaller than the dimension.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::<_, u16, i32>::new(5, 10, 1000).seed(1).minhash().unwrap();
+    /// let id = lsh.store_set(&[3, 42, 998]).unwrap();
+    /// assert!(lsh.query_set(&[3, 42, 998]).unwrap().contains(&id));
+    /// ```
+    pub fn store_set(&mut self, set: &[usize]) -> Result<T::Id> {
+        let v = self.set_to_vec(set)?;
+        let hashes = self.hashers.iter().map(|h| h.hash_set(set)).collect();
+        self.put_hashes(hashes, &v)
     }
 
-    /// Query bucket collision for a batch of data points in parallel.
+    /// Query the bucket ids of a set, given by the indexes of its present shingles. See
+    /// [store_set](#method.store_set).
     ///
     /// # Arguments
-    /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch_arr_par(&self, vs: ArrayView2<N>) -> Result<Vec<Vec<u32>>> {
-        vs.axis_iter(Axis(0))
-            .into_par_iter()
-            .map(|v| self.query_bucket_ids(v.as_slice().unwrap()))
-            .collect()
+    /// * `set` - Shingle indexes, smaller than the dimension.
+    pub fn query_set(&self, set: &[usize]) -> Result<Vec<T::Id>> {
+        self.validate_set(set)?;
+        let bucket_union = if self._multi_probe {
+            // the probes are derived from the dense binary vector.
+            let v = self.set_to_vec(set)?;
+            self.bucket_union(|f| self.for_each_probe_hashes(&v, f))?
+        } else {
+            self.bucket_union(|f| {
+                self.hashers
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, proj)| f(i, &[proj.hash_set(set)]))
+            })?
+        };
+        Ok(bucket_union.into_iter().collect())
+    }
+
+    fn validate_set(&self, set: &[usize]) -> Result<()> {
+        match set.iter().find(|&&i| i >= self.dim) {
+            Some(i) => Err(Error::Failed(format!(
+                "shingle index {} is out of bounds for dimension {}",
+                i, self.dim
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The binary vector with ones at the indexes in `set`.
+    fn set_to_vec(&self, set: &[usize]) -> Result<Vec<N>> {
+        self.validate_set(set)?;
+        let mut v = vec![N::zero(); self.dim];
+        for &i in set {
+            v[i] = N::one();
+        }
+        Ok(v)
     }
 }
 
 impl<H, N, T, K> LSH<H, N, T, K>
 where
-    H: VecHash<N, K>,
-    N: Numeric + Sync,
+    N: Numeric,
+    H: VecHash<N, K> + MaybeSerde,
     T: HashTables<N, K>,
     K: Integer,
 {
-    /// Store multiple vectors in storage. Before storing the storage capacity is possibly
-    /// increased to match the data points.
+    /// Create a new LSH from existing hashers, e.g. with learned projections or a custom
+    /// [VecHash](trait.VecHash.html) implementation. One hasher is needed per hash table.
+    /// Asymmetric hashers can hash stored data points and queries differently by overriding
+    /// `hash_vec_put` and `hash_vec_query`. Multi-probing requires the hasher to return a
+    /// probing implementation from `as_query_directed_probe` or `as_step_wise_probe`.
+    ///
+    /// Note that if the backend already holds hashers (i.e. an existing SQLite database),
+    /// those are loaded instead.
     ///
     /// # Arguments
-    /// * `vs` - Array of data points.
+    /// * `hashers` - Hashers for hash tables `0..L`.
     ///
     /// # Examples
-    ///```
+    /// ```
     /// use lsh_rs::prelude::*;
-    /// let mut lsh = LshSql::new(5, 10, 3).srp().unwrap();
-    /// let vs = &[vec![2., 3., 4.],
-    ///            vec![-1., -1., 1.]];
-    /// let ids = lsh.store_vecs(vs);
+    /// let hashers = (0..10)
+    ///     .map(|seed| SignRandomProjections::new(5, 3, seed + 1))
+    ///     .collect();
+    /// let mut lsh = LshMem::<_, f32>::new(5, 10, 3).with_hashers(hashers).unwrap();
     /// ```
-    pub fn store_vecs(&mut self, vs: &[Vec<N>]) -> Result<Vec<u32>> {
-        self.validate_vec(&vs[0])?;
-        self.hash_tables
-            .as_mut()
-            .unwrap()
-            .increase_storage(vs.len());
-
-        let mut ht = self.hash_tables.take().unwrap();
-        let mut insert_idx = Vec::with_capacity(vs.len());
-        for (i, proj) in self.hashers.iter().enumerate() {
-            for v in vs.iter() {
-                let hash = proj.hash_vec_put(v);
-                match (ht.put(hash, v, i), i) {
-                    // only for the first hash table save the index as it will be the same for all
-                    (Ok(idx), 0) => insert_idx.push(idx),
-                    (Err(e), _) => return Err(e),
-                    _ => {}
-                }
-            }
+    pub fn with_hashers(&mut self, hashers: Vec<H>) -> Result<Self> {
+        if hashers.len() != self.n_hash_tables {
+            return Err(Error::Failed(format!(
+                "expected {} hashers, one per hash table, got {}",
+                self.n_hash_tables,
+                hashers.len()
+            )));
         }
-        self.hash_tables.replace(ht);
-        Ok(insert_idx)
+        lsh_from_lsh(self, hashers)
     }
 
-    /// Store a 2D array in storage. Before storing the storage capacity is possibly
-    /// increased to match the data points.
+    /// Write the hashers to `path`, without the hash tables and data points. Together with the
+    /// database, the file is enough to start a query-only replica, see
+    /// [from_hashers](#method.from_hashers) and
+    /// [open_readonly_with_hashers](#method.open_readonly_with_hashers).
     ///
     /// # Arguments
-    /// * `vs` - Array of data points.
+    /// * `path` - Path of the hasher file.
     ///
     /// # Examples
-    ///```
+    /// ```
     /// use lsh_rs::prelude::*;
-    /// use ndarray::prelude::*;
-    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
-    /// let vs = array![[1., 2., 3.], [4., 5., 6.]];
-    /// let ids = lsh.store_array(vs.view());
+    /// let lsh = LshMem::<_, f32>::new(5, 10, 3).srp().unwrap();
+    /// let path = std::env::temp_dir().join("lsh_hashers.bincode");
+    /// lsh.export_hashers(&path).unwrap();
+    /// let replica = LshMem::<SignRandomProjections<f32>>::new(5, 10, 3)
+    ///     .from_hashers(&path)
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     replica.hashers[0].hash_vec_query(&[2., 3., 4.]),
+    ///     lsh.hashers[0].hash_vec_query(&[2., 3., 4.])
+    /// );
     /// ```
-    pub fn store_array(&mut self, vs: ArrayView2<N>) -> Result<Vec<u32>> {
-        self.validate_vec(vs.slice(s![0, ..]).as_slice().unwrap())?;
-        self.hash_tables
-            .as_mut()
-            .unwrap()
-            .increase_storage(vs.len());
+    #[cfg(feature = "serde")]
+    pub fn export_hashers<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let hashers = bincode::serialize(&self.hashers)?;
+        let hb = HasherBlob {
+            hashers: &hashers,
+            n_hash_tables: self.n_hash_tables,
+            n_projections: self.n_projections,
+            dim: self.dim,
+            _seed: self._seed,
+            hash_flavor: HASH_FLAVOR,
+            hashers_version: HASHERS_VERSION,
+        };
+        let blob = frame(&bincode::serialize(&hb)?);
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&blob)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
 
-        let mut ht = self.hash_tables.take().unwrap();
-        let mut insert_idx = Vec::with_capacity(vs.len());
-        for (i, proj) in self.hashers.iter().enumerate() {
-            for v in vs.axis_iter(Axis(0)) {
-                let hash = proj.hash_vec_put(v.as_slice().unwrap());
-                match (ht.put(hash, v.as_slice().unwrap(), i), i) {
-                    // only for the first hash table save the index as it will be the same for all
-                    (Ok(idx), 0) => insert_idx.push(idx),
-                    (Err(e), _) => return Err(e),
-                    _ => {}
-                }
-            }
+    /// Create a new LSH with the hashers of a file written by
+    /// [export_hashers](#method.export_hashers), instead of generating them. This is a terminal
+    /// builder method like [with_hashers](#method.with_hashers); `n_projections`,
+    /// `n_hash_tables` and `dim` should match the exported index.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the hasher file.
+    #[cfg(feature = "serde")]
+    pub fn from_hashers<P: AsRef<Path>>(&mut self, path: P) -> Result<Self> {
+        let hashers = self.read_hashers(path.as_ref())?;
+        self.with_hashers(hashers)
+    }
+
+    /// Read the hashers of a hasher file and take over its seed.
+    #[cfg(feature = "serde")]
+    fn read_hashers(&mut self, path: &Path) -> Result<Vec<H>> {
+        let mut buf: Vec<u8> = vec![];
+        File::open(path)?.read_to_end(&mut buf)?;
+        let hb: HasherBlob = bincode::deserialize(unframe(&buf)?)?;
+        check_hash_flavor(Some(hb.hash_flavor))?;
+        check_hashers_version(hb.hashers_version)?;
+        if (hb.n_hash_tables, hb.n_projections, hb.dim)
+            != (self.n_hash_tables, self.n_projections, self.dim)
+        {
+            return Err(Error::Failed(format!(
+                "hasher file doesn't match the index, it has {} hash tables, {} projections \
+                 and dimension {}",
+                hb.n_hash_tables, hb.n_projections, hb.dim
+            )));
         }
-        self.hash_tables.replace(ht);
-        Ok(insert_idx)
+        self._seed = hb._seed;
+        Ok(bincode::deserialize(hb.hashers)?)
     }
 }
 
 impl<H, N, T, K> LSH<H, N, T, K>
 where
     N: Numeric,
-    H: VecHash<N, K>,
+    H: FromHashFamily<N, K>,
     T: HashTables<N, K>,
     K: Integer,
 {
-    /// Create a new Base LSH
+    /// Create a new LSH from a [configuration](config/struct.LshConfig.html), e.g. one that was
+    /// read from a file. The hasher type has to match the hash family of the configuration.
+    pub fn from_config(cfg: &LshConfig) -> Result<Self> {
+        let mut builder = LSH::builder_from_config(cfg);
+        H::build(&mut builder, &cfg.hasher)
+    }
+
+    /// The [configuration](config/struct.LshConfig.html) of this index. Build the same index
+    /// again with [from_config](#method.from_config), given that a seed is set.
+    pub fn config(&self) -> LshConfig {
+        LshConfig {
+            hasher: self.hashers[0].family(),
+            n_projections: self.n_projections,
+            n_hash_tables: self.n_hash_tables,
+            dim: self.dim,
+            seed: self._seed,
+            only_index: self.only_index_storage,
+            multi_probe: self._multi_probe,
+            multi_probe_budget: self._multi_probe_budget,
+            flat_storage: self._flat_storage,
+            packed_keys: self._packed_keys,
+            min_bucket_size: self._min_bucket_size,
+            max_bucket_size: self._max_bucket_size,
+            boundary_eps: self._boundary_eps,
+            db_path: self._db_path.clone(),
+            auto_index: self._auto_index,
+            projections_per_table: self._projections_per_table.clone(),
+            shared_projections: self._shared_projections,
+            projection_density: self._projection_density,
+            read_only: self._read_only,
+        }
+    }
+}
+
+impl<H, N, T, K> LSH<H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K> + Sync,
+    T: HashTables<N, K> + Sync,
+    K: Integer,
+{
+    /// Query bucket collision for a batch of data points in parallel.
     ///
     /// # Arguments
-    ///
+    /// * `vs` - Array of data points.
+    pub fn query_bucket_ids_batch_par(&self, vs: &[Vec<N>]) -> Result<Vec<Vec<T::Id>>> {
+        self.validate_batch(vs)?;
+        vs.into_par_iter()
+            .enumerate()
+            .map(|(row, v)| self.query_bucket_ids(v).map_err(batch_err(row)))
+            .collect()
+    }
+
+    /// Query bucket collision for a batch of data points in parallel.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points, one per row.
+    pub fn query_bucket_ids_batch_arr_par(&self, vs: ArrayView2<N>) -> Result<Vec<Vec<T::Id>>> {
+        self.validate_shape(&vs)?;
+        vs.axis_iter(Axis(0))
+            .into_par_iter()
+            .enumerate()
+            .map(|(row, v)| {
+                self.query_bucket_ids(&view_slice(&v))
+                    .map_err(batch_err(row))
+            })
+            .collect()
+    }
+
+    /// Query a batch of data points and merge the candidates of all queries into a single
+    /// top `k`. Every candidate is returned once, with its value aggregated over the queries.
+    /// The queries run in parallel and only the aggregated candidates are kept, so the
+    /// candidates of the individual queries are never collected.
+    ///
+    /// Returns `(index, value)` sorted by descending count or score, or by ascending distance
+    /// for [BatchAggregate::Nearest](enum.BatchAggregate.html).
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    /// * `k` - Number of candidates.
+    /// * `aggregate` - How the candidates of the queries are merged.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::<_, f32, i32>::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    /// lsh.store_vecs(&[vec![1., 1., 1.], vec![-10., 20., 5.]]).unwrap();
+    /// let queries = [vec![1., 1., 1.1], vec![1., 1.1, 1.], vec![-10., 20., 5.]];
+    /// let top = lsh
+    ///     .query_batch_top_k(&queries, 1, &BatchAggregate::Frequency)
+    ///     .unwrap();
+    /// assert_eq!(top, vec![(0, 2.)]);
+    /// ```
+    pub fn query_batch_top_k(
+        &self,
+        vs: &[Vec<N>],
+        k: usize,
+        aggregate: &BatchAggregate,
+    ) -> Result<Vec<(T::Id, f64)>> {
+        self.validate_batch(vs)?;
+        let nearest = matches!(aggregate, BatchAggregate::Nearest);
+        let merge = move |acc: &mut FnvHashMap<T::Id, f64>, idx: T::Id, value: f64| {
+            acc.entry(idx)
+                .and_modify(|v| *v = if nearest { v.min(value) } else { *v + value })
+                .or_insert(value);
+        };
+        let merged = vs
+            .into_par_iter()
+            .enumerate()
+            .try_fold(FnvHashMap::default, |mut acc, (row, v)| -> Result<_> {
+                let candidates: Vec<(T::Id, f64)> = match aggregate {
+                    BatchAggregate::Frequency => self
+                        .query_bucket_ids(v)
+                        .map(|ids| ids.into_iter().map(|idx| (idx, 1.)).collect()),
+                    BatchAggregate::Score(weight) => {
+                        self.query_bucket_ids_scored(v, weight).map(|scores| {
+                            scores
+                                .into_iter()
+                                .map(|(idx, score)| (idx, score as f64))
+                                .collect()
+                        })
+                    }
+                    BatchAggregate::Nearest => self.candidate_distances(v),
+                }
+                .map_err(batch_err(row))?;
+                for (idx, value) in candidates {
+                    merge(&mut acc, idx, value);
+                }
+                Ok(acc)
+            })
+            .try_reduce(FnvHashMap::default, |a, b| {
+                let (mut a, b) = if a.len() < b.len() { (b, a) } else { (a, b) };
+                for (idx, value) in b {
+                    merge(&mut a, idx, value);
+                }
+                Ok(a)
+            })?;
+
+        let mut top: Vec<(T::Id, f64)> = merged.into_iter().collect();
+        top.sort_unstable_by(|a, b| {
+            let ord = a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal);
+            let ord = if nearest { ord } else { ord.reverse() };
+            ord.then(a.0.cmp(&b.0))
+        });
+        top.truncate(k);
+        Ok(top)
+    }
+}
+
+impl<H, N, T, K> LSH<H, N, T, K>
+where
+    H: VecHash<N, K> + MaybeSerde,
+    N: Numeric + Sync,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    /// Fit the hashers to a sample of the data points, e.g. the maximum norm `M` of
+    /// [MIPS](struct.MIPS.html). Hashers that don't have to be fitted are left untouched, see
+    /// [VecHashFit](trait.VecHashFit.html). Backends that store the hashers, i.e. SQLite and
+    /// the memory mapped log, store the fitted hashers.
+    ///
+    /// Hashers have to be fitted before data points are stored, storing fails with
+    /// `Error::NotFitted` until then. The stored hashes depend on the fit, so an index can only
+    /// be fitted while it is empty.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::<_, f32, i32>::new(5, 10, 3).mips(4., 0.83, 3).unwrap();
+    /// let vs = vec![vec![2., 3., 4.], vec![-1., 0., 2.]];
+    /// lsh.fit(&vs).unwrap();
+    /// assert!(lsh.hashers[0].is_fitted());
+    /// lsh.store_vec(&vs[0]).unwrap();
+    /// ```
+    pub fn fit(&mut self, vs: &[Vec<N>]) -> Result<()> {
+        self.validate_batch(vs)?;
+        if self.hash_tables.as_ref().unwrap().n_entries()? > 0 {
+            return Err(Error::Failed(
+                "the hashers can only be fitted before data points are stored".to_string(),
+            ));
+        }
+        self.fit_hashers(vs)?;
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .update_hashers(&self.hashers)
+    }
+
+    /// Merge the data points of `other` into this index, e.g. indexes that were built on
+    /// separate machines with the same seed and parameters. Both indexes need identical
+    /// hashers, otherwise the buckets wouldn't match. The ids of `other` are shifted by the
+    /// returned offset, its user ids and payloads are kept.
+    ///
+    /// The merge isn't part of the write-ahead log, see [compact](#method.compact).
+    ///
+    /// # Arguments
+    /// * `other` - Index with the same hashers and backend.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut a = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    /// let mut b = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    /// a.store_vec(&[2., 3., 4.]).unwrap();
+    /// b.store_vec(&[-1., 0., 2.]).unwrap();
+    /// let offset = a.merge(b).unwrap();
+    /// assert_eq!(offset, 1);
+    /// assert_eq!(a.query_bucket_ids(&[-1., 0., 2.]).unwrap(), vec![1]);
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn merge(&mut self, other: Self) -> Result<T::Id> {
+        if self.dim != other.dim
+            || self.n_projections != other.n_projections
+            || self.n_hash_tables != other.n_hash_tables
+            || bincode::serialize(&self.hashers)? != bincode::serialize(&other.hashers)?
+        {
+            return Err(Error::Failed(
+                "cannot merge indexes with different hashers".to_string(),
+            ));
+        }
+        if self.only_index_storage != other.only_index_storage {
+            return Err(Error::Failed(
+                "cannot merge an index that stores its data points with an index only index"
+                    .to_string(),
+            ));
+        }
+        if let Some(&id) = other
+            .user_ids
+            .by_id
+            .keys()
+            .find(|id| self.user_ids.by_id.contains_key(id))
+        {
+            return Err(Error::DuplicateId(id));
+        }
+        let ht = self.hash_tables.as_mut().unwrap();
+        let other_ht = other.hash_tables.as_ref().unwrap();
+        let offset = ht.merge(other_ht)?;
+        for (&idx, &id) in &other.user_ids.by_idx {
+            self.user_ids
+                .insert(T::Id::from_index(idx.index() + offset.index()), id);
+        }
+        Ok(offset)
+    }
+}
+
+impl<H, N, T, K> LSH<H, N, T, K>
+where
+    H: VecHash<N, K>,
+    N: Numeric + Sync,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    /// Store multiple vectors in storage. Before storing the storage capacity is possibly
+    /// increased to match the data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    ///
+    /// # Examples
+    ///```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshSql::new(5, 10, 3).srp().unwrap();
+    /// let vs = &[vec![2., 3., 4.],
+    ///            vec![-1., -1., 1.]];
+    /// let ids = lsh.store_vecs(vs);
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(n = vs.len()))
+    )]
+    pub fn store_vecs(&mut self, vs: &[Vec<N>]) -> Result<Vec<T::Id>> {
+        // validate all rows up front, so that a batch isn't partially stored.
+        self.validate_put_batch(vs)?;
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .increase_storage(vs.len());
+
+        let mut insert_idx = Vec::with_capacity(vs.len());
+        // the backends assign a new index once a vector is stored in all hash tables, so the
+        // vectors have to be the outer loop.
+        for v in vs.iter() {
+            let hashes = self.hashers.iter().map(|h| h.hash_vec_put(v)).collect();
+            insert_idx.push(self.put_hashes(hashes, v)?);
+        }
+        Ok(insert_idx)
+    }
+
+    /// Store data points from an iterator, e.g. one that reads them from disk, without holding
+    /// all of them in memory. The iterator is consumed in chunks of `chunk_size` data points.
+    /// The SQLite backends commit every chunk in a single transaction.
+    ///
+    /// # Arguments
+    /// * `iter` - Data points.
+    /// * `chunk_size` - Number of data points that are stored at once.
+    ///
+    /// # Examples
+    ///```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let vs = (0..100).map(|i| vec![i as f32, 1., -1.]);
+    /// let ids = lsh.store_from_iter(vs, 32).unwrap();
+    /// assert_eq!(ids.len(), 100);
+    /// ```
+    pub fn store_from_iter<I>(&mut self, iter: I, chunk_size: usize) -> Result<Vec<T::Id>>
+    where
+        I: IntoIterator<Item = Vec<N>>,
+    {
+        if chunk_size == 0 {
+            return Err(Error::Failed(
+                "chunk_size should be larger than 0".to_string(),
+            ));
+        }
+        let mut iter = iter.into_iter();
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .increase_storage(iter.size_hint().0);
+
+        let mut insert_idx = vec![];
+        loop {
+            let chunk: Vec<Vec<N>> = iter.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            let offset = insert_idx.len();
+            let ids = self.store_vecs(&chunk).map_err(|e| match e {
+                Error::Batch { row, source } => Error::Batch {
+                    row: row + offset,
+                    source,
+                },
+                e => e,
+            })?;
+            insert_idx.extend(ids);
+            self.hash_tables.as_mut().unwrap().end_chunk()?;
+        }
+        Ok(insert_idx)
+    }
+
+    /// Store the data points of `iter` in a freshly built index, so an index can be built with
+    /// its data in one expression. An `LSH` can't be collected with `FromIterator`, as the
+    /// hashers need parameters. See [store_from_iter](#method.store_from_iter).
+    ///
+    /// # Arguments
+    /// * `iter` - Data points.
+    ///
+    /// # Examples
+    ///```
+    /// use lsh_rs::prelude::*;
+    /// let vs = (0..100).map(|i| vec![i as f32, 1., -1.]);
+    /// let lsh = LshMem::new(5, 10, 3).srp().unwrap().with_vecs(vs).unwrap();
+    /// // every data point is in all 10 hash tables.
+    /// assert_eq!(lsh.n_entries().unwrap(), 100 * 10);
+    /// ```
+    pub fn with_vecs<I>(mut self, iter: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = Vec<N>>,
+    {
+        self.store_from_iter(iter, EXTEND_CHUNK_SIZE)?;
+        Ok(self)
+    }
+
+    /// Same as [store_vecs](#method.store_vecs), but the hashes are computed in parallel.
+    /// Hashing dominates storing, so this is much faster for large batches. The data points are
+    /// inserted in order afterwards, so they get the same ids as with `store_vecs`.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    ///
+    /// # Examples
+    ///```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let vs = &[vec![2., 3., 4.],
+    ///            vec![-1., -1., 1.]];
+    /// let ids = lsh.store_vecs_par(vs).unwrap();
+    /// assert_eq!(ids, vec![0, 1]);
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(n = vs.len()))
+    )]
+    pub fn store_vecs_par(&mut self, vs: &[Vec<N>]) -> Result<Vec<T::Id>>
+    where
+        H: Sync,
+    {
+        self.validate_put_batch(vs)?;
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .increase_storage(vs.len());
+
+        let mut insert_idx = Vec::with_capacity(vs.len());
+        for chunk in vs.chunks(STORE_PAR_CHUNK_SIZE) {
+            let hashers = &self.hashers;
+            let hashes: Vec<Vec<Vec<K>>> = chunk
+                .par_iter()
+                .map(|v| hashers.iter().map(|proj| proj.hash_vec_put(v)).collect())
+                .collect();
+            for (v, hashes) in chunk.iter().zip(hashes) {
+                insert_idx.push(self.put_hashes(hashes, v)?);
+            }
+        }
+        Ok(insert_idx)
+    }
+
+    /// Store a 2D array in storage. Before storing the storage capacity is possibly
+    /// increased to match the data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points, one per row.
+    ///
+    /// # Examples
+    ///```
+    /// use lsh_rs::prelude::*;
+    /// use ndarray::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let vs = array![[1., 2., 3.], [4., 5., 6.]];
+    /// let ids = lsh.store_array(vs.view());
+    /// ```
+    pub fn store_array(&mut self, vs: ArrayView2<N>) -> Result<Vec<T::Id>> {
+        self.validate_shape(&vs)?;
+        if self.hashers.iter().any(|h| h.as_fit().is_some()) {
+            for (row, v) in vs.outer_iter().enumerate() {
+                self.validate_put(&view_slice(&v)).map_err(batch_err(row))?;
+            }
+        }
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .increase_storage(vs.nrows());
+
+        let mut insert_idx = Vec::with_capacity(vs.nrows());
+        for v in vs.axis_iter(Axis(0)) {
+            let v = view_slice(&v);
+            let hashes = self.hashers.iter().map(|h| h.hash_vec_put(&v)).collect();
+            insert_idx.push(self.put_hashes(hashes, &v)?);
+        }
+        Ok(insert_idx)
+    }
+}
+
+impl<H, N, T, K> LSH<H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    /// Create a new Base LSH
+    ///
+    /// # Arguments
+    ///
     /// * `n_projections` - Hash length. Every projections creates an hashed integer
     /// * `n_hash_tables` - Increases the chance of finding the closest but has a performance and space cost.
     /// * `dim` - Dimensions of the data points.
@@ -343,20 +1307,207 @@ where
             _multi_probe: false,
             _multi_probe_budget: 16,
             _db_path: "./lsh.db3".to_string(),
+            _flat_storage: false,
+            _packed_keys: false,
+            _auto_index: Some(AUTO_INDEX_THRESHOLD),
+            _min_bucket_size: 0,
+            _max_bucket_size: None,
+            skipped_buckets: SkipCounter::default(),
+            _boundary_eps: 0.,
+            _projections_per_table: None,
+            _shared_projections: None,
+            _projection_density: None,
+            _read_only: false,
+            user_ids: UserIds::default(),
+            wal: WalWriter::default(),
+            _query_observer: None,
             phantom: PhantomData,
         };
         lsh
     }
 
+    /// Hash length of hash table `hash_table`. This is `n_projections`, unless set per hash
+    /// table with [projections_per_table](#method.projections_per_table).
+    pub fn table_projections(&self, hash_table: usize) -> usize {
+        self._projections_per_table
+            .as_ref()
+            .and_then(|ks| ks.get(hash_table).copied())
+            .unwrap_or(self.n_projections)
+    }
+
+    /// Builder with the settings of this index, without hashers and hash tables, for hash
+    /// tables with hash length `n_projections`. The hash lengths per hash table are kept for the
+    /// same hash length. The user ids and the write-ahead log stay with the index.
+    fn builder_like(&self, n_projections: usize, n_hash_tables: usize) -> Self {
+        let projections_per_table = self
+            ._projections_per_table
+            .as_ref()
+            .filter(|_| n_projections == self.n_projections)
+            .map(|ks| {
+                let mut ks = ks.clone();
+                ks.resize(n_hash_tables, n_projections);
+                ks
+            });
+        LSH {
+            n_hash_tables,
+            n_projections,
+            hashers: Vec::with_capacity(0),
+            dim: self.dim,
+            hash_tables: None,
+            _seed: self._seed,
+            only_index_storage: self.only_index_storage,
+            _multi_probe: self._multi_probe,
+            _multi_probe_budget: self._multi_probe_budget,
+            _db_path: self._db_path.clone(),
+            _flat_storage: self._flat_storage,
+            _packed_keys: self._packed_keys,
+            _auto_index: self._auto_index,
+            _min_bucket_size: self._min_bucket_size,
+            _max_bucket_size: self._max_bucket_size,
+            skipped_buckets: SkipCounter::default(),
+            _boundary_eps: self._boundary_eps,
+            _projections_per_table: projections_per_table,
+            _shared_projections: self._shared_projections,
+            _projection_density: self._projection_density,
+            _read_only: self._read_only,
+            user_ids: UserIds::default(),
+            wal: WalWriter::default(),
+            _query_observer: self._query_observer.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Builder with the parameters of a configuration, without hashers and hash tables.
+    fn builder_from_config(cfg: &LshConfig) -> Self {
+        let mut builder = LSH::new(cfg.n_projections, cfg.n_hash_tables, cfg.dim);
+        builder._seed = cfg.seed;
+        builder.only_index_storage = cfg.only_index;
+        builder._multi_probe = cfg.multi_probe;
+        builder._multi_probe_budget = cfg.multi_probe_budget;
+        builder._flat_storage = cfg.flat_storage;
+        builder._packed_keys = cfg.packed_keys;
+        builder._min_bucket_size = cfg.min_bucket_size;
+        builder._max_bucket_size = cfg.max_bucket_size;
+        builder._boundary_eps = cfg.boundary_eps;
+        builder._db_path = cfg.db_path.clone();
+        builder._auto_index = cfg.auto_index;
+        builder._projections_per_table = cfg.projections_per_table.clone();
+        builder._shared_projections = cfg.shared_projections;
+        builder._projection_density = cfg.projection_density;
+        builder._read_only = cfg.read_only;
+        builder
+    }
+
+    pub(crate) fn is_only_index(&self) -> bool {
+        self.only_index_storage
+    }
+
+    pub(crate) fn multi_probe_budget(&self) -> Option<usize> {
+        if self._multi_probe {
+            Some(self.probe_budget())
+        } else {
+            None
+        }
+    }
+
+    /// Multi-probe budget per hash table. A budget of 0 resolves to the adaptive default.
+    pub(crate) fn probe_budget(&self) -> usize {
+        self.resolve_probe_budget(&self.hashers)
+    }
+
+    fn resolve_probe_budget(&self, hashers: &[H]) -> usize {
+        let k_min = (0..self.n_hash_tables)
+            .map(|i| self.table_projections(i))
+            .min()
+            .unwrap_or(self.n_projections);
+        resolve_probe_budget(
+            hashers,
+            self.n_projections,
+            k_min,
+            self.n_hash_tables,
+            self._multi_probe_budget,
+        )
+    }
+
+    /// Whether the hashers have to be fitted, see [fit](#method.fit).
+    pub(crate) fn needs_fit(&self) -> bool {
+        self.hashers
+            .iter()
+            .any(|h| h.as_fit().is_some_and(|h| !h.is_fitted()))
+    }
+
+    /// Fail if the hashers have to be fitted before data points can be stored.
+    pub(crate) fn check_fitted(&self) -> Result<()> {
+        if self.needs_fit() {
+            return Err(Error::NotFitted);
+        }
+        Ok(())
+    }
+
+    /// Fit the hashers that can be fitted on `vs`, see [fit](#method.fit).
+    pub(crate) fn fit_hashers(&mut self, vs: &[Vec<N>]) -> Result<()> {
+        for h in self.hashers.iter_mut() {
+            if let Some(h) = h.as_fit_mut() {
+                h.fit(vs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate a data point that is being stored: its dimension, and whether the fitted
+    /// hashers can store it, see [VecHashFit](trait.VecHashFit.html).
+    pub(crate) fn validate_put(&self, v: &[N]) -> Result<()> {
+        self.validate_vec(v)?;
+        for h in self.hashers.iter().filter_map(|h| h.as_fit()) {
+            if !h.is_fitted() {
+                return Err(Error::NotFitted);
+            }
+            h.check_put(v)?;
+        }
+        Ok(())
+    }
+
+    /// Validate all rows of a batch that is being stored, see
+    /// [validate_put](#method.validate_put).
+    pub(crate) fn validate_put_batch(&self, vs: &[Vec<N>]) -> Result<()> {
+        vs.iter()
+            .enumerate()
+            .try_for_each(|(row, v)| self.validate_put(v).map_err(batch_err(row)))
+    }
+
     pub(crate) fn validate_vec<A>(&self, v: &[A]) -> Result<()> {
-        if !(v.len() == self.dim) {
-            return Err(Error::Failed(
-                "data point is not valid, are the dimensions correct?".to_string(),
-            ));
+        self.validate_dim(v.len())
+    }
+
+    pub(crate) fn validate_dim(&self, dim: usize) -> Result<()> {
+        if dim != self.dim {
+            return Err(Error::DimensionMismatch {
+                expected: self.dim,
+                actual: dim,
+            });
         };
         Ok(())
     }
 
+    /// Validate that a 2D array holds one data point per row.
+    pub(crate) fn validate_shape(&self, vs: &ArrayView2<N>) -> Result<()> {
+        if vs.ncols() != self.dim {
+            return Err(Error::ShapeMismatch {
+                rows: vs.nrows(),
+                cols: vs.ncols(),
+                expected: self.dim,
+            });
+        }
+        Ok(())
+    }
+
+    /// Validate all rows of a batch. The error contains the index of the first invalid row.
+    pub(crate) fn validate_batch(&self, vs: &[Vec<N>]) -> Result<()> {
+        vs.iter()
+            .enumerate()
+            .try_for_each(|(row, v)| self.validate_vec(v).map_err(batch_err(row)))
+    }
+
     /// Set seed of LSH
     /// # Arguments
     /// * `seed` - Seed for the RNG's if 0, RNG's are seeded randomly.
@@ -374,20 +1525,200 @@ where
 
     /// Enable multi-probing LSH and set multi-probing parameters.
     ///
+    /// The probing sequences are deterministic, so the same query always returns the same
+    /// candidates, also on replicas of the index.
+    ///
     /// # Arguments
-    /// * `budget` - The number of probes (close hashes) will be executed per query.
+    /// * `budget` - The number of probes (close hashes) will be executed per query. If 0, the
+    ///   budget is chosen from `K` and `L`, see [adaptive_probe_budget](fn.adaptive_probe_budget.html),
+    ///   and capped at the number of distinct probes. Building fails if a budget that isn't 0
+    ///   exceeds the number of distinct probes, or if the hasher doesn't support multi-probing.
     pub fn multi_probe(&mut self, budget: usize) -> &mut Self {
         self._multi_probe = true;
         self._multi_probe_budget = budget;
         self
     }
 
-    pub fn base(&mut self) -> &mut Self {
-        self._multi_probe = false;
+    /// Skip buckets with less than `n` data points when collecting query candidates. Tiny
+    /// buckets often only contribute noise candidates, especially with aggressive multi-probing.
+    ///
+    /// # Arguments
+    /// * `n` - Minimal bucket size. Defaults to 0, i.e. no buckets are skipped.
+    pub fn min_bucket_size(&mut self, n: usize) -> &mut Self {
+        self._min_bucket_size = n;
         self
     }
 
-    /// Increase storage of the `hash_tables` backend. This can reduce system calls.
+    /// Skip buckets with more than `n` data points when collecting query candidates. Skewed
+    /// data can create giant buckets that blow up the candidates and the query latency. How
+    /// often buckets are skipped is reported by [describe](#method.describe) and
+    /// [n_skipped_buckets](#method.n_skipped_buckets).
+    ///
+    /// # Arguments
+    /// * `n` - Maximal bucket size. Defaults to `None`, i.e. no buckets are skipped.
+    pub fn max_bucket_size(&mut self, n: Option<usize>) -> &mut Self {
+        self._max_bucket_size = n;
+        self
+    }
+
+    /// Also probe the neighboring cell of every projection that lies within `eps` of a cell
+    /// boundary. Points (almost) on a boundary can flip cells between storing and querying due
+    /// to floating point rounding, e.g. with `f32` or with and without BLAS. This only has
+    /// effect for L2 hashers and without multi-probing, which already probes neighboring cells.
+    ///
+    /// # Arguments
+    /// * `eps` - Margin relative to the bucket width `r`. Defaults to 0, i.e. no extra probes.
+    pub fn boundary_eps(&mut self, eps: f64) -> &mut Self {
+        self._boundary_eps = eps;
+        self
+    }
+
+    /// Set the hash length of every hash table, instead of using `n_projections` for all of
+    /// them. Tables with longer hashes have a higher precision, tables with shorter hashes a
+    /// higher recall. Mixing them smooths the precision/ recall curve.
+    ///
+    /// # Arguments
+    /// * `ks` - Hash length per hash table, the length should be `n_hash_tables`.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(8, 3, 3)
+    ///     .projections_per_table(vec![4, 8, 12])
+    ///     .srp()
+    ///     .unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// assert_eq!(lsh.table_projections(2), 12);
+    /// ```
+    pub fn projections_per_table(&mut self, ks: Vec<usize>) -> &mut Self {
+        self._projections_per_table = Some(ks);
+        self
+    }
+
+    /// Let the [L2](#method.l2) hash tables share a single projection matrix of `pool_rows`
+    /// rows, instead of drawing a projection matrix per hash table. Every hash table projects
+    /// with `n_projections` evenly spaced rows of the shared matrix, with a random first row and
+    /// spacing, which reduces the memory of the hashers from
+    /// `n_hash_tables * n_projections * dim` to `pool_rows * dim` values.
+    ///
+    /// No two hash tables project with the same rows, so the pool needs enough rows for a
+    /// distinct selection per hash table. Hash tables that share some of their rows are still
+    /// correlated, a pool of a few times `n_projections` keeps them close to independent.
+    /// Serializing the hashers, e.g. with [dump](#method.dump) or the SQL backend, copies the
+    /// rows per hash table, so loaded hashers don't share memory.
+    ///
+    /// # Arguments
+    /// * `pool_rows` - Rows of the shared projection matrix, at least the longest hash length.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::<_, f32, i32>::new(8, 200, 64)
+    ///     .shared_projections(64)
+    ///     .l2(4.)
+    ///     .unwrap();
+    /// lsh.store_vec(&[1.; 64]).unwrap();
+    /// ```
+    pub fn shared_projections(&mut self, pool_rows: usize) -> &mut Self {
+        self._shared_projections = Some(pool_rows);
+        self
+    }
+
+    /// Use very sparse random projections in the [SignRandomProjections](struct.SignRandomProjections.html)
+    /// and [L2](struct.L2.html) hashers, instead of dense Gaussian projections. Only a fraction
+    /// `density` of the projection entries is non-zero, which speeds up hashing of high
+    /// dimensional data points. Achlioptas' projections have a density of `1 / 3`, Li's very
+    /// sparse projections `1 / sqrt(dim)`. Can't be combined with
+    /// [shared_projections](#method.shared_projections).
+    ///
+    /// # Arguments
+    /// * `density` - Fraction of non-zero entries, in `(0, 1]`.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let dim = 1024;
+    /// let mut lsh = LshMem::<_, f32>::new(16, 10, dim)
+    ///     .projection_density(1. / (dim as f32).sqrt())
+    ///     .srp()
+    ///     .unwrap();
+    /// lsh.store_vec(&vec![1.; dim]).unwrap();
+    /// ```
+    pub fn projection_density(&mut self, density: f32) -> &mut Self {
+        self._projection_density = Some(density);
+        self
+    }
+
+    /// Open the existing database of [set_database_file](#method.set_database_file) in
+    /// read-only mode, e.g. on a read-only volume. No tables are created, no transaction is
+    /// started and the hashers are loaded from the database instead of generated. Storing,
+    /// deleting or updating returns `Error::ReadOnly`. Only the indexes are available, see
+    /// [only_index](#method.only_index).
+    ///
+    /// Only the `SqlTable` backend can be opened read-only, the other backends return
+    /// `Error::NotImplemented`. [open_readonly](#method.open_readonly) does the same without a
+    /// hash family.
+    ///
+    /// # Examples
+    #[cfg_attr(feature = "sqlite", doc = "```no_run")]
+    #[cfg_attr(not(feature = "sqlite"), doc = "```ignore")]
+    /// use lsh_rs::prelude::*;
+    /// let lsh = LshSql::<_, f32>::new(9, 10, 3)
+    ///     .set_database_file("./lsh.db3")
+    ///     .read_only()
+    ///     .srp()
+    ///     .unwrap();
+    /// ```
+    pub fn read_only(&mut self) -> &mut Self {
+        self._read_only = true;
+        self
+    }
+
+    /// Send the events of the queries to `observer`, e.g. the hashing time and bucket sizes per
+    /// hash table and the number of unique candidates per query. See
+    /// [QueryObserver](trait.QueryObserver.html). Can also be set on a built index.
+    ///
+    /// # Arguments
+    /// * `observer` - Receives the events. Keep a clone of the `Arc` to read what it recorded.
+    pub fn query_observer(&mut self, observer: Arc<dyn QueryObserver>) -> &mut Self {
+        self._query_observer = Some(observer);
+        self
+    }
+
+    /// Store the data points in a single aligned, row-major allocation instead of a
+    /// separate allocation per data point. Rows are aligned to 32 bytes (`f32x8`), which
+    /// speeds up exact distance computations on the candidates.
+    /// This only has effect with the `MemoryTable` backend.
+    pub fn flat_storage(&mut self) -> &mut Self {
+        self._flat_storage = true;
+        self
+    }
+
+    /// Store the bucket keys in a compact layout instead of a `Vec<K>` per key. Binary hashes,
+    /// like those of [SignRandomProjections](struct.SignRandomProjections.html), are packed in
+    /// one bit per projection (up to 64 projections). Other hashes of at most 22 bytes, e.g.
+    /// short L2 hashes, are stored inline. This saves the allocation of every key and speeds up
+    /// the hashing and comparison of keys.
+    /// This only has effect with the `MemoryTable` backend.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(20, 10, 3).packed_keys().srp().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![0]);
+    /// ```
+    pub fn packed_keys(&mut self) -> &mut Self {
+        self._packed_keys = true;
+        self
+    }
+
+    pub fn base(&mut self) -> &mut Self {
+        self._multi_probe = false;
+        self
+    }
+
+    /// Increase storage of the `hash_tables` backend. This can reduce system calls.
     ///
     /// # Arguments
     /// * `upper_bound` - The maximum storage capacity required.
@@ -409,232 +1740,2705 @@ where
         self
     }
 
-    /// Collects statistics of the buckets in the `hash_tables`.
-    /// # Statistics
-    /// * average bucket length
-    /// * minimal bucket length
-    /// * maximum bucket length
-    /// * bucket lenght standard deviation
-    pub fn describe(&self) -> Result<String> {
-        self.hash_tables.as_ref().unwrap().describe()
+    /// Automatically create the hash index when committing, once the hash tables contain at
+    /// least `threshold` rows. Queries on large unindexed tables are slow. Defaults to
+    /// `Some(10000)`, pass `None` to disable.
+    /// This only has effect with the `SqlTable` backend.
+    ///
+    /// # Arguments
+    /// * `threshold` - Minimal number of rows before the index is created.
+    pub fn auto_index(&mut self, threshold: Option<u32>) -> &mut Self {
+        self._auto_index = threshold;
+        self
+    }
+
+    /// Collects statistics of the buckets in the `hash_tables`, see
+    /// [TableStats](stats/struct.TableStats.html). Use this instead of
+    /// [describe](#method.describe) for automated parameter tuning.
+    ///
+    /// The bucket statistics are computed per hash table from the
+    /// [bucket_histogram](trait.HashTables.html#method.bucket_histogram) of the backend.
+    /// Returns [Error::NotImplemented](enum.Error.html) if the backend doesn't report a
+    /// histogram.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]]).unwrap();
+    /// let stats = lsh.stats().unwrap();
+    /// assert_eq!(stats.n_entries, 20);
+    /// assert!(stats.tables.iter().all(|t| t.max <= 2));
+    /// ```
+    pub fn stats(&self) -> Result<TableStats> {
+        self.stats_with_max(usize::MAX)
+    }
+
+    /// [stats](#method.stats) over at most `max_buckets` buckets per hash table.
+    fn stats_with_max(&self, max_buckets: usize) -> Result<TableStats> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        let tables = (0..self.n_hash_tables)
+            .map(|i| {
+                let histogram = ht.bucket_histogram(i, max_buckets)?;
+                Ok(BucketStats::from_histogram(i, &histogram))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let approximate_bytes = match ht.approximate_bytes() {
+            Ok(bytes) => Some(bytes),
+            Err(Error::NotImplemented) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(TableStats {
+            n_hash_tables: self.n_hash_tables,
+            n_entries: ht.n_entries()?,
+            approximate_bytes,
+            unique_hash_values: ht.get_unique_hash_int().len(),
+            tables,
+        })
+    }
+
+    /// Collects statistics of the buckets in the `hash_tables` and formats them.
+    /// # Statistics
+    /// * number of entries (ids over all buckets)
+    /// * approximate size in bytes, if the backend reports it
+    /// * average bucket length
+    /// * minimal bucket length
+    /// * maximum bucket length
+    /// * bucket lenght standard deviation
+    ///
+    /// The statistics are those of [stats](#method.stats), computed over at most 5000 buckets
+    /// per hash table. Backends that don't report a histogram fall back to their own
+    /// [describe](trait.HashTables.html#method.describe).
+    pub fn describe(&self) -> Result<String> {
+        let stats = match self.stats_with_max(DESCRIBE_MAX as usize) {
+            Ok(stats) => stats,
+            Err(Error::NotImplemented) => return self.hash_tables.as_ref().unwrap().describe(),
+            Err(e) => return Err(e),
+        };
+        // empty hash tables have no bucket lengths.
+        let summaries: Vec<_> = stats.tables.iter().filter(|s| s.n_buckets > 0).collect();
+
+        let mut out = format!("No. of tables: {}\n", stats.n_hash_tables);
+        out.push_str(&format!("Entries: {}\n", stats.n_entries));
+        if let Some(bytes) = stats.approximate_bytes {
+            out.push_str(&format!("Approximate bytes: {}\n", bytes));
+        }
+        out.push_str(&format!(
+            "Unique hash values: {}\n",
+            stats.unique_hash_values
+        ));
+        out.push_str("\nHash collisions:\n");
+        let column = |values: Vec<String>| format!("[{}]", values.join(", "));
+        let avg = summaries.iter().map(|s| format!("{:.3}", s.avg)).collect();
+        let std_dev = summaries
+            .iter()
+            .map(|s| format!("{:.3}", s.std_dev))
+            .collect();
+        let min = summaries.iter().map(|s| s.min.to_string()).collect();
+        let max = summaries.iter().map(|s| s.max.to_string()).collect();
+        out.push_str(&format!("avg:\t{}\n", column(avg)));
+        out.push_str(&format!("std-dev:\t{}\n", column(std_dev)));
+        out.push_str(&format!("min:\t{}\n", column(min)));
+        out.push_str(&format!("max:\t{}\n", column(max)));
+        if let Some(max_bucket_size) = self._max_bucket_size {
+            out.push_str(&format!(
+                "\nSkipped buckets (> {}): {}\n",
+                max_bucket_size,
+                self.n_skipped_buckets()
+            ));
+        }
+        Ok(out)
+    }
+
+    /// Number of ids over all buckets of all hash tables.
+    pub fn n_entries(&self) -> Result<usize> {
+        self.hash_tables.as_ref().unwrap().n_entries()
+    }
+
+    /// Approximate memory or disk usage of the backend in bytes.
+    pub fn approximate_bytes(&self) -> Result<usize> {
+        self.hash_tables.as_ref().unwrap().approximate_bytes()
+    }
+
+    /// Bucket length histogram and unique hash count of every hash table.
+    pub fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        self.hash_tables.as_ref().unwrap().hash_table_stats()
+    }
+
+    /// Write the [statistics](stats/struct.HashTableStats.html) of every hash table to a file,
+    /// for offline analysis.
+    ///
+    /// # Arguments
+    /// * `path` - File to write to.
+    /// * `format` - [StatsFormat::Json](stats/enum.StatsFormat.html) or `StatsFormat::Csv`.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// use lsh_rs::stats::StatsFormat;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., 0., 2.]]).unwrap();
+    /// let path = std::env::temp_dir().join("lsh_stats.csv");
+    /// lsh.export_stats(&path, StatsFormat::Csv).unwrap();
+    /// ```
+    pub fn export_stats<P: AsRef<Path>>(&self, path: P, format: StatsFormat) -> Result<()> {
+        let stats = self.hash_table_stats()?;
+        let f = BufWriter::new(File::create(path)?);
+        write_stats(&stats, format, f)
+    }
+
+    /// Enumerate the candidate pairs of the index (a self-join), e.g. as the first step of
+    /// near duplicate clustering. A candidate pair `(a, b)` with `a < b` shares a bucket in at
+    /// least `min_collisions` hash tables. `f` receives `(a, b, collisions)` for every pair,
+    /// sorted by `(a, b)`, so the output doesn't depend on the backend.
+    ///
+    /// The pairs and their collision counts are held in memory, and a bucket of `n` ids adds
+    /// `n * (n - 1) / 2` pairs. Large buckets can make this expensive.
+    ///
+    /// # Arguments
+    /// * `min_collisions` - Minimal number of hash tables in which a pair collides. At least 1.
+    /// * `f` - Receives `(a, b, collisions)`. An error stops the enumeration.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    /// lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., 0., 2.], vec![2., 3., 4.]]).unwrap();
+    /// let mut pairs = vec![];
+    /// lsh.candidate_pairs(10, |a, b, _| {
+    ///     pairs.push((a, b));
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// assert_eq!(pairs, vec![(0, 2)]);
+    /// ```
+    pub fn candidate_pairs<F>(&self, min_collisions: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(T::Id, T::Id, usize) -> Result<()>,
+    {
+        if min_collisions == 0 {
+            return Err(Error::Failed(
+                "min_collisions should be at least 1".to_string(),
+            ));
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut collisions: FnvHashMap<(T::Id, T::Id), usize> = FnvHashMap::default();
+        for i in 0..self.n_hash_tables {
+            ht.for_each_bucket(i, &mut |ids| {
+                for (j, &a) in ids.iter().enumerate() {
+                    for &b in &ids[j + 1..] {
+                        *collisions.entry((a, b)).or_insert(0) += 1;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        let mut pairs: Vec<_> = collisions
+            .into_iter()
+            .filter(|&(_, n)| n >= min_collisions)
+            .collect();
+        pairs.sort_unstable();
+        for ((a, b), n) in pairs {
+            f(a, b, n)?;
+        }
+        Ok(())
+    }
+
+    /// Write the [candidate pairs](#method.candidate_pairs) as CSV with the columns
+    /// `a,b,collisions`. Returns the number of pairs.
+    ///
+    /// # Arguments
+    /// * `min_collisions` - Minimal number of hash tables in which a pair collides. At least 1.
+    /// * `w` - Writer, e.g. a buffered file.
+    pub fn write_candidate_pairs<W: Write>(
+        &self,
+        min_collisions: usize,
+        mut w: W,
+    ) -> Result<usize> {
+        writeln!(w, "a,b,collisions")?;
+        let mut n_pairs = 0;
+        self.candidate_pairs(min_collisions, |a, b, n| {
+            n_pairs += 1;
+            writeln!(w, "{},{},{}", a, b, n)?;
+            Ok(())
+        })?;
+        w.flush()?;
+        Ok(n_pairs)
+    }
+
+    /// Bulk load hashes that were computed elsewhere (e.g. in Spark) from a CSV file with
+    /// `table_idx,hash,id` rows. A header row is skipped. Only the indexes are stored, so this
+    /// requires [only_index](#method.only_index) storage. The hashers must be the ones that
+    /// computed the hashes, otherwise queries won't find the imported ids.
+    ///
+    /// The hash is hex encoded, the bytes are the hash values of the table in native byte
+    /// order (little endian on common platforms), i.e. the layout of the SQLite backend. The
+    /// hashes are validated against the hash length and integer type of the hashers. Every hash
+    /// table is sorted and inserted at once. Returns the number of imported rows.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let path = std::env::temp_dir().join("lsh_import_hashes.csv");
+    /// std::fs::write(&path, "table_idx,hash,id\n0,0001,0\n1,0100,0\n").unwrap();
+    /// let mut lsh: LshMem<_, f32> = LshMem::new(2, 2, 3).only_index().srp().unwrap();
+    /// assert_eq!(lsh.import_hashes_csv(&path).unwrap(), 2);
+    /// ```
+    pub fn import_hashes_csv<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        if !self.only_index_storage {
+            return Err(Error::Failed(
+                "importing hashes requires only_index storage, no data points are imported"
+                    .to_string(),
+            ));
+        }
+        let mut s = String::new();
+        BufReader::new(File::open(path)?).read_to_string(&mut s)?;
+        let mut tables: Vec<Vec<(Vec<K>, T::Id)>> = vec![vec![]; self.n_hash_tables];
+        let projections: Vec<usize> = (0..self.n_hash_tables)
+            .map(|i| self.table_projections(i))
+            .collect();
+        let mut n = 0;
+        for (row, line) in s.lines().enumerate() {
+            if line.trim().is_empty() || (row == 0 && line.starts_with("table_idx")) {
+                continue;
+            }
+            let (table_idx, hash, idx) =
+                parse_hash_line(line, &projections).map_err(batch_err(row))?;
+            tables[table_idx].push((hash, idx));
+            n += 1;
+        }
+        let ht = self.hash_tables.as_mut().unwrap();
+        for (i, mut entries) in tables.into_iter().enumerate() {
+            entries.sort_unstable();
+            ht.insert_ids(&entries, i)?;
+        }
+        Ok(n)
+    }
+
+    /// Start an ingestion session. Data points that are stored through the session can be
+    /// removed again with [rollback](struct.IngestSession.html#method.rollback), e.g. when bad
+    /// data is detected halfway a bulk ingest. Deletes and updates are not undone.
+    ///
+    /// The SQLite backends use a savepoint, the in memory backend removes the ids that were
+    /// assigned during the session.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let mut session = lsh.begin_ingest().unwrap();
+    /// session.store_vecs(&[vec![2., 3., 4.], vec![-1., 0., 2.]]).unwrap();
+    /// session.rollback().unwrap();
+    /// assert_eq!(lsh.store_vec(&[2., 3., 4.]).unwrap(), 0);
+    /// ```
+    pub fn begin_ingest(&mut self) -> Result<IngestSession<'_, H, N, T, K>> {
+        self.wal.append(Record::<N>::Begin)?;
+        self.hash_tables.as_mut().unwrap().begin_ingest()?;
+        Ok(IngestSession {
+            lsh: self,
+            done: false,
+        })
+    }
+
+    /// Store a single vector in storage. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let v = &[2., 3., 4.];
+    /// let id = lsh.store_vec(v);
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn store_vec(&mut self, v: &[N]) -> Result<T::Id> {
+        self.validate_put(v)?;
+        let hashes = self.hashers.iter().map(|h| h.hash_vec_put(v)).collect();
+        self.put_hashes(hashes, v)
+    }
+
+    /// Store data point `v` under `hashes`, one per hash table. Every store method ends here.
+    /// The hash tables are borrowed, so they are left in place when the backend fails. If a put
+    /// fails, e.g. with [Busy](enum.Error.html#variant.Busy), the data point is removed from the
+    /// hash tables it was already put in.
+    pub(crate) fn put_hashes(&mut self, hashes: Vec<Vec<K>>, v: &[N]) -> Result<T::Id> {
+        if self._read_only {
+            return Err(Error::ReadOnly);
+        }
+        let ht = self.hash_tables.as_mut().unwrap();
+        if self.wal.is_enabled() {
+            let next = ht.next_id()?.index() as u64;
+            self.wal.append(Record::Store(next, Cow::Borrowed(v)))?;
+        }
+        let mut idx = T::Id::zero();
+        for (i, hash) in hashes.iter().enumerate() {
+            match ht.put(hash.clone(), v, i) {
+                Ok(id) => idx = id,
+                Err(e) => {
+                    for (j, hash_j) in hashes[..i].iter().enumerate() {
+                        ht.remove_idx(idx, hash_j, j)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(idx)
+    }
+
+    /// Same as [store_vec](#method.store_vec), for a (possibly not contiguous) view, e.g. a
+    /// column of a 2D array. Only views that aren't contiguous are copied.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// use ndarray::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let vs = array![[2., 1.], [3., 0.], [4., 1.]];
+    /// let id = lsh.store_arr(vs.column(0)).unwrap();
+    /// assert!(lsh.query_bucket_ids_arr(vs.column(0)).unwrap().contains(&id));
+    /// ```
+    #[doc(alias = "store_view")]
+    pub fn store_arr(&mut self, v: ArrayView1<N>) -> Result<T::Id> {
+        self.store_vec(&view_slice(&v))
+    }
+
+    /// Store a data point under a user provided id. The data point also gets an internal id,
+    /// which is returned. [query_bucket_user_ids](#method.query_bucket_user_ids) returns the user
+    /// ids of the candidates.
+    ///
+    /// The ids are kept in memory and saved by [dump](#method.dump). They aren't stored in the
+    /// SQLite and mmap backends.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    /// * `id` - User id of the data point. Fails with `Error::DuplicateId` if it is in use.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// lsh.store_vec_with_id(&[2., 3., 4.], 1 << 40).unwrap();
+    /// assert_eq!(lsh.query_bucket_user_ids(&[2., 3., 4.]).unwrap(), vec![1 << 40]);
+    /// ```
+    pub fn store_vec_with_id(&mut self, v: &[N], id: u64) -> Result<T::Id> {
+        if self.user_ids.by_id.contains_key(&id) {
+            return Err(Error::DuplicateId(id));
+        }
+        let idx = self.store_vec(v)?;
+        self.wal
+            .append(Record::<N>::UserId(idx.index() as u64, id))?;
+        self.user_ids.insert(idx, id);
+        Ok(idx)
+    }
+
+    /// User id of the data point with internal id `idx`. Data points that were stored without a
+    /// user id return their internal id.
+    pub fn user_id(&self, idx: T::Id) -> u64 {
+        self.user_ids
+            .by_idx
+            .get(&idx)
+            .copied()
+            .unwrap_or_else(|| idx.to_u64().unwrap())
+    }
+
+    /// Internal id of the data point with user id `id`, if it exists.
+    pub fn internal_id(&self, id: u64) -> Option<T::Id> {
+        self.user_ids.by_id.get(&id).copied()
+    }
+
+    /// Store a data point with a payload, e.g. a string id or a small blob of metadata.
+    /// [query_bucket_payloads](#method.query_bucket_payloads) returns the payloads of the
+    /// candidates.
+    ///
+    /// Payloads are supported by the `MemoryTable` (saved by [dump](#method.dump)) and the
+    /// SQLite backends. Other backends fail with `Error::NotImplemented` and don't store the
+    /// data point.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    /// * `payload` - Bytes to attach to the data point.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let id = lsh.store_vec_with_payload(&[2., 3., 4.], b"doc-1").unwrap();
+    /// assert_eq!(
+    ///     lsh.query_bucket_payloads(&[2., 3., 4.]).unwrap(),
+    ///     vec![(id, Some(b"doc-1".to_vec()))]
+    /// );
+    /// ```
+    pub fn store_vec_with_payload(&mut self, v: &[N], payload: &[u8]) -> Result<T::Id> {
+        let idx = self.store_vec(v)?;
+        self.wal
+            .append(Record::<N>::Payload(idx.index() as u64, payload))?;
+        if let Err(e) = self.hash_tables.as_mut().unwrap().put_payload(idx, payload) {
+            // don't leave a data point without its payload behind.
+            self.delete_by_idx(idx)?;
+            return Err(e);
+        }
+        Ok(idx)
+    }
+
+    /// Attach a payload to the stored data point `idx`, replacing its previous payload. See
+    /// [store_vec_with_payload](#method.store_vec_with_payload).
+    pub fn set_payload(&mut self, idx: T::Id, payload: &[u8]) -> Result<()> {
+        self.wal
+            .append(Record::<N>::Payload(idx.index() as u64, payload))?;
+        self.hash_tables.as_mut().unwrap().put_payload(idx, payload)
+    }
+
+    /// The payload of data point `idx`, `None` if it was stored without payload.
+    pub fn payload(&self, idx: T::Id) -> Result<Option<Payload>> {
+        self.hash_tables.as_ref().unwrap().payload(idx)
+    }
+
+    /// Same as [delete_vec](#method.delete_vec), for a (possibly not contiguous) view.
+    #[doc(alias = "delete_view")]
+    pub fn delete_arr(&mut self, v: ArrayView1<N>) -> Result<()> {
+        self.delete_vec(&view_slice(&v))
+    }
+
+    /// Update a data point in the `hash_tables`.
+    ///
+    /// # Arguments
+    /// * `idx` - Id of the hash that needs to be updated.
+    /// * `new_v` - New data point that needs to be hashed.
+    /// * `old_v` - Old data point. Needed to remove the old hash.
+    pub fn update_by_idx(&mut self, idx: T::Id, new_v: &[N], old_v: &[N]) -> Result<()> {
+        self.validate_put(new_v)?;
+        self.validate_vec(old_v)?;
+        self.wal.append(Record::Update(
+            idx.index() as u64,
+            Cow::Borrowed(new_v),
+            Cow::Borrowed(old_v),
+        ))?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        for (i, proj) in self.hashers.iter().enumerate() {
+            let new_hash = proj.hash_vec_put(new_v);
+            let old_hash = proj.hash_vec_put(old_v);
+            ht.update_by_idx(&old_hash, new_hash, idx, i)?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "query",
+            level = "debug",
+            skip_all,
+            fields(multi_probe = self._multi_probe, n_candidates)
+        )
+    )]
+    fn bucket_union<F>(&self, for_each_hashes: F) -> Result<Bucket<T::Id>>
+    where
+        F: FnOnce(&mut dyn FnMut(usize, &[Vec<K>]) -> Result<()>) -> Result<()>,
+    {
+        let query_start = self.observe_start();
+        let mut bucket_union = FnvHashSet::default();
+        let mut start = self.observe_start();
+        for_each_hashes(&mut |i, hashes| {
+            let hashed = self.observe_start();
+            let len = self.process_buckets_union_result(hashes, i, &mut bucket_union)?;
+            self.observe_table(i, start, hashed, hashes.len(), len);
+            start = self.observe_start();
+            Ok(())
+        })?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("n_candidates", bucket_union.len());
+        if let (Some(observer), Some(start)) = (&self._query_observer, query_start) {
+            observer.on_query(&QueryEvent {
+                n_candidates: bucket_union.len(),
+                elapsed: start.elapsed(),
+            });
+        }
+        Ok(bucket_union)
+    }
+
+    fn query_bucket_union(&self, v: &[N]) -> Result<Bucket<T::Id>> {
+        self.validate_vec(v)?;
+        self.bucket_union(|f| self.for_each_query_hashes(v, f))
+    }
+
+    /// Call `f` with the index of every hash table and the hashes that a query of `v` looks up
+    /// in it: the probes of [multi_probe](#method.multi_probe), the cells within
+    /// [boundary_eps](#method.boundary_eps) of `v`, or else only the hash of `v`.
+    pub(crate) fn for_each_query_hashes<F>(&self, v: &[N], mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, &[Vec<K>]) -> Result<()>,
+    {
+        if self._multi_probe {
+            return self.for_each_probe_hashes(v, f);
+        }
+        for (i, proj) in self.hashers.iter().enumerate() {
+            if self._boundary_eps > 0. {
+                f(i, &proj.hash_vec_query_boundary(v, self._boundary_eps))?;
+            } else {
+                f(i, &[proj.hash_vec_query(v)])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start of a timed step of a query. `None` if no [QueryObserver](trait.QueryObserver.html)
+    /// is set, so unobserved queries aren't timed.
+    pub(crate) fn observe_start(&self) -> Option<Instant> {
+        self._query_observer.as_ref().map(|_| Instant::now())
+    }
+
+    /// Send the query of hash table `hash_table` to the observer. The table was hashed between
+    /// `start` and `hashed` and its buckets were fetched after `hashed`.
+    pub(crate) fn observe_table(
+        &self,
+        hash_table: usize,
+        start: Option<Instant>,
+        hashed: Option<Instant>,
+        n_buckets: usize,
+        bucket_len: usize,
+    ) {
+        if let (Some(observer), Some(start), Some(hashed)) = (&self._query_observer, start, hashed)
+        {
+            observer.on_hash_table(&TableEvent {
+                hash_table,
+                hash_time: hashed - start,
+                bucket_time: hashed.elapsed(),
+                n_buckets,
+                bucket_len,
+            });
+        }
+    }
+
+    /// Query all buckets in the hash tables. The union of the matching buckets over the `L`
+    /// hash tables is returned
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket(&self, v: &[N]) -> Result<Vec<&[N]>> {
+        self.validate_vec(v)?;
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot query bucket, use query_bucket_ids".to_string(),
+            ));
+        }
+        let bucket_union = self.query_bucket_union(v)?;
+
+        bucket_union
+            .iter()
+            .map(|&idx| Ok(self.hash_tables.as_ref().unwrap().idx_to_datapoint(idx)?))
+            .collect()
+    }
+
+    /// Query all buckets in the hash tables and return the data point indexes. The union of the
+    /// matching buckets of `L` hash tables is returned.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_ids(&self, v: &[N]) -> Result<Vec<T::Id>> {
+        self.validate_vec(v)?;
+        let bucket_union = self.query_bucket_union(v)?;
+        Ok(bucket_union.iter().copied().collect())
+    }
+
+    /// Number of candidates that a query of `v` finds per hash table, i.e. the size of the
+    /// queried bucket, or the summed sizes of the probed buckets. Buckets are counted without
+    /// fetching their ids, e.g. with a `COUNT(*)` query in SQLite. Buckets that are skipped by
+    /// [min_bucket_size](#method.min_bucket_size) and [max_bucket_size](#method.max_bucket_size)
+    /// aren't counted.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn bucket_sizes(&self, v: &[N]) -> Result<Vec<usize>> {
+        self.validate_vec(v)?;
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut sizes = vec![0; self.n_hash_tables];
+        let count = |i: usize, hashes: &[Vec<K>]| -> Result<()> {
+            for hash in hashes {
+                let len = ht.bucket_len(hash, i)?;
+                if self.within_bucket_limits(len) {
+                    sizes[i] += len;
+                }
+            }
+            Ok(())
+        };
+        self.for_each_query_hashes(v, count)?;
+        Ok(sizes)
+    }
+
+    /// Sum of the [bucket_sizes](#method.bucket_sizes) of a query of `v`, without collecting
+    /// the candidates, e.g. for query planning. This is an upper bound of the number of ids
+    /// that [query_bucket_ids](#method.query_bucket_ids) returns, not the number of distinct
+    /// candidates: a data point that is found in multiple hash tables is counted for every
+    /// hash table.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// assert_eq!(lsh.bucket_size_sum(&[2., 3., 4.]).unwrap(), 10);
+    /// assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().len(), 1);
+    /// ```
+    pub fn bucket_size_sum(&self, v: &[N]) -> Result<usize> {
+        Ok(self.bucket_sizes(v)?.iter().sum())
+    }
+
+    /// Same as [query_bucket_ids](#method.query_bucket_ids), but only returns the ids that
+    /// weren't returned before in `session`. A bloom filter tracks the returned ids, so a small
+    /// fraction of new ids can be dropped, see [QuerySession](struct.QuerySession.html).
+    ///
+    /// # Arguments
+    /// * `session` - Ids returned by earlier queries.
+    /// * `v` - Query vector
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    /// let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// let mut session = QuerySession::new(1000, 0.01).unwrap();
+    /// assert_eq!(lsh.query_bucket_ids_dedup(&mut session, &[2., 3., 4.]).unwrap(), vec![id]);
+    /// assert!(lsh.query_bucket_ids_dedup(&mut session, &[2., 3., 4.]).unwrap().is_empty());
+    /// ```
+    pub fn query_bucket_ids_dedup(
+        &self,
+        session: &mut QuerySession,
+        v: &[N],
+    ) -> Result<Vec<T::Id>> {
+        let mut ids = self.query_bucket_ids(v)?;
+        session.retain_new(&mut ids);
+        Ok(ids)
+    }
+
+    /// Candidate pairs of the stored data point `idx` for a near-duplicate search, i.e. the
+    /// candidates of `v` (the data point of `idx`) other than `idx`. Pairs are unordered and
+    /// only returned once per `session`, so querying every data point of a batch yields every
+    /// candidate pair once. A bloom filter tracks the returned pairs, so a small fraction of new
+    /// pairs can be dropped, see [QuerySession](struct.QuerySession.html).
+    ///
+    /// # Arguments
+    /// * `session` - Pairs returned by earlier queries.
+    /// * `idx` - Id of the queried data point.
+    /// * `v` - Data point of `idx`.
+    pub fn query_bucket_pairs_dedup(
+        &self,
+        session: &mut QuerySession,
+        idx: T::Id,
+        v: &[N],
+    ) -> Result<Vec<T::Id>> {
+        let mut pairs: Vec<(T::Id, T::Id)> = self
+            .query_bucket_ids(v)?
+            .into_iter()
+            .filter(|&other| other != idx)
+            .map(|other| (idx.min(other), idx.max(other)))
+            .collect();
+        session.retain_new(&mut pairs);
+        Ok(pairs
+            .into_iter()
+            .map(|(a, b)| if a == idx { b } else { a })
+            .collect())
+    }
+
+    /// Same as [query_bucket_ids](#method.query_bucket_ids), but returns the user ids of
+    /// [store_vec_with_id](#method.store_vec_with_id).
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_user_ids(&self, v: &[N]) -> Result<Vec<u64>> {
+        let ids = self.query_bucket_ids(v)?;
+        Ok(ids.into_iter().map(|idx| self.user_id(idx)).collect())
+    }
+
+    /// Same as [query_bucket_ids](#method.query_bucket_ids), but also returns the payloads of
+    /// [store_vec_with_payload](#method.store_vec_with_payload). Candidates without payload
+    /// return `None`.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_payloads(&self, v: &[N]) -> Result<Vec<(T::Id, Option<Payload>)>> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        self.query_bucket_ids(v)?
+            .into_iter()
+            .map(|idx| Ok((idx, ht.payload(idx)?)))
+            .collect()
+    }
+
+    /// Same as [query_bucket](#method.query_bucket), for a (possibly not contiguous) view.
+    #[doc(alias = "query_bucket_view")]
+    pub fn query_bucket_arr(&self, v: ArrayView1<N>) -> Result<Vec<&[N]>> {
+        self.query_bucket(&view_slice(&v))
+    }
+
+    /// Same as [query_bucket_ids](#method.query_bucket_ids), for a (possibly not contiguous)
+    /// view, e.g. a column or a sliced row of a 2D array. Only views that aren't contiguous are
+    /// copied. They aren't hashed in place on purpose: the dot products of strided views are
+    /// summed in another order than those of contiguous data, so the hash of a point near a
+    /// bucket boundary could differ from the hash it was stored with. The copy is a single pass
+    /// over `v`, the projections of the `L` hashers are `L * K` passes.
+    #[doc(alias = "query_bucket_ids_view")]
+    pub fn query_bucket_ids_arr(&self, v: ArrayView1<N>) -> Result<Vec<T::Id>> {
+        self.query_bucket_ids(&view_slice(&v))
+    }
+
+    /// Query all buckets in the hash tables and score every candidate by its weighted number
+    /// of bucket collisions over the `L` hash tables. The candidates are returned as
+    /// `(index, score)`, sorted by descending score. This is a cheap prefilter before exact
+    /// re-ranking.
+    ///
+    /// The probes of [multi_probe](#method.multi_probe) and
+    /// [boundary_eps](#method.boundary_eps) are queried. A candidate that is found by multiple
+    /// probes of a hash table collides once in that table, with the largest weight of these
+    /// probes. With [CollisionWeight::Count](enum.CollisionWeight.html#variant.Count) the
+    /// score is the number of hash tables that the candidate collides in.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `weight` - Weight of a collision. See [CollisionWeight](enum.CollisionWeight.html).
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn query_bucket_ids_scored(
+        &self,
+        v: &[N],
+        weight: &CollisionWeight,
+    ) -> Result<Vec<(T::Id, f32)>> {
+        self.validate_vec(v)?;
+        if let CollisionWeight::PerTable(w) = weight {
+            if w.len() != self.n_hash_tables {
+                return Err(Error::Failed(format!(
+                    "Got {} table weights, expected {}",
+                    w.len(),
+                    self.n_hash_tables
+                )));
+            }
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut scores: FnvHashMap<T::Id, f32> = FnvHashMap::default();
+        let mut table: FnvHashMap<T::Id, f32> = FnvHashMap::default();
+
+        self.for_each_query_hashes(v, |i, hashes| {
+            table.clear();
+            for bucket in ht.query_buckets(hashes, i)? {
+                #[cfg(feature = "tracing")]
+                tracing::trace!(hash_table = i, bucket_len = bucket.len(), "bucket");
+                if bucket.is_empty() || !self.keep_bucket(bucket.len()) {
+                    continue;
+                }
+                let w = match weight {
+                    CollisionWeight::Count => 1.,
+                    CollisionWeight::InverseBucketSize => 1. / bucket.len() as f32,
+                    CollisionWeight::PerTable(w) => w[i],
+                };
+                for idx in bucket {
+                    let s = table.entry(idx).or_insert(w);
+                    *s = s.max(w);
+                }
+            }
+            for (&idx, &w) in &table {
+                *scores.entry(idx).or_insert(0.) += w;
+            }
+            Ok(())
+        })?;
+        let mut scores: Vec<(T::Id, f32)> = scores.into_iter().collect();
+        scores.sort_unstable_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+        Ok(scores)
+    }
+
+    /// Query bucket collision for a batch of data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    pub fn query_bucket_ids_batch(&self, vs: &[Vec<N>]) -> Result<Vec<Vec<T::Id>>> {
+        self.validate_batch(vs)?;
+        vs.iter()
+            .enumerate()
+            .map(|(row, v)| self.query_bucket_ids(v).map_err(batch_err(row)))
+            .collect()
+    }
+
+    /// Query bucket collision for a batch of data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points, one per row.
+    pub fn query_bucket_ids_batch_arr(&self, vs: ArrayView2<N>) -> Result<Vec<Vec<T::Id>>> {
+        self.validate_shape(&vs)?;
+        vs.axis_iter(Axis(0))
+            .enumerate()
+            .map(|(row, v)| {
+                self.query_bucket_ids(&view_slice(&v))
+                    .map_err(batch_err(row))
+            })
+            .collect()
+    }
+
+    /// Delete data point from storage. This does not free memory as the storage vector isn't resized.
+    ///
+    /// Backends that don't store the data points, e.g. SQLite, can't find the id of a data
+    /// point and fail with `Error::NotImplemented`. Use
+    /// [delete_by_idx](#method.delete_by_idx) there.
+    ///
+    /// # Arguments
+    /// * `v` - Data point
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn delete_vec(&mut self, v: &[N]) -> Result<()> {
+        self.validate_vec(v)?;
+        self.wal.append(Record::Delete(Cow::Borrowed(v)))?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        for (i, proj) in self.hashers.iter().enumerate() {
+            let hash = proj.hash_vec_query(v);
+            match ht.delete(&hash, v, i) {
+                // the data point was already deleted from this hash table.
+                Ok(_) | Err(Error::NotFound) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete a data point by its id, e.g. when the caller owns the data points in
+    /// [only_index](#method.only_index) mode. If the data point is stored, the id is removed
+    /// from the buckets of its hashes. Otherwise the backend has to find the id, which means a
+    /// scan over all buckets for the in memory backend.
+    ///
+    /// # Arguments
+    /// * `idx` - Id of the data point.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).only_index().srp().unwrap();
+    /// let idx = lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// lsh.delete_by_idx(idx).unwrap();
+    /// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().is_empty());
+    /// ```
+    pub fn delete_by_idx(&mut self, idx: T::Id) -> Result<()> {
+        self.wal
+            .append(Record::<N>::DeleteIdx(idx.index() as u64))?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        if !self.only_index_storage {
+            let hashers = &self.hashers;
+            let hashes = ht.with_datapoint(idx, |v| {
+                hashers
+                    .iter()
+                    .map(|h| h.hash_vec_put(v))
+                    .collect::<Vec<_>>()
+            });
+            if let Ok(hashes) = hashes {
+                for (i, hash) in hashes.iter().enumerate() {
+                    ht.remove_idx(idx, hash, i)?;
+                }
+                ht.remove_payload(idx)?;
+                self.user_ids.remove(idx);
+                return Ok(());
+            }
+        }
+        ht.delete_by_idx(idx)?;
+        self.user_ids.remove(idx);
+        Ok(())
+    }
+
+    /// Delete a data point by its user id. See [delete_by_idx](#method.delete_by_idx).
+    ///
+    /// # Arguments
+    /// * `id` - User id of the data point.
+    pub fn delete_by_user_id(&mut self, id: u64) -> Result<()> {
+        let idx = self.internal_id(id).ok_or(Error::NotFound)?;
+        self.delete_by_idx(idx)
+    }
+
+    /// Check that `hash_table` exists and that `hash` has its hash length.
+    fn validate_hash(&self, hash_table: usize, hash: &[K]) -> Result<()> {
+        if hash_table >= self.n_hash_tables {
+            return Err(Error::TableNotExist);
+        }
+        let k = self.table_projections(hash_table);
+        if hash.len() != k {
+            return Err(Error::Failed(format!(
+                "hash has length {}, hash table {} expects {}",
+                hash.len(),
+                hash_table,
+                k
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hash a data point with the hasher of every hash table. Returns the `L` hashes under
+    /// which the data point is stored, e.g. to debug bucket collisions or to shard by hash.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let lsh = LshMem::new(3, 2, 3).seed(1).srp().unwrap();
+    /// let hashes = lsh.hash_vec(&[2., 3., 4.]).unwrap();
+    /// assert_eq!(hashes.len(), 2);
+    /// assert_eq!(hashes[1], lsh.hashers[1].hash_vec_put(&[2., 3., 4.]));
+    /// ```
+    pub fn hash_vec(&self, v: &[N]) -> Result<Vec<Vec<K>>> {
+        self.validate_vec(v)?;
+        self.check_fitted()?;
+        Ok(self.hashers.iter().map(|h| h.hash_vec_put(v)).collect())
+    }
+
+    /// Same as [hash_vec](#method.hash_vec), but hashes `v` as a query. Only differs for
+    /// asymmetric hashers, e.g. [MIPS](struct.MIPS.html).
+    ///
+    /// # Arguments
+    /// * `v` - Query data point.
+    pub fn hash_vec_query(&self, v: &[N]) -> Result<Vec<Vec<K>>> {
+        self.validate_vec(v)?;
+        Ok(self.hashers.iter().map(|h| h.hash_vec_query(v)).collect())
+    }
+
+    /// Insert an id in the bucket of `hash` without hashing a data point, e.g. for external
+    /// rebalancing tools. No data point is stored for the id. The id counter continues after
+    /// `idx`.
+    ///
+    /// # Arguments
+    /// * `hash_table` - Number of the hash table. Ranging from 0 to L.
+    /// * `hash` - Hash of the bucket, with the hash length of the hash table.
+    /// * `idx` - Id to insert.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(3, 2, 3).only_index().srp().unwrap();
+    /// let hash = lsh.hashers[1].hash_vec_query(&[2., 3., 4.]);
+    /// lsh.insert_raw(1, &hash, 7).unwrap();
+    /// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&7));
+    /// lsh.remove_raw(1, &hash, 7).unwrap();
+    /// ```
+    pub fn insert_raw(&mut self, hash_table: usize, hash: &[K], idx: T::Id) -> Result<()> {
+        self.validate_hash(hash_table, hash)?;
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .insert_ids(&[(hash.to_vec(), idx)], hash_table)
+    }
+
+    /// Remove an id from the bucket of `hash`. The data point of the id is not removed.
+    ///
+    /// # Arguments
+    /// * `hash_table` - Number of the hash table. Ranging from 0 to L.
+    /// * `hash` - Hash of the bucket, with the hash length of the hash table.
+    /// * `idx` - Id to remove.
+    pub fn remove_raw(&mut self, hash_table: usize, hash: &[K], idx: T::Id) -> Result<()> {
+        self.validate_hash(hash_table, hash)?;
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .remove_idx(idx, hash, hash_table)
+    }
+
+    /// Whether a queried bucket of `len` data points adds to the candidates, see
+    /// [min_bucket_size](#method.min_bucket_size) and [max_bucket_size](#method.max_bucket_size).
+    fn keep_bucket(&self, len: usize) -> bool {
+        if len < self._min_bucket_size {
+            return false;
+        }
+        match self._max_bucket_size {
+            Some(max) if len > max => {
+                self.skipped_buckets.increment();
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Same as `keep_bucket`, without counting the skipped buckets.
+    fn within_bucket_limits(&self, len: usize) -> bool {
+        len >= self._min_bucket_size && self._max_bucket_size.is_none_or(|max| len <= max)
+    }
+
+    /// Number of queried buckets that were skipped because they were larger than
+    /// [max_bucket_size](#method.max_bucket_size).
+    pub fn n_skipped_buckets(&self) -> u64 {
+        self.skipped_buckets.get()
+    }
+
+    /// Add the buckets of `hashes` in one hash table to `bucket_union`, querying them at once.
+    /// Returns the summed size of the buckets.
+    pub(crate) fn process_buckets_union_result(
+        &self,
+        hashes: &[Vec<K>],
+        hash_table_idx: usize,
+        bucket_union: &mut Bucket<T::Id>,
+    ) -> Result<usize> {
+        let buckets = self
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .query_buckets(hashes, hash_table_idx)?;
+        let mut len = 0;
+        for bucket in buckets {
+            len += bucket.len();
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                hash_table = hash_table_idx,
+                bucket_len = bucket.len(),
+                "bucket"
+            );
+            if !bucket.is_empty() && self.keep_bucket(bucket.len()) {
+                bucket_union.extend(bucket);
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl<H, N, T, K> LSH<H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    /// Query the `k` nearest neighbors of `q`. The union of the matching buckets is re-ranked by
+    /// the [distance](trait.VecHash.html#method.distance) of the hasher: cosine distance for
+    /// SRP, euclidean distance for L2, negative inner product for MIPS and Jaccard distance for
+    /// MinHash. Returns the indexes and distances sorted by ascending distance. Fewer than `k`
+    /// neighbors are returned if the buckets hold fewer candidates.
+    ///
+    /// The data points need to be stored, so this fails with [only_index](struct.LSH.html#method.only_index).
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `k` - Number of neighbors
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh: LshMem<_, f32, i32> = LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    /// lsh.store_vecs(&[vec![1., 1., 1.], vec![1., 1., 2.]]).unwrap();
+    /// let neighbors = lsh.query_top_k(&[1., 1., 1.], 1).unwrap();
+    /// assert_eq!(neighbors, vec![(0, 0.)]);
+    /// ```
+    pub fn query_top_k(&self, v: &[N], k: usize) -> Result<Vec<(T::Id, f64)>> {
+        self.validate_vec(v)?;
+        self.top_k(v, k)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(k))
+    )]
+    fn top_k(&self, q: &[N], k: usize) -> Result<Vec<(T::Id, f64)>> {
+        let mut neighbors = self.candidate_distances(q)?;
+        sort_by_distance(&mut neighbors);
+        neighbors.truncate(k);
+        Ok(neighbors)
+    }
+
+    /// Query the candidates of `v` within `max_distance`. The distance is the
+    /// [distance](trait.VecHash.html#method.distance) of the hasher, like in
+    /// [query_top_k](#method.query_top_k). Returns the indexes with their distances.
+    ///
+    /// The data points need to be stored, so this fails with [only_index](struct.LSH.html#method.only_index).
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `max_distance` - Candidates with a larger distance are dropped.
+    /// * `sorted` - Sort by ascending distance. Otherwise the order is arbitrary.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh: LshMem<_, f32> = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    /// lsh.store_vecs(&[vec![1., 1., 1.], vec![1., 1., 1.1], vec![-1., -1., -1.]]).unwrap();
+    /// let neighbors = lsh.query_bucket_ids_within(&[1., 1., 1.], 0.1, true).unwrap();
+    /// assert_eq!(neighbors.iter().map(|&(idx, _)| idx).collect::<Vec<_>>(), vec![0, 1]);
+    /// ```
+    pub fn query_bucket_ids_within(
+        &self,
+        v: &[N],
+        max_distance: f64,
+        sorted: bool,
+    ) -> Result<Vec<(T::Id, f64)>> {
+        self.validate_vec(v)?;
+        let mut neighbors = self.candidate_distances(v)?;
+        neighbors.retain(|&(_, dist)| dist <= max_distance);
+        if sorted {
+            sort_by_distance(&mut neighbors);
+        }
+        Ok(neighbors)
+    }
+
+    /// The candidates of `q` with their distance to `q`, unsorted.
+    fn candidate_distances(&self, q: &[N]) -> Result<Vec<(T::Id, f64)>> {
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot rank neighbors, the data points are not stored".to_string(),
+            ));
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let hasher = &self.hashers[0];
+        self.query_bucket_union(q)?
+            .into_iter()
+            .map(|idx| {
+                let dist = ht.with_datapoint(idx, |dp| hasher.distance(q, dp))?;
+                let dist = dist.ok_or_else(|| {
+                    Error::Failed("the hasher doesn't define a distance".to_string())
+                })?;
+                Ok((idx, dist))
+            })
+            .collect()
+    }
+}
+
+impl<H, N, T, K> LSH<H, N, T, K>
+where
+    N: Numeric + Float,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    /// Same as [query_top_k](struct.LSH.html#method.query_top_k), but returns the indexes and
+    /// distances as ndarrays, so they can be handed to numpy without intermediate `Vec`s.
+    ///
+    /// # Arguments
+    /// * `q` - Query vector
+    /// * `k` - Number of neighbors
+    pub fn query_top_k_arr(&self, q: &[N], k: usize) -> Result<(Array1<T::Id>, Array1<N>)> {
+        self.validate_vec(q)?;
+        let (ids, dists): (Vec<T::Id>, Vec<N>) = self
+            .top_k(q, k)?
+            .into_iter()
+            .map(|(idx, dist)| (idx, N::from_f64(dist).unwrap()))
+            .unzip();
+        Ok((Array1::from(ids), Array1::from(dists)))
+    }
+
+    /// Query the `k` nearest neighbors for a batch of data points. Row `i` of the returned
+    /// arrays holds the neighbors of row `i` of `vs`, see
+    /// [query_top_k_arr](struct.LSH.html#method.query_top_k_arr).
+    /// Rows with fewer than `k` neighbors are padded with index `u32::MAX` and distance infinity.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points, one per row.
+    /// * `k` - Number of neighbors
+    pub fn query_top_k_batch_arr(
+        &self,
+        vs: ArrayView2<N>,
+        k: usize,
+    ) -> Result<(Array2<T::Id>, Array2<N>)> {
+        self.validate_shape(&vs)?;
+        let mut ids = Array2::from_elem((vs.nrows(), k), T::Id::max_value());
+        let mut dists = Array2::from_elem((vs.nrows(), k), N::infinity());
+
+        for (row, v) in vs.axis_iter(Axis(0)).enumerate() {
+            let v = v.to_vec();
+            let neighbors = self.top_k(&v, k).map_err(batch_err(row))?;
+            for (col, (idx, dist)) in neighbors.into_iter().enumerate() {
+                ids[[row, col]] = idx;
+                dists[[row, col]] = N::from_f64(dist).unwrap();
+            }
+        }
+        Ok((ids, dists))
+    }
+}
+
+impl<H, N, K> LSH<H, N, ShardedMemoryTable<N, K>, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    K: Integer,
+{
+    /// Same as [store_vec](#method.store_vec), but takes `&self`, so that data points can be
+    /// stored while other threads query the index.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// use std::sync::Arc;
+    /// let lsh = Arc::new(LshShared::new(5, 10, 3).seed(1).srp().unwrap());
+    /// let writer = {
+    ///     let lsh = lsh.clone();
+    ///     std::thread::spawn(move || lsh.store_vec_shared(&[2., 3., 4.]).unwrap())
+    /// };
+    /// // query while the writer is storing.
+    /// lsh.query_bucket_ids(&[2., 3., 4.]).unwrap();
+    /// let id = writer.join().unwrap();
+    /// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&id));
+    /// ```
+    pub fn store_vec_shared(&self, v: &[N]) -> Result<u32> {
+        self.validate_put(v)?;
+        let hashes = self.hashers.iter().map(|h| h.hash_vec_put(v)).collect();
+        self.hash_tables.as_ref().unwrap().put_shared(hashes, v)
+    }
+
+    /// Same as [store_vecs](#method.store_vecs), but takes `&self`. Queries running at the
+    /// same time can see a part of the batch.
+    pub fn store_vecs_shared(&self, vs: &[Vec<N>]) -> Result<Vec<u32>> {
+        self.validate_put_batch(vs)?;
+        vs.iter().map(|v| self.store_vec_shared(v)).collect()
+    }
+
+    /// Same as [delete_by_idx](#method.delete_by_idx), but takes `&self`.
+    pub fn delete_by_idx_shared(&self, idx: u32) -> Result<()> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        if self.only_index_storage {
+            return ht.delete_by_idx_shared(idx);
+        }
+        let hashes = ht.with_datapoint(idx, |v| {
+            self.hashers
+                .iter()
+                .map(|h| h.hash_vec_put(v))
+                .collect::<Vec<_>>()
+        })?;
+        for (i, hash) in hashes.iter().enumerate() {
+            ht.remove_idx_shared(idx, hash, i)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<N, H, K, I> LSH<H, N, SqlTableMem<N, K, I>, K>
+where
+    N: Numeric,
+    H: FromHashFamily<N, K> + MaybeSerde,
+    K: Integer,
+    I: SqlIdx,
+{
+    /// Save the whole index, the hash tables, the hashers and the
+    /// [configuration](#method.config), to a SQLite file. The index stays in memory.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the database file. An existing database is overwritten.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let path = std::env::temp_dir().join("lsh_backup.db3");
+    /// let mut lsh = LshSqlMem::<_, f32>::new(5, 10, 3).srp().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// lsh.backup(&path).unwrap();
+    ///
+    /// let restored = LshSqlMem::<SignRandomProjections<f32>>::restore(&path).unwrap();
+    /// assert_eq!(restored.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![0]);
+    /// ```
+    pub fn backup<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let cfg = serde_json::to_string(&self.config())?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.write_meta("config", &cfg)?;
+        ht.backup(path)
+    }
+
+    /// Load an index that was saved with [backup](#method.backup) in memory.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the database file.
+    pub fn restore<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let cfg = {
+            let conn = rusqlite::Connection::open_with_flags(
+                path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+            )?;
+            read_meta(&conn, "config")?
+        };
+        let cfg: LshConfig = match cfg {
+            Some(cfg) => serde_json::from_str(&cfg)?,
+            None => {
+                return Err(Error::Failed(
+                    "database has no LSH configuration, it wasn't written by backup".to_string(),
+                ))
+            }
+        };
+        let mut ht = SqlTableMem::restore(cfg.n_hash_tables, cfg.only_index, path)?;
+        ht.auto_index(cfg.auto_index);
+        let mut lsh = LSH::builder_from_config(&cfg);
+        lsh.hashers = ht.load_hashers()?;
+        lsh.hash_tables = Some(ht);
+        Ok(lsh)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<N, H, K> LSH<H, N, MmapTable<N, K>, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    K: Integer,
+{
+    /// Write the changes to the log file of the MmapTable backend. See
+    /// [MmapTable::flush](struct.MmapTable.html#method.flush).
+    pub fn flush(&self) -> Result<()> {
+        self.hash_tables.as_ref().unwrap().flush()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<N, H, K, I> LSH<H, N, SqlTable<N, K, I>, K>
+where
+    N: Numeric,
+    H: VecHash<N, K> + MaybeSerde,
+    K: Integer,
+    I: SqlIdx,
+{
+    /// Commit SqlTable backend
+    pub fn commit(&mut self) -> Result<()> {
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.commit()?;
+        Ok(())
+    }
+
+    /// Init transaction of SqlTable backend.
+    pub fn init_transaction(&mut self) -> Result<()> {
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.init_transaction()?;
+        Ok(())
+    }
+
+    /// Report the fragmentation of the SQLite database. See
+    /// [SqlTable::fragmentation_report](struct.SqlTable.html#method.fragmentation_report).
+    pub fn fragmentation_report(&self) -> Result<FragmentationReport> {
+        self.hash_tables.as_ref().unwrap().fragmentation_report()
+    }
+
+    /// Compact the SQLite database. See [SqlTable::vacuum](struct.SqlTable.html#method.vacuum).
+    pub fn vacuum(&mut self) -> Result<()> {
+        self.hash_tables.as_ref().unwrap().vacuum()
+    }
+
+    /// Set how to handle a database that is locked by another process. See
+    /// [SqlTable::set_busy_policy](struct.SqlTable.html#method.set_busy_policy).
+    pub fn set_busy_policy(&mut self, policy: BusyPolicy) -> Result<()> {
+        self.hash_tables.as_mut().unwrap().set_busy_policy(policy)
+    }
+
+    /// Store the data points in SQLite and register the distance functions on the connection.
+    /// See [SqlTable::enable_sql_distance](struct.SqlTable.html#method.enable_sql_distance).
+    pub fn enable_sql_distance(&mut self) -> Result<()> {
+        self.hash_tables.as_mut().unwrap().enable_sql_distance()
+    }
+
+    /// Query the `k` nearest neighbors of `v`, re-ranked by `distance` within SQLite.
+    /// Only the hash of the query itself is used, multi-probe is not taken into account.
+    /// Requires [enable_sql_distance](struct.LSH.html#method.enable_sql_distance).
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `k` - Number of neighbors
+    /// * `distance` - [SqlDistance::L2](enum.SqlDistance.html) or `SqlDistance::Cosine`.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let path = std::env::temp_dir().join("lsh_sql_distance.db3");
+    /// # std::fs::remove_file(&path).ok();
+    /// let mut lsh = LshSql::new(5, 10, 3)
+    ///     .seed(1)
+    ///     .set_database_file(path.to_str().unwrap())
+    ///     .srp()
+    ///     .unwrap();
+    /// lsh.enable_sql_distance().unwrap();
+    /// lsh.store_vecs(&[vec![1., 1., 1.], vec![2., 2., 2.]]).unwrap();
+    /// let neighbors = lsh.query_top_k_sql(&[1., 1., 1.], 1, SqlDistance::L2).unwrap();
+    /// assert_eq!(neighbors, vec![(0, 0.)]);
+    /// ```
+    pub fn query_top_k_sql(
+        &self,
+        v: &[N],
+        k: usize,
+        distance: SqlDistance,
+    ) -> Result<Vec<(I, f64)>> {
+        self.validate_vec(v)?;
+        let hashes: Vec<Vec<K>> = self.hashers.iter().map(|h| h.hash_vec_query(v)).collect();
+        self.hash_tables
+            .as_ref()
+            .unwrap()
+            .query_top_k(&hashes, v, k, distance)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<N, H, K, I> LSH<H, N, SqlTable<N, K, I>, K>
+where
+    N: Numeric,
+    H: VecHash<N, K> + MaybeSerde,
+    K: Integer,
+    I: SqlIdx,
+{
+    /// Open an existing SQLite index in read-only mode. The hashers are loaded from the database,
+    /// which fails if they are missing. Nothing is written to the database file, so it can be
+    /// served from a read-only volume. Storing, deleting or updating returns `Error::ReadOnly`.
+    ///
+    /// This is a terminal builder method, `n_hash_tables`, `n_projections` and `dim` should
+    /// match the stored index.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the database file.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use lsh_rs::prelude::*;
+    /// let lsh = LshSql::<SignRandomProjections<f32>>::new(9, 10, 3)
+    ///     .open_readonly("./lsh.db3")
+    ///     .unwrap();
+    /// ```
+    pub fn open_readonly<P: AsRef<Path>>(&mut self, path: P) -> Result<Self> {
+        self.open_readonly_from(path, None)
+    }
+
+    /// Same as [open_readonly](#method.open_readonly), but the hashers are read from a file
+    /// written by [export_hashers](#method.export_hashers) instead of from the database. The
+    /// database doesn't need to hold the hashers, so query replicas can be started from the
+    /// database and the hasher file alone.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the database file.
+    /// * `hashers` - Path of the hasher file.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use lsh_rs::prelude::*;
+    /// let lsh = LshSql::<SignRandomProjections<f32>>::new(9, 10, 3)
+    ///     .open_readonly_with_hashers("./lsh.db3", "./hashers.bincode")
+    ///     .unwrap();
+    /// ```
+    pub fn open_readonly_with_hashers<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        path: P,
+        hashers: Q,
+    ) -> Result<Self> {
+        let hashers = self.read_hashers(hashers.as_ref())?;
+        self.open_readonly_from(path, Some(hashers))
+    }
+
+    /// Open the database at `path` in read-only mode, the same as the
+    /// [read_only](#method.read_only) builder.
+    fn open_readonly_from<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        hashers: Option<Vec<H>>,
+    ) -> Result<Self> {
+        self._db_path = path.as_ref().to_string_lossy().to_string();
+        self._read_only = true;
+        lsh_read_only(self, hashers)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<N, H, K, I> LSH<H, N, SqlTable<N, K, I>, K>
+where
+    N: Numeric,
+    H: VecHash<N, K> + MaybeSerde + Send,
+    K: Integer,
+    I: SqlIdx,
+{
+    /// Query bucket collision for a batch of data points in parallel. A SQLite connection can't
+    /// be shared between threads, so every worker thread queries its own read-only connection to
+    /// the database file. The connections are kept open for the next parallel queries.
+    ///
+    /// Only available for an index opened with [open_readonly](#method.open_readonly). A
+    /// writable database is locked exclusively by its connection.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    pub fn query_bucket_ids_batch_par_sql(&self, vs: &[Vec<N>]) -> Result<Vec<Vec<I>>> {
+        self.validate_batch(vs)?;
+        self.par_readers(vs.len(), |lsh, row| lsh.query_bucket_ids(&vs[row]))
+    }
+
+    /// Query bucket collision for a batch of data points in parallel, see
+    /// [query_bucket_ids_batch_par_sql](#method.query_bucket_ids_batch_par_sql).
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points, one per row.
+    pub fn query_bucket_ids_batch_arr_par_sql(&self, vs: ArrayView2<N>) -> Result<Vec<Vec<I>>> {
+        self.validate_shape(&vs)?;
+        self.par_readers(vs.nrows(), |lsh, row| {
+            lsh.query_bucket_ids(&view_slice(&vs.row(row)))
+        })
+    }
+
+    /// Run `f` for rows `0..n_rows`. The rows are split in contiguous chunks, one per worker
+    /// thread, and every chunk is queried by its own read-only copy of this LSH.
+    fn par_readers<R, F>(&self, n_rows: usize, f: F) -> Result<Vec<R>>
+    where
+        R: Send,
+        F: Fn(&Self, usize) -> Result<R> + Sync,
+    {
+        let table = self.hash_tables.as_ref().unwrap();
+        if !table.is_read_only() {
+            return Err(Error::Failed(
+                "parallel SQLite queries need an index opened with open_readonly".to_string(),
+            ));
+        }
+        if n_rows == 0 {
+            return Ok(vec![]);
+        }
+        let n_readers = rayon::current_num_threads().min(n_rows);
+        let chunk_size = n_rows.div_ceil(n_readers);
+        // the readers use the hashers of this index, the database may not hold them.
+        let hashers = bincode::serialize(&self.hashers)?;
+        let mut pooled = table.take_readers(n_readers);
+        let readers = (0..n_readers)
+            .map(|_| {
+                let mut ht = match pooled.pop() {
+                    Some(ht) => ht,
+                    None => SqlTable::open_readonly(self.n_hash_tables, &self._db_path)?,
+                };
+                ht.set_busy_policy(table.busy_policy())?;
+                lsh_with_read_only_table(self, ht, Some(bincode::deserialize(&hashers)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let chunks = readers
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, mut reader)| {
+                let rows = i * chunk_size..((i + 1) * chunk_size).min(n_rows);
+                let results = rows
+                    .map(|row| f(&reader, row).map_err(batch_err(row)))
+                    .collect::<Result<Vec<_>>>();
+                (
+                    results,
+                    reader.n_skipped_buckets(),
+                    reader.hash_tables.take(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut out = Vec::with_capacity(n_rows);
+        let mut failed = None;
+        for (results, skipped, ht) in chunks {
+            self.skipped_buckets.add(skipped);
+            // the connections are returned to the pool, also if a query failed.
+            table.put_readers(ht);
+            match results {
+                Ok(results) => out.extend(results),
+                Err(e) => failed = failed.or(Some(e)),
+            }
+        }
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(out),
+        }
+    }
+}
+
+/// An ingestion session, started with [begin_ingest](struct.LSH.html#method.begin_ingest).
+/// Data points are stored through the session, which dereferences to the
+/// [LSH](struct.LSH.html). The session ends with [commit](#method.commit) or
+/// [rollback](#method.rollback). A session that is dropped without commit is rolled back.
+pub struct IngestSession<'a, H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    lsh: &'a mut LSH<H, N, T, K>,
+    done: bool,
+}
+
+impl<'a, H, N, T, K> IngestSession<'a, H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    /// Keep the data points stored during the session.
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.lsh.wal.append(Record::<N>::Commit)?;
+        self.lsh.hash_tables.as_mut().unwrap().commit_ingest()
+    }
+
+    /// Remove the data points stored during the session. Their ids will be reassigned.
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        self.lsh.wal.append(Record::<N>::Rollback)?;
+        self.lsh.hash_tables.as_mut().unwrap().rollback_ingest()
+    }
+}
+
+impl<'a, H, N, T, K> std::ops::Deref for IngestSession<'a, H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    type Target = LSH<H, N, T, K>;
+
+    fn deref(&self) -> &LSH<H, N, T, K> {
+        self.lsh
+    }
+}
+
+impl<'a, H, N, T, K> std::ops::DerefMut for IngestSession<'a, H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    fn deref_mut(&mut self) -> &mut LSH<H, N, T, K> {
+        self.lsh
+    }
+}
+
+impl<'a, H, N, T, K> Drop for IngestSession<'a, H, N, T, K>
+where
+    N: Numeric,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.lsh.wal.append(Record::<N>::Rollback);
+            let _ = self.lsh.hash_tables.as_mut().unwrap().rollback_ingest();
+        }
+    }
+}
+
+/// Store data points with `extend`, in chunks of `EXTEND_CHUNK_SIZE` with
+/// [store_from_iter](struct.LSH.html#method.store_from_iter).
+///
+/// # Panics
+/// `Extend` can't return an error, so a failing store panics. Use `store_from_iter` to handle
+/// the error.
+///
+/// # Examples
+///```
+/// use lsh_rs::prelude::*;
+/// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+/// lsh.extend(vec![vec![2., 3., 4.], vec![-1., -1., 1.]]);
+/// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&0));
+/// ```
+impl<H, N, T, K> Extend<Vec<N>> for LSH<H, N, T, K>
+where
+    H: VecHash<N, K>,
+    N: Numeric + Sync,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    fn extend<I: IntoIterator<Item = Vec<N>>>(&mut self, iter: I) {
+        if let Err(e) = self.store_from_iter(iter, EXTEND_CHUNK_SIZE) {
+            panic!("could not store data points: {}", e)
+        }
+    }
+}
+
+/// Weight of a bucket collision, used in
+/// [query_bucket_ids_scored](struct.LSH.html#method.query_bucket_ids_scored).
+#[derive(Debug, Clone)]
+pub enum CollisionWeight {
+    /// Every collision counts as 1, the score is the number of colliding hash tables.
+    Count,
+    /// A collision counts as `1 / bucket size`. A hit in a small bucket is stronger evidence
+    /// than a hit in a large one.
+    InverseBucketSize,
+    /// A collision in hash table `i` counts as `weights[i]`, e.g. learned per table reliabilities.
+    PerTable(Vec<f32>),
+}
+
+/// Aggregation of the candidates of a batch of queries, used in
+/// [query_batch_top_k](struct.LSH.html#method.query_batch_top_k).
+#[derive(Debug, Clone)]
+pub enum BatchAggregate {
+    /// The value of a candidate is the number of queries that have it in their buckets.
+    Frequency,
+    /// The value of a candidate is the sum of its collision scores over the queries, see
+    /// [query_bucket_ids_scored](struct.LSH.html#method.query_bucket_ids_scored).
+    Score(CollisionWeight),
+    /// The value of a candidate is its smallest distance to any of the queries. Requires the
+    /// data points to be stored.
+    Nearest,
+}
+
+/// Components of a serialized [LSH](struct.LSH.html) that can be loaded selectively with
+/// [load_parts](struct.LSH.html#method.load_parts). Parts can be combined with `|`.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::prelude::*;
+/// let parts = Parts::HASHERS | Parts::TABLES;
+/// assert!(parts.contains(Parts::HASHERS));
+/// assert!(!parts.contains(Parts::VECTORS));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parts(u8);
+
+impl Parts {
+    /// The hash functions.
+    pub const HASHERS: Parts = Parts(1);
+    /// The hash tables, i.e. the buckets with data point indexes.
+    pub const TABLES: Parts = Parts(1 << 1);
+    /// The original data points.
+    pub const VECTORS: Parts = Parts(1 << 2);
+    /// All of the above.
+    pub const ALL: Parts = Parts(0b111);
+
+    /// Check if all parts in `other` are also in `self`.
+    pub fn contains(self, other: Parts) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for Parts {
+    type Output = Parts;
+
+    fn bitor(self, rhs: Parts) -> Parts {
+        Parts(self.0 | rhs.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+const HEADER_LEN: usize = DUMP_MAGIC.len() + 8;
+#[cfg(feature = "serde")]
+const FOOTER_LEN: usize = 4;
+
+#[cfg(feature = "serde")]
+/// Frame a serialized dump as `magic | payload length (u64) | payload | crc32 of payload`.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(HEADER_LEN + payload.len() + FOOTER_LEN);
+    blob.extend_from_slice(DUMP_MAGIC);
+    blob.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    blob.extend_from_slice(payload);
+    blob.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    blob
+}
+
+#[cfg(feature = "serde")]
+/// Validate the framing of a dump and return the payload.
+fn unframe(blob: &[u8]) -> Result<&[u8]> {
+    if blob.len() < HEADER_LEN || &blob[..DUMP_MAGIC.len()] != DUMP_MAGIC {
+        return Err(Error::CorruptIndex {
+            what: "header",
+            expected: u32::from_le_bytes(*DUMP_MAGIC) as u64,
+            found: blob
+                .get(..4)
+                .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as u64),
+        });
+    }
+    let mut len = [0; 8];
+    len.copy_from_slice(&blob[DUMP_MAGIC.len()..HEADER_LEN]);
+    let len = u64::from_le_bytes(len);
+    let expected = HEADER_LEN as u64 + len + FOOTER_LEN as u64;
+    if blob.len() as u64 != expected {
+        return Err(Error::CorruptIndex {
+            what: "length in bytes",
+            expected,
+            found: blob.len() as u64,
+        });
+    }
+    let (payload, crc) = blob[HEADER_LEN..].split_at(len as usize);
+    let crc = u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]);
+    let found = crc32fast::hash(payload);
+    if crc != found {
+        return Err(Error::CorruptIndex {
+            what: "checksum",
+            expected: crc as u64,
+            found: found as u64,
+        });
+    }
+    Ok(payload)
+}
+
+#[cfg(feature = "serde")]
+/// Intermediate data structure for serialization. Only contains the absolute
+/// necessities for reproducible results. The components are serialized separately so
+/// that they can be skipped during deserialization.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "I: DeserializeOwned"))]
+struct IntermediatBlob<'a, I> {
+    #[serde(borrow)]
+    hash_tables: &'a [u8],
+    #[serde(borrow)]
+    vec_store: &'a [u8],
+    #[serde(borrow)]
+    hashers: &'a [u8],
+    n_hash_tables: usize,
+    n_projections: usize,
+    dim: usize,
+    _seed: u64,
+    /// Missing in dumps from before the summation was recorded.
+    #[serde(borrow, deserialize_with = "deserialize_trailing")]
+    hash_flavor: Option<&'a str>,
+    /// Missing in dumps from before user ids.
+    #[serde(deserialize_with = "deserialize_trailing")]
+    user_ids: Vec<(I, u64)>,
+    /// Missing in dumps from before payloads.
+    #[serde(deserialize_with = "deserialize_trailing")]
+    payloads: Vec<(I, Vec<u8>)>,
+    /// Missing (0) in dumps from before the layout of the hashers was versioned.
+    #[serde(deserialize_with = "deserialize_trailing")]
+    hashers_version: u32,
+}
+
+#[cfg(feature = "serde")]
+/// The hashers of [export_hashers](struct.LSH.html#method.export_hashers).
+#[derive(Serialize, Deserialize)]
+struct HasherBlob<'a> {
+    #[serde(borrow)]
+    hashers: &'a [u8],
+    n_hash_tables: usize,
+    n_projections: usize,
+    dim: usize,
+    _seed: u64,
+    #[serde(borrow)]
+    hash_flavor: &'a str,
+    /// Missing (0) in exports from before the layout of the hashers was versioned.
+    #[serde(deserialize_with = "deserialize_trailing")]
+    hashers_version: u32,
+}
+
+#[cfg(feature = "serde")]
+/// Older dumps end before the fields that were added later, so a missing field is read as its
+/// default.
+fn deserialize_trailing<'de, D, T>(d: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(T::deserialize(d).unwrap_or_default())
+}
+
+/// Counter of the buckets that queries skipped. Queries take `&self`, so the counter is atomic.
+#[derive(Debug, Default)]
+struct SkipCounter(AtomicU64);
+
+impl SkipCounter {
+    fn increment(&self) {
+        self.add(1);
+    }
+
+    fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for SkipCounter {
+    fn clone(&self) -> Self {
+        SkipCounter(AtomicU64::new(self.get()))
+    }
+}
+
+/// User provided ids of the data points, see
+/// [store_vec_with_id](struct.LSH.html#method.store_vec_with_id).
+#[derive(Debug, Clone, Default)]
+struct UserIds<I: Idx> {
+    by_idx: FnvHashMap<I, u64>,
+    by_id: FnvHashMap<u64, I>,
+}
+
+impl<I: Idx> UserIds<I> {
+    fn insert(&mut self, idx: I, id: u64) {
+        self.by_idx.insert(idx, id);
+        self.by_id.insert(id, idx);
+    }
+
+    fn remove(&mut self, idx: I) {
+        if let Some(id) = self.by_idx.remove(&idx) {
+            self.by_id.remove(&id);
+        }
+    }
+
+    /// Pairs of internal and user id, sorted by internal id.
+    #[cfg(feature = "serde")]
+    fn to_pairs(&self) -> Vec<(I, u64)> {
+        let mut pairs: Vec<_> = self.by_idx.iter().map(|(&idx, &id)| (idx, id)).collect();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    #[cfg(feature = "serde")]
+    fn from_pairs(pairs: &[(I, u64)]) -> Self {
+        let mut user_ids = UserIds::default();
+        for &(idx, id) in pairs {
+            user_ids.insert(idx, id);
+        }
+        user_ids
+    }
+}
+
+/// Check that an index was hashed with the summation of this build. Other summations round
+/// differently, so the query hashes wouldn't match the stored hashes.
+pub(crate) fn check_hash_flavor(found: Option<&str>) -> Result<()> {
+    match found {
+        Some(found) if found != HASH_FLAVOR => Err(Error::HashFlavorMismatch {
+            expected: HASH_FLAVOR,
+            found: found.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Check that serialized hashers have the layout of this version. The fields of the hashers
+/// are read in order, so hashers of another layout can't be read.
+#[cfg(feature = "serde")]
+fn check_hashers_version(found: u32) -> Result<()> {
+    if found != HASHERS_VERSION {
+        return Err(Error::CorruptIndex {
+            what: "hasher layout version",
+            expected: HASHERS_VERSION as u64,
+            found: found as u64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+/// A bucket in the portable export.
+#[derive(Serialize, Deserialize)]
+struct PortableBucket<K, I> {
+    hash: Vec<K>,
+    ids: Vec<I>,
+}
+
+/// Check that the ids of the buckets of a portable export occur once per hash table and index
+/// into the `n_vectors` exported data points.
+#[cfg(feature = "serde")]
+fn check_portable_ids<K, I: Idx>(
+    hash_tables: &[Vec<PortableBucket<K, I>>],
+    n_vectors: Option<usize>,
+) -> Result<()> {
+    for (i, tbl) in hash_tables.iter().enumerate() {
+        let mut seen = FnvHashSet::default();
+        for &idx in tbl.iter().flat_map(|b| &b.ids) {
+            if !seen.insert(idx) {
+                return Err(Error::Failed(format!(
+                    "id {} occurs multiple times in hash table {}",
+                    idx.index(),
+                    i
+                )));
+            }
+            if let Some(n) = n_vectors {
+                if idx.index() >= n {
+                    return Err(Error::Failed(format!(
+                        "id {} of hash table {} has no data point, {} are exported",
+                        idx.index(),
+                        i,
+                        n
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+/// Portable export, see [export_portable](struct.LSH.html#method.export_portable).
+#[derive(Serialize)]
+struct PortableRef<'a, H, N, K, I> {
+    format: &'static str,
+    version: u32,
+    hash_family: Option<HashFamily>,
+    n_projections: usize,
+    n_hash_tables: usize,
+    dim: usize,
+    seed: u64,
+    hash_flavor: &'static str,
+    hashers: &'a [H],
+    hash_tables: Vec<Vec<PortableBucket<K, I>>>,
+    vectors: Option<Vec<&'a [N]>>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct Portable<H, N, K, I> {
+    format: String,
+    version: u32,
+    hash_family: Option<HashFamily>,
+    n_projections: usize,
+    n_hash_tables: usize,
+    dim: usize,
+    seed: u64,
+    #[serde(default)]
+    hash_flavor: Option<String>,
+    hashers: Vec<H>,
+    hash_tables: Vec<Vec<PortableBucket<K, I>>>,
+    vectors: Option<Vec<Vec<N>>>,
+}
+
+#[cfg(feature = "serde")]
+impl<H, N, K, I> LSH<H, N, MemoryTable<N, K, I>, K>
+where
+    H: Serialize + DeserializeOwned + VecHash<N, K>,
+    N: Numeric + DeserializeOwned,
+    K: Integer + DeserializeOwned,
+    I: Idx,
+{
+    /// Deserialize MemoryTable backend
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.load_parts(path, Parts::ALL)
+    }
+
+    /// Deserialize only the given [Parts](struct.Parts.html) of a MemoryTable backend.
+    /// Parts that are not requested are left untouched.
+    ///
+    /// Loading the hash tables without the vectors is useful when only
+    /// [query_bucket_ids](struct.LSH.html#method.query_bucket_ids) is needed. Loading the vectors
+    /// requires hash tables, either loaded in the same call or already present.
+    pub fn load_parts<P: AsRef<Path>>(&mut self, path: P, parts: Parts) -> Result<()> {
+        let mut f = File::open(path)?;
+        let mut buf: Vec<u8> = vec![];
+        f.read_to_end(&mut buf)?;
+
+        let ib: IntermediatBlob<I> = bincode::deserialize(unframe(&buf)?)?;
+        self.load_blob(&ib, parts)
     }
 
-    /// Store a single vector in storage. Returns id.
+    /// Open the dump at `path`, or create a new index from `cfg` if the file doesn't exist.
+    /// The hash family, hash length, number of hash tables, dimension and (if set) seed of the
+    /// dump have to match `cfg`. Settings that aren't part of the dump, like multi-probing,
+    /// are taken from `cfg`.
     ///
     /// # Arguments
-    /// * `v` - Data point.
+    /// * `path` - Path of a [dump](#method.dump).
+    /// * `cfg` - Configuration of the index.
     ///
     /// # Examples
     /// ```
     /// use lsh_rs::prelude::*;
-    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
-    /// let v = &[2., 3., 4.];
-    /// let id = lsh.store_vec(v);
+    /// let path = std::env::temp_dir().join("lsh_open_or_create.bincode");
+    /// # std::fs::remove_file(&path).ok();
+    /// let mut cfg = LshConfig::new(HashFamily::L2 { r: 4. }, 5, 10, 3);
+    /// cfg.seed = 1;
+    /// let mut lsh = LshMem::<L2<f32, i32>, f32, i32>::open_or_create(&path, &cfg).unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// lsh.dump(&path).unwrap();
+    ///
+    /// let lsh = LshMem::<L2<f32, i32>, f32, i32>::open_or_create(&path, &cfg).unwrap();
+    /// assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![0]);
     /// ```
-    pub fn store_vec(&mut self, v: &[N]) -> Result<u32> {
-        self.validate_vec(v)?;
+    pub fn open_or_create<P: AsRef<Path>>(path: P, cfg: &LshConfig) -> Result<Self>
+    where
+        H: FromHashFamily<N, K>,
+    {
+        let mut lsh = Self::from_config(cfg)?;
+        let mut f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(lsh),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf: Vec<u8> = vec![];
+        f.read_to_end(&mut buf)?;
 
-        let mut idx = 0;
-        let mut ht = self.hash_tables.take().unwrap();
-        for (i, proj) in self.hashers.iter().enumerate() {
-            let hash = proj.hash_vec_put(v);
-            idx = ht.put(hash, &v, i)?;
+        let ib: IntermediatBlob<I> = bincode::deserialize(unframe(&buf)?)?;
+        let mismatch = |what: &str, found: String, expected: String| {
+            Err(Error::Failed(format!(
+                "dump has {} {}, configuration expects {}",
+                what, found, expected
+            )))
+        };
+        if ib.n_hash_tables != cfg.n_hash_tables {
+            return mismatch(
+                "n_hash_tables",
+                ib.n_hash_tables.to_string(),
+                cfg.n_hash_tables.to_string(),
+            );
         }
-        self.hash_tables.replace(ht);
-        Ok(idx)
-    }
-
-    /// Update a data point in the `hash_tables`.
-    ///
-    /// # Arguments
-    /// * `idx` - Id of the hash that needs to be updated.
-    /// * `new_v` - New data point that needs to be hashed.
-    /// * `old_v` - Old data point. Needed to remove the old hash.
-    pub fn update_by_idx(&mut self, idx: u32, new_v: &[N], old_v: &[N]) -> Result<()> {
-        let mut ht = self.hash_tables.take().unwrap();
-        for (i, proj) in self.hashers.iter().enumerate() {
-            let new_hash = proj.hash_vec_put(new_v);
-            let old_hash = proj.hash_vec_put(old_v);
-            ht.update_by_idx(&old_hash, new_hash, idx, i)?;
+        if ib.n_projections != cfg.n_projections {
+            return mismatch(
+                "n_projections",
+                ib.n_projections.to_string(),
+                cfg.n_projections.to_string(),
+            );
         }
-        self.hash_tables.replace(ht);
-        Ok(())
+        if ib.dim != cfg.dim {
+            return mismatch("dim", ib.dim.to_string(), cfg.dim.to_string());
+        }
+        if cfg.seed != 0 && ib._seed != cfg.seed {
+            return mismatch("seed", ib._seed.to_string(), cfg.seed.to_string());
+        }
+        lsh.load_blob(&ib, Parts::ALL)?;
+        let family = lsh.hashers[0].family();
+        if family != cfg.hasher {
+            return mismatch(
+                "hash family",
+                format!("{:?}", family),
+                format!("{:?}", cfg.hasher),
+            );
+        }
+        Ok(lsh)
     }
 
-    fn query_bucket_union(&self, v: &[N]) -> Result<Bucket> {
-        self.validate_vec(v)?;
-        if self._multi_probe {
-            return self.multi_probe_bucket_union(v);
+    fn load_blob(&mut self, ib: &IntermediatBlob<I>, parts: Parts) -> Result<()> {
+        check_hash_flavor(ib.hash_flavor)?;
+        if parts.contains(Parts::HASHERS) {
+            check_hashers_version(ib.hashers_version)?;
+            self.hashers = bincode::deserialize(ib.hashers)?;
         }
-
-        let mut bucket_union = FnvHashSet::default();
-
-        for (i, proj) in self.hashers.iter().enumerate() {
-            let hash = proj.hash_vec_query(v);
-            self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+        if parts.contains(Parts::TABLES) {
+            let mut ht: Option<MemoryTable<N, K, I>> = bincode::deserialize(ib.hash_tables)?;
+            if let Some(ht) = ht.as_mut() {
+                if self._packed_keys {
+                    ht.packed_keys()?;
+                }
+                ht.set_payloads(ib.payloads.clone());
+            }
+            self.hash_tables = ht;
+            self.user_ids = UserIds::from_pairs(&ib.user_ids);
         }
-        Ok(bucket_union)
+        if parts.contains(Parts::VECTORS) {
+            let vec_store: Option<VecStore<N>> = bincode::deserialize(ib.vec_store)?;
+            match (self.hash_tables.as_mut(), vec_store) {
+                (Some(ht), Some(vec_store)) => ht.vec_store = vec_store,
+                (None, Some(_)) => {
+                    return Err(Error::Failed(
+                        "No hash tables to load the vectors in.".to_string(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        self.n_hash_tables = ib.n_hash_tables;
+        self.n_projections = ib.n_projections;
+        self.dim = ib.dim;
+        self._seed = ib._seed;
+
+        Ok(())
     }
 
-    /// Query all buckets in the hash tables. The union of the matching buckets over the `L`
-    /// hash tables is returned
+    /// Serialize MemoryTable backend
     ///
-    /// # Arguments
-    /// * `v` - Query vector
-    pub fn query_bucket(&self, v: &[N]) -> Result<Vec<&Vec<N>>> {
-        self.validate_vec(v)?;
-        if self.only_index_storage {
-            return Err(Error::Failed(
-                "cannot query bucket, use query_bucket_ids".to_string(),
-            ));
-        }
-        let bucket_union = self.query_bucket_union(v)?;
+    /// The dump is written to a temporary file that replaces `path` once it is complete, so an
+    /// existing dump stays intact if writing fails.
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_dump(path.as_ref())?;
+        Ok(())
+    }
 
-        bucket_union
-            .iter()
-            .map(|&idx| Ok(self.hash_tables.as_ref().unwrap().idx_to_datapoint(idx)?))
-            .collect()
+    /// Write a dump to `path` and return the checksum of its payload.
+    fn write_dump(&self, path: &Path) -> Result<u32> {
+        let hash_tables = bincode::serialize(&self.hash_tables)?;
+        let vec_store = bincode::serialize(&self.hash_tables.as_ref().map(|ht| &ht.vec_store))?;
+        let hashers = bincode::serialize(&self.hashers)?;
+
+        let ib = IntermediatBlob {
+            hash_tables: &hash_tables,
+            vec_store: &vec_store,
+            hashers: &hashers,
+            n_hash_tables: self.n_hash_tables,
+            n_projections: self.n_projections,
+            dim: self.dim,
+            _seed: self._seed,
+            hash_flavor: Some(HASH_FLAVOR),
+            user_ids: self.user_ids.to_pairs(),
+            payloads: self
+                .hash_tables
+                .as_ref()
+                .map_or_else(Vec::new, MemoryTable::payload_pairs),
+            hashers_version: HASHERS_VERSION,
+        };
+        let payload = bincode::serialize(&ib)?;
+        let blob = frame(&payload);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&blob)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(crc32fast::hash(&payload))
     }
 
-    /// Query all buckets in the hash tables and return the data point indexes. The union of the
-    /// matching buckets of `L` hash tables is returned.
+    /// Keep a write-ahead log, so that the index survives restarts without a full
+    /// [dump](#method.dump) after every change. A snapshot (a dump) of the index is written to
+    /// `path`, and every following change is appended as a compact record to a log next to it,
+    /// at `path` with a `.wal` extension. [recover](#method.recover) loads the snapshot and
+    /// replays the log, [compact](#method.compact) merges the log into a new snapshot.
+    ///
+    /// The log records the data points that are stored, updated or deleted, user ids, payloads
+    /// and ingestion sessions. The raw inserts and removals and imported hashes are only
+    /// persisted by [compact](#method.compact). [rebuild](#method.rebuild),
+    /// [add_hash_tables](#method.add_hash_tables) and
+    /// [drop_hash_tables](#method.drop_hash_tables) fail while the log is enabled, see
+    /// [disable_wal](#method.disable_wal). A record is written and flushed to the disk before
+    /// the change is made, so the change survives a crash. Clones of the index don't write to
+    /// the log.
     ///
     /// # Arguments
-    /// * `v` - Query vector
-    pub fn query_bucket_ids(&self, v: &[N]) -> Result<Vec<u32>> {
-        self.validate_vec(v)?;
-        let bucket_union = self.query_bucket_union(v)?;
-        Ok(bucket_union.iter().copied().collect())
+    /// * `path` - Path of the snapshot.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let path = std::env::temp_dir().join("lsh_enable_wal.bincode");
+    /// let mut lsh = LshMem::<_, f32>::new(5, 10, 3).seed(1).srp().unwrap();
+    /// lsh.enable_wal(&path).unwrap();
+    /// let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+    ///
+    /// let mut recovered = LshMem::<_, f32>::new(5, 10, 3).srp().unwrap();
+    /// recovered.recover(&path).unwrap();
+    /// assert_eq!(recovered.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![id]);
+    /// ```
+    pub fn enable_wal<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.write_snapshot(path.as_ref())
     }
 
-    /// Query bucket collision for a batch of data points.
-    ///
-    /// # Arguments
-    /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch(&self, vs: &[Vec<N>]) -> Result<Vec<Vec<u32>>> {
-        vs.iter().map(|v| self.query_bucket_ids(v)).collect()
+    /// Stop logging the changes, e.g. to [rebuild](#method.rebuild) the index. The snapshot
+    /// and its log are left on disk. Enable the log again with [enable_wal](#method.enable_wal),
+    /// which writes a new snapshot.
+    pub fn disable_wal(&mut self) {
+        self.wal = WalWriter::default();
     }
 
-    /// Query bucket collision for a batch of data points.
-    ///
-    /// # Arguments
-    /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch_arr(&self, vs: ArrayView2<N>) -> Result<Vec<Vec<u32>>> {
-        vs.axis_iter(Axis(0))
-            .map(|v| self.query_bucket_ids(v.as_slice().unwrap()))
-            .collect()
+    /// Merge the write-ahead log into a new snapshot and start an empty log. See
+    /// [enable_wal](#method.enable_wal).
+    pub fn compact(&mut self) -> Result<()> {
+        let path = match self.wal.path() {
+            Some(path) => path.to_path_buf(),
+            None => {
+                return Err(Error::Failed(
+                    "no write-ahead log, see enable_wal".to_string(),
+                ))
+            }
+        };
+        self.write_snapshot(&path)
     }
 
-    /// Delete data point from storage. This does not free memory as the storage vector isn't resized.
+    /// Load the snapshot at `path` and replay its write-ahead log, see
+    /// [enable_wal](#method.enable_wal). Like [load](#method.load), the settings that aren't
+    /// part of the snapshot are kept. Changes are logged again afterwards.
     ///
-    /// # Arguments
-    /// * `v` - Data point
-    pub fn delete_vec(&mut self, v: &[N]) -> Result<()> {
-        self.validate_vec(v)?;
-        for (i, proj) in self.hashers.iter().enumerate() {
-            let hash = proj.hash_vec_query(v);
-            let mut ht = self.hash_tables.take().unwrap();
-            ht.delete(&hash, v, i).unwrap_or_default();
-            self.hash_tables = Some(ht)
+    /// A record that was only partly written, e.g. because the process crashed during the
+    /// append, ends the log and is cut off. An ingestion session that wasn't committed is
+    /// rolled back.
+    pub fn recover<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        self.wal = WalWriter::default();
+        let mut buf: Vec<u8> = vec![];
+        File::open(path)?.read_to_end(&mut buf)?;
+        let payload = unframe(&buf)?;
+        let snapshot = crc32fast::hash(payload);
+        let ib: IntermediatBlob<I> = bincode::deserialize(payload)?;
+        self.load_blob(&ib, Parts::ALL)?;
+
+        let log = match std::fs::read(wal::log_path(path)) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        // without a log of this snapshot, e.g. after a crash during compact, the snapshot holds
+        // every change.
+        if !wal::check_header(&log, snapshot, std::mem::size_of::<N>())? {
+            self.wal = WalWriter::create(path, snapshot, std::mem::size_of::<N>())?;
+            return Ok(());
         }
+        let (records, len) = wal::decode::<N>(&log)?;
+        self.replay(records)?;
+        self.wal = WalWriter::open(path, len)?;
         Ok(())
     }
 
-    pub(crate) fn process_bucket_union_result(
-        &self,
-        hash: &[K],
-        hash_table_idx: usize,
-        bucket_union: &mut Bucket,
-    ) -> Result<()> {
-        match self
-            .hash_tables
-            .as_ref()
-            .unwrap()
-            .query_bucket(hash, hash_table_idx)
-        {
-            Err(Error::NotFound) => Ok(()),
-            Ok(bucket) => {
-                *bucket_union = bucket_union.union(&bucket).copied().collect();
-                Ok(())
+    fn write_snapshot(&mut self, path: &Path) -> Result<()> {
+        let snapshot = self.write_dump(path)?;
+        self.wal = WalWriter::create(path, snapshot, std::mem::size_of::<N>())?;
+        Ok(())
+    }
+
+    fn replay(&mut self, records: Vec<Record<'_, N>>) -> Result<()> {
+        let mut in_session = false;
+        for record in records {
+            match record {
+                Record::Store(idx, v) => {
+                    let found = self.store_vec(&v)?.index() as u64;
+                    if found != idx {
+                        return Err(Error::CorruptIndex {
+                            what: "id in the write-ahead log",
+                            expected: idx,
+                            found,
+                        });
+                    }
+                }
+                Record::Delete(v) => self.delete_vec(&v)?,
+                Record::DeleteIdx(idx) => {
+                    wal::replayed(self.delete_by_idx(I::from_index(idx as usize)))?
+                }
+                Record::UserId(idx, id) => self.user_ids.insert(I::from_index(idx as usize), id),
+                Record::Payload(idx, payload) => wal::replayed(
+                    self.hash_tables
+                        .as_mut()
+                        .unwrap()
+                        .put_payload(I::from_index(idx as usize), payload),
+                )?,
+                Record::Update(idx, new_v, old_v) => {
+                    wal::replayed(self.update_by_idx(I::from_index(idx as usize), &new_v, &old_v))?
+                }
+                Record::Begin => {
+                    self.hash_tables.as_mut().unwrap().begin_ingest()?;
+                    in_session = true;
+                }
+                Record::Commit => {
+                    self.hash_tables.as_mut().unwrap().commit_ingest()?;
+                    in_session = false;
+                }
+                Record::Rollback => {
+                    self.hash_tables.as_mut().unwrap().rollback_ingest()?;
+                    in_session = false;
+                }
             }
-            Err(e) => Err(e),
         }
+        if in_session {
+            self.hash_tables.as_mut().unwrap().rollback_ingest()?;
+        }
+        Ok(())
     }
 }
 
-#[cfg(feature = "sqlite")]
-impl<N, H, K> LSH<H, N, SqlTable<N, K>, K>
+#[cfg(feature = "serde")]
+impl<H, N, K, I> LSH<H, N, MemoryTable<N, K, I>, K>
 where
-    N: Numeric,
-    H: VecHash<N, K> + Serialize,
-    K: Integer,
+    H: Serialize + DeserializeOwned + FromHashFamily<N, K>,
+    N: Numeric + DeserializeOwned,
+    K: Integer + DeserializeOwned,
+    I: Idx,
 {
-    /// Commit SqlTable backend
-    pub fn commit(&mut self) -> Result<()> {
-        let ht = self.hash_tables.as_mut().unwrap();
-        ht.commit()?;
+    /// Export the hashers, the bucket assignments and the data points in a documented JSON
+    /// layout, so that they can be inspected or reused by other tools (e.g. numpy or FAISS).
+    /// Read it back with [import_portable](#method.import_portable).
+    ///
+    /// The layout is:
+    ///
+    /// ```text
+    /// {
+    ///   "format": "lsh-rs-portable",
+    ///   "version": 2,
+    ///   // the hash family and its parameters, see HashFamily.
+    ///   "hash_family": {"family": "l2", "r": 4.0},
+    ///   "n_projections": K, "n_hash_tables": L, "dim": d, "seed": 0,
+    ///   // summation of the projections: "ndarray", "blas", "simd" or "stable".
+    ///   "hash_flavor": "ndarray",
+    ///   // one object per hash table with the fields of the hasher. Matrices are stored
+    ///   // row-major as {"v": 1, "dim": [rows, cols], "data": [...]}.
+    ///   "hashers": [{...}, ...],
+    ///   // per hash table, the buckets sorted by hash. ids index into "vectors".
+    ///   "hash_tables": [[{"hash": [...], "ids": [...]}, ...], ...],
+    ///   // the data points, or null if only indexes are stored.
+    ///   "vectors": [[...], ...]
+    /// }
+    /// ```
+    pub fn export_portable<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        let hash_tables = (0..self.n_hash_tables)
+            .map(|i| {
+                let mut buckets: Vec<_> = ht
+                    .buckets(i)
+                    .map(|(hash, bucket)| {
+                        let mut ids: Vec<I> = bucket.iter().copied().collect();
+                        ids.sort_unstable();
+                        PortableBucket {
+                            hash: hash.into_owned(),
+                            ids,
+                        }
+                    })
+                    .collect();
+                buckets.sort_unstable_by(|a, b| a.hash.cmp(&b.hash));
+                buckets
+            })
+            .collect();
+        let vectors = if self.only_index_storage {
+            None
+        } else {
+            Some(
+                (0..ht.vec_store.len())
+                    .map(|idx| ht.vec_store.get(idx))
+                    .collect(),
+            )
+        };
+        let portable = PortableRef {
+            format: PORTABLE_FORMAT,
+            version: PORTABLE_VERSION,
+            hash_family: self.hashers.first().map(|h| h.family()),
+            n_projections: self.n_projections,
+            n_hash_tables: self.n_hash_tables,
+            dim: self.dim,
+            seed: self._seed,
+            hash_flavor: HASH_FLAVOR,
+            hashers: &self.hashers,
+            hash_tables,
+            vectors,
+        };
+        let mut f = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut f, &portable)?;
+        f.flush()?;
         Ok(())
     }
 
-    /// Init transaction of SqlTable backend.
-    pub fn init_transaction(&mut self) -> Result<()> {
-        let ht = self.hash_tables.as_mut().unwrap();
-        ht.init_transaction()?;
+    /// Import an export of [export_portable](#method.export_portable). The hash family has to
+    /// match the exported hash family. Every id can occur once per hash table and has to index
+    /// into the data points, if they are exported. The data points are restored in the nested
+    /// storage layout.
+    pub fn import_portable<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let f = BufReader::new(File::open(path)?);
+        let portable: Portable<H, N, K, I> = serde_json::from_reader(f)?;
+        if portable.format != PORTABLE_FORMAT || portable.version != PORTABLE_VERSION {
+            return Err(Error::Failed(format!(
+                "unsupported format {} version {}",
+                portable.format, portable.version
+            )));
+        }
+        // hashers of other families can have the same fields, e.g. L1 and L2.
+        let family = portable.hashers.first().map(|h| h.family());
+        if family.as_ref().map(std::mem::discriminant)
+            != portable.hash_family.as_ref().map(std::mem::discriminant)
+        {
+            return Err(Error::Failed(format!(
+                "export holds hash family {:?}, expected {:?}",
+                portable.hash_family, family
+            )));
+        }
+        check_hash_flavor(portable.hash_flavor.as_deref())?;
+        if portable.hashers.len() != portable.n_hash_tables
+            || portable.hash_tables.len() != portable.n_hash_tables
+        {
+            return Err(Error::Failed(
+                "number of hashers and hash tables don't match n_hash_tables".to_string(),
+            ));
+        }
+        check_portable_ids(
+            &portable.hash_tables,
+            portable.vectors.as_ref().map(Vec::len),
+        )?;
+        let buckets = portable
+            .hash_tables
+            .into_iter()
+            .map(|tbl| tbl.into_iter().map(|b| (b.hash, b.ids)).collect())
+            .collect();
+        self.only_index_storage = portable.vectors.is_none();
+        let mut ht = MemoryTable::from_buckets(buckets, portable.vectors);
+        if self._packed_keys {
+            ht.packed_keys()?;
+        }
+        self.hash_tables = Some(ht);
+        self.hashers = portable.hashers;
+        self.n_hash_tables = portable.n_hash_tables;
+        self.n_projections = portable.n_projections;
+        self.dim = portable.dim;
+        self._seed = portable.seed;
         Ok(())
     }
 }
 
-/// Intermediate data structure for serialization. Only contains the absolute
-/// necessities for reproducible results.
-#[derive(Serialize, Deserialize)]
-struct IntermediatBlob {
-    hash_tables: Vec<u8>,
-    hashers: Vec<u8>,
-    n_hash_tables: usize,
-    n_projections: usize,
-    dim: usize,
-    _seed: u64,
-}
-
-impl<H, N, K> LSH<H, N, MemoryTable<N, K>, K>
+impl<H, N, K, I> LSH<H, N, MemoryTable<N, K, I>, K>
 where
-    H: Serialize + DeserializeOwned + VecHash<N, K>,
-    N: Numeric + DeserializeOwned,
-    K: Integer + DeserializeOwned,
+    H: VecHash<N, K>,
+    N: Numeric,
+    K: Integer,
+    I: Idx,
 {
-    /// Deserialize MemoryTable backend
-    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let mut f = File::open(path)?;
-        let mut buf: Vec<u8> = vec![];
-        f.read_to_end(&mut buf)?;
+    /// The hash tables can't be changed while the write-ahead log is enabled, as the log
+    /// records the changes of the data points only.
+    fn check_wal_disabled(&self, change: &str) -> Result<()> {
+        if self.wal.is_enabled() {
+            return Err(Error::Failed(format!(
+                "{} isn't part of the write-ahead log, disable it first",
+                change
+            )));
+        }
+        Ok(())
+    }
 
-        let ib: IntermediatBlob = bincode::deserialize(&buf)?;
-        self.hashers = bincode::deserialize(&ib.hashers)?;
-        self.hash_tables = bincode::deserialize(&ib.hash_tables)?;
-        self.n_hash_tables = ib.n_hash_tables;
-        self.n_projections = ib.n_projections;
-        self.dim = ib.dim;
-        self._seed = ib._seed;
+    /// Take a consistent point in time copy of the index, e.g. for a backup while ingestion
+    /// continues. Copying the index is a memory copy, which is much faster than the
+    /// serialization and disk writes of [dump](struct.LSH.html#method.dump). The snapshot can be
+    /// dumped from another thread, while data points are stored in the original.
+    ///
+    /// # Examples
+    #[cfg_attr(feature = "serde", doc = "```")]
+    #[cfg_attr(not(feature = "serde"), doc = "```ignore")]
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    ///
+    /// let snapshot = lsh.snapshot();
+    /// let path = std::env::temp_dir().join("lsh_snapshot.bincode");
+    /// let backup = std::thread::spawn(move || snapshot.dump(path));
+    /// lsh.store_vec(&[-1., 0., 2.]).unwrap();
+    /// backup.join().unwrap().unwrap();
+    /// ```
+    pub fn snapshot(&self) -> Self
+    where
+        H: Clone,
+    {
+        self.clone()
+    }
+
+    /// Re-hash all stored data points into fresh hash tables with `n_projections` and
+    /// `n_hash_tables`, e.g. when the index has outgrown its initial parameters. The indexes of
+    /// the data points are preserved and deleted data points stay deleted. The other settings
+    /// (seed, multi-probe, storage layout etc.), the user ids and payloads are kept.
+    ///
+    /// Fails if only the indexes are stored, as there is nothing to re-hash, or while the
+    /// write-ahead log is enabled, see [disable_wal](#method.disable_wal).
+    ///
+    /// # Arguments
+    /// * `n_projections` - Hash length of the new hash tables.
+    /// * `n_hash_tables` - Number of new hash tables.
+    /// * `build` - Finish the builder with the hash family, e.g. `|lsh| lsh.srp()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 2, 3).srp().unwrap();
+    /// lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., 0., 2.]]).unwrap();
+    /// lsh.rebuild(8, 10, |lsh| lsh.srp()).unwrap();
+    /// assert_eq!(lsh.n_hash_tables, 10);
+    /// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&0));
+    /// ```
+    pub fn rebuild<F>(&mut self, n_projections: usize, n_hash_tables: usize, build: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<Self>,
+    {
+        self.check_wal_disabled("rebuild")?;
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot rebuild, the data points are not stored".to_string(),
+            ));
+        }
+        let mut lsh = build(&mut self.builder_like(n_projections, n_hash_tables))?;
 
+        let old = self.hash_tables.as_ref().unwrap();
+        if lsh.needs_fit() {
+            let vs: Vec<Vec<N>> = (0..old.vec_store.len())
+                .map(|i| old.vec_store.get(i).to_vec())
+                .collect();
+            lsh.fit_hashers(&vs)?;
+        }
+        let live: FnvHashSet<I> = old
+            .buckets(0)
+            .flat_map(|(_, b)| b.iter().copied())
+            .collect();
+        lsh.increase_storage(old.vec_store.len())?;
+        for i in 0..old.vec_store.len() {
+            let idx = I::from_index(i);
+            let v = old.vec_store.get(i);
+            // store every data point, so that the indexes are preserved.
+            lsh.store_vec(v)?;
+            if !live.contains(&idx) {
+                let ht = lsh.hash_tables.as_mut().unwrap();
+                for (i, proj) in lsh.hashers.iter().enumerate() {
+                    ht.remove_idx(idx, &proj.hash_vec_put(v), i)?;
+                }
+            }
+        }
+        lsh.hash_tables
+            .as_mut()
+            .unwrap()
+            .set_payloads(old.payload_pairs());
+        lsh.user_ids = std::mem::take(&mut self.user_ids);
+        lsh.wal = std::mem::take(&mut self.wal);
+        *self = lsh;
         Ok(())
     }
 
-    /// Serialize MemoryTable backend
-    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let hash_tables = bincode::serialize(&self.hash_tables)?;
-        let hashers = bincode::serialize(&self.hashers)?;
+    /// Add `n` hash tables, e.g. to increase recall without a full [rebuild](#method.rebuild).
+    /// The stored data points are hashed into the new tables. With a seed, the new hash tables
+    /// are the ones that an index with `n_hash_tables + n` tables would have.
+    ///
+    /// Fails if only the indexes are stored and the index isn't empty, as there is nothing to
+    /// hash, or while the write-ahead log is enabled, see [disable_wal](#method.disable_wal).
+    ///
+    /// # Arguments
+    /// * `n` - Number of hash tables to add.
+    /// * `build` - Finish the builder with the hash family, e.g. `|lsh| lsh.srp()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// let mut lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+    /// lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., 0., 2.]]).unwrap();
+    /// lsh.add_hash_tables(3, |lsh| lsh.srp()).unwrap();
+    /// assert_eq!(lsh.n_hash_tables, 5);
+    /// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&0));
+    /// ```
+    pub fn add_hash_tables<F>(&mut self, n: usize, build: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<Self>,
+    {
+        self.check_wal_disabled("add_hash_tables")?;
+        let ht = self.hash_tables.as_ref().unwrap();
+        if self.only_index_storage && ht.buckets(0).next().is_some() {
+            return Err(Error::Failed(
+                "cannot add hash tables, the data points are not stored".to_string(),
+            ));
+        }
+        let n_hash_tables = self.n_hash_tables + n;
+        // the hashers of the first tables are drawn first, so the builder reproduces the
+        // existing hashers and only the new ones are kept.
+        let mut builder = self.builder_like(self.n_projections, n_hash_tables);
+        let mut new_hashers = build(&mut builder)?.hashers.split_off(self.n_hash_tables);
 
-        let ib = IntermediatBlob {
-            hash_tables,
-            hashers,
-            n_hash_tables: self.n_hash_tables,
-            n_projections: self.n_projections,
-            dim: self.dim,
-            _seed: self._seed,
-        };
-        let mut f = File::create(path)?;
-        let blob = bincode::serialize(&ib)?;
-        f.write(&blob)?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        let live: Vec<I> = ht.buckets(0).flat_map(|(_, b)| b.iter().copied()).collect();
+        if new_hashers.iter().any(|h| h.as_fit().is_some()) {
+            // fit the new hashers on the stored data points.
+            let vs: Vec<Vec<N>> = live
+                .iter()
+                .map(|idx| ht.vec_store.get(idx.index()).to_vec())
+                .collect();
+            for h in new_hashers.iter_mut().filter_map(|h| h.as_fit_mut()) {
+                h.fit(&vs)?;
+            }
+        }
+        ht.add_tables(n);
+        for (i, proj) in new_hashers.iter().enumerate() {
+            let entries = live
+                .iter()
+                .map(|&idx| (proj.hash_vec_put(ht.vec_store.get(idx.index())), idx))
+                .collect::<Vec<_>>();
+            ht.insert_ids(&entries, self.n_hash_tables + i)?;
+        }
+        if self._packed_keys {
+            ht.packed_keys()?;
+        }
+        self.hashers.extend(new_hashers);
+        self._projections_per_table = builder._projections_per_table;
+        self.n_hash_tables = n_hash_tables;
+        Ok(())
+    }
+
+    /// Remove the last `n` hash tables to reduce memory, at the cost of recall. At least one
+    /// hash table is kept. Fails while the write-ahead log is enabled, see
+    /// [disable_wal](#method.disable_wal).
+    ///
+    /// # Arguments
+    /// * `n` - Number of hash tables to remove.
+    pub fn drop_hash_tables(&mut self, n: usize) -> Result<()> {
+        self.check_wal_disabled("drop_hash_tables")?;
+        if n >= self.n_hash_tables {
+            return Err(Error::Failed(format!(
+                "cannot drop {} of {} hash tables, at least one should be kept",
+                n, self.n_hash_tables
+            )));
+        }
+        self.n_hash_tables -= n;
+        self.hash_tables.as_mut().unwrap().drop_tables(n);
+        self.hashers.truncate(self.n_hash_tables);
+        if let Some(ks) = self._projections_per_table.as_mut() {
+            ks.truncate(self.n_hash_tables);
+        }
         Ok(())
     }
+
+    /// Like [describe](struct.LSH.html#method.describe), extended with a query benchmark on
+    /// `n_queries` random stored data points. See [benchmark_queries](stats/fn.benchmark_queries.html).
+    pub fn describe_with_benchmark(&self, n_queries: usize) -> Result<String> {
+        let bench = crate::stats::benchmark_queries(self, n_queries, self._seed)?;
+        Ok(format!("{}\n{}", self.describe()?, bench))
+    }
 }
@@ -0,0 +1,173 @@
+use crate::data::{Integer, Numeric};
+use crate::prelude::*;
+
+/// Object safe subset of [LSH](struct.LSH.html) that is needed to index a metric.
+trait MetricIndex<N>: Send + Sync {
+    fn validate_put(&self, v: &[N]) -> Result<()>;
+    fn store_vec(&mut self, v: &[N]) -> Result<u32>;
+    fn query_bucket_ids(&self, v: &[N]) -> Result<Vec<u32>>;
+}
+
+impl<H, N, T, K> MetricIndex<N> for LSH<H, N, T, K>
+where
+    H: VecHash<N, K> + Send + Sync,
+    N: Numeric,
+    T: HashTables<N, K, Id = u32> + Send + Sync,
+    K: Integer,
+{
+    fn validate_put(&self, v: &[N]) -> Result<()> {
+        LSH::validate_put(self, v)
+    }
+
+    fn store_vec(&mut self, v: &[N]) -> Result<u32> {
+        LSH::store_vec(self, v)
+    }
+
+    fn query_bucket_ids(&self, v: &[N]) -> Result<Vec<u32>> {
+        LSH::query_bucket_ids(self, v)
+    }
+}
+
+/// Index the same data points under multiple metrics, e.g. cosine (SRP) and L2.
+///
+/// The data points are stored once and every metric is an index only [LSH](struct.LSH.html)
+/// with its own hash tables. Ids are shared over the metrics.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::prelude::*;
+/// let mut lsh = MultiMetricLSH::new(3);
+/// lsh.add_metric("cosine", LshMem::new(5, 10, 3).only_index().srp().unwrap())
+///     .unwrap()
+///     .add_metric("l2", LshMem::<_, f32, i32>::new(5, 10, 3).only_index().l2(4.).unwrap())
+///     .unwrap();
+/// let idx = lsh.store_vec(&[2., 3., 4.]).unwrap();
+/// assert!(lsh.query_bucket_ids("l2", &[2., 3., 4.]).unwrap().contains(&idx));
+/// assert!(lsh.query_bucket_ids("cosine", &[4., 6., 8.]).unwrap().contains(&idx));
+/// ```
+pub struct MultiMetricLSH<N: Numeric> {
+    dim: usize,
+    vec_store: VecStore<N>,
+    metrics: Vec<(String, Box<dyn MetricIndex<N>>)>,
+}
+
+impl<N: Numeric> MultiMetricLSH<N> {
+    /// # Arguments
+    /// * `dim` - Dimension of the data points.
+    pub fn new(dim: usize) -> Self {
+        MultiMetricLSH {
+            dim,
+            vec_store: VecStore::default(),
+            metrics: vec![],
+        }
+    }
+
+    /// Add a metric. The `lsh` should be created with
+    /// [only_index](struct.LSH.html#method.only_index) and be empty. Data points that are
+    /// already stored are indexed in the new metric.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the metric, used in the queries.
+    /// * `lsh` - Index of the metric.
+    pub fn add_metric<H, T, K>(&mut self, name: &str, lsh: LSH<H, N, T, K>) -> Result<&mut Self>
+    where
+        H: 'static + VecHash<N, K> + Send + Sync,
+        T: 'static + HashTables<N, K, Id = u32> + Send + Sync,
+        K: Integer,
+    {
+        if self.metric(name).is_ok() {
+            return Err(Error::Failed(format!("metric {} already exists", name)));
+        }
+        if !lsh.is_only_index() {
+            return Err(Error::Failed(
+                "metric should be created with only_index".to_string(),
+            ));
+        }
+        lsh.validate_dim(self.dim)?;
+        let mut lsh: Box<dyn MetricIndex<N>> = Box::new(lsh);
+        for idx in 0..self.vec_store.len() as u32 {
+            if lsh.store_vec(self.vec_store.get(idx as usize))? != idx {
+                return Err(Error::Failed(format!("metric {} is not empty", name)));
+            }
+        }
+        self.metrics.push((name.to_string(), lsh));
+        Ok(self)
+    }
+
+    fn metric(&self, name: &str) -> Result<&dyn MetricIndex<N>> {
+        self.metrics
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, lsh)| lsh.as_ref())
+            .ok_or_else(|| Error::Failed(format!("unknown metric {}", name)))
+    }
+
+    /// Names of the metrics.
+    pub fn metrics(&self) -> Vec<&str> {
+        self.metrics.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    /// Store a data point in all metrics. The data point is validated for all metrics before
+    /// it is stored, so a data point that one of the metrics refuses, e.g. a MIPS metric that
+    /// isn't fitted, is stored in none of them.
+    pub fn store_vec(&mut self, v: &[N]) -> Result<u32> {
+        if v.len() != self.dim {
+            return Err(Error::DimensionMismatch {
+                expected: self.dim,
+                actual: v.len(),
+            });
+        }
+        for (_, lsh) in self.metrics.iter() {
+            lsh.validate_put(v)?;
+        }
+        let idx = self.vec_store.len() as u32;
+        for (name, lsh) in self.metrics.iter_mut() {
+            if lsh.store_vec(v)? != idx {
+                return Err(Error::Failed(format!(
+                    "ids of metric {} are out of sync",
+                    name
+                )));
+            }
+        }
+        self.vec_store.push(v);
+        Ok(idx)
+    }
+
+    /// Store a batch of data points in all metrics.
+    pub fn store_vecs(&mut self, vs: &[Vec<N>]) -> Result<Vec<u32>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Query the buckets of `metric` and return the data point indexes.
+    pub fn query_bucket_ids(&self, metric: &str, v: &[N]) -> Result<Vec<u32>> {
+        self.metric(metric)?.query_bucket_ids(v)
+    }
+
+    /// Query the buckets of `metric` and return the data points.
+    pub fn query_bucket(&self, metric: &str, v: &[N]) -> Result<Vec<&[N]>> {
+        Ok(self
+            .query_bucket_ids(metric, v)?
+            .into_iter()
+            .map(|idx| self.vec_store.get(idx as usize))
+            .collect())
+    }
+
+    /// Data point with index `idx`.
+    pub fn get(&self, idx: u32) -> Option<&[N]> {
+        if (idx as usize) < self.vec_store.len() {
+            Some(self.vec_store.get(idx as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Number of stored data points.
+    pub fn len(&self) -> usize {
+        self.vec_store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec_store.is_empty()
+    }
+}
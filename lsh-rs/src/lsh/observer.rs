@@ -0,0 +1,64 @@
+//! Observation of the queries of an index, e.g. to export metrics. See
+//! [query_observer](struct.LSH.html#method.query_observer).
+use std::time::Duration;
+
+/// Receives an event for every queried hash table and for every query. The methods do nothing
+/// by default, so only the events of interest need to be implemented.
+///
+/// Queries take `&self` and may run on multiple threads at once, so an observer should record
+/// events with atomics or behind a lock.
+///
+/// # Examples
+/// ```
+/// use lsh_rs::prelude::*;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Candidates(AtomicUsize);
+///
+/// impl QueryObserver for Candidates {
+///     fn on_query(&self, event: &QueryEvent) {
+///         self.0.fetch_add(event.n_candidates, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let observer = Arc::new(Candidates::default());
+/// let mut lsh = LshMem::<_, f32>::new(5, 10, 3)
+///     .query_observer(observer.clone())
+///     .srp()
+///     .unwrap();
+/// lsh.store_vec(&[2., 3., 4.]).unwrap();
+/// lsh.query_bucket_ids(&[2., 3., 4.]).unwrap();
+/// assert_eq!(observer.0.load(Ordering::Relaxed), 1);
+/// ```
+pub trait QueryObserver: Send + Sync {
+    /// Called after the buckets of a hash table are queried.
+    fn on_hash_table(&self, _event: &TableEvent) {}
+    /// Called after the candidates of all hash tables are collected.
+    fn on_query(&self, _event: &QueryEvent) {}
+}
+
+/// Query of a single hash table, see [QueryObserver](trait.QueryObserver.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableEvent {
+    /// Index of the hash table.
+    pub hash_table: usize,
+    /// Time spent hashing the query, including the probes of multi-probing.
+    pub hash_time: Duration,
+    /// Time spent fetching the buckets from the backend.
+    pub bucket_time: Duration,
+    /// Number of queried buckets, more than 1 for multi-probing and `boundary_eps`.
+    pub n_buckets: usize,
+    /// Summed size of the queried buckets, before deduplication.
+    pub bucket_len: usize,
+}
+
+/// A complete query, see [QueryObserver](trait.QueryObserver.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryEvent {
+    /// Number of unique candidates over all hash tables.
+    pub n_candidates: usize,
+    /// Total duration of the query.
+    pub elapsed: Duration,
+}
@@ -22,7 +22,7 @@ fn test_index_only() {
     let mut lsh = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
     let v1 = &[2., 3., 4.];
     lsh.store_vec(v1).unwrap();
-    assert_eq!(lsh.hash_tables.unwrap().vec_store.map.len(), 1);
+    assert_eq!(lsh.hash_tables.unwrap().vec_store.len(), 1);
 
     // Test if vec storage is empty
     let mut lsh = hi8::LshMem::new(5, 9, 3)
@@ -31,11 +31,12 @@ fn test_index_only() {
         .l2(2.)
         .unwrap();
     lsh.store_vec(v1).unwrap();
-    assert_eq!(lsh.hash_tables.as_ref().unwrap().vec_store.map.len(), 0);
+    assert_eq!(lsh.hash_tables.as_ref().unwrap().vec_store.len(), 0);
     lsh.query_bucket_ids(v1).unwrap();
 }
 
 #[test]
+#[cfg(feature = "serde")]
 fn test_serialization() {
     let mut lsh = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
     let v1 = &[2., 3., 4.];
@@ -53,12 +54,755 @@ fn test_serialization() {
     println!("{:?}", lsh.hash_tables)
 }
 
+#[test]
+fn test_payloads() {
+    let mut lsh = hi8::LshMem::<_, f32>::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let (v1, v2) = (&[2., 3., 4.], &[-2., 3., -4.]);
+    let idx = lsh.store_vec_with_payload(v1, b"first").unwrap();
+    lsh.store_vec(v2).unwrap();
+    assert_eq!(
+        lsh.query_bucket_payloads(v1).unwrap(),
+        vec![(idx, Some(b"first".to_vec()))]
+    );
+    assert_eq!(lsh.query_bucket_payloads(v2).unwrap(), vec![(1, None)]);
+    lsh.set_payload(1, b"second").unwrap();
+    assert_eq!(lsh.payload(1).unwrap(), Some(b"second".to_vec()));
+    assert!(lsh.set_payload(2, b"missing").is_err());
+
+    // the payloads are part of the dump.
+    #[cfg(feature = "serde")]
+    {
+        let mut tmp = std::env::temp_dir();
+        tmp.push("lsh");
+        std::fs::create_dir(&tmp).unwrap_or_default();
+        tmp.push("serialized_payloads.bincode");
+        lsh.dump(&tmp).unwrap();
+        let mut loaded = hi8::LshMem::<_, f32>::new(5, 9, 3).seed(1).l2(2.).unwrap();
+        loaded.load(&tmp).unwrap();
+        assert_eq!(loaded.payload(0).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(loaded.payload(1).unwrap(), Some(b"second".to_vec()));
+    }
+
+    // a rollback or delete removes the payloads.
+    let mut session = lsh.begin_ingest().unwrap();
+    session.store_vec_with_payload(v1, b"third").unwrap();
+    session.rollback().unwrap();
+    assert_eq!(lsh.payload(2).unwrap(), None);
+    lsh.delete_by_idx(0).unwrap();
+    assert_eq!(lsh.payload(0).unwrap(), None);
+
+    // not supported by the sharded backend.
+    let mut lsh = LshShared::new(5, 9, 3).seed(1).srp().unwrap();
+    assert!(lsh.store_vec_with_payload(v1, b"first").is_err());
+    assert!(lsh.query_bucket_ids(v1).unwrap().is_empty());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_payloads_sql_file() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("payloads.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+
+    let open = || {
+        hi8::LshSql::<_, f32>::new(5, 9, 3)
+            .seed(1)
+            .set_database_file(tmp.to_str().unwrap())
+            .l2(2.)
+            .unwrap()
+    };
+    let v1 = &[2., 3., 4.];
+    {
+        let mut lsh = open();
+        assert_eq!(lsh.store_vec_with_payload(v1, b"first").unwrap(), 0);
+        assert!(matches!(
+            lsh.set_payload(1, b"missing"),
+            Err(Error::NotFound)
+        ));
+        lsh.commit().unwrap();
+    }
+    // the stored ids are known after reopening the database.
+    let mut lsh = open();
+    lsh.set_payload(0, b"second").unwrap();
+    assert_eq!(lsh.payload(0).unwrap(), Some(b"second".to_vec()));
+    assert!(lsh.set_payload(1, b"missing").is_err());
+    std::fs::remove_file(&tmp).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sql_reopen_ids() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("reopen_ids.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+
+    let open = || {
+        hi8::LshSql::<_, f32>::new(5, 9, 3)
+            .seed(1)
+            .set_database_file(tmp.to_str().unwrap())
+            .l2(2.)
+            .unwrap()
+    };
+    let (v1, v2) = (&[2., 3., 4.], &[-2., 3., -4.]);
+    let first = {
+        let mut lsh = open();
+        let idx = lsh.store_vec(v1).unwrap();
+        lsh.commit().unwrap();
+        idx
+    };
+    // the ids continue after the stored ids.
+    let mut lsh = open();
+    let second = lsh.store_vec(v2).unwrap();
+    assert_ne!(first, second);
+    lsh.delete_by_idx(second).unwrap();
+    assert!(lsh.query_bucket_ids(v1).unwrap().contains(&first));
+    std::fs::remove_file(&tmp).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_payloads_sql() {
+    let mut lsh = hi8::LshSqlMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let (v1, v2) = (&[2., 3., 4.], &[-2., 3., -4.]);
+    lsh.store_vec(v2).unwrap();
+    // no payload table yet.
+    assert_eq!(lsh.payload(0).unwrap(), None);
+    let idx = lsh.store_vec_with_payload(v1, b"first").unwrap();
+    assert_eq!(
+        lsh.query_bucket_payloads(v1).unwrap(),
+        vec![(idx, Some(b"first".to_vec()))]
+    );
+    assert_eq!(lsh.query_bucket_payloads(v2).unwrap(), vec![(0, None)]);
+    lsh.delete_by_idx(idx).unwrap();
+    assert_eq!(lsh.payload(idx).unwrap(), None);
+}
+
+#[test]
+fn test_mips_fit() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., 0., 2.], vec![3., -3., 4.]];
+    let mut lsh = LshMem::<_, f32, i32>::new(5, 4, 3)
+        .seed(1)
+        .mips(4., 0.83, 3)
+        .unwrap();
+    assert!(matches!(lsh.store_vec(&vs[0]), Err(Error::NotFitted)));
+    assert!(matches!(
+        lsh.store_vecs(&vs),
+        Err(Error::Batch { row: 0, .. })
+    ));
+    assert!(lsh.fit(&[vec![0.; 3]]).is_err());
+    assert!(!lsh.hashers[0].is_fitted());
+
+    lsh.fit(&vs[..2]).unwrap();
+    assert!(lsh.hashers.iter().all(|h| h.is_fitted()));
+    lsh.store_vecs(&vs[..2]).unwrap();
+    // the norm of the last data point exceeds the fitted maximum norm.
+    assert!(lsh.store_vec(&vs[2]).is_err());
+    // refitting would change the hashes of the stored data points.
+    assert!(lsh.fit(&vs).is_err());
+    lsh.add_hash_tables(2, |lsh| lsh.mips(4., 0.83, 3)).unwrap();
+    assert!(lsh.hashers.iter().all(|h| h.is_fitted()));
+    lsh.rebuild(4, 2, |lsh| lsh.mips(4., 0.83, 3)).unwrap();
+    assert!(lsh.query_bucket_ids(&vs[1]).unwrap().contains(&1));
+
+    // the fitted state is part of the dump.
+    #[cfg(feature = "serde")]
+    {
+        let mut tmp = std::env::temp_dir();
+        tmp.push("lsh");
+        std::fs::create_dir(&tmp).unwrap_or_default();
+        tmp.push("serialized_mips.bincode");
+        lsh.dump(&tmp).unwrap();
+        let mut loaded = LshMem::<_, f32, i32>::new(4, 2, 3)
+            .mips(4., 0.83, 3)
+            .unwrap();
+        loaded.load(&tmp).unwrap();
+        assert!(loaded.hashers.iter().all(|h| h.is_fitted()));
+        loaded.store_vec(&vs[1]).unwrap();
+    }
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_mips_fit_sql() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("mips_fit.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+
+    let vs = vec![vec![2., 3., 4.], vec![-1., 0., 2.]];
+    let open = || {
+        LshSql::<_, f32, i32>::new(5, 2, 3)
+            .seed(1)
+            .set_database_file(tmp.to_str().unwrap())
+            .mips(4., 0.83, 3)
+            .unwrap()
+    };
+    {
+        let mut lsh = open();
+        lsh.fit(&vs).unwrap();
+        lsh.store_vec(&vs[0]).unwrap();
+        lsh.commit().unwrap();
+    }
+    // the fitted hashers are loaded from the database.
+    let mut lsh = open();
+    assert!(lsh.hashers.iter().all(|h| h.is_fitted()));
+    lsh.store_vec(&vs[1]).unwrap();
+    std::fs::remove_file(&tmp).unwrap();
+}
+
+#[test]
+fn test_bucket_size_sum() {
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| vec![(i % 5) as f32, (i % 3) as f32 - 1., 1.])
+        .collect();
+    let q = [1., 0., 1.];
+    let mut lsh = hi8::LshMem::new(3, 4, 3).seed(1).l2(2.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let sizes = lsh.bucket_sizes(&q).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    for (i, proj) in lsh.hashers.iter().enumerate() {
+        let bucket = ht.query_bucket(&proj.hash_vec_query(&q), i);
+        assert_eq!(sizes[i], bucket.map_or(0, |b| b.len()));
+    }
+    assert_eq!(
+        lsh.bucket_size_sum(&q).unwrap(),
+        sizes.iter().sum::<usize>()
+    );
+    assert!(lsh.bucket_size_sum(&q).unwrap() >= lsh.query_bucket_ids(&q).unwrap().len());
+
+    // the probes of a hash table are summed.
+    lsh.multi_probe(8);
+    let sizes = lsh.bucket_sizes(&q).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    let mut expected = vec![0; 4];
+    lsh.for_each_probe_hashes(&q, |i, hashes| {
+        expected[i] = ht.query_buckets(hashes, i)?.iter().map(|b| b.len()).sum();
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(sizes, expected);
+
+    // skipped buckets aren't counted.
+    lsh.max_bucket_size(Some(0));
+    assert_eq!(lsh.bucket_size_sum(&q).unwrap(), 0);
+    assert_eq!(lsh.n_skipped_buckets(), 0);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_bucket_size_sum_sql() {
+    let mut lsh = hi8::LshSqlMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let mut mem = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let vs = [vec![2., 3., 4.], vec![2., 3., 4.1], vec![-2., 3., -4.]];
+    lsh.store_vecs(&vs).unwrap();
+    mem.store_vecs(&vs).unwrap();
+    for v in &vs {
+        assert_eq!(lsh.bucket_sizes(v).unwrap(), mem.bucket_sizes(v).unwrap());
+    }
+}
+
+#[test]
+fn test_query_observer() {
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Events {
+        tables: Mutex<Vec<TableEvent>>,
+        queries: Mutex<Vec<QueryEvent>>,
+    }
+    impl QueryObserver for Events {
+        fn on_hash_table(&self, event: &TableEvent) {
+            self.tables.lock().unwrap().push(*event);
+        }
+        fn on_query(&self, event: &QueryEvent) {
+            self.queries.lock().unwrap().push(*event);
+        }
+    }
+
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| vec![(i % 5) as f32, (i % 3) as f32 - 1., 1.])
+        .collect();
+    let q = [1., 0., 1.];
+    let events = Arc::new(Events::default());
+    let mut lsh = hi8::LshMem::new(3, 4, 3)
+        .seed(1)
+        .query_observer(events.clone())
+        .l2(2.)
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let ids = lsh.query_bucket_ids(&q).unwrap();
+    {
+        let tables = events.tables.lock().unwrap();
+        let queries = events.queries.lock().unwrap();
+        assert_eq!(tables.len(), 4);
+        let sizes = lsh.bucket_sizes(&q).unwrap();
+        for (i, event) in tables.iter().enumerate() {
+            assert_eq!(event.hash_table, i);
+            assert_eq!(event.n_buckets, 1);
+            assert_eq!(event.bucket_len, sizes[i]);
+        }
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].n_candidates, ids.len());
+    }
+
+    // every probed table is observed.
+    lsh.multi_probe(8);
+    lsh.query_bucket_ids(&q).unwrap();
+    let tables = events.tables.lock().unwrap();
+    assert_eq!(tables.len(), 8);
+    assert!(tables[4..].iter().all(|e| e.n_buckets > 1));
+    assert_eq!(events.queries.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_query_dedup() {
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let vs = vec![
+        vec![2., 3., 4.],
+        vec![2., 3., 4.1],
+        vec![2.1, 3., 4.],
+        vec![-2., -3., -4.],
+    ];
+    lsh.store_vecs(&vs).unwrap();
+    let mut session = QuerySession::new(100, 0.001).unwrap();
+    let mut pairs = vec![];
+    for (i, v) in vs.iter().enumerate() {
+        let idx = i as u32;
+        for other in lsh.query_bucket_pairs_dedup(&mut session, idx, v).unwrap() {
+            pairs.push((idx.min(other), idx.max(other)));
+        }
+    }
+    pairs.sort_unstable();
+    assert_eq!(pairs, vec![(0, 1), (0, 2), (1, 2)]);
+    assert_eq!(session.n_emitted(), 3);
+
+    session.clear();
+    let ids = lsh.query_bucket_ids_dedup(&mut session, &vs[0]).unwrap();
+    assert_eq!(ids.len(), 3);
+    // only the new candidates of the next query.
+    assert_eq!(
+        lsh.query_bucket_ids_dedup(&mut session, &vs[3]).unwrap(),
+        vec![3]
+    );
+    assert!(QuerySession::new(0, 0.01).is_err());
+    assert!(QuerySession::new(100, 1.).is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_wal() {
+    let mut path = std::env::temp_dir();
+    path.push("lsh");
+    std::fs::create_dir(&path).unwrap_or_default();
+    path.push("wal.bincode");
+    let mut log = path.as_os_str().to_owned();
+    log.push(".wal");
+
+    let mut lsh = hi8::LshMem::<_, f32>::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let (v1, v2, v3) = (&[2., 3., 4.], &[-2., 3., -4.], &[1., 0., 1.]);
+    lsh.store_vec(v1).unwrap();
+    lsh.enable_wal(&path).unwrap();
+    lsh.store_vec_with_id(v2, 1 << 40).unwrap();
+    lsh.store_vecs(&[v3.to_vec(), v1.to_vec()]).unwrap();
+    lsh.set_payload(2, b"third").unwrap();
+    lsh.delete_by_idx(3).unwrap();
+    // a failed change is logged as well, its replay fails the same way.
+    assert!(lsh.delete_by_idx(7).is_err());
+    lsh.update_by_idx(2, &[1., 0., 2.], v3).unwrap();
+    let mut session = lsh.begin_ingest().unwrap();
+    session.store_vec(v1).unwrap();
+    session.rollback().unwrap();
+    // changes of the hash tables aren't logged.
+    assert!(lsh.rebuild(5, 10, |lsh| lsh.l2(2.)).is_err());
+
+    let recover = |path: &std::path::Path| {
+        let mut lsh = hi8::LshMem::<_, f32>::new(5, 9, 3).l2(2.).unwrap();
+        lsh.recover(path).unwrap();
+        lsh
+    };
+    let mut recovered = recover(&path);
+    for v in &[v1, v2, v3, &[1., 0., 2.]] {
+        assert_eq!(
+            recovered.query_bucket_ids(*v).unwrap(),
+            lsh.query_bucket_ids(*v).unwrap()
+        );
+    }
+    assert_eq!(recovered.internal_id(1 << 40), Some(1));
+    assert_eq!(recovered.payload(2).unwrap(), Some(b"third".to_vec()));
+    // the ids continue where the log ended.
+    assert_eq!(recovered.store_vec(v1).unwrap(), 4);
+
+    // a partly written record is cut off.
+    let logged = recover(&path);
+    assert_eq!(logged.query_bucket_ids(v1).unwrap(), vec![0, 4]);
+    let len = std::fs::metadata(&log).unwrap().len();
+    let mut f = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+    std::io::Write::write_all(&mut f, &[1, 0, 0]).unwrap();
+    let mut recovered = recover(&path);
+    assert_eq!(std::fs::metadata(&log).unwrap().len(), len);
+    assert_eq!(recovered.query_bucket_ids(v1).unwrap(), vec![0, 4]);
+
+    // compact merges the log into the snapshot.
+    recovered.compact().unwrap();
+    assert_eq!(std::fs::metadata(&log).unwrap().len(), 16);
+    assert_eq!(recover(&path).query_bucket_ids(v1).unwrap(), vec![0, 4]);
+
+    // the log of an older snapshot, e.g. after a crash during compact, is ignored.
+    recovered.store_vec(v2).unwrap();
+    let stale = std::fs::read(&log).unwrap();
+    recovered.compact().unwrap();
+    std::fs::write(&log, &stale).unwrap();
+    assert_eq!(recover(&path).query_bucket_ids(v2).unwrap(), vec![1, 5]);
+    assert_eq!(std::fs::metadata(&log).unwrap().len(), 16);
+
+    recovered.disable_wal();
+    recovered.add_hash_tables(1, |lsh| lsh.l2(2.)).unwrap();
+
+    std::fs::write(&log, b"not a log, but long enough").unwrap();
+    let mut lsh = hi8::LshMem::<_, f32>::new(5, 9, 3).l2(2.).unwrap();
+    assert!(lsh.recover(&path).is_err());
+    assert!(lsh.compact().is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_user_ids() {
+    let mut lsh = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let (v1, v2) = (&[2., 3., 4.], &[-2., 3., -4.]);
+    let idx = lsh.store_vec_with_id(v1, u64::MAX).unwrap();
+    lsh.store_vec(v2).unwrap();
+    assert!(matches!(
+        lsh.store_vec_with_id(v2, u64::MAX),
+        Err(Error::DuplicateId(u64::MAX))
+    ));
+    assert_eq!(lsh.query_bucket_user_ids(v1).unwrap(), vec![u64::MAX]);
+    // data points without a user id keep their internal id.
+    assert_eq!(lsh.query_bucket_user_ids(v2).unwrap(), vec![1]);
+    assert_eq!(lsh.internal_id(u64::MAX), Some(idx));
+
+    // the user ids are part of the dump.
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("serialized_user_ids.bincode");
+    lsh.dump(&tmp).unwrap();
+    let mut loaded = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    loaded.load(&tmp).unwrap();
+    assert_eq!(loaded.query_bucket_user_ids(v1).unwrap(), vec![u64::MAX]);
+
+    loaded.delete_by_user_id(u64::MAX).unwrap();
+    assert!(loaded.query_bucket_user_ids(v1).unwrap().is_empty());
+    assert_eq!(loaded.internal_id(u64::MAX), None);
+    assert!(loaded.delete_by_user_id(u64::MAX).is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_corrupt_dump() {
+    let mut lsh = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    lsh.store_vec(&[2., 3., 4.]).unwrap();
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("serialized_corrupt.bincode");
+    lsh.dump(&tmp).unwrap();
+    let blob = std::fs::read(&tmp).unwrap();
+
+    // truncated
+    std::fs::write(&tmp, &blob[..blob.len() - 10]).unwrap();
+    match lsh.load(&tmp) {
+        Err(Error::CorruptIndex {
+            what: "length in bytes",
+            expected,
+            found,
+        }) => assert_eq!(
+            (expected, found),
+            (blob.len() as u64, blob.len() as u64 - 10)
+        ),
+        r => panic!("expected a corrupt index, got {:?}", r),
+    }
+
+    // flipped byte
+    let mut corrupt = blob.clone();
+    corrupt[20] ^= 0xff;
+    std::fs::write(&tmp, &corrupt).unwrap();
+    match lsh.load(&tmp) {
+        Err(Error::CorruptIndex {
+            what: "checksum", ..
+        }) => {}
+        r => panic!("expected a corrupt index, got {:?}", r),
+    }
+
+    // a new dump replaces the corrupt file
+    lsh.dump(&tmp).unwrap();
+    lsh.load(&tmp).unwrap();
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_load_parts() {
+    let mut lsh = hi8::LshMem::new(5, 9, 3).seed(1).l2(2.).unwrap();
+    let v1 = &[2., 3., 4.];
+    lsh.store_vec(v1).unwrap();
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("serialized_parts.bincode");
+    lsh.dump(&tmp).unwrap();
+
+    let mut lsh2 = hi8::LshMem::new(5, 9, 3).seed(2).l2(2.).unwrap();
+    lsh2.load_parts(&tmp, Parts::HASHERS | Parts::TABLES)
+        .unwrap();
+    assert!(lsh2.query_bucket_ids(v1).unwrap().contains(&0));
+    assert!(lsh2.hash_tables.as_ref().unwrap().vec_store.is_empty());
+    assert!(lsh2.query_bucket(v1).is_err());
+
+    lsh2.load_parts(&tmp, Parts::VECTORS).unwrap();
+    assert_eq!(lsh2.query_bucket(v1).unwrap(), vec![&v1[..]]);
+}
+
+#[test]
+fn test_store_batch_idx() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., 2., 5.], vec![8., -3., 1.]];
+    let mut lsh = hi8::LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    assert_eq!(lsh.store_vecs(&vs).unwrap(), vec![0, 1, 2]);
+    assert_eq!(
+        lsh.store_array(ndarray::aview2(&[[1., 1., 1.], [2., 2., 3.]]))
+            .unwrap(),
+        vec![3, 4]
+    );
+    for (i, v) in vs.iter().enumerate() {
+        assert!(lsh.query_bucket_ids(v).unwrap().contains(&(i as u32)));
+    }
+}
+
+#[test]
+fn test_min_bucket_size() {
+    let vs = vec![vec![2., 3., 4.], vec![2.1, 3., 4.], vec![-20., 30., -4.]];
+    let mut lsh = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert!(lsh.query_bucket_ids(&vs[2]).unwrap().contains(&2));
+
+    // the distant vector only lands in buckets of size 1.
+    lsh.min_bucket_size(2);
+    assert!(!lsh.query_bucket_ids(&vs[2]).unwrap().contains(&2));
+    let ids = lsh.query_bucket_ids(&vs[0]).unwrap();
+    assert!(ids.contains(&0) && ids.contains(&1));
+}
+
+#[test]
+fn test_max_bucket_size() {
+    let vs = vec![vec![2., 3., 4.], vec![2., 3., 4.], vec![-20., 30., -4.]];
+    let mut lsh = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert_eq!(lsh.n_skipped_buckets(), 0);
+
+    // the duplicates share buckets of size 2, the distant vector is alone.
+    lsh.max_bucket_size(Some(1));
+    assert!(lsh.query_bucket_ids(&vs[0]).unwrap().is_empty());
+    assert_eq!(lsh.n_skipped_buckets(), 10);
+    assert_eq!(lsh.query_bucket_ids(&vs[2]).unwrap(), vec![2]);
+    assert!(lsh
+        .describe()
+        .unwrap()
+        .contains("Skipped buckets (> 1): 10"));
+    assert_eq!(lsh.config().max_bucket_size, Some(1));
+}
+
+#[test]
+fn test_multi_metric() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., 2., 5.]];
+    let mut lsh = MultiMetricLSH::new(3);
+    lsh.add_metric("cosine", LshMem::new(5, 10, 3).only_index().srp().unwrap())
+        .unwrap();
+    assert_eq!(lsh.store_vecs(&vs).unwrap(), vec![0, 1]);
+
+    // stored data points are indexed when a metric is added.
+    let l2 = LshMem::<_, f32, i32>::new(5, 10, 3)
+        .only_index()
+        .l2(4.)
+        .unwrap();
+    lsh.add_metric("l2", l2).unwrap();
+    assert_eq!(lsh.metrics(), vec!["cosine", "l2"]);
+    assert!(lsh.query_bucket_ids("l2", &vs[1]).unwrap().contains(&1));
+    assert!(lsh
+        .query_bucket("cosine", &vs[1])
+        .unwrap()
+        .contains(&&vs[1][..]));
+    assert_eq!(lsh.get(1), Some(&vs[1][..]));
+
+    assert!(lsh.query_bucket_ids("jaccard", &vs[0]).is_err());
+    assert!(lsh
+        .add_metric("l2", LshMem::new(5, 10, 3).only_index().srp().unwrap())
+        .is_err());
+    // the data points should only be stored once.
+    assert!(lsh
+        .add_metric("srp", LshMem::new(5, 10, 3).srp().unwrap())
+        .is_err());
+    assert!(lsh.store_vec(&[1., 2.]).is_err());
+
+    // a data point that one metric refuses is stored in none of them.
+    let mut mips = LshMem::<_, f32, i32>::new(5, 4, 3)
+        .only_index()
+        .mips(4., 0.83, 3)
+        .unwrap();
+    mips.fit(&vs).unwrap();
+    lsh.add_metric("mips", mips).unwrap();
+    assert!(lsh.store_vec(&[10., 10., 10.]).is_err());
+    assert_eq!(lsh.len(), 2);
+    assert_eq!(lsh.store_vec(&[1., 1., 1.]).unwrap(), 2);
+    assert!(lsh
+        .query_bucket_ids("l2", &[1., 1., 1.])
+        .unwrap()
+        .contains(&2));
+}
+
+#[test]
+fn test_query_scored() {
+    let mut lsh = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    let v1 = &[2., 3., 4.];
+    let v2 = &[2.1, 3., 4.];
+    let v3 = &[-20., 30., -4.];
+    lsh.store_vecs(&[v1.to_vec(), v2.to_vec(), v3.to_vec()])
+        .unwrap();
+
+    let scores = lsh
+        .query_bucket_ids_scored(v1, &CollisionWeight::Count)
+        .unwrap();
+    assert_eq!(scores[0], (0, 10.));
+    let mut ids = scores.iter().map(|&(idx, _)| idx).collect::<Vec<_>>();
+    ids.sort();
+    let mut expected = lsh.query_bucket_ids(v1).unwrap();
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    let scores = lsh
+        .query_bucket_ids_scored(v1, &CollisionWeight::InverseBucketSize)
+        .unwrap();
+    assert!(scores.iter().all(|&(_, s)| s > 0. && s <= 10.));
+
+    let weights = CollisionWeight::PerTable(vec![0.; 10]);
+    let scores = lsh.query_bucket_ids_scored(v1, &weights).unwrap();
+    assert!(scores.iter().all(|&(_, s)| s == 0.));
+    assert!(lsh
+        .query_bucket_ids_scored(v1, &CollisionWeight::PerTable(vec![1.]))
+        .is_err());
+
+    // the probes are scored, a table counts once however many of its probes collide.
+    lsh.multi_probe(8);
+    let scores = lsh
+        .query_bucket_ids_scored(v1, &CollisionWeight::Count)
+        .unwrap();
+    assert!(scores.iter().all(|&(_, s)| (1. ..=10.).contains(&s)));
+    let mut ids = scores.iter().map(|&(idx, _)| idx).collect::<Vec<_>>();
+    ids.sort();
+    let mut expected = lsh.query_bucket_ids(v1).unwrap();
+    expected.sort();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_merge() {
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.], vec![-20., 30., -4.]];
+    let mut a = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    let mut b = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    a.store_vec_with_id(&vs[0], 10).unwrap();
+    b.store_vec(&vs[1]).unwrap();
+    b.store_vec_with_payload(&vs[2], b"c").unwrap();
+
+    assert_eq!(a.merge(b).unwrap(), 1);
+    assert_eq!(a.query_bucket_ids(&vs[1]).unwrap(), vec![1]);
+    assert_eq!(a.query_bucket_ids(&vs[2]).unwrap(), vec![2]);
+    assert_eq!(a.payload(2).unwrap(), Some(b"c".to_vec()));
+    assert_eq!(a.user_id(0), 10);
+    assert_eq!(a.query_top_k(&vs[2], 1).unwrap(), vec![(2, 0.)]);
+    assert_eq!(a.store_vec(&vs[0]).unwrap(), 3);
+
+    // other seed, other hashers.
+    let c = hi8::LshMem::new(5, 10, 3).seed(2).l2(4.).unwrap();
+    assert!(a.merge(c).is_err());
+    let mut c = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    c.store_vec_with_id(&vs[1], 10).unwrap();
+    assert!(matches!(a.merge(c), Err(Error::DuplicateId(10))));
+    let c = hi8::LshMem::new(5, 10, 3)
+        .seed(1)
+        .only_index()
+        .l2(4.)
+        .unwrap();
+    assert!(a.merge(c).is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_merge_sql() {
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.]];
+    let mut a = hi8::LshSqlMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    let mut b = hi8::LshSqlMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    a.store_vec(&vs[0]).unwrap();
+    b.store_vec_with_payload(&vs[1], b"b").unwrap();
+
+    assert_eq!(a.merge(b).unwrap(), 1);
+    assert_eq!(a.query_bucket_ids(&vs[1]).unwrap(), vec![1]);
+    assert_eq!(a.payload(1).unwrap(), Some(b"b".to_vec()));
+    assert_eq!(a.store_vec(&vs[0]).unwrap(), 2);
+}
+
+#[test]
+fn test_query_batch_top_k() {
+    let mut lsh = hi8::LshMem::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    let vs = vec![vec![2., 3., 4.], vec![2.1, 3., 4.], vec![-20., 30., -4.]];
+    lsh.store_vecs(&vs).unwrap();
+    let queries = vec![vec![2., 3., 4.], vec![2., 3.1, 4.], vec![-20., 30., -4.]];
+
+    let top = lsh
+        .query_batch_top_k(&queries, 2, &BatchAggregate::Frequency)
+        .unwrap();
+    assert_eq!(top, vec![(0, 2.), (1, 2.)]);
+
+    let top = lsh
+        .query_batch_top_k(&queries, 10, &BatchAggregate::Nearest)
+        .unwrap();
+    assert_eq!(top.len(), 3);
+    assert_eq!((top[0].0, top[1].0), (0, 2));
+    assert!(top[0].1 == 0. && top[1].1 == 0.);
+
+    // the scores are summed over the queries.
+    let weight = CollisionWeight::Count;
+    let top = lsh
+        .query_batch_top_k(&queries[..1], 1, &BatchAggregate::Score(weight.clone()))
+        .unwrap();
+    assert_eq!(top, vec![(0, 10.)]);
+    let top = lsh
+        .query_batch_top_k(&queries, 3, &BatchAggregate::Score(weight))
+        .unwrap();
+    assert!(top[0].1 > 10.);
+
+    let weight = CollisionWeight::PerTable(vec![1.]);
+    match lsh.query_batch_top_k(&queries, 1, &BatchAggregate::Score(weight)) {
+        Err(Error::Batch { .. }) => {}
+        r => panic!("expected a batch error, got {:?}", r),
+    }
+}
+
 #[test]
 #[cfg(feature = "sqlite")]
 fn test_db() {
+    let p = std::env::temp_dir().join("lsh_test_db.db3");
+    let p = p.to_str().unwrap();
+    let _ = std::fs::remove_file(p);
     let v1 = &[2., 3., 4.];
     {
-        let mut lsh = hi8::LshSql::new(5, 2, 3).seed(2).srp().unwrap();
+        let mut lsh = hi8::LshSql::new(5, 2, 3)
+            .seed(2)
+            .set_database_file(p)
+            .srp()
+            .unwrap();
         lsh.store_vec(v1).unwrap();
         assert!(lsh.query_bucket_ids(v1).unwrap().contains(&0));
         lsh.commit().unwrap();
@@ -66,9 +810,14 @@ fn test_db() {
     }
 
     // tests if the same db is reused.
-    let lsh2 = hi8::LshSql::new(5, 2, 3).srp().unwrap();
+    let lsh2 = hi8::LshSql::new(5, 2, 3)
+        .set_database_file(p)
+        .srp()
+        .unwrap();
     lsh2.describe().unwrap();
     assert!(lsh2.query_bucket_ids(v1).unwrap().contains(&0));
+    drop(lsh2);
+    std::fs::remove_file(p).unwrap();
 }
 
 #[test]
@@ -80,3 +829,1803 @@ fn test_mem_db() {
     assert!(lsh.query_bucket_ids(v1).unwrap().contains(&0));
     lsh.describe().unwrap();
 }
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_flat_storage() {
+    let mut lsh = hi8::LshMem::<_, f32>::new(5, 9, 3)
+        .seed(1)
+        .flat_storage()
+        .l2(2.)
+        .unwrap();
+    let v1 = &[2., 3., 4.];
+    let v2 = &[-1., 2., 5.];
+    lsh.store_vecs(&[v1.to_vec(), v2.to_vec()]).unwrap();
+    assert!(lsh.query_bucket(v1).unwrap().contains(&&v1[..]));
+
+    let vec_store = &lsh.hash_tables.as_ref().unwrap().vec_store;
+    assert_eq!(vec_store.len(), 2);
+    assert_eq!(vec_store.get(1), v2);
+    match vec_store {
+        VecStore::Flat(store) => {
+            assert_eq!(store.as_slice().as_ptr() as usize % 32, 0);
+            assert_eq!(store.stride(), 8);
+        }
+        _ => panic!("expected flat storage"),
+    }
+
+    // padding is not serialized and restored on load.
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("serialized_flat.bincode");
+    lsh.dump(&tmp).unwrap();
+    lsh.load(&tmp).unwrap();
+    assert_eq!(lsh.hash_tables.as_ref().unwrap().vec_store.get(1), v2);
+}
+
+#[test]
+fn test_packed_keys() {
+    let vs: Vec<Vec<f32>> = (0..30)
+        .map(|i| {
+            vec![
+                (i % 7) as f32 - 3.,
+                (i % 5) as f32 - 2.,
+                (i % 3) as f32 - 1.,
+            ]
+        })
+        .collect();
+    fn store_and_query<H: VecHash<f32, K> + crate::data::MaybeSerde, K: crate::data::Integer>(
+        lsh: &mut LshMem<H, f32, K>,
+        vs: &[Vec<f32>],
+    ) -> Vec<Vec<u32>> {
+        lsh.store_vecs(vs).unwrap();
+        vs.iter()
+            .map(|v| {
+                let mut ids = lsh.query_bucket_ids(v).unwrap();
+                ids.sort_unstable();
+                ids
+            })
+            .collect()
+    }
+    // short hashes are stored inline, long hashes on the heap.
+    for &k in &[3, 12] {
+        let mut lsh = LshMem::<_, f32, i32>::new(k, 4, 3).seed(1).l2(1.).unwrap();
+        let mut packed = LshMem::<_, f32, i32>::new(k, 4, 3)
+            .seed(1)
+            .packed_keys()
+            .l2(1.)
+            .unwrap();
+        assert_eq!(
+            store_and_query(&mut packed, &vs),
+            store_and_query(&mut lsh, &vs)
+        );
+        assert_eq!(
+            packed.hash_table_stats().unwrap(),
+            lsh.hash_table_stats().unwrap()
+        );
+    }
+
+    for &sign in &[SignConvention::Binary, SignConvention::Bipolar] {
+        let mut lsh = LshMem::new(20, 4, 3).seed(1).srp_with_sign(sign).unwrap();
+        let mut packed = LshMem::new(20, 4, 3)
+            .seed(1)
+            .packed_keys()
+            .srp_with_sign(sign)
+            .unwrap();
+        let expected = store_and_query(&mut lsh, &vs);
+        assert_eq!(store_and_query(&mut packed, &vs), expected);
+        assert!(packed.approximate_bytes().unwrap() < lsh.approximate_bytes().unwrap());
+
+        let ht = packed.hash_tables.as_ref().unwrap();
+        let mut hashes: Vec<_> = ht.buckets(0).map(|(h, _)| h.into_owned()).collect();
+        let mut expected_hashes: Vec<_> = lsh
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .buckets(0)
+            .map(|(h, _)| h.into_owned())
+            .collect();
+        hashes.sort_unstable();
+        expected_hashes.sort_unstable();
+        assert_eq!(hashes, expected_hashes);
+
+        #[cfg(feature = "serde")]
+        {
+            let mut tmp = std::env::temp_dir();
+            tmp.push("lsh");
+            std::fs::create_dir(&tmp).unwrap_or_default();
+            tmp.push("packed_keys.bincode");
+            packed.dump(&tmp).unwrap();
+            // the key layout isn't part of the dump, the keys are packed again on load.
+            lsh.load(&tmp).unwrap();
+            packed.load(&tmp).unwrap();
+            assert!(packed.approximate_bytes().unwrap() < lsh.approximate_bytes().unwrap());
+            for (v, ids) in vs.iter().zip(&expected) {
+                let mut a = lsh.query_bucket_ids(v).unwrap();
+                let mut b = packed.query_bucket_ids(v).unwrap();
+                a.sort_unstable();
+                b.sort_unstable();
+                assert_eq!(&a, ids);
+                assert_eq!(&b, ids);
+            }
+            std::fs::remove_file(&tmp).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_with_hashers() {
+    let hashers = (0..4)
+        .map(|seed| <L2>::new(3, 2., 5, seed + 1))
+        .collect::<Vec<_>>();
+    let mut lsh = LshMem::new(5, 4, 3).with_hashers(hashers.clone()).unwrap();
+    let v1 = &[2., 3., 4.];
+    lsh.store_vec(v1).unwrap();
+    assert!(lsh.query_bucket_ids(v1).unwrap().contains(&0));
+    assert_eq!(lsh.hashers[3].a, hashers[3].a);
+
+    // one hasher per hash table is required.
+    assert!(LshMem::<_, f32, i32>::new(5, 5, 3)
+        .with_hashers(hashers)
+        .is_err());
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct NegatedQuery(SignRandomProjections<f32>);
+
+impl VecHash<f32, i8> for NegatedQuery {
+    fn hash_vec_query(&self, v: &[f32]) -> Vec<i8> {
+        let q: Vec<f32> = v.iter().map(|x| -x).collect();
+        self.0.hash_vec_put(&q)
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Vec<i8> {
+        self.0.hash_vec_put(v)
+    }
+}
+
+#[test]
+fn test_with_hashers_asymmetric() {
+    let hashers = (0..3)
+        .map(|seed| NegatedQuery(SignRandomProjections::new(4, 3, seed + 1)))
+        .collect();
+    let mut lsh = LshMem::<_, f32>::new(4, 3, 3)
+        .with_hashers(hashers)
+        .unwrap();
+    lsh.store_vec(&[1., 2., 3.]).unwrap();
+    // queries are hashed with hash_vec_query, stored data points with hash_vec_put.
+    assert_eq!(lsh.query_bucket_ids(&[-1., -2., -3.]).unwrap(), vec![0]);
+    assert!(lsh.query_bucket_ids(&[1., 2., 3.]).unwrap().is_empty());
+}
+
+#[test]
+fn test_hash_vec() {
+    let hashers = (0..3)
+        .map(|seed| NegatedQuery(SignRandomProjections::new(4, 3, seed + 1)))
+        .collect();
+    let lsh = LshMem::<_, f32>::new(4, 3, 3)
+        .with_hashers(hashers)
+        .unwrap();
+    let v = &[1., 2., 3.];
+    let hashes = lsh.hash_vec(v).unwrap();
+    assert_eq!(hashes.len(), 3);
+    for (i, hash) in hashes.iter().enumerate() {
+        assert_eq!(hash, &lsh.hashers[i].hash_vec_put(v));
+    }
+    assert_eq!(lsh.hash_vec_query(&[-1., -2., -3.]).unwrap(), hashes);
+    assert!(matches!(
+        lsh.hash_vec(&[1., 2.]),
+        Err(Error::DimensionMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_batch_dimension_error() {
+    let mut lsh = LshMem::<_, f32>::new(5, 4, 3).seed(1).srp().unwrap();
+    let vs = vec![vec![1., 2., 3.], vec![1., 2.], vec![2., 3., 1.]];
+    match lsh.store_vecs(&vs) {
+        Err(Error::Batch { row, source }) => {
+            assert_eq!(row, 1);
+            assert!(matches!(
+                *source,
+                Error::DimensionMismatch {
+                    expected: 3,
+                    actual: 2
+                }
+            ));
+        }
+        _ => panic!("expected a batch error"),
+    }
+    // nothing is stored if one of the rows is invalid.
+    assert_eq!(lsh.hash_tables.as_ref().unwrap().vec_store.len(), 0);
+    assert!(matches!(
+        lsh.query_bucket_ids_batch(&vs),
+        Err(Error::Batch { row: 1, .. })
+    ));
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_open_readonly() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("readonly.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+
+    let v1 = &[2., 3., 4.];
+    {
+        let mut lsh = hi8::LshSql::<_, f32>::new(5, 2, 3)
+            .seed(2)
+            .set_database_file(tmp.to_str().unwrap())
+            .srp()
+            .unwrap();
+        lsh.store_vec(v1).unwrap();
+        lsh.commit().unwrap();
+    }
+    let mut lsh = hi8::LshSql::<SignRandomProjections<f32>>::new(5, 2, 3)
+        .open_readonly(&tmp)
+        .unwrap();
+    assert!(lsh.query_bucket_ids(v1).unwrap().contains(&0));
+    assert!(matches!(lsh.store_vec(v1), Err(Error::ReadOnly)));
+
+    // the number of hash tables should match.
+    assert!(hi8::LshSql::<SignRandomProjections<f32>>::new(5, 3, 3)
+        .open_readonly(&tmp)
+        .is_err());
+    std::fs::remove_file(&tmp).unwrap();
+    // missing database
+    assert!(hi8::LshSql::<SignRandomProjections<f32>>::new(5, 2, 3)
+        .open_readonly(&tmp)
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_read_only_builder() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("read_only_builder.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+    let path = tmp.to_str().unwrap();
+
+    let v1 = &[2., 3., 4.];
+    {
+        let mut lsh = hi8::LshSql::<_, f32>::new(5, 2, 3)
+            .seed(2)
+            .set_database_file(path)
+            .srp()
+            .unwrap();
+        lsh.store_vec(v1).unwrap();
+        lsh.commit().unwrap();
+    }
+    // the hashers of the database are used, not the ones of another seed.
+    let mut lsh = hi8::LshSql::<_, f32>::new(5, 2, 3)
+        .seed(3)
+        .set_database_file(path)
+        .read_only()
+        .srp()
+        .unwrap();
+    assert!(lsh.config().read_only);
+    assert_eq!(lsh.query_bucket_ids(v1).unwrap(), vec![0]);
+    assert!(matches!(lsh.store_vec(v1), Err(Error::ReadOnly)));
+    assert!(matches!(lsh.delete_by_idx(0), Err(Error::ReadOnly)));
+    // a failed store leaves the index usable.
+    assert_eq!(lsh.query_bucket_ids(v1).unwrap(), vec![0]);
+    let replica = hi8::LshSql::<SignRandomProjections<f32>>::new(5, 2, 3)
+        .open_readonly(path)
+        .unwrap();
+    assert_eq!(replica.hash_vec(v1).unwrap(), lsh.hash_vec(v1).unwrap());
+
+    assert!(matches!(
+        hi8::LshMem::<_, f32>::new(5, 2, 3).read_only().srp(),
+        Err(Error::NotImplemented)
+    ));
+    std::fs::remove_file(&tmp).unwrap();
+    // nothing is created.
+    assert!(hi8::LshSql::<_, f32>::new(5, 2, 3)
+        .set_database_file(path)
+        .read_only()
+        .srp()
+        .is_err());
+    assert!(!tmp.exists());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_export_hashers() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    let hashers = tmp.join("replica_hashers.bincode");
+    tmp.push("replica.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+
+    let v1 = &[2., 3., 4.];
+    {
+        let mut lsh = hi8::LshSql::<_, f32>::new(5, 2, 3)
+            .seed(2)
+            .set_database_file(tmp.to_str().unwrap())
+            .srp()
+            .unwrap();
+        lsh.store_vec(v1).unwrap();
+        lsh.export_hashers(&hashers).unwrap();
+        // a replica doesn't need the hashers in the database.
+        let ht = lsh.hash_tables.as_ref().unwrap();
+        ht.conn.execute_batch("DELETE FROM state;").unwrap();
+        lsh.commit().unwrap();
+    }
+    assert!(hi8::LshSql::<SignRandomProjections<f32>>::new(5, 2, 3)
+        .open_readonly(&tmp)
+        .is_err());
+    let lsh = hi8::LshSql::<SignRandomProjections<f32>>::new(5, 2, 3)
+        .open_readonly_with_hashers(&tmp, &hashers)
+        .unwrap();
+    assert_eq!(lsh.query_bucket_ids(v1).unwrap(), vec![0]);
+    assert_eq!(lsh.config().seed, 2);
+
+    let mem = hi8::LshMem::<SignRandomProjections<f32>>::new(5, 2, 3)
+        .from_hashers(&hashers)
+        .unwrap();
+    assert_eq!(
+        mem.hashers[1].hash_vec_query(v1),
+        lsh.hashers[1].hash_vec_query(v1)
+    );
+    // the parameters should match.
+    assert!(hi8::LshMem::<SignRandomProjections<f32>>::new(5, 3, 3)
+        .from_hashers(&hashers)
+        .is_err());
+    std::fs::remove_file(&tmp).unwrap();
+    std::fs::remove_file(&hashers).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_query_batch_par_sql() {
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("par_sql.db3");
+    std::fs::remove_file(&tmp).unwrap_or_default();
+
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| {
+            vec![
+                (i % 7) as f32 - 3.,
+                (i % 5) as f32 - 2.,
+                (i % 3) as f32 - 1.,
+            ]
+        })
+        .collect();
+    {
+        let mut lsh = hi8::LshSql::<_, f32>::new(5, 4, 3)
+            .seed(2)
+            .set_database_file(tmp.to_str().unwrap())
+            .srp()
+            .unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        lsh.commit().unwrap();
+        // a writable index holds an exclusive lock.
+        assert!(lsh.query_bucket_ids_batch_par_sql(&vs).is_err());
+    }
+    let lsh = hi8::LshSql::<SignRandomProjections<f32>>::new(5, 4, 3)
+        .open_readonly(&tmp)
+        .unwrap();
+    let expected = lsh.query_bucket_ids_batch(&vs).unwrap();
+    assert_eq!(lsh.query_bucket_ids_batch_par_sql(&vs).unwrap(), expected);
+
+    let arr = ndarray::Array2::from_shape_fn((vs.len(), 3), |(i, j)| vs[i][j]);
+    assert_eq!(
+        lsh.query_bucket_ids_batch_arr_par_sql(arr.view()).unwrap(),
+        expected
+    );
+    assert!(matches!(
+        lsh.query_bucket_ids_batch_par_sql(&[vec![1., 2.]]),
+        Err(Error::Batch { row: 0, .. })
+    ));
+    // the connections of the workers are kept open for the next queries.
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    let readers = ht.take_readers(usize::MAX);
+    assert_eq!(readers.len(), rayon::current_num_threads().min(vs.len()));
+    ht.put_readers(readers);
+    std::fs::remove_file(&tmp).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_ingest_session() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.]];
+
+    let mut lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+    lsh.store_vec(&vs[0]).unwrap();
+    let mut session = lsh.begin_ingest().unwrap();
+    assert!(session.begin_ingest().is_err());
+    session.store_vecs(&vs).unwrap();
+    assert_eq!(session.query_bucket_ids(&vs[1]).unwrap(), vec![2]);
+    session.rollback().unwrap();
+    assert_eq!(lsh.query_bucket_ids(&vs[0]).unwrap(), vec![0]);
+    assert!(lsh.query_bucket_ids(&vs[1]).unwrap().is_empty());
+
+    // dropping a session rolls back
+    lsh.begin_ingest().unwrap().store_vec(&vs[1]).unwrap();
+    assert_eq!(lsh.store_vec(&vs[1]).unwrap(), 1);
+
+    let mut session = lsh.begin_ingest().unwrap();
+    session.store_vec(&vs[1]).unwrap();
+    session.commit().unwrap();
+    assert_eq!(lsh.query_bucket_ids(&vs[1]).unwrap(), vec![1, 2]);
+
+    let mut lsh = LshSqlMem::new(5, 2, 3).seed(1).srp().unwrap();
+    lsh.store_vec(&vs[0]).unwrap();
+    let mut session = lsh.begin_ingest().unwrap();
+    session.store_vecs(&vs).unwrap();
+    let mut ids = session.query_bucket_ids(&vs[0]).unwrap();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![0, 1]);
+    session.rollback().unwrap();
+    assert_eq!(lsh.query_bucket_ids(&vs[0]).unwrap(), vec![0]);
+    assert_eq!(lsh.store_vec(&vs[1]).unwrap(), 1);
+
+    let mut session = lsh.begin_ingest().unwrap();
+    session.store_vec(&vs[0]).unwrap();
+    session.commit().unwrap();
+    let mut ids = lsh.query_bucket_ids(&vs[0]).unwrap();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![0, 2]);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_portable() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![0.5, 1., -2.]];
+    let p = std::env::temp_dir().join("lsh_portable.json");
+    let mut lsh = LshMem::<_, f32, i32>::new(5, 3, 3).seed(1).l2(2.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh.export_portable(&p).unwrap();
+
+    let mut imported = LshMem::<_, f32, i32>::new(1, 1, 1).l2(1.).unwrap();
+    imported.import_portable(&p).unwrap();
+    for v in &vs {
+        let mut expected = lsh.query_bucket_ids(v).unwrap();
+        let mut ids = imported.query_bucket_ids(v).unwrap();
+        expected.sort_unstable();
+        ids.sort_unstable();
+        assert_eq!(ids, expected);
+        assert_eq!(imported.query_bucket(v).unwrap().len(), ids.len());
+    }
+    // ids continue after the imported ones.
+    assert_eq!(imported.store_vec(&vs[0]).unwrap(), 3);
+
+    let mut srp = LshMem::<_, f32>::new(5, 3, 3).srp().unwrap();
+    assert!(srp.import_portable(&p).is_err());
+    // L1 hashers have the same fields as L2 hashers, only the family tells them apart.
+    let mut l1 = LshMem::<_, f32, i32>::new(5, 3, 3).l1(2.).unwrap();
+    assert!(l1.import_portable(&p).is_err());
+
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&p).unwrap()).unwrap();
+    assert_eq!(
+        json["hash_family"],
+        serde_json::json!({"family": "l2", "r": 2.0})
+    );
+    let import_with = |edit: &dyn Fn(&mut serde_json::Value)| {
+        let mut json = json.clone();
+        edit(&mut json);
+        std::fs::write(&p, json.to_string()).unwrap();
+        imported.clone().import_portable(&p)
+    };
+    // ids without a data point and ids that occur twice in a hash table are refused.
+    assert!(import_with(&|json| json["hash_tables"][0][0]["ids"][0] = 3.into()).is_err());
+    assert!(import_with(&|json| {
+        let ids = json["hash_tables"][1][0]["ids"].as_array_mut().unwrap();
+        ids.push(ids[0].clone());
+    })
+    .is_err());
+    assert!(import_with(&|_| {}).is_ok());
+    std::fs::remove_file(&p).unwrap();
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_hash_flavor() {
+    use crate::constants::HASH_FLAVOR;
+    let v = [2., 3., 4.];
+    let mut lsh = LshMem::<_, f32, i32>::new(5, 3, 3).seed(1).l2(2.).unwrap();
+    lsh.store_vec(&v).unwrap();
+    let mut tmp = std::env::temp_dir();
+    tmp.push("lsh");
+    std::fs::create_dir(&tmp).unwrap_or_default();
+    tmp.push("serialized_flavor.bincode");
+    lsh.dump(&tmp).unwrap();
+    let blob = std::fs::read(&tmp).unwrap();
+
+    // the payload ends with the flavor: Some tag, length and bytes, the lengths of the empty
+    // user ids and payloads and the version of the hasher layout.
+    let payload = &blob[12..blob.len() - 4];
+    let flavor_end = payload.len() - 20;
+    let flavor_start = flavor_end - 9 - HASH_FLAVOR.len();
+    let reframe = |payload: &[u8]| {
+        let mut blob = b"LSHD".to_vec();
+        blob.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        blob.extend_from_slice(payload);
+        blob.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        blob
+    };
+    // dumps from before the flavor was recorded hold hashers of an older layout.
+    std::fs::write(&tmp, reframe(&payload[..flavor_start])).unwrap();
+    assert!(matches!(
+        lsh.load(&tmp),
+        Err(Error::CorruptIndex {
+            what: "hasher layout version",
+            expected: 1,
+            found: 0
+        })
+    ));
+    assert_eq!(lsh.query_bucket_ids(&v).unwrap(), vec![0]);
+
+    let mut other = payload.to_vec();
+    for b in &mut other[flavor_start + 9..flavor_end] {
+        *b = b'x';
+    }
+    std::fs::write(&tmp, reframe(&other)).unwrap();
+    match lsh.load(&tmp) {
+        Err(Error::HashFlavorMismatch { expected, found }) => {
+            assert_eq!(
+                (expected, found),
+                (HASH_FLAVOR, "x".repeat(HASH_FLAVOR.len()))
+            )
+        }
+        r => panic!("expected a hash flavor mismatch, got {:?}", r),
+    }
+
+    let p = std::env::temp_dir().join("lsh_portable_flavor.json");
+    lsh.export_portable(&p).unwrap();
+    let mut json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&p).unwrap()).unwrap();
+    assert_eq!(json["hash_flavor"], HASH_FLAVOR);
+    json["hash_flavor"] = "other".into();
+    std::fs::write(&p, json.to_string()).unwrap();
+    assert!(matches!(
+        lsh.import_portable(&p),
+        Err(Error::HashFlavorMismatch { .. })
+    ));
+    std::fs::remove_file(&p).unwrap();
+
+    let sum = crate::data::pairwise_dot(ndarray::aview1(&[1.; 100]), ndarray::aview1(&[0.5; 100]));
+    assert_eq!(sum, 50.);
+}
+
+#[test]
+fn test_lanes_project() {
+    use crate::data::{lanes_dot, lanes_project};
+    use ndarray::{aview1, Array2};
+    // rows and dimensions that don't fill the blocks and chunks.
+    let a = Array2::from_shape_fn((7, 19), |(i, j)| (i as f32 - 3.) * 0.7 + j as f32 * 0.13);
+    let v: Vec<f32> = (0..19).map(|j| 1. - j as f32 * 0.21).collect();
+    let p = lanes_project(a.as_slice().unwrap(), &v);
+    let expected = a.dot(&aview1(&v));
+    for (i, row) in a.outer_iter().enumerate() {
+        assert_eq!(p[i], lanes_dot(row.as_slice().unwrap(), &v));
+        assert!((p[i] - expected[i]).abs() < 1e-4);
+    }
+    assert_eq!(lanes_project::<f32>(&[], &[]).len(), 0);
+    assert_eq!(lanes_dot(&[1i32, 2, 3], &[4, 5, 6]), 32);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_hash_flavor_sql() {
+    let p = std::env::temp_dir().join("lsh_hash_flavor.db3");
+    let p = p.to_str().unwrap();
+    let _ = std::fs::remove_file(p);
+    {
+        let mut lsh = LshSql::<_, f32>::new(5, 2, 3)
+            .set_database_file(p)
+            .srp()
+            .unwrap();
+        lsh.store_vec(&[2., 3., 4.]).unwrap();
+        lsh.commit().unwrap();
+    }
+    // reopening checks the recorded summation.
+    LshSql::<_, f32>::new(5, 2, 3)
+        .set_database_file(p)
+        .srp()
+        .unwrap();
+    let conn = rusqlite::Connection::open(p).unwrap();
+    conn.execute("UPDATE meta SET value = 'other'", []).unwrap();
+    drop(conn);
+    match LshSql::<_, f32>::new(5, 2, 3).set_database_file(p).srp() {
+        Err(Error::HashFlavorMismatch { found, .. }) => assert_eq!(found, "other"),
+        Err(e) => panic!("expected a hash flavor mismatch, got {:?}", e),
+        Ok(_) => panic!("expected a hash flavor mismatch"),
+    }
+    std::fs::remove_file(p).unwrap();
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn test_mmap() {
+    let p = std::env::temp_dir().join("lsh_test_mmap.mmap");
+    let p = p.to_str().unwrap();
+    let _ = std::fs::remove_file(p);
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![2., 3.1, 4.]];
+    let open = |seed| {
+        LshMmap::<_, f32, i32>::new(5, 4, 3)
+            .seed(seed)
+            .set_database_file(p)
+            .l2(4.)
+            .unwrap()
+    };
+    // the hashers are a record of the log, but not a data point.
+    drop(open(1));
+    {
+        let mut lsh = open(1);
+        assert_eq!(lsh.store_vecs(&vs).unwrap(), vec![0, 1, 2]);
+        lsh.flush().unwrap();
+    }
+    // the hashers and data points are restored from the file, regardless of the seed.
+    let mut lsh = open(2);
+    assert!(lsh.query_bucket_ids(&vs[0]).unwrap().contains(&0));
+    assert_eq!(lsh.query_top_k(&vs[2], 1).unwrap()[0].0, 2);
+    assert_eq!(lsh.store_vec(&[0., 0., 1.]).unwrap(), 3);
+    lsh.delete_vec(&vs[1]).unwrap();
+    assert!(!lsh.query_bucket_ids(&vs[1]).unwrap().contains(&1));
+    std::fs::remove_file(p).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sql_mem_backup() {
+    let p = std::env::temp_dir().join("lsh_sql_mem_backup.db3");
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.]];
+    let mut lsh = LshSqlMem::<_, f32, i32>::new(5, 4, 3)
+        .seed(3)
+        .min_bucket_size(1)
+        .l2(4.)
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh.backup(&p).unwrap();
+    // the index stays in memory.
+    lsh.store_vec(&[0., 0., 1.]).unwrap();
+
+    let mut restored = LshSqlMem::<L2, f32, i32>::restore(&p).unwrap();
+    assert_eq!(restored.config(), lsh.config());
+    assert_eq!(restored.hashers[0].a, lsh.hashers[0].a);
+    assert_eq!(restored.query_bucket_ids(&vs[1]).unwrap(), vec![1]);
+    assert_eq!(restored.store_vec(&[0., 0., 1.]).unwrap(), 2);
+
+    std::fs::write(&p, b"").unwrap();
+    assert!(LshSqlMem::<L2, f32, i32>::restore(&p).is_err());
+    std::fs::remove_file(&p).unwrap();
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_u64_ids() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.]];
+    let mut lsh = LSH::<_, f32, MemoryTable<f32, i32, u64>, i32>::new(5, 4, 3)
+        .seed(3)
+        .l2(4.)
+        .unwrap();
+    let ids: Vec<u64> = lsh.store_vecs(&vs).unwrap();
+    assert_eq!(ids, vec![0, 1]);
+    assert!(lsh.query_bucket_ids(&vs[1]).unwrap().contains(&1u64));
+
+    let p = std::env::temp_dir().join("lsh_u64_ids.bin");
+    lsh.dump(&p).unwrap();
+    let mut loaded = LSH::<_, f32, MemoryTable<f32, i32, u64>, i32>::new(5, 4, 3)
+        .seed(3)
+        .l2(4.)
+        .unwrap();
+    loaded.load(&p).unwrap();
+    assert_eq!(
+        loaded.query_bucket_ids(&vs[1]).unwrap(),
+        lsh.query_bucket_ids(&vs[1]).unwrap()
+    );
+    std::fs::remove_file(&p).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_u64_ids_sql() {
+    let mut lsh = LSH::<_, f32, SqlTableMem<f32, i32, u64>, i32>::new(5, 4, 3)
+        .seed(3)
+        .l2(4.)
+        .unwrap();
+    let ids: Vec<u64> = lsh
+        .store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]])
+        .unwrap();
+    assert_eq!(ids, vec![0, 1]);
+    assert_eq!(lsh.query_bucket_ids(&[-1., -1., 1.]).unwrap(), vec![1]);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_delete_vec_sql() {
+    let mut lsh = hi8::LshSqlMem::<_, f32>::new(5, 4, 3)
+        .seed(1)
+        .srp()
+        .unwrap();
+    let idx = lsh.store_vec(&[2., 3., 4.]).unwrap();
+    // the data points aren't stored, so the id can't be found.
+    assert!(matches!(
+        lsh.delete_vec(&[2., 3., 4.]),
+        Err(Error::NotImplemented)
+    ));
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![idx]);
+    lsh.delete_by_idx(idx).unwrap();
+    assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().is_empty());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_multi_probe_sql() {
+    let vs: Vec<Vec<f32>> = (0..50)
+        .map(|i| vec![(i % 7) as f32, (i % 5) as f32, (i % 3) as f32])
+        .collect();
+    let mut lsh_mem = LshMem::<_, f32, i32>::new(4, 3, 3)
+        .seed(2)
+        .multi_probe(20)
+        .l2(2.)
+        .unwrap();
+    let mut lsh_sql = LshSqlMem::<_, f32, i32>::new(4, 3, 3)
+        .seed(2)
+        .multi_probe(20)
+        .l2(2.)
+        .unwrap();
+    lsh_mem.store_vecs(&vs).unwrap();
+    lsh_sql.store_vecs(&vs).unwrap();
+    for v in &vs[..5] {
+        let mut a = lsh_mem.query_bucket_ids(v).unwrap();
+        let mut b = lsh_sql.query_bucket_ids(v).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+}
+
+#[test]
+fn test_boundary_eps() {
+    // h(x) = floor(x). A data point on the boundary at 3 and a query that only differs by
+    // floating point rounding (0.9 / 0.3 = 2.9999998 in f32) end up in different cells.
+    let mut hasher = <L2>::new(1, 1., 1, 1);
+    hasher.a = ndarray::array![[1.]].into_shared();
+    hasher.b = ndarray::array![0.];
+    let mut lsh = LshMem::new(1, 1, 1).with_hashers(vec![hasher]).unwrap();
+    lsh.store_vec(&[3.]).unwrap();
+    let q = &[0.9_f32 / 0.3];
+    assert!(lsh.query_bucket_ids(q).unwrap().is_empty());
+
+    lsh.boundary_eps(1e-3);
+    assert_eq!(lsh.query_bucket_ids(q).unwrap(), vec![0]);
+}
+
+#[test]
+fn test_query_top_k_arr() {
+    // a zero projection puts every data point in the same bucket.
+    let mut hasher = <L2>::new(2, 1., 1, 1);
+    hasher.a = ndarray::array![[0., 0.]].into_shared();
+    hasher.b = ndarray::array![0.];
+    let mut lsh = LshMem::new(1, 1, 2).with_hashers(vec![hasher]).unwrap();
+    lsh.store_vecs(&[vec![0., 0.], vec![3., 4.], vec![1., 0.]])
+        .unwrap();
+
+    let (ids, dists) = lsh.query_top_k_arr(&[0., 0.], 2).unwrap();
+    assert_eq!(ids, ndarray::array![0, 2]);
+    assert_eq!(dists, ndarray::array![0., 1.]);
+
+    let qs = ndarray::array![[0., 0.], [3., 4.]];
+    let (ids, dists) = lsh.query_top_k_batch_arr(qs.view(), 4).unwrap();
+    assert_eq!(ids.row(1), ndarray::array![1, 2, 0, u32::MAX]);
+    assert_eq!(dists.row(1)[2], 5.);
+    assert!(dists[[0, 3]].is_infinite());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_export_stats() {
+    use crate::stats::StatsFormat;
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.], vec![2., 3., 4.1]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let stats = lsh.hash_table_stats().unwrap();
+    assert_eq!(stats.len(), 3);
+    assert!(stats.iter().all(|s| s.n_entries == 3));
+
+    let mut lsh_sql = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh_sql.store_vecs(&vs).unwrap();
+    assert_eq!(lsh_sql.hash_table_stats().unwrap(), stats);
+
+    let dir = std::env::temp_dir();
+    let csv = dir.join("lsh_test_export_stats.csv");
+    lsh.export_stats(&csv, StatsFormat::Csv).unwrap();
+    let csv = std::fs::read_to_string(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("table,n_buckets,n_entries,bucket_len,bucket_count")
+    );
+    let n_bins: usize = stats.iter().map(|s| s.histogram.len()).sum();
+    assert_eq!(lines.count(), n_bins);
+
+    let json = dir.join("lsh_test_export_stats.json");
+    lsh.export_stats(&json, StatsFormat::Json).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(json).unwrap()).unwrap();
+    assert_eq!(json[0]["n_buckets"], stats[0].n_buckets);
+}
+
+#[test]
+fn test_query_bucket_ids_within() {
+    let mut lsh: LshMem<_, f32, i32> = LshMem::new(2, 5, 2).seed(1).l2(100.).unwrap();
+    lsh.store_vecs(&[vec![0., 3.], vec![0., 1.], vec![0., 2.]])
+        .unwrap();
+    let within = lsh.query_bucket_ids_within(&[0., 0.], 2., true).unwrap();
+    assert_eq!(within, vec![(1, 1.), (2, 2.)]);
+    let within = lsh.query_bucket_ids_within(&[0., 0.], 1.5, false).unwrap();
+    assert_eq!(within, vec![(1, 1.)]);
+
+    let mut lsh = LshMem::new(2, 5, 3).only_index().srp().unwrap();
+    lsh.store_vec(&[1., 1., 1.]).unwrap();
+    assert!(lsh
+        .query_bucket_ids_within(&[1., 1., 1.], 0.1, true)
+        .is_err());
+}
+
+#[test]
+fn test_query_top_k() {
+    let mut lsh = LshMem::new(2, 5, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&[vec![1., 1., 1.], vec![2., 2., 2.1], vec![-1., -1., -1.]])
+        .unwrap();
+    let top = lsh.query_top_k(&[1., 1., 1.], 2).unwrap();
+    // same direction, so a cosine distance of (almost) 0.
+    assert_eq!(top[0].0, 0);
+    assert_eq!(top[1].0, 1);
+    assert!(top[1].1 < 1e-3);
+
+    let mut lsh = hi32::LshMem::<_, u8>::new(5, 5, 4)
+        .seed(1)
+        .minhash()
+        .unwrap();
+    lsh.store_vecs(&[vec![1, 1, 0, 0], vec![1, 1, 1, 0]])
+        .unwrap();
+    let top = lsh.query_top_k(&[1, 1, 0, 0], 2).unwrap();
+    assert_eq!(top[0], (0, 0.));
+    if let Some(&(idx, dist)) = top.get(1) {
+        assert_eq!(idx, 1);
+        assert!((dist - 1. / 3.).abs() < 1e-9);
+    }
+
+    let mut lsh = LshMem::new(2, 5, 3).only_index().srp().unwrap();
+    lsh.store_vec(&[1., 1., 1.]).unwrap();
+    assert!(lsh.query_top_k(&[1., 1., 1.], 2).is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_snapshot() {
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vec(&[2., 3., 4.]).unwrap();
+    let snapshot = lsh.snapshot();
+    lsh.store_vec(&[2., 3., 4.]).unwrap();
+
+    let path = std::env::temp_dir().join("lsh_test_snapshot.bincode");
+    snapshot.dump(&path).unwrap();
+    let mut restored = LshMem::new(5, 3, 3).srp().unwrap();
+    restored.load(&path).unwrap();
+    assert_eq!(restored.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![0]);
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().len(), 2);
+}
+
+#[test]
+fn test_rebuild() {
+    let mut lsh = LshMem::<_, f32, i32>::new(5, 2, 3)
+        .seed(1)
+        .multi_probe(4)
+        .l2(4.)
+        .unwrap();
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.], vec![1., -3., 4.]];
+    lsh.store_vecs(&vs).unwrap();
+    lsh.delete_vec(&vs[1]).unwrap();
+    lsh.store_vec_with_id(&[3., 3., 3.], 42).unwrap();
+    let cfg = lsh.config();
+
+    lsh.rebuild(6, 4, |lsh| lsh.l2(4.)).unwrap();
+    assert_eq!((lsh.n_projections, lsh.n_hash_tables), (6, 4));
+    // the settings besides the hash length and number of hash tables are kept.
+    assert_eq!(
+        lsh.config(),
+        LshConfig {
+            n_projections: 6,
+            n_hash_tables: 4,
+            ..cfg
+        }
+    );
+    assert_eq!(lsh.user_id(3), 42);
+    assert!(lsh.query_bucket_ids(&vs[0]).unwrap().contains(&0));
+    assert!(lsh.query_bucket_ids(&vs[2]).unwrap().contains(&2));
+    assert!(!lsh.query_bucket_ids(&vs[1]).unwrap().contains(&1));
+    // new data points continue after the existing indexes.
+    assert_eq!(lsh.store_vec(&[0., 1., 1.]).unwrap(), 4);
+
+    let mut lsh = LshMem::<_, f32>::new(5, 2, 3).only_index().srp().unwrap();
+    assert!(lsh.rebuild(6, 4, |lsh| lsh.srp()).is_err());
+}
+
+#[test]
+fn test_add_drop_hash_tables() {
+    let vs: Vec<Vec<f32>> = (0..30)
+        .map(|i| vec![i as f32, (i % 7) as f32 - 3., 1.])
+        .collect();
+    let mut lsh = LshMem::new(4, 2, 3)
+        .seed(1)
+        .packed_keys()
+        .projections_per_table(vec![4, 3])
+        .srp()
+        .unwrap();
+    let mut full = LshMem::new(4, 5, 3)
+        .seed(1)
+        .projections_per_table(vec![4, 3, 4, 4, 4])
+        .srp()
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    full.store_vecs(&vs).unwrap();
+    lsh.delete_by_idx(3).unwrap();
+    full.delete_by_idx(3).unwrap();
+
+    lsh.add_hash_tables(3, |lsh| lsh.srp()).unwrap();
+    assert_eq!(lsh.n_hash_tables, 5);
+    assert_eq!(lsh.hashers.len(), 5);
+    for v in &vs {
+        let mut ids = lsh.query_bucket_ids(v).unwrap();
+        let mut expected = full.query_bucket_ids(v).unwrap();
+        ids.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(ids, expected);
+    }
+    assert_eq!(lsh.store_vec(&[0., 1., 1.]).unwrap(), 30);
+
+    lsh.drop_hash_tables(4).unwrap();
+    assert_eq!(lsh.n_hash_tables, 1);
+    assert_eq!(lsh.hash_table_stats().unwrap().len(), 1);
+    assert!(lsh.query_bucket_ids(&vs[5]).unwrap().contains(&5));
+    assert!(lsh.drop_hash_tables(1).is_err());
+
+    let mut lsh = LshMem::<_, f32>::new(5, 2, 3).only_index().srp().unwrap();
+    lsh.add_hash_tables(1, |lsh| lsh.srp()).unwrap();
+    lsh.store_vec(&[1., 2., 3.]).unwrap();
+    assert!(lsh.add_hash_tables(1, |lsh| lsh.srp()).is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_import_hashes_csv() {
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.], vec![1., -3., 4.]];
+    let source = LshMem::<_, f32, i32>::new(3, 2, 3).seed(1).l2(4.).unwrap();
+    let mut csv = "table_idx,hash,id\n".to_string();
+    for (idx, v) in vs.iter().enumerate() {
+        for (i, hasher) in source.hashers.iter().enumerate() {
+            let hex: String = hasher
+                .hash_vec_query(v)
+                .iter()
+                .flat_map(|h| h.to_ne_bytes().to_vec())
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            csv.push_str(&format!("{},{},{}\n", i, hex, idx));
+        }
+    }
+    let path = std::env::temp_dir().join("lsh_test_import_hashes.csv");
+    std::fs::write(&path, csv).unwrap();
+
+    let mut lsh = LshMem::<_, f32, i32>::new(3, 2, 3)
+        .seed(1)
+        .only_index()
+        .l2(4.)
+        .unwrap();
+    assert_eq!(lsh.import_hashes_csv(&path).unwrap(), 6);
+    let mut lsh_sql = LshSqlMem::<_, f32, i32>::new(3, 2, 3)
+        .seed(1)
+        .only_index()
+        .l2(4.)
+        .unwrap();
+    assert_eq!(lsh_sql.import_hashes_csv(&path).unwrap(), 6);
+    for (idx, v) in vs.iter().enumerate() {
+        assert!(lsh.query_bucket_ids(v).unwrap().contains(&(idx as u32)));
+        assert!(lsh_sql.query_bucket_ids(v).unwrap().contains(&(idx as u32)));
+    }
+    // new data points continue after the imported ids.
+    assert_eq!(lsh.store_vec(&[0., 1., 1.]).unwrap(), 3);
+
+    // a hash of the wrong length is rejected with its row.
+    std::fs::write(&path, "0,0001,0\n").unwrap();
+    assert!(matches!(
+        lsh.import_hashes_csv(&path),
+        Err(Error::Batch { row: 0, .. })
+    ));
+    // non-ASCII hashes are rejected, also when a character spans two hex digits.
+    for line in &[
+        "0,0é0,0\n".to_string(),
+        format!("0,0é{},0\n", "0".repeat(21)),
+    ] {
+        std::fs::write(&path, line).unwrap();
+        assert!(matches!(
+            lsh.import_hashes_csv(&path),
+            Err(Error::Batch { row: 0, .. })
+        ));
+    }
+    let mut lsh_i8 = hi8::LshMem::<_, f32>::new(2, 1, 3)
+        .only_index()
+        .srp()
+        .unwrap();
+    std::fs::write(&path, "0,0é0,0\n").unwrap();
+    assert!(lsh_i8.import_hashes_csv(&path).is_err());
+    // the data points can't be imported.
+    let mut lsh = LshMem::<_, f32, i32>::new(3, 2, 3).l2(4.).unwrap();
+    assert!(lsh.import_hashes_csv(&path).is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_delete_by_idx() {
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let mut lsh_idx = LshMem::new(5, 3, 3).seed(1).only_index().srp().unwrap();
+    let mut lsh_sql = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh_idx.store_vecs(&vs).unwrap();
+    lsh_sql.store_vecs(&vs).unwrap();
+
+    lsh.delete_by_idx(0).unwrap();
+    lsh_idx.delete_by_idx(0).unwrap();
+    lsh_sql.delete_by_idx(0).unwrap();
+    for ids in [
+        lsh.query_bucket_ids(&vs[0]).unwrap(),
+        lsh_idx.query_bucket_ids(&vs[0]).unwrap(),
+        lsh_sql.query_bucket_ids(&vs[0]).unwrap(),
+    ]
+    .iter()
+    {
+        assert!(!ids.contains(&0));
+    }
+    assert!(lsh_idx.query_bucket_ids(&vs[1]).unwrap().contains(&1));
+    assert!(matches!(lsh_idx.delete_by_idx(0), Err(Error::NotFound)));
+    assert!(matches!(lsh_sql.delete_by_idx(0), Err(Error::NotFound)));
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_projections_per_table() {
+    let v = [2., 3., 4.];
+    let mut lsh = LshMem::new(8, 3, 3)
+        .seed(1)
+        .projections_per_table(vec![4, 8, 12])
+        .multi_probe(4)
+        .srp()
+        .unwrap();
+    let mut lsh_sql = LshSqlMem::new(8, 3, 3)
+        .seed(1)
+        .projections_per_table(vec![4, 8, 12])
+        .srp()
+        .unwrap();
+    let lens: Vec<usize> = lsh
+        .hashers
+        .iter()
+        .map(|h| h.hash_vec_query(&v).len())
+        .collect();
+    assert_eq!(lens, vec![4, 8, 12]);
+
+    let idx = lsh.store_vec(&v).unwrap();
+    lsh_sql.store_vec(&v).unwrap();
+    lsh.base();
+    assert!(lsh.query_bucket_ids(&v).unwrap().contains(&idx));
+    assert!(lsh_sql.query_bucket_ids(&v).unwrap().contains(&idx));
+    assert_eq!(lsh.config().projections_per_table, Some(vec![4, 8, 12]));
+
+    // one hash length per hash table is required.
+    assert!(LshMem::<_, f32>::new(8, 3, 3)
+        .projections_per_table(vec![4, 8])
+        .srp()
+        .is_err());
+}
+
+#[test]
+fn test_shared_projections() {
+    let v = [2., 3., 4.];
+    let mut lsh = LshMem::<_, f32, i32>::new(4, 10, 3)
+        .seed(1)
+        .shared_projections(12)
+        .l2(2.)
+        .unwrap();
+    // the projections of every hash table point into the same matrix, at distinct rows.
+    let ptrs: Vec<usize> = lsh.hashers.iter().map(|h| h.a.as_ptr() as usize).collect();
+    let start = *ptrs.iter().min().unwrap();
+    let row_bytes = 3 * std::mem::size_of::<f32>();
+    let mut rows = std::collections::HashSet::new();
+    for (h, ptr) in lsh.hashers.iter().zip(ptrs) {
+        assert_eq!(h.a.dim(), (4, 3));
+        assert_eq!((ptr - start) % row_bytes, 0);
+        let (offset, step) = ((ptr - start) / row_bytes, h.a.strides()[0] as usize / 3);
+        assert!(offset + 3 * step < 12);
+        assert!(rows.insert((offset, step)));
+    }
+    let idx = lsh.store_vec(&v).unwrap();
+    assert!(lsh.query_bucket_ids(&v).unwrap().contains(&idx));
+
+    let cfg = lsh.config();
+    assert_eq!(cfg.shared_projections, Some(12));
+    let replay = LshMem::<L2<f32, i32>, f32, i32>::from_config(&cfg).unwrap();
+    assert_eq!(replay.hashers[9].a, lsh.hashers[9].a);
+
+    // the shared matrix needs at least a row per projection.
+    assert!(LshMem::<_, f32, i32>::new(4, 2, 3)
+        .shared_projections(3)
+        .l2(2.)
+        .is_err());
+    // 6 rows hold only 3 distinct selections of 4 rows.
+    assert!(LshMem::<_, f32, i32>::new(4, 4, 3)
+        .shared_projections(6)
+        .l2(2.)
+        .is_err());
+}
+
+#[test]
+fn test_projection_density() {
+    let v = [2., 3., 4., 0., 1., -1.];
+    let mut lsh = LshMem::<_, f32>::new(4, 10, 6)
+        .seed(1)
+        .projection_density(0.5)
+        .srp()
+        .unwrap();
+    let idx = lsh.store_vec(&v).unwrap();
+    assert!(lsh.query_bucket_ids(&v).unwrap().contains(&idx));
+
+    let mut lsh = LshMem::<_, f32, i32>::new(4, 10, 6)
+        .seed(1)
+        .projection_density(0.5)
+        .l2(2.)
+        .unwrap();
+    let idx = lsh.store_vec(&v).unwrap();
+    assert!(lsh.query_bucket_ids(&v).unwrap().contains(&idx));
+    let cfg = lsh.config();
+    assert_eq!(cfg.projection_density, Some(0.5));
+    let replay = LshMem::<L2<f32, i32>, f32, i32>::from_config(&cfg).unwrap();
+    assert_eq!(
+        replay.hashers[9].hash_vec_query(&v),
+        lsh.hashers[9].hash_vec_query(&v)
+    );
+
+    assert!(LshMem::<_, f32>::new(4, 2, 6)
+        .projection_density(0.)
+        .srp()
+        .is_err());
+    assert!(LshMem::<_, f32, i32>::new(4, 2, 6)
+        .projection_density(0.5)
+        .shared_projections(8)
+        .l2(2.)
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "f16")]
+fn test_f16() {
+    use half::f16;
+    let vs: Vec<Vec<f16>> = [[2., 3., 4.], [2.1, 3., 4.], [-20., 30., -4.]]
+        .iter()
+        .map(|v| v.iter().map(|&x| f16::from_f32(x)).collect())
+        .collect();
+    let mut lsh = LshMem::<_, f16, i32>::new(5, 10, 3).seed(1).l2(4.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let top = lsh.query_top_k(&vs[0], 2).unwrap();
+    assert_eq!(top.iter().map(|n| n.0).collect::<Vec<_>>(), vec![0, 1]);
+
+    let mut lsh = LshMem::<_, f16>::new(5, 10, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert!(lsh.query_bucket_ids(&vs[2]).unwrap().contains(&2));
+
+    // a sum in half precision stops growing at 0.25, as 1e-4 is below half its precision.
+    let a = vec![f16::from_f32(0.01); 4096];
+    let dot = crate::dist::inner_prod(&a, &a).to_f32();
+    assert!((dot - 0.4096).abs() < 1e-3);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_raw_insert_remove() {
+    let v = [2., 3., 4.];
+    let mut lsh = LshMem::new(3, 2, 3).seed(1).srp().unwrap();
+    let mut lsh_sql = LshSqlMem::new(3, 2, 3).seed(1).srp().unwrap();
+    let hash = lsh.hashers[0].hash_vec_query(&v);
+    lsh.insert_raw(0, &hash, 5).unwrap();
+    assert!(lsh.query_bucket_ids(&v).unwrap().contains(&5));
+    lsh.remove_raw(0, &hash, 5).unwrap();
+    assert!(!lsh.query_bucket_ids(&v).unwrap().contains(&5));
+    lsh_sql.insert_raw(0, &hash, 5).unwrap();
+    assert!(lsh_sql.query_bucket_ids(&v).unwrap().contains(&5));
+    lsh_sql.remove_raw(0, &hash, 5).unwrap();
+    assert!(matches!(
+        lsh_sql.remove_raw(0, &hash, 5),
+        Err(Error::NotFound)
+    ));
+
+    assert!(matches!(
+        lsh.insert_raw(2, &hash, 0),
+        Err(Error::TableNotExist)
+    ));
+    assert!(lsh.insert_raw(0, &hash[..2], 0).is_err());
+    assert!(lsh.remove_raw(1, &[0, 1, 0, 1], 0).is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_open_or_create() {
+    let path = std::env::temp_dir().join("lsh_test_open_or_create.bincode");
+    std::fs::remove_file(&path).ok();
+    let mut cfg = LshConfig::new(
+        HashFamily::Srp {
+            sign: SignConvention::Binary,
+        },
+        5,
+        3,
+        3,
+    );
+    cfg.seed = 2;
+    let mut lsh = LshMem::<SignRandomProjections<f32>, f32>::open_or_create(&path, &cfg).unwrap();
+    lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., 0., 2.]])
+        .unwrap();
+    lsh.dump(&path).unwrap();
+
+    let lsh = LshMem::<SignRandomProjections<f32>, f32>::open_or_create(&path, &cfg).unwrap();
+    assert!(lsh.query_bucket_ids(&[-1., 0., 2.]).unwrap().contains(&1));
+    assert_eq!(lsh.config(), cfg);
+
+    let mut other = cfg.clone();
+    other.dim = 4;
+    assert!(LshMem::<SignRandomProjections<f32>, f32>::open_or_create(&path, &other).is_err());
+    let mut other = cfg.clone();
+    other.hasher = HashFamily::Srp {
+        sign: SignConvention::Bipolar,
+    };
+    assert!(LshMem::<SignRandomProjections<f32>, f32>::open_or_create(&path, &other).is_err());
+}
+
+#[test]
+fn test_array_views() {
+    use ndarray::prelude::*;
+    let vs = array![[2., -1.], [3., 0.], [4., 2.]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    // columns are not contiguous.
+    let ids: Vec<u32> = vs
+        .axis_iter(Axis(1))
+        .map(|v| lsh.store_arr(v).unwrap())
+        .collect();
+    assert_eq!(ids, vec![0, 1]);
+    assert!(lsh.query_bucket_ids_arr(vs.column(1)).unwrap().contains(&1));
+    assert!(lsh
+        .query_bucket_arr(vs.column(0))
+        .unwrap()
+        .contains(&&[2., 3., 4.][..]));
+    assert_eq!(
+        lsh.query_bucket_ids_batch_arr(vs.t()).unwrap()[0],
+        lsh.query_bucket_ids(&[2., 3., 4.]).unwrap()
+    );
+    lsh.store_array(vs.t()).unwrap();
+
+    lsh.delete_arr(vs.column(1)).unwrap();
+    assert!(!lsh.query_bucket_ids_arr(vs.column(1)).unwrap().contains(&1));
+
+    // a strided slice is queried as its contiguous copy.
+    let wide = array![2., 0., 3., 0., 4., 0.];
+    let strided = wide.slice(s![..;2]);
+    assert!(strided.as_slice().is_none());
+    assert_eq!(
+        lsh.query_bucket_ids_arr(strided).unwrap(),
+        lsh.query_bucket_ids(&[2., 3., 4.]).unwrap()
+    );
+}
+
+#[test]
+fn test_array_shape() {
+    // 3 data points of dimension 2, stored as columns instead of rows.
+    let vs = ndarray::arr2(&[[1f32, 2., 3.], [4., 5., 6.]]);
+    let mut lsh = LshMem::new(5, 4, 2).seed(1).srp().unwrap();
+    let err = lsh.store_array(vs.view()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ShapeMismatch {
+            rows: 2,
+            cols: 3,
+            expected: 2
+        }
+    ));
+    assert!(matches!(
+        lsh.query_bucket_ids_batch_arr(vs.view()),
+        Err(Error::ShapeMismatch { .. })
+    ));
+    assert!(matches!(
+        lsh.query_bucket_ids_batch_arr_par(vs.view()),
+        Err(Error::ShapeMismatch { .. })
+    ));
+
+    assert_eq!(lsh.store_array(vs.t()).unwrap(), vec![0, 1, 2]);
+    let ids = lsh.query_bucket_ids_batch_arr(vs.t()).unwrap();
+    assert!(ids
+        .iter()
+        .enumerate()
+        .all(|(i, ids)| ids.contains(&(i as u32))));
+}
+
+#[test]
+fn test_store_vecs_par() {
+    let vs: Vec<Vec<f32>> = (0..50)
+        .map(|i| vec![i as f32, (i % 7) as f32 - 3., 1.])
+        .collect();
+    let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    let mut lsh_par = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    let ids = lsh.store_vecs(&vs).unwrap();
+    assert_eq!(lsh_par.store_vecs_par(&vs).unwrap(), ids);
+    for v in &vs {
+        let mut a = lsh.query_bucket_ids(v).unwrap();
+        let mut b = lsh_par.query_bucket_ids(v).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_backend_metrics() {
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.], vec![2., 3., 4.1]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let mut lsh_sql = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh_sql.store_vecs(&vs).unwrap();
+
+    assert_eq!(lsh.n_entries().unwrap(), 9);
+    assert_eq!(lsh_sql.n_entries().unwrap(), 9);
+    assert!(lsh.approximate_bytes().unwrap() > 0);
+    assert!(lsh_sql.approximate_bytes().unwrap() > 0);
+
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    let ht_sql = lsh_sql.hash_tables.as_ref().unwrap();
+    for i in 0..3 {
+        let stats = &lsh.hash_table_stats().unwrap()[i];
+        assert_eq!(&ht.bucket_histogram(i, 1000).unwrap(), &stats.histogram);
+        assert_eq!(&ht_sql.bucket_histogram(i, 1000).unwrap(), &stats.histogram);
+        // at most one bucket.
+        let one: usize = ht_sql
+            .bucket_histogram(i, 1)
+            .unwrap()
+            .iter()
+            .map(|bin| bin.n_buckets)
+            .sum();
+        assert_eq!(one, 1);
+    }
+    assert!(matches!(
+        ht.bucket_histogram(3, 1000),
+        Err(Error::TableNotExist)
+    ));
+
+    let d = lsh.describe().unwrap();
+    assert!(d.contains("Entries: 9"));
+    assert_eq!(d.lines().last(), lsh_sql.describe().unwrap().lines().last());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_store_from_iter() {
+    let vs: Vec<Vec<f32>> = (0..25).map(|i| vec![i as f32, 1., -1.]).collect();
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let mut lsh_sql = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let ids = lsh.store_from_iter(vs.clone(), 10).unwrap();
+    assert_eq!(ids, (0..25).collect::<Vec<u32>>());
+    assert_eq!(lsh_sql.store_from_iter(vs.clone(), 7).unwrap(), ids);
+    assert_eq!(lsh_sql.n_entries().unwrap(), 75);
+
+    // rows of a failing chunk are reported relative to the whole iterator.
+    let mut bad = vs.clone();
+    bad[12] = vec![1., 2.];
+    assert!(matches!(
+        lsh.store_from_iter(bad, 10),
+        Err(Error::Batch { row: 12, .. })
+    ));
+    assert!(lsh.store_from_iter(vs, 0).is_err());
+}
+
+#[test]
+fn test_extend() {
+    let vs: Vec<Vec<f32>> = (0..25).map(|i| vec![i as f32, 1., -1.]).collect();
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.extend(vs.clone());
+    let built = LshMem::new(5, 3, 3)
+        .seed(1)
+        .srp()
+        .unwrap()
+        .with_vecs(vs.clone())
+        .unwrap();
+    for v in &vs {
+        assert_eq!(
+            lsh.query_bucket_ids(v).unwrap(),
+            built.query_bucket_ids(v).unwrap()
+        );
+    }
+    assert_eq!(lsh.store_vec(&vs[0]).unwrap(), 25);
+}
+
+#[test]
+#[should_panic(expected = "could not store data points")]
+fn test_extend_panics() {
+    let mut lsh = LshMem::new(5, 3, 3).srp().unwrap();
+    lsh.extend(vec![vec![1., 2.]]);
+}
+
+#[test]
+fn test_store_set() {
+    let sets = vec![vec![1, 5, 9], vec![1, 5, 9, 12], vec![30, 31]];
+    let mut lsh = hi32::LshMem::<_, u8>::new(4, 6, 40)
+        .seed(1)
+        .minhash()
+        .unwrap();
+    let mut lsh_vec = hi32::LshMem::<_, u8>::new(4, 6, 40)
+        .seed(1)
+        .minhash()
+        .unwrap();
+    for set in &sets {
+        let v: Vec<u8> = (0..40).map(|i| set.contains(&i) as u8).collect();
+        assert_eq!(lsh.store_set(set).unwrap(), lsh_vec.store_vec(&v).unwrap());
+        // the binary vector is stored.
+        assert_eq!(lsh.hash_tables.as_ref().unwrap().vec_store.get(0).len(), 40);
+    }
+    for set in &sets {
+        let v: Vec<u8> = (0..40).map(|i| set.contains(&i) as u8).collect();
+        let mut a = lsh.query_set(set).unwrap();
+        let mut b = lsh_vec.query_bucket_ids(&v).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+    assert!(lsh.query_set(&[30, 31]).unwrap().contains(&2));
+    assert!(lsh.store_set(&[3, 40]).is_err());
+    assert!(lsh.query_set(&[40]).is_err());
+
+    // set queries are observed like vector queries.
+    #[derive(Default)]
+    struct Tables(std::sync::Mutex<Vec<usize>>);
+    impl QueryObserver for Tables {
+        fn on_hash_table(&self, event: &TableEvent) {
+            self.0.lock().unwrap().push(event.hash_table);
+        }
+    }
+    let tables = std::sync::Arc::new(Tables::default());
+    lsh.query_observer(tables.clone());
+    lsh.query_set(&sets[0]).unwrap();
+    assert_eq!(*tables.0.lock().unwrap(), (0..6).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_b_minhash() {
+    let mut lsh = hi8::LshMem::<_, u16>::new(16, 4, 500)
+        .seed(1)
+        .b_minhash(2)
+        .unwrap();
+    let a = (0..500).map(|i| (i % 5 == 0) as u16).collect::<Vec<_>>();
+    let mut b = a.clone();
+    b[1] = 1;
+    lsh.store_vecs(&[a.clone(), b]).unwrap();
+    let top = lsh.query_top_k(&a, 2).unwrap();
+    assert_eq!(top[0], (0, 0.));
+
+    let cfg = lsh.config();
+    assert_eq!(cfg.hasher, HashFamily::BMinHash { b: 2 });
+    let lsh = hi8::LshMem::<BMinHash<u16, i8>, u16>::from_config(&cfg).unwrap();
+    assert_eq!(lsh.hashers[0].b(), 2);
+    assert!(hi8::LshMem::<_, u16>::new(3, 1, 10).b_minhash(8).is_err());
+}
+
+#[test]
+fn test_bit_sampling() {
+    let mut lsh = LshMem::<_, u64>::new(8, 6, 2)
+        .seed(1)
+        .bit_sampling()
+        .unwrap();
+    let a = [0xdead_beef_u64, 0x0123_4567_89ab_cdef];
+    let b = [a[0] ^ 0b101, a[1]];
+    let c = [!a[0], !a[1]];
+    lsh.store_vecs(&[a.to_vec(), b.to_vec(), c.to_vec()])
+        .unwrap();
+    let top = lsh.query_top_k(&a, 3).unwrap();
+    assert_eq!(top[..2], [(0, 0.), (1, 2.)]);
+    assert!(!lsh.query_bucket_ids(&a).unwrap().contains(&2));
+
+    let cfg = lsh.config();
+    assert_eq!(cfg.hasher, HashFamily::BitSampling);
+    let other = LshMem::<BitSampling<u64, i8>, u64>::from_config(&cfg).unwrap();
+    assert_eq!(other.hashers[0].positions(), lsh.hashers[0].positions());
+    assert!(LshMem::<_, u8>::new(17, 1, 2).bit_sampling().is_err());
+}
+
+#[test]
+fn test_l1() {
+    let mut lsh = LshMem::<_, f32, i32>::new(4, 8, 3).seed(1).l1(8.).unwrap();
+    lsh.store_vecs(&[vec![1., 1., 1.], vec![1., 2., 1.], vec![50., -50., 50.]])
+        .unwrap();
+    let top = lsh.query_top_k(&[1., 1., 1.], 3).unwrap();
+    assert_eq!(top[0], (0, 0.));
+    assert!(top.iter().all(|&(idx, _)| idx != 2));
+
+    let cfg = lsh.config();
+    assert_eq!(cfg.hasher, HashFamily::L1 { r: 8. });
+    let replay = LshMem::<L1<f32, i32>, f32, i32>::from_config(&cfg).unwrap();
+    assert_eq!(replay.hashers[0].a, lsh.hashers[0].a);
+}
+
+#[test]
+fn test_srp_quantized() {
+    let scale = [0.5, 0.5, 2.];
+    let mut lsh = LshMem::<_, i8>::new(6, 4, 3)
+        .seed(1)
+        .srp_quantized(&scale)
+        .unwrap();
+    lsh.store_vecs(&[vec![10, 20, -3], vec![11, 20, -3], vec![-10, -20, 3]])
+        .unwrap();
+    let top = lsh.query_top_k(&[10, 20, -3], 3).unwrap();
+    assert_eq!(top[0], (0, 0.));
+    assert!(top.iter().all(|&(idx, _)| idx != 2));
+
+    let cfg = lsh.config();
+    let replay = LshMem::<QuantizedSignRandomProjections, i8>::from_config(&cfg).unwrap();
+    assert_eq!(replay.hashers[2].scale(), &scale);
+    assert!(LshMem::<_, i8>::new(6, 4, 3)
+        .srp_quantized(&[1., 2.])
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_stats() {
+    let vs = [vec![2., 3., 4.], vec![-1., 0., 2.], vec![2., 3., 4.1]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let mut lsh_sql = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh_sql.store_vecs(&vs).unwrap();
+
+    let stats = lsh.stats().unwrap();
+    let stats_sql = lsh_sql.stats().unwrap();
+    assert_eq!(stats.n_hash_tables, 3);
+    assert_eq!(stats.n_entries, 9);
+    assert_eq!(stats.tables, stats_sql.tables);
+    for (i, (t, hts)) in stats
+        .tables
+        .iter()
+        .zip(lsh.hash_table_stats().unwrap())
+        .enumerate()
+    {
+        assert_eq!(t.table, i);
+        assert_eq!(t.n_buckets, hts.n_buckets);
+        assert!(t.min >= 1 && t.min <= t.max && t.max <= 3);
+        assert!((t.avg - 3. / t.n_buckets as f64).abs() < 1e-9);
+    }
+
+    // an empty index has zeroed bucket statistics.
+    let lsh = LshMem::<_, f32>::new(5, 2, 3).seed(1).srp().unwrap();
+    let stats = lsh.stats().unwrap();
+    assert_eq!(stats.n_entries, 0);
+    assert!(stats.tables.iter().all(|t| t.n_buckets == 0 && t.max == 0));
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_candidate_pairs() {
+    let vs = [
+        vec![2., 3., 4.],
+        vec![-1., 0., 2.],
+        vec![2., 3., 4.1],
+        vec![2., 3., 4.],
+    ];
+    let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    let mut lsh_sql = LshSqlMem::new(5, 4, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh_sql.store_vecs(&vs).unwrap();
+
+    let (mut pairs, mut pairs_sql) = (vec![], vec![]);
+    lsh.candidate_pairs(1, |a, b, n| {
+        pairs.push((a, b, n));
+        Ok(())
+    })
+    .unwrap();
+    lsh_sql
+        .candidate_pairs(1, |a, b, n| {
+            pairs_sql.push((a, b, n));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(pairs, pairs_sql);
+    // sorted, and identical data points collide in every hash table.
+    assert!(pairs
+        .windows(2)
+        .all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+    assert!(pairs.contains(&(0, 3, 4)));
+
+    let mut buf = vec![];
+    let n = lsh.write_candidate_pairs(4, &mut buf).unwrap();
+    let csv = String::from_utf8(buf).unwrap();
+    assert_eq!(csv.lines().next(), Some("a,b,collisions"));
+    assert_eq!(csv.lines().count(), n + 1);
+    assert!(csv.contains("0,3,4"));
+
+    // the callback can stop the enumeration.
+    assert!(lsh
+        .candidate_pairs(1, |_, _, _| Err(Error::Failed("stop".to_string())))
+        .is_err());
+    assert!(lsh.candidate_pairs(0, |_, _, _| Ok(())).is_err());
+}
+
+#[test]
+fn test_shared() {
+    use std::sync::Arc;
+    let lsh = Arc::new(
+        LshShared::<_, f32, i32>::new(4, 6, 3)
+            .seed(1)
+            .l2(4.)
+            .unwrap(),
+    );
+    let vs: Vec<Vec<f32>> = (0..200).map(|i| vec![i as f32, 1., -1.]).collect();
+
+    let writer = {
+        let (lsh, vs) = (lsh.clone(), vs.clone());
+        std::thread::spawn(move || lsh.store_vecs_shared(&vs).unwrap())
+    };
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            let (lsh, vs) = (lsh.clone(), vs.clone());
+            std::thread::spawn(move || {
+                for v in &vs {
+                    // a query never returns an id that isn't stored yet.
+                    for (idx, _) in lsh.query_top_k(v, 3).unwrap() {
+                        assert!(idx < 200);
+                    }
+                }
+            })
+        })
+        .collect();
+    let ids = writer.join().unwrap();
+    readers.into_iter().for_each(|r| r.join().unwrap());
+    assert_eq!(ids, (0..200).collect::<Vec<u32>>());
+
+    for (idx, v) in ids.iter().zip(&vs) {
+        assert_eq!(lsh.query_top_k(v, 1).unwrap()[0], (*idx, 0.));
+    }
+    assert_eq!(lsh.n_entries().unwrap(), 200 * 6);
+    lsh.delete_by_idx_shared(7).unwrap();
+    assert!(!lsh.query_bucket_ids(&vs[7]).unwrap().contains(&7));
+    assert!(lsh.delete_by_idx_shared(7).is_err());
+
+    // the &mut api behaves as the MemoryTable.
+    let mut lsh = LshShared::<_, f32, i32>::new(4, 6, 3)
+        .seed(1)
+        .l2(4.)
+        .unwrap();
+    let mut lsh_mem = LshMem::<_, f32, i32>::new(4, 6, 3).seed(1).l2(4.).unwrap();
+    assert_eq!(
+        lsh.store_vecs(&vs).unwrap(),
+        lsh_mem.store_vecs(&vs).unwrap()
+    );
+    assert_eq!(
+        lsh.query_top_k(&vs[3], 5).unwrap(),
+        lsh_mem.query_top_k(&vs[3], 5).unwrap()
+    );
+    lsh.delete_by_idx(3).unwrap();
+    assert!(!lsh.query_bucket_ids(&vs[3]).unwrap().contains(&3));
+}
+
+#[test]
+fn test_lsh_forest() {
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| {
+            vec![
+                (i % 7) as f32 - 3.,
+                (i % 5) as f32 - 2.,
+                (i / 7) as f32 + 1.,
+            ]
+        })
+        .collect();
+    let mut forest = LshForest::srp(12, 5, 3, 1).unwrap();
+    assert!(forest.query_bucket_ids(&vs[0], 1).unwrap().is_empty());
+    assert_eq!(
+        forest.store_vecs(&vs).unwrap(),
+        (0..40).collect::<Vec<u32>>()
+    );
+    assert_eq!(forest.len(), 40);
+
+    for (idx, v) in vs.iter().enumerate() {
+        assert!(forest
+            .query_bucket_ids(v, 1)
+            .unwrap()
+            .contains(&(idx as u32)));
+        assert!(forest.query_bucket_ids(v, 10).unwrap().len() >= 10);
+    }
+    assert_eq!(
+        forest.query_bucket_ids(&vs[3], 100).unwrap(),
+        (0..40).collect::<Vec<u32>>()
+    );
+    assert!(matches!(
+        forest.query_bucket_ids(&[1., 2.], 1),
+        Err(Error::DimensionMismatch {
+            expected: 3,
+            actual: 2
+        })
+    ));
+
+    // the hashers of an LSH can be shared with a forest.
+    let lsh = LshMem::<_, f32, i32>::new(8, 4, 3).seed(1).l2(2.).unwrap();
+    let mut forest = LshForest::new(lsh.hashers.clone(), 3).unwrap();
+    assert_eq!(forest.n_trees(), 4);
+    forest.store_vecs(&vs).unwrap();
+    assert!(forest.query_bucket_ids(&vs[5], 1).unwrap().contains(&5));
+    assert!(LshForest::<L2<f32, i32>, f32, i32>::new(vec![], 3).is_err());
+
+    let mut forest = LshForest::l2(8, 4, 3, 2., 1).unwrap();
+    forest.store_vecs(&vs).unwrap();
+    assert!(forest.query_bucket_ids(&vs[5], 3).unwrap().len() >= 3);
+
+    // a batch with an invalid row isn't partially stored.
+    let batch = vec![vec![1., 2., 3.], vec![1., 2.]];
+    assert!(matches!(
+        forest.store_vecs(&batch),
+        Err(Error::Batch { row: 1, .. })
+    ));
+    assert_eq!(forest.len(), 40);
+}
+
+#[test]
+fn test_srp_packed() {
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| vec![(i % 7) as f32 - 3., (i % 5) as f32 - 2., 1.])
+        .collect();
+    // the packed hashes hold the same bits, so the buckets are equal.
+    let mut lsh = LshMem::new(70, 4, 3).seed(1).srp().unwrap();
+    let mut packed = hi_packed::LshMem::new(70, 4, 3)
+        .seed(1)
+        .srp_packed()
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    packed.store_vecs(&vs).unwrap();
+    for v in &vs {
+        let mut a = lsh.query_bucket_ids(v).unwrap();
+        let mut b = packed.query_bucket_ids(v).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+    assert_eq!(packed.hashers[0].hash_vec_query(&vs[0]).len(), 2);
+    assert!(packed.approximate_bytes().unwrap() < lsh.approximate_bytes().unwrap());
+    assert!(packed.describe().is_ok());
+    // the unique hash words of the statistics aren't truncated.
+    let words = packed.hash_tables.as_ref().unwrap().get_unique_hash_int();
+    assert!(words.iter().any(|&w| w > u32::MAX as u64));
+
+    let mut lsh = LshMem::new(10, 4, 3).seed(1).multi_probe(5).srp().unwrap();
+    let mut packed = hi_packed::LshMem::new(10, 4, 3)
+        .seed(1)
+        .multi_probe(5)
+        .srp_packed()
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    packed.store_vecs(&vs).unwrap();
+    let mut a = lsh.query_bucket_ids(&vs[3]).unwrap();
+    let mut b = packed.query_bucket_ids(&vs[3]).unwrap();
+    a.sort_unstable();
+    b.sort_unstable();
+    assert_eq!(a, b);
+
+    let cfg = packed.config();
+    assert_eq!(cfg.hasher, HashFamily::SrpPacked);
+    let other = hi_packed::LshMem::<PackedSignRandomProjections<f32>>::from_config(&cfg).unwrap();
+    assert_eq!(
+        other.hashers[0].hash_vec_query(&vs[5]),
+        packed.hashers[0].hash_vec_query(&vs[5])
+    );
+}
@@ -0,0 +1,347 @@
+//! Write-ahead log of an in memory index, see [enable_wal](struct.LSH.html#method.enable_wal).
+//!
+//! The log starts with a header: magic, version, the checksum of the snapshot that the log
+//! continues and the size of the values of the data points. Every change is appended as a
+//! record: tag (u32), length of the payload in bytes (u32), id (u64), payload and the crc32 of
+//! the preceding bytes of the record. The log is written in native endianness.
+use crate::prelude::*;
+use std::borrow::Cow;
+use std::fs::File;
+#[cfg(feature = "serde")]
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+const MAGIC: &[u8; 4] = b"LSHW";
+#[cfg(feature = "serde")]
+const VERSION: u32 = 1;
+#[cfg(feature = "serde")]
+const HEADER_LEN: usize = 16;
+/// tag, length of the payload and id.
+const RECORD_HEADER_LEN: usize = 16;
+const RECORD_FOOTER_LEN: usize = 4;
+
+/// Kind of a record in the log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Store = 1,
+    Delete = 2,
+    DeleteIdx = 3,
+    UserId = 4,
+    Payload = 5,
+    Begin = 6,
+    Commit = 7,
+    Rollback = 8,
+    Update = 9,
+}
+
+#[cfg(feature = "serde")]
+impl Tag {
+    fn from_u32(tag: u32) -> Option<Tag> {
+        match tag {
+            1 => Some(Tag::Store),
+            2 => Some(Tag::Delete),
+            3 => Some(Tag::DeleteIdx),
+            4 => Some(Tag::UserId),
+            5 => Some(Tag::Payload),
+            6 => Some(Tag::Begin),
+            7 => Some(Tag::Commit),
+            8 => Some(Tag::Rollback),
+            9 => Some(Tag::Update),
+            _ => None,
+        }
+    }
+}
+
+/// A change to the index. Ids are stored as `u64`, whatever the id type of the index.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Record<'a, N: Clone> {
+    /// A data point was stored under an id.
+    Store(u64, Cow<'a, [N]>),
+    /// A data point was deleted by its values.
+    Delete(Cow<'a, [N]>),
+    /// A data point was deleted by its id.
+    DeleteIdx(u64),
+    /// A user id was attached to an id.
+    UserId(u64, u64),
+    /// A payload was attached to an id.
+    Payload(u64, &'a [u8]),
+    /// An ingestion session was started.
+    Begin,
+    /// The ingestion session was committed.
+    Commit,
+    /// The ingestion session was rolled back.
+    Rollback,
+    /// The data point with an id was updated to new values (the first) from old values.
+    Update(u64, Cow<'a, [N]>, Cow<'a, [N]>),
+}
+
+fn as_bytes<T>(v: &[T]) -> &[u8] {
+    let data = v.as_ptr() as *const u8;
+    unsafe { std::slice::from_raw_parts(data, std::mem::size_of_val(v)) }
+}
+
+impl<'a, N: Copy> Record<'a, N> {
+    fn encode(&self) -> Vec<u8> {
+        let user_id;
+        let update;
+        let (tag, id, payload) = match self {
+            Record::Store(idx, v) => (Tag::Store, *idx, as_bytes(v)),
+            Record::Delete(v) => (Tag::Delete, 0, as_bytes(v)),
+            Record::DeleteIdx(idx) => (Tag::DeleteIdx, *idx, &[][..]),
+            Record::UserId(idx, id) => {
+                user_id = id.to_ne_bytes();
+                (Tag::UserId, *idx, &user_id[..])
+            }
+            Record::Payload(idx, payload) => (Tag::Payload, *idx, *payload),
+            Record::Begin => (Tag::Begin, 0, &[][..]),
+            Record::Commit => (Tag::Commit, 0, &[][..]),
+            Record::Rollback => (Tag::Rollback, 0, &[][..]),
+            Record::Update(idx, new_v, old_v) => {
+                update = [as_bytes(new_v), as_bytes(old_v)].concat();
+                (Tag::Update, *idx, &update[..])
+            }
+        };
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + payload.len() + RECORD_FOOTER_LEN);
+        buf.extend_from_slice(&(tag as u32).to_ne_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        buf.extend_from_slice(&id.to_ne_bytes());
+        buf.extend_from_slice(payload);
+        let crc = crc32fast::hash(&buf);
+        buf.extend_from_slice(&crc.to_ne_bytes());
+        buf
+    }
+}
+
+#[cfg(feature = "serde")]
+fn read_u32(log: &[u8], pos: usize) -> u32 {
+    let mut b = [0; 4];
+    b.copy_from_slice(&log[pos..pos + 4]);
+    u32::from_ne_bytes(b)
+}
+
+#[cfg(feature = "serde")]
+fn read_u64(log: &[u8], pos: usize) -> u64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(&log[pos..pos + 8]);
+    u64::from_ne_bytes(b)
+}
+
+/// Path of the log of the snapshot at `path`.
+#[cfg(feature = "serde")]
+pub(crate) fn log_path(path: &Path) -> PathBuf {
+    let mut log = path.as_os_str().to_owned();
+    log.push(".wal");
+    log.into()
+}
+
+/// Check the header of `log`. Returns whether the log continues the snapshot with checksum
+/// `snapshot`. A log that is too short to hold a header was never written to.
+#[cfg(feature = "serde")]
+pub(crate) fn check_header(log: &[u8], snapshot: u32, value_size: usize) -> Result<bool> {
+    if log.len() < HEADER_LEN {
+        return Ok(false);
+    }
+    if &log[..4] != MAGIC {
+        return Err(Error::CorruptIndex {
+            what: "write-ahead log header",
+            expected: u32::from_ne_bytes(*MAGIC) as u64,
+            found: read_u32(log, 0) as u64,
+        });
+    }
+    let version = read_u32(log, 4);
+    if version != VERSION {
+        return Err(Error::CorruptIndex {
+            what: "write-ahead log version",
+            expected: VERSION as u64,
+            found: version as u64,
+        });
+    }
+    if read_u32(log, 8) != snapshot {
+        return Ok(false);
+    }
+    let found = read_u32(log, 12);
+    if found as usize != value_size {
+        return Err(Error::CorruptIndex {
+            what: "write-ahead log value size",
+            expected: value_size as u64,
+            found: found as u64,
+        });
+    }
+    Ok(true)
+}
+
+/// Decode the records of `log`, which has a valid header. Decoding stops at the first
+/// incomplete or damaged record, which is what a crash during an append leaves behind. Returns
+/// the records and the length of the intact part of the log.
+#[cfg(feature = "serde")]
+pub(crate) fn decode<N: Copy>(log: &[u8]) -> Result<(Vec<Record<'_, N>>, usize)> {
+    let mut records = vec![];
+    let mut pos = HEADER_LEN;
+    while pos + RECORD_HEADER_LEN <= log.len() {
+        let len = read_u32(log, pos + 4) as usize;
+        let end = pos + RECORD_HEADER_LEN + len + RECORD_FOOTER_LEN;
+        if end > log.len()
+            || read_u32(log, end - RECORD_FOOTER_LEN)
+                != crc32fast::hash(&log[pos..end - RECORD_FOOTER_LEN])
+        {
+            break;
+        }
+        let id = read_u64(log, pos + 8);
+        let payload = &log[pos + RECORD_HEADER_LEN..end - RECORD_FOOTER_LEN];
+        let values = || -> Cow<'_, [N]> {
+            payload
+                .chunks_exact(std::mem::size_of::<N>())
+                .map(|c| unsafe { std::ptr::read_unaligned(c.as_ptr() as *const N) })
+                .collect()
+        };
+        let tag = read_u32(log, pos);
+        let record = match Tag::from_u32(tag) {
+            Some(Tag::Store) => Record::Store(id, values()),
+            Some(Tag::Delete) => Record::Delete(values()),
+            Some(Tag::DeleteIdx) => Record::DeleteIdx(id),
+            Some(Tag::UserId) if len == 8 => Record::UserId(id, read_u64(payload, 0)),
+            Some(Tag::Payload) => Record::Payload(id, payload),
+            Some(Tag::Begin) => Record::Begin,
+            Some(Tag::Commit) => Record::Commit,
+            Some(Tag::Rollback) => Record::Rollback,
+            Some(Tag::Update) => {
+                let v = values();
+                let (new_v, old_v) = v.split_at(v.len() / 2);
+                Record::Update(id, Cow::Owned(new_v.to_vec()), Cow::Owned(old_v.to_vec()))
+            }
+            _ => {
+                return Err(Error::Failed(format!(
+                    "invalid record with tag {} in the write-ahead log",
+                    tag
+                )))
+            }
+        };
+        records.push(record);
+        pos = end;
+    }
+    Ok((records, pos))
+}
+
+/// Result of the replay of a change. The record is written before the change is made, so a
+/// change that failed because the data point doesn't exist fails the same way when replayed.
+#[cfg(feature = "serde")]
+pub(crate) fn replayed(r: Result<()>) -> Result<()> {
+    match r {
+        Err(Error::NotFound) => Ok(()),
+        r => r,
+    }
+}
+
+/// The log file of an index and the path of its snapshot.
+#[derive(Debug)]
+struct LogFile {
+    file: File,
+    path: PathBuf,
+}
+
+/// Appends the changes of an index to its log, if the log is enabled. Clones of an index don't
+/// write to the log.
+#[derive(Debug, Default)]
+pub(crate) struct WalWriter(Option<LogFile>);
+
+impl Clone for WalWriter {
+    fn clone(&self) -> Self {
+        WalWriter(None)
+    }
+}
+
+impl WalWriter {
+    /// Start an empty log for the snapshot at `path` with checksum `snapshot`. The log is
+    /// written to a temporary file first, so a crash leaves either the old or the new log.
+    #[cfg(feature = "serde")]
+    pub(crate) fn create(path: &Path, snapshot: u32, value_size: usize) -> Result<Self> {
+        let log = log_path(path);
+        let mut tmp = log.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_ne_bytes());
+        header.extend_from_slice(&snapshot.to_ne_bytes());
+        header.extend_from_slice(&(value_size as u32).to_ne_bytes());
+        let mut f = File::create(&tmp)?;
+        f.write_all(&header)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, &log)?;
+        WalWriter::open(path, HEADER_LEN)
+    }
+
+    /// Continue the log of the snapshot at `path` after its first `len` bytes. A damaged tail
+    /// after `len` is cut off.
+    #[cfg(feature = "serde")]
+    pub(crate) fn open(path: &Path, len: usize) -> Result<Self> {
+        let file = OpenOptions::new().append(true).open(log_path(path))?;
+        file.set_len(len as u64)?;
+        Ok(WalWriter(Some(LogFile {
+            file,
+            path: path.to_path_buf(),
+        })))
+    }
+
+    /// Path of the snapshot, `None` if the log isn't enabled.
+    #[cfg(feature = "serde")]
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.0.as_ref().map(|log| log.path.as_path())
+    }
+
+    /// Whether the changes are logged.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Append `record` to the log and flush it to the disk, if the log is enabled. Append
+    /// before the change is made, so a crash in between replays the change.
+    pub(crate) fn append<N: Copy>(&mut self, record: Record<'_, N>) -> Result<()> {
+        if let Some(log) = self.0.as_mut() {
+            log.file.write_all(&record.encode())?;
+            log.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "serde")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_torn_tail() {
+        let mut log = vec![0; HEADER_LEN];
+        let records: Vec<Record<f32>> = vec![
+            Record::Store(0, Cow::Owned(vec![1., 2.])),
+            Record::UserId(0, 1 << 40),
+            Record::Payload(0, b"doc"),
+            Record::Begin,
+            Record::DeleteIdx(0),
+            Record::Update(0, Cow::Owned(vec![3., 4.]), Cow::Owned(vec![1., 2.])),
+        ];
+        for r in &records {
+            log.extend(r.encode());
+        }
+        let (decoded, len) = decode::<f32>(&log).unwrap();
+        assert_eq!(decoded, records);
+        assert_eq!(len, log.len());
+
+        // a crash during an append leaves part of a record.
+        let intact = log.len();
+        log.extend(&Record::Delete(Cow::Owned(vec![1f32, 2.])).encode()[..10]);
+        let (decoded, len) = decode::<f32>(&log).unwrap();
+        assert_eq!(decoded.len(), records.len());
+        assert_eq!(len, intact);
+
+        // a damaged record ends the log as well.
+        log.truncate(intact);
+        log[intact - 1] ^= 1;
+        let (decoded, _) = decode::<f32>(&log).unwrap();
+        assert_eq!(decoded.len(), records.len() - 1);
+    }
+}
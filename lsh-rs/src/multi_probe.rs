@@ -4,15 +4,17 @@ use crate::{prelude::*, utils::create_rng};
 use fnv::FnvHashSet;
 use itertools::Itertools;
 use ndarray::prelude::*;
-use ndarray::stack;
 use num::{Float, One, Zero};
-use rand::distributions::Uniform;
 use rand::seq::SliceRandom;
 use rand::Rng;
-use statrs::function::factorial::binomial;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
+/// Callback that receives every probed hash and its score.
+pub type ProbeFn<'a, K> = &'a mut dyn FnMut(&[K], Option<f64>) -> Result<()>;
+
 /// Query directed probing
 ///
 /// Implementation of paper:
@@ -23,6 +25,32 @@ use std::collections::BinaryHeap;
 
 pub trait QueryDirectedProbe<N, K> {
     fn query_directed_probe(&self, q: &[N], budget: usize) -> Result<Vec<Vec<K>>>;
+
+    /// Same as `query_directed_probe`, but also returns the score of every probe
+    /// (lower scores are closer to the query). The default implementation doesn't score the probes.
+    fn query_directed_probe_scored(
+        &self,
+        q: &[N],
+        budget: usize,
+    ) -> Result<Vec<(Vec<K>, Option<f64>)>> {
+        let hashes = self.query_directed_probe(q, budget)?;
+        Ok(hashes.into_iter().map(|hash| (hash, None)).collect())
+    }
+
+    /// Same as `query_directed_probe_scored`, but passes every probe to `f` instead of
+    /// collecting them. The buffers in `scratch` are reused, so no hash is allocated per probe.
+    fn query_directed_probe_with(
+        &self,
+        q: &[N],
+        budget: usize,
+        _scratch: &mut QueryScratch<N, K>,
+        f: ProbeFn<'_, K>,
+    ) -> Result<()> {
+        for (hash, score) in self.query_directed_probe_scored(q, budget)? {
+            f(&hash, score)?
+        }
+        Ok(())
+    }
 }
 
 /// Step wise probing
@@ -35,63 +63,102 @@ where
     N: Numeric,
 {
     fn step_wise_probe(&self, q: &[N], budget: usize, hash_len: usize) -> Result<Vec<Vec<i8>>> {
-        let probing_seq = step_wise_probing(hash_len, budget, false);
         let original_hash = self.hash_vec_query(q);
+        Ok(sign_flip_probes(
+            &original_hash,
+            self.sign_convention(),
+            budget,
+            hash_len,
+        ))
+    }
+}
 
-        let a = probing_seq
+impl StepWiseProbe<i8, i8> for QuantizedSignRandomProjections {
+    fn step_wise_probe(&self, q: &[i8], budget: usize, hash_len: usize) -> Result<Vec<Vec<i8>>> {
+        let original_hash = self.hash_vec_query(q);
+        Ok(sign_flip_probes(
+            &original_hash,
+            self.sign_convention(),
+            budget,
+            hash_len,
+        ))
+    }
+}
+
+impl<N> StepWiseProbe<N, u64> for PackedSignRandomProjections<N>
+where
+    N: Numeric,
+{
+    fn step_wise_probe(&self, q: &[N], budget: usize, hash_len: usize) -> Result<Vec<Vec<u64>>> {
+        let original_hash = self.hash_vec_query(q);
+        let probing_seq = step_wise_probing(hash_len, budget, false);
+        Ok(probing_seq
             .iter()
             .map(|pertub| {
-                original_hash
-                    .iter()
-                    .zip(pertub)
-                    .map(
-                        |(&original, &shift)| {
-                            if shift == 1 {
-                                original * -1
-                            } else {
-                                original
-                            }
-                        },
-                    )
-                    .collect_vec()
+                let mut hash = original_hash.clone();
+                for (i, _) in pertub.iter().enumerate().filter(|(_, &shift)| shift == 1) {
+                    hash[i / 64] ^= 1 << (i % 64);
+                }
+                hash
             })
-            .collect_vec();
-        Ok(a)
+            .collect_vec())
     }
 }
 
-fn uniform_without_replacement<T: Copy>(bucket: &mut [T], n: usize) -> Vec<T> {
-    // https://stackoverflow.com/questions/196017/unique-non-repeating-random-numbers-in-o1#196065
-    let mut max_idx = bucket.len() - 1;
-    let mut rng = create_rng(0);
+/// Probes of a sign random projections hash, with the bits of the step wise probing sequence
+/// flipped.
+fn sign_flip_probes(
+    original_hash: &[i8],
+    sign: SignConvention,
+    budget: usize,
+    hash_len: usize,
+) -> Vec<Vec<i8>> {
+    let probing_seq = step_wise_probing(hash_len, budget, false);
+    probing_seq
+        .iter()
+        .map(|pertub| {
+            original_hash
+                .iter()
+                .zip(pertub)
+                .map(|(&original, &shift)| {
+                    if shift == 1 {
+                        sign.flip(original)
+                    } else {
+                        original
+                    }
+                })
+                .collect_vec()
+        })
+        .collect_vec()
+}
 
+/// Sample `n` elements of `bucket` without replacement. `n` is capped at the length of `bucket`.
+fn uniform_without_replacement<T: Copy, R: Rng>(bucket: &mut [T], n: usize, rng: &mut R) -> Vec<T> {
+    // partial Fisher-Yates shuffle. The first i elements are the samples.
+    let n = std::cmp::min(n, bucket.len());
     let mut samples = Vec::with_capacity(n);
 
-    for _ in 0..n {
-        let idx = rng.sample(Uniform::new(0, max_idx));
-        debug_assert!(idx < bucket.len());
-        unsafe {
-            samples.push(*bucket.get_unchecked(idx));
-        };
-        bucket.swap(idx, max_idx);
-        max_idx -= 1;
+    for i in 0..n {
+        let idx = rng.gen_range(i, bucket.len());
+        bucket.swap(i, idx);
+        samples.push(bucket[i]);
     }
     samples
 }
 
-fn create_hash_permutation(hash_len: usize, n: usize) -> Vec<i8> {
+/// Randomly perturb `n` indexes of a hash. Sampling is seeded per call, so that replicas
+/// answering the same query generate the same probes. If `seed` is 0, the RNG is seeded randomly.
+fn create_hash_permutation(hash_len: usize, n: usize, seed: u64) -> Vec<i8> {
     let mut permut = vec![0; hash_len];
     let shift_options = [-1i8, 1];
+    let mut rng = create_rng(seed);
 
     let mut idx: Vec<usize> = (0..hash_len).collect();
-    let candidate_idx = uniform_without_replacement(&mut idx, n);
+    let candidate_idx = uniform_without_replacement(&mut idx, n, &mut rng);
 
-    let mut rng = create_rng(0);
     for i in candidate_idx {
-        debug_assert!(i < permut.len());
         let v = *shift_options.choose(&mut rng).unwrap();
-        // bounds check not needed as i cannot be larger than permut
-        unsafe { *permut.get_unchecked_mut(i) += v }
+        permut[i] += v
     }
     permut
 }
@@ -139,6 +206,78 @@ fn step_wise_perturb(
     a
 }
 
+/// Adaptive multi-probe budget for `k` projections and `l` hash tables.
+///
+/// Lv et al. (2007) report that most of the gain of multi-probing comes from the `2k`
+/// perturbation vectors that shift a single projection by one bucket, and that multi-probing
+/// reaches the recall of basic LSH with 14-18 times fewer hash tables. So we probe all `2k`
+/// single shifts for `L <= 10`, and proportionally less with more hash tables, but at least `k`.
+pub fn adaptive_probe_budget(k: usize, l: usize) -> usize {
+    let single_shifts = 2 * k;
+    (single_shifts * 10 / l.max(10)).max(k).max(1)
+}
+
+/// Number of distinct probes for a hash of length `k`. `None` if it doesn't fit in a `usize`.
+fn max_probes(k: usize, query_directed: bool) -> Option<usize> {
+    // query directed probing shifts every projection by -1, 0 or +1. step wise probing flips bits.
+    let base: usize = if query_directed { 3 } else { 2 };
+    base.checked_pow(k as u32).map(|n| n - 1)
+}
+
+/// Whether `hasher` probes query directed. `None` if it doesn't support multi-probing.
+fn is_query_directed<N, K, H: VecHash<N, K>>(hasher: &H) -> Option<bool> {
+    if hasher.as_query_directed_probe().is_some() {
+        Some(true)
+    } else if hasher.as_step_wise_probe().is_some() {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Multi-probe budget per hash table that is used for the configured `budget`. A budget of 0
+/// resolves to the [adaptive_probe_budget](fn.adaptive_probe_budget.html), clamped to the
+/// distinct probes of the shortest hash `k_min`.
+pub(crate) fn resolve_probe_budget<N, K, H: VecHash<N, K>>(
+    hashers: &[H],
+    k: usize,
+    k_min: usize,
+    l: usize,
+    budget: usize,
+) -> usize {
+    if budget > 0 {
+        return budget;
+    }
+    let adaptive = adaptive_probe_budget(k, l);
+    match hashers
+        .first()
+        .and_then(is_query_directed)
+        .and_then(|query_directed| max_probes(k_min, query_directed))
+    {
+        Some(max) => adaptive.min(max),
+        None => adaptive,
+    }
+}
+
+/// Check that `hasher` supports multi-probing and that the resolved `budget` doesn't exceed
+/// the distinct probes of a hash of length `k`.
+pub(crate) fn validate_multi_probe<N, K, H: VecHash<N, K>>(
+    hasher: &H,
+    k: usize,
+    budget: usize,
+) -> Result<()> {
+    let query_directed = is_query_directed(hasher).ok_or_else(|| {
+        Error::Failed("multi-probing is not supported by this hasher".to_string())
+    })?;
+    match max_probes(k, query_directed) {
+        Some(max) if budget > max => Err(Error::Failed(format!(
+            "multi-probe budget {} exceeds the {} distinct probes of a hash with length {}",
+            budget, max, k
+        ))),
+        _ => Ok(()),
+    }
+}
+
 /// Generates new hashes by step wise shifting one indexes.
 /// First all one index shifts are returned (these are closer to the original hash)
 /// then the two index shifts, three index shifts etc.
@@ -151,152 +290,140 @@ fn step_wise_probing(hash_len: usize, mut budget: usize, two_shifts: bool) -> Ve
     // number of combinations (indexes we allow to perturb)
     let mut k = 1;
     while budget > 0 && k <= n {
-        // binomial coefficient
-        // times two as we have -1 and +1.
-        let multiply;
-        if two_shifts {
-            multiply = 2
-        } else {
-            multiply = 1
-        }
-        let n_combinations = binomial(n, k) as usize * multiply;
-
+        let before = hash_perturbs.len();
         step_wise_perturb(n as usize, k as usize, two_shifts)
             .take(budget as usize)
             .for_each(|v| {
                 let mut new_perturb = vec![0; hash_len];
                 v.iter().for_each(|(idx, shift)| {
-                    debug_assert!(*idx < new_perturb.len());
-                    let v = unsafe { new_perturb.get_unchecked_mut(*idx) };
-                    *v += *shift;
+                    new_perturb[*idx] += *shift;
                 });
                 hash_perturbs.push(new_perturb)
             });
         k += 1;
-        budget -= n_combinations;
+        // with two shifts every combination of k indexes has 2^k sign variants, so subtract
+        // what was actually generated rather than the binomial coefficient.
+        budget -= hash_perturbs.len() - before;
     }
     hash_perturbs
 }
 
-#[derive(PartialEq, Clone)]
-struct PerturbState<'a, N, K>
-where
-    N: Numeric + Float + Copy,
-{
-    // original sorted zj
-    z: &'a [usize],
+/// Reusable buffers for query directed probing. Reusing a scratch over the hash tables of a
+/// query (or over queries) avoids allocating the probed hashes and perturbation states per probe.
+pub struct QueryScratch<N, K> {
     // original xi(delta)
-    distances: &'a [N],
+    distances: Vec<N>,
+    // argsort of the distances
+    z: Vec<usize>,
+    // buffer for the probed hash
+    hash: Vec<K>,
+    heap: BinaryHeap<PerturbState<N>>,
+    // recycled selections of popped states
+    pool: Vec<Vec<usize>>,
+}
+
+impl<N, K> QueryScratch<N, K> {
+    pub fn new() -> Self {
+        QueryScratch {
+            distances: vec![],
+            z: vec![],
+            hash: vec![],
+            heap: BinaryHeap::new(),
+            pool: vec![],
+        }
+    }
+}
+
+impl<N, K> Default for QueryScratch<N, K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(PartialEq, Clone)]
+struct PerturbState<N> {
     // selection of zjs
     // We start with the first one, as this is the lowest score.
     selection: Vec<usize>,
-    switchpoint: usize,
-    original_hash: Option<Vec<K>>,
+    score: N,
 }
 
-impl<'a, N, K> PerturbState<'a, N, K>
+impl<N> PerturbState<N>
 where
     N: Numeric + Float,
-    K: Integer,
 {
-    fn new(z: &'a [usize], distances: &'a [N], switchpoint: usize, hash: Vec<K>) -> Self {
-        PerturbState {
-            z,
-            distances,
-            selection: vec![0],
-            switchpoint,
-            original_hash: Some(hash),
-        }
-    }
-
-    fn score(&self) -> N {
+    fn new(selection: Vec<usize>, z: &[usize], distances: &[N]) -> Self {
         let mut score = Zero::zero();
-        for &index in self.selection.iter() {
-            debug_assert!(index < self.z.len());
-            let zj = unsafe { *self.z.get_unchecked(index) };
-            debug_assert!(zj < self.distances.len());
-            unsafe { score += self.distances.get_unchecked(zj).clone() };
+        for &index in selection.iter() {
+            score += distances[z[index]];
         }
-        score
+        PerturbState { selection, score }
     }
 
-    // map zj value to (i, delta) as in paper
-    fn i_delta(&self) -> Vec<(usize, K)> {
-        let mut out = Vec::with_capacity(self.z.len());
-        for &idx in self.selection.iter() {
-            debug_assert!(idx < self.z.len());
-            let zj = unsafe { *self.z.get_unchecked(idx) };
-            let delta;
-            let index;
-            if zj >= self.switchpoint {
-                delta = One::one();
-                index = zj - self.switchpoint;
-            } else {
-                delta = K::from_i8(-1).unwrap();
-                index = zj;
-            }
-            out.push((index, delta))
+    fn next(
+        &self,
+        expand: bool,
+        z: &[usize],
+        distances: &[N],
+        pool: &mut Vec<Vec<usize>>,
+    ) -> Option<Self> {
+        let max = self.selection[self.selection.len() - 1];
+        if max == z.len() - 1 {
+            return None;
         }
-        out
-    }
-
-    fn check_bounds(&mut self, max: usize) -> Result<()> {
-        if max == self.z.len() - 1 {
-            Err(Error::Failed("Out of bounds".to_string()))
+        let mut selection = pool.pop().unwrap_or_default();
+        selection.clear();
+        selection.extend_from_slice(&self.selection);
+        if expand {
+            selection.push(max + 1)
         } else {
-            self.selection.push(max + 1);
-            Ok(())
+            *selection.last_mut().unwrap() = max + 1
         }
+        Some(PerturbState::new(selection, z, distances))
     }
 
-    fn shift(&mut self) -> Result<()> {
-        let max = self.selection.pop().unwrap();
-        self.check_bounds(max)
+    fn shift(&self, z: &[usize], distances: &[N], pool: &mut Vec<Vec<usize>>) -> Option<Self> {
+        self.next(false, z, distances, pool)
     }
 
-    fn expand(&mut self) -> Result<()> {
-        let max = self.selection[self.selection.len() - 1];
-        self.check_bounds(max)
+    fn expand(&self, z: &[usize], distances: &[N], pool: &mut Vec<Vec<usize>>) -> Option<Self> {
+        self.next(true, z, distances, pool)
     }
 
-    fn gen_hash(&mut self) -> Vec<K> {
-        let mut hash = self.original_hash.take().expect("hash already taken");
-        for (i, delta) in self.i_delta() {
-            debug_assert!(i < hash.len());
-            let ptr = unsafe { hash.get_unchecked_mut(i) };
-            *ptr += delta
+    // map zj value to (i, delta) as in paper and apply them to the hash.
+    fn perturb<K: Integer>(&self, z: &[usize], switchpoint: usize, hash: &mut [K]) {
+        for &idx in self.selection.iter() {
+            let zj = z[idx];
+            let (i, delta) = if zj >= switchpoint {
+                (zj - switchpoint, One::one())
+            } else {
+                (zj, K::from_i8(-1).unwrap())
+            };
+            hash[i] += delta
         }
-        hash
     }
 }
 
 // implement ordering so that we can create a min heap
-impl<N, K> Ord for PerturbState<'_, N, K>
+impl<N> Ord for PerturbState<N>
 where
     N: Numeric + Float,
-    K: Integer,
 {
-    fn cmp(&self, other: &PerturbState<N, K>) -> Ordering {
+    fn cmp(&self, other: &PerturbState<N>) -> Ordering {
         self.partial_cmp(other).unwrap()
     }
 }
 
-impl<N, K> PartialOrd for PerturbState<'_, N, K>
+impl<N> PartialOrd for PerturbState<N>
 where
     N: Numeric + Float,
-    K: Integer,
 {
-    fn partial_cmp(&self, other: &PerturbState<N, K>) -> Option<Ordering> {
-        other.score().partial_cmp(&self.score())
+    fn partial_cmp(&self, other: &PerturbState<N>) -> Option<Ordering> {
+        other.score.partial_cmp(&self.score)
     }
 }
 
-impl<N, K> Eq for PerturbState<'_, N, K>
-where
-    N: Numeric + Float,
-    K: Integer,
-{
-}
+impl<N> Eq for PerturbState<N> where N: Numeric + Float {}
 
 macro_rules! impl_query_directed_probe {
     ($vechash:ident) => {
@@ -314,8 +441,8 @@ macro_rules! impl_query_directed_probe {
                     None => self.hash_vec(q).to_vec(),
                     Some(h) => h.iter().map(|&k| N::from(k).unwrap()).collect_vec(),
                 };
-                let f = self.a.dot(&aview1(q)) + &self.b;
-                let xi_min1 = f - &aview1(&hash) * self.r;
+                let r = self.r;
+                let xi_min1 = self.project(q) - &aview1(&hash).mapv(|h| h * r);
                 let xi_plus1: Array1<N> = xi_min1.map(|x| self.r - *x);
                 (xi_min1, xi_plus1)
             }
@@ -327,6 +454,35 @@ macro_rules! impl_query_directed_probe {
             K: Integer,
         {
             fn query_directed_probe(&self, q: &[N], budget: usize) -> Result<Vec<Vec<K>>> {
+                let hashes = self.query_directed_probe_scored(q, budget)?;
+                Ok(hashes.into_iter().map(|(hash, _)| hash).collect())
+            }
+
+            fn query_directed_probe_scored(
+                &self,
+                q: &[N],
+                budget: usize,
+            ) -> Result<Vec<(Vec<K>, Option<f64>)>> {
+                let mut hashes = Vec::with_capacity(budget + 1);
+                self.query_directed_probe_with(
+                    q,
+                    budget,
+                    &mut QueryScratch::new(),
+                    &mut |hash, score| {
+                        hashes.push((hash.to_vec(), score));
+                        Ok(())
+                    },
+                )?;
+                Ok(hashes)
+            }
+
+            fn query_directed_probe_with(
+                &self,
+                q: &[N],
+                budget: usize,
+                scratch: &mut QueryScratch<N, K>,
+                f: ProbeFn<'_, K>,
+            ) -> Result<()> {
                 // https://www.cs.princeton.edu/cass/papers/mplsh_vldb07.pdf
                 // https://www.youtube.com/watch?v=c5DHtx5VxX8
                 let hash = self.hash_vec_query(q);
@@ -335,23 +491,31 @@ macro_rules! impl_query_directed_probe {
                 // < this point = -1
                 let switchpoint = xi_min.len();
 
-                let distances: Vec<N> = stack!(Axis(0), xi_min, xi_plus).to_vec();
+                let QueryScratch {
+                    distances,
+                    z,
+                    hash: probe,
+                    heap,
+                    pool,
+                } = scratch;
+                distances.clear();
+                distances.extend(xi_min.iter().chain(xi_plus.iter()));
 
                 // indexes of the least scores to the highest
                 // all below is an argsort
-                let z = distances.clone();
-                let mut z = z.iter().enumerate().collect::<Vec<_>>();
-                z.sort_unstable_by(|(_idx_a, a), (_idx_b, b)| a.partial_cmp(b).unwrap());
-                let z = z.iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
+                z.clear();
+                z.extend(0..distances.len());
+                z.sort_unstable_by(|&a, &b| distances[a].partial_cmp(&distances[b]).unwrap());
 
-                let mut hashes = Vec::with_capacity(budget + 1);
-                hashes.push(hash.clone());
+                f(&hash, Some(0.))?;
                 // Algorithm 1 from paper
-                let mut heap = BinaryHeap::new();
-                let a0 = PerturbState::new(&z, &distances, switchpoint, hash);
-                heap.push(a0);
+                heap.clear();
+                let mut selection = pool.pop().unwrap_or_default();
+                selection.clear();
+                selection.push(0);
+                heap.push(PerturbState::new(selection, z, distances));
                 for _ in 0..budget {
-                    let mut ai = match heap.pop() {
+                    let ai = match heap.pop() {
                         Some(ai) => ai,
                         None => {
                             return Err(Error::Failed(
@@ -359,17 +523,19 @@ macro_rules! impl_query_directed_probe {
                             ))
                         }
                     };
-                    let mut a_s = ai.clone();
-                    let mut a_e = ai.clone();
-                    if a_s.shift().is_ok() {
+                    if let Some(a_s) = ai.shift(z, distances, pool) {
                         heap.push(a_s);
                     }
-                    if a_e.expand().is_ok() {
+                    if let Some(a_e) = ai.expand(z, distances, pool) {
                         heap.push(a_e);
                     }
-                    hashes.push(ai.gen_hash())
+                    probe.clear();
+                    probe.extend_from_slice(&hash);
+                    ai.perturb(z, switchpoint, probe);
+                    f(probe, ai.score.to_f64())?;
+                    pool.push(ai.selection);
                 }
-                Ok(hashes)
+                Ok(())
             }
         }
     };
@@ -377,6 +543,31 @@ macro_rules! impl_query_directed_probe {
 impl_query_directed_probe!(L2);
 impl_query_directed_probe!(MIPS);
 
+/// A single probe of a probing sequence.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Probe<K> {
+    /// Perturbation that is added to the original hash.
+    pub perturbation: Vec<K>,
+    /// Score of the perturbation, lower is closer to the query.
+    /// Only available for query directed probing.
+    pub score: Option<f64>,
+    /// The hash that is probed.
+    pub hash: Vec<K>,
+}
+
+/// Probing sequence of one hash table. Used to inspect multi-probe behavior.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProbingSequence<K> {
+    /// Index of the hash table.
+    pub hash_table: usize,
+    /// Hash of the query without perturbation.
+    pub original_hash: Vec<K>,
+    /// Probes in the order they are executed.
+    pub probes: Vec<Probe<K>>,
+}
+
 impl<N, K, H, T> LSH<H, N, T, K>
 where
     N: Numeric,
@@ -384,48 +575,144 @@ where
     H: VecHash<N, K>,
     T: HashTables<N, K>,
 {
-    pub fn multi_probe_bucket_union(&self, v: &[N]) -> Result<FnvHashSet<u32>> {
+    /// Export the multi-probe sequence (perturbations, scores and resulting hashes) that
+    /// is generated for a query, per hash table. Useful to tune the multi-probe budget.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    pub fn probing_sequence(&self, v: &[N]) -> Result<Vec<ProbingSequence<K>>> {
+        self.validate_vec(v)?;
+        self.hashers
+            .iter()
+            .enumerate()
+            .map(|(i, hasher)| {
+                let original_hash = hasher.hash_vec_query(v);
+                let hashes = if let Some(h) = hasher.as_query_directed_probe() {
+                    h.query_directed_probe_scored(v, self.probe_budget())?
+                } else if let Some(h) = hasher.as_step_wise_probe() {
+                    h.step_wise_probe(v, self.probe_budget(), self.table_projections(i))?
+                        .into_iter()
+                        .map(|hash| (hash, None))
+                        .collect()
+                } else {
+                    return Err(Error::NotImplemented);
+                };
+                let probes = hashes
+                    .into_iter()
+                    .map(|(hash, score)| Probe {
+                        perturbation: hash
+                            .iter()
+                            .zip(&original_hash)
+                            .map(|(&h, &o)| h - o)
+                            .collect(),
+                        score,
+                        hash,
+                    })
+                    .collect();
+                Ok(ProbingSequence {
+                    hash_table: i,
+                    original_hash,
+                    probes,
+                })
+            })
+            .collect()
+    }
+}
+
+impl<N, K, H, T> LSH<H, N, T, K>
+where
+    N: Numeric,
+    K: Integer,
+    H: VecHash<N, K>,
+    T: HashTables<N, K>,
+{
+    pub fn multi_probe_bucket_union(&self, v: &[N]) -> Result<FnvHashSet<T::Id>> {
         self.validate_vec(v)?;
         let mut bucket_union = FnvHashSet::default();
+        let mut start = self.observe_start();
+        self.for_each_probe_hashes(v, |i, hashes| {
+            let hashed = self.observe_start();
+            // query the probes of a hash table at once.
+            let len = self.process_buckets_union_result(hashes, i, &mut bucket_union)?;
+            self.observe_table(i, start, hashed, hashes.len(), len);
+            start = self.observe_start();
+            Ok(())
+        })?;
+        Ok(bucket_union)
+    }
 
+    /// Call `f` with the index of every hash table and the hashes of the probes of `v` in it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "probe",
+            level = "debug",
+            skip_all,
+            fields(budget = self.probe_budget())
+        )
+    )]
+    pub(crate) fn for_each_probe_hashes<F>(&self, v: &[N], mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, &[Vec<K>]) -> Result<()>,
+    {
         // Check if hasher has implemented this trait. If so follow this more specialized path.
         // Only L2 should have implemented it. This is the trick to choose a different function
         // path for the L2 struct.
         let h0 = &self.hashers[0];
         if h0.as_query_directed_probe().is_some() {
+            let mut scratch = QueryScratch::new();
+            // the buffers of the probes are reused over the hash tables, only the first hash
+            // table allocates them.
+            let mut hashes: Vec<Vec<K>> = vec![];
             for (i, hasher) in self.hashers.iter().enumerate() {
                 if let Some(h) = hasher.as_query_directed_probe() {
-                    let hashes = h.query_directed_probe(v, self._multi_probe_budget)?;
-                    for hash in hashes {
-                        self.process_bucket_union_result(&hash, i, &mut bucket_union)?
-                    }
+                    let mut n = 0;
+                    h.query_directed_probe_with(
+                        v,
+                        self.probe_budget(),
+                        &mut scratch,
+                        &mut |hash, _| {
+                            match hashes.get_mut(n) {
+                                Some(buf) => {
+                                    buf.clear();
+                                    buf.extend_from_slice(hash);
+                                }
+                                None => hashes.push(hash.to_vec()),
+                            }
+                            n += 1;
+                            Ok(())
+                        },
+                    )?;
+                    f(i, &hashes[..n])?;
                 }
             }
         } else if h0.as_step_wise_probe().is_some() {
             for (i, hasher) in self.hashers.iter().enumerate() {
                 if let Some(h) = hasher.as_step_wise_probe() {
                     let hashes =
-                        h.step_wise_probe(v, self._multi_probe_budget, self.n_projections)?;
-                    for hash in hashes {
-                        self.process_bucket_union_result(&hash, i, &mut bucket_union)?
-                    }
+                        h.step_wise_probe(v, self.probe_budget(), self.table_projections(i))?;
+                    f(i, &hashes)?;
                 }
             }
         } else {
             unimplemented!()
         }
-        Ok(bucket_union)
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
     #[test]
     fn test_permutation() {
-        let permut = create_hash_permutation(5, 3);
+        let permut = create_hash_permutation(5, 3, 1);
         println!("{:?}", permut);
+        assert_eq!(permut.iter().filter(|&&x| x != 0).count(), 3);
+        assert_eq!(permut, create_hash_permutation(5, 3, 1));
     }
 
     #[test]
@@ -442,6 +729,56 @@ mod test {
         let a = step_wise_probing(4, 20, true);
         assert_eq!(vec![1, 0, 0, 0], a[0]);
         assert_eq!(vec![0, 1, -1, 0], a[a.len() - 1]);
+
+        // budget smaller than the number of single index shifts.
+        assert_eq!(step_wise_probing(8, 3, false).len(), 3);
+    }
+
+    #[test]
+    fn test_srp_sign_convention() {
+        let q = &[1., -2., 3.];
+        for &(sign, bits) in &[
+            (SignConvention::Binary, [0, 1]),
+            (SignConvention::Bipolar, [-1, 1]),
+        ] {
+            let srp = SignRandomProjections::<f32>::new(5, 3, 1).with_sign_convention(sign);
+            let original = srp.hash_vec_query(q);
+            let probes = srp.step_wise_probe(q, 5, 5).unwrap();
+            assert_eq!(probes.len(), 5);
+            for probe in probes {
+                // every probe is a valid hash that differs in a single bit.
+                assert!(probe.iter().all(|b| bits.contains(b)));
+                assert_eq!(
+                    probe.iter().zip(&original).filter(|(a, b)| a != b).count(),
+                    1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_srp_packed() {
+        let q = &[1., -2., 3.];
+        let pack = |bits: &[i8]| {
+            let mut words = vec![0u64; bits.len().div_ceil(64)];
+            for (i, &b) in bits.iter().enumerate() {
+                words[i / 64] |= (b as u64) << (i % 64);
+            }
+            words
+        };
+        // more than 64 projections span two words.
+        let srp = SignRandomProjections::<f32>::new(70, 3, 1);
+        let packed = PackedSignRandomProjections::from(srp.clone());
+        let hash = srp.hash_vec_query(q);
+        assert_eq!(packed.hash_vec_query(q), pack(&hash));
+
+        let probes: Vec<Vec<u64>> = srp
+            .step_wise_probe(q, 80, 70)
+            .unwrap()
+            .into_iter()
+            .map(|probe| pack(&probe))
+            .collect();
+        assert_eq!(packed.step_wise_probe(q, 80, 70).unwrap(), probes);
     }
 
     #[test]
@@ -458,14 +795,20 @@ mod test {
         // argsort
         let z = vec![1, 6, 0, 3, 2, 5, 7, 4];
         let switchpoint = 4;
-        let a0 = PerturbState::new(&z, &distances, switchpoint, vec![0, 0, 0, 0]);
+        let mut pool = vec![];
+        let gen_hash = |state: &PerturbState<f64>| {
+            let mut hash = vec![0, 0, 0, 0];
+            state.perturb(&z, switchpoint, &mut hash);
+            hash
+        };
+        let a0 = PerturbState::new(vec![0], &z, &distances);
         // initial selection is the first zj [0]
         // This leads to:
         //   distance/score:    0.1
         //   index:             1
         //   delta:             -1
-        assert_eq!(a0.clone().gen_hash(), [0, -1, 0, 0]);
-        assert_eq!(a0.score(), 0.1);
+        assert_eq!(gen_hash(&a0), [0, -1, 0, 0]);
+        assert_eq!(a0.score, 0.1);
         assert_eq!(a0.selection, [0]);
 
         // after expansion operation selection is [0, 1]
@@ -474,10 +817,9 @@ mod test {
         //   index:             [1, 2]
         //   delta:             [-1, 1]
 
-        let mut ae = a0.clone();
-        ae.expand().unwrap();
-        assert_eq!(ae.gen_hash(), [0, -1, 1, 0]);
-        assert_eq!(ae.score(), 0.1 + 0.8);
+        let ae = a0.expand(&z, &distances, &mut pool).unwrap();
+        assert_eq!(gen_hash(&ae), [0, -1, 1, 0]);
+        assert_eq!(ae.score, 0.1 + 0.8);
         assert_eq!(ae.selection, [0, 1]);
 
         // after shift operation selection is [1]
@@ -485,11 +827,15 @@ mod test {
         //   distance/ score:   0.8
         //   index:             2
         //   delta:             1
-        let mut a_s = a0.clone();
-        a_s.shift().unwrap();
-        assert_eq!(a_s.gen_hash(), [0, 0, 1, 0]);
-        assert_eq!(a_s.score(), 0.8);
+        let a_s = a0.shift(&z, &distances, &mut pool).unwrap();
+        assert_eq!(gen_hash(&a_s), [0, 0, 1, 0]);
+        assert_eq!(a_s.score, 0.8);
         assert_eq!(a_s.selection, [1]);
+
+        // the last zj can't be shifted or expanded.
+        let last = PerturbState::new(vec![7], &z, &distances);
+        assert!(last.shift(&z, &distances, &mut pool).is_none());
+        assert!(last.expand(&z, &distances, &mut pool).is_none());
     }
 
     #[test]
@@ -499,11 +845,136 @@ mod test {
         println!("{:?}", hashes)
     }
 
+    #[test]
+    fn test_probing_sequence() {
+        let lsh = LshMem::<_, f32, i32>::new(3, 2, 4)
+            .seed(1)
+            .multi_probe(4)
+            .l2(4.)
+            .unwrap();
+        let seq = lsh.probing_sequence(&[1., 2., 3., 1.]).unwrap();
+        assert_eq!(seq.len(), 2);
+        let probes = &seq[0].probes;
+        // the original hash and the budget
+        assert_eq!(probes.len(), 5);
+        assert_eq!(probes[0].hash, seq[0].original_hash);
+        assert_eq!(probes[0].perturbation, [0, 0, 0]);
+        assert!(probes.windows(2).all(|w| w[0].score <= w[1].score));
+        for p in probes {
+            let hash: Vec<_> = seq[0]
+                .original_hash
+                .iter()
+                .zip(&p.perturbation)
+                .map(|(o, d)| o + d)
+                .collect();
+            assert_eq!(hash, p.hash);
+        }
+    }
+
     #[test]
     fn test_query_directed_bounds() {
         // if shift and expand operation have reached the end of the vecs an error should be returned
-        let mut lsh = hi8::LshMem::new(2, 1, 1).multi_probe(1000).l2(4.).unwrap();
+        let mut lsh = hi8::LshMem::new(2, 1, 1).l2(4.).unwrap();
+        lsh.multi_probe(1000);
         lsh.store_vec(&[1.]).unwrap();
         assert!(lsh.query_bucket_ids(&[1.]).is_err())
     }
+
+    #[test]
+    fn test_validate_multi_probe() {
+        // 3^2 - 1 distinct probes
+        assert!(hi8::LshMem::<_, f32>::new(2, 1, 1)
+            .multi_probe(9)
+            .l2(4.)
+            .is_err());
+        assert!(hi8::LshMem::<_, f32>::new(2, 1, 1)
+            .multi_probe(8)
+            .l2(4.)
+            .is_ok());
+        // 2^3 - 1 distinct bit flips
+        assert!(LshMem::<_, f32>::new(3, 1, 1).multi_probe(8).srp().is_err());
+        assert!(LshMem::<_, u8>::new(3, 1, 1)
+            .multi_probe(2)
+            .minhash()
+            .is_err());
+    }
+
+    #[test]
+    fn test_adaptive_budget() {
+        assert_eq!(adaptive_probe_budget(8, 4), 16);
+        assert_eq!(adaptive_probe_budget(8, 20), 8);
+        assert_eq!(adaptive_probe_budget(0, 1), 1);
+
+        let mut lsh = hi8::LshMem::<_, f32>::new(8, 4, 3)
+            .seed(1)
+            .multi_probe(0)
+            .l2(4.)
+            .unwrap();
+        assert_eq!(lsh.probe_budget(), 16);
+        lsh.store_vec(&[1., 2., 3.]).unwrap();
+        let seq = lsh.probing_sequence(&[1., 2., 3.]).unwrap();
+        // the original hash + the budget
+        assert!(seq.iter().all(|s| s.probes.len() == 17));
+
+        // the adaptive budget of 2 is clamped to the single bit flip of a hash of length 1.
+        let mut lsh = LshMem::<_, f32>::new(1, 1, 3)
+            .seed(1)
+            .multi_probe(0)
+            .srp()
+            .unwrap();
+        assert_eq!(lsh.probe_budget(), 1);
+        lsh.store_vec(&[1., 2., 3.]).unwrap();
+        let seq = lsh.probing_sequence(&[1., 2., 3.]).unwrap();
+        assert_eq!(seq[0].probes.len(), 1);
+        assert!(lsh.query_bucket_ids(&[1., 2., 3.]).is_ok());
+    }
+
+    // The indexes of the probing sequences are computed from runtime data. Check that they stay
+    // in bounds for arbitrary inputs.
+    proptest! {
+        #[test]
+        fn prop_uniform_without_replacement(len in 0usize..64, n in 0usize..80, seed in 1u64..) {
+            let mut bucket: Vec<usize> = (0..len).collect();
+            let samples = uniform_without_replacement(&mut bucket, n, &mut create_rng(seed));
+            prop_assert_eq!(samples.len(), n.min(len));
+            let unique: FnvHashSet<_> = samples.iter().collect();
+            prop_assert_eq!(unique.len(), samples.len());
+            prop_assert!(samples.iter().all(|&s| s < len));
+        }
+
+        #[test]
+        fn prop_hash_permutation(hash_len in 1usize..32, n in 0usize..40, seed in 1u64..) {
+            let permut = create_hash_permutation(hash_len, n, seed);
+            prop_assert_eq!(permut.len(), hash_len);
+            prop_assert_eq!(permut.iter().filter(|&&x| x != 0).count(), n.min(hash_len));
+        }
+
+        #[test]
+        fn prop_step_wise_probing(hash_len in 1usize..12, budget in 0usize..200, two_shifts: bool) {
+            let probes = step_wise_probing(hash_len, budget, two_shifts);
+            prop_assert!(probes.len() <= budget);
+            prop_assert!(probes
+                .iter()
+                .all(|p| p.len() == hash_len && p.iter().all(|x| x.abs() <= 1)));
+        }
+
+        #[test]
+        fn prop_query_directed_probe(
+            k in 1usize..10,
+            budget in 0usize..64,
+            seed in 1u64..1000,
+            q in vec(-10f32..10., 4),
+        ) {
+            let l2 = <L2>::new(4, 2., k, seed);
+            let original = l2.hash_vec_query(&q);
+            // budgets that exceed the number of perturbations return an error.
+            if let Ok(hashes) = l2.query_directed_probe(&q, budget) {
+                prop_assert!(hashes.len() <= budget + 1);
+                for hash in hashes {
+                    prop_assert_eq!(hash.len(), k);
+                    prop_assert!(hash.iter().zip(&original).all(|(h, o)| (h - o).abs() <= 1));
+                }
+            }
+        }
+    }
 }
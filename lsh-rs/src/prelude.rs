@@ -1,12 +1,32 @@
 //! Re-export of the public api of lsh-rs.
+#[cfg(feature = "mmap")]
+pub use crate::table::mmap::MmapTable;
 #[cfg(feature = "sqlite")]
-pub use crate::table::{sqlite::SqlTable, sqlite_mem::SqlTableMem};
+pub use crate::table::{
+    sqlite::{BusyPolicy, FragmentationReport, SqlDistance, SqlIdx, SqlTable},
+    sqlite_mem::SqlTableMem,
+};
 pub use crate::{
+    config::{HashFamily, LshConfig},
     error::{Error, Result},
-    hash::{MinHash, SignRandomProjections, VecHash, L2, MIPS},
-    lsh::lsh::LSH,
-    multi_probe::{QueryDirectedProbe, StepWiseProbe},
-    table::{general::HashTables, mem::MemoryTable},
+    hash::{
+        BMinHash, BitSampling, MinHash, PackedSignRandomProjections,
+        QuantizedSignRandomProjections, SetHash, SignConvention, SignRandomProjections, VecHash,
+        VecHashFit, L1, L2, MIPS,
+    },
+    lsh::dedup::QuerySession,
+    lsh::forest::LshForest,
+    lsh::lsh::{BatchAggregate, CollisionWeight, IngestSession, Parts, LSH},
+    lsh::multi_metric::MultiMetricLSH,
+    lsh::observer::{QueryEvent, QueryObserver, TableEvent},
+    multi_probe::{
+        Probe, ProbeFn, ProbingSequence, QueryDirectedProbe, QueryScratch, StepWiseProbe,
+    },
+    table::{
+        general::HashTables,
+        mem::{FlatVecStore, MemoryTable, VecStore},
+        shared::ShardedMemoryTable,
+    },
 };
 
 #[cfg(feature = "sqlite")]
@@ -14,6 +34,12 @@ pub type LshSql<H, N = f32, K = i8> = LSH<H, N, SqlTable<N, K>, K>;
 #[cfg(feature = "sqlite")]
 pub type LshSqlMem<H, N = f32, K = i8> = LSH<H, N, SqlTableMem<N, K>, K>;
 pub type LshMem<H, N = f32, K = i8> = LSH<H, N, MemoryTable<N, K>, K>;
+/// In memory LSH that can be written and queried from multiple threads, see
+/// [ShardedMemoryTable](struct.ShardedMemoryTable.html).
+pub type LshShared<H, N = f32, K = i8> = LSH<H, N, ShardedMemoryTable<N, K>, K>;
+/// LSH backed by a memory-mapped log file, see [MmapTable](struct.MmapTable.html).
+#[cfg(feature = "mmap")]
+pub type LshMmap<H, N = f32, K = i8> = LSH<H, N, MmapTable<N, K>, K>;
 
 macro_rules! concrete_lsh_structs {
     ($mod_name:ident, $K:ty) => {
@@ -24,6 +50,9 @@ macro_rules! concrete_lsh_structs {
             #[cfg(feature = "sqlite")]
             pub type LshSqlMem<H, N = f32> = LSH<H, N, SqlTableMem<N, $K>, $K>;
             pub type LshMem<H, N = f32> = LSH<H, N, MemoryTable<N, $K>, $K>;
+            pub type LshShared<H, N = f32> = LSH<H, N, ShardedMemoryTable<N, $K>, $K>;
+            #[cfg(feature = "mmap")]
+            pub type LshMmap<H, N = f32> = LSH<H, N, MmapTable<N, $K>, $K>;
         }
     };
 }
@@ -31,3 +60,5 @@ concrete_lsh_structs!(hi8, i8);
 concrete_lsh_structs!(hi16, i16);
 concrete_lsh_structs!(hi32, i32);
 concrete_lsh_structs!(hi64, i64);
+// u64 hash words, for the packed hashes of PackedSignRandomProjections.
+concrete_lsh_structs!(hi_packed, u64);
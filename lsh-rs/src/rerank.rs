@@ -0,0 +1,109 @@
+//! Re-ranking of query candidates by their exact distance.
+//!
+//! LSH only returns candidates, e.g. the ids of
+//! [query_bucket_ids](../struct.LSH.html#method.query_bucket_ids). When the data points are
+//! kept outside of the index (see [only_index](../struct.LSH.html#method.only_index)), the
+//! candidates can be ranked with [rerank_candidates](fn.rerank_candidates.html).
+//!
+//! # Examples
+//!
+//! ```
+//! use lsh_rs::prelude::*;
+//! use lsh_rs::rerank::{rerank_candidates, Metric};
+//!
+//! let vs = vec![vec![2., 3., 4.], vec![-1., 0., 2.], vec![2., 3., 4.1]];
+//! let mut lsh = LshMem::<_, f32>::new(5, 10, 3).seed(1).only_index().srp().unwrap();
+//! lsh.store_vecs(&vs).unwrap();
+//!
+//! let q = [2., 3., 4.];
+//! let candidates: Vec<(u32, &[f32])> = lsh
+//!     .query_bucket_ids(&q)
+//!     .unwrap()
+//!     .into_iter()
+//!     .map(|idx| (idx, &vs[idx as usize][..]))
+//!     .collect();
+//! let neighbors = rerank_candidates(&q, &candidates, Metric::L2, 1).unwrap();
+//! assert_eq!(neighbors[0].0, 0);
+//! ```
+use crate::data::Numeric;
+pub use crate::dist::Metric;
+use crate::error::Result;
+use rayon::prelude::*;
+
+/// Rank the candidates of `query` by their exact `metric` distance and keep the `k` closest.
+/// The distances are computed in parallel. Returns the ids and distances sorted by ascending
+/// distance, ties by ascending id. Fewer than `k` are returned if there are fewer candidates.
+///
+/// # Arguments
+/// * `query` - Query vector.
+/// * `candidates` - Ids with their data points, e.g. the candidates of a query.
+/// * `metric` - Distance to rank by.
+/// * `k` - Number of candidates to keep.
+///
+/// # Errors
+///
+/// `Error::DimensionMismatch` if a candidate doesn't have the dimension of the query.
+pub fn rerank_candidates<N: Numeric + Sync>(
+    query: &[N],
+    candidates: &[(u32, &[N])],
+    metric: Metric,
+    k: usize,
+) -> Result<Vec<(u32, f64)>> {
+    let mut ranked: Vec<(u32, f64)> = candidates
+        .par_iter()
+        .map(|&(idx, v)| Ok((idx, metric.distance(query, v)?)))
+        .collect::<Result<_>>()?;
+    ranked.par_sort_unstable_by(|a, b| {
+        a.1.partial_cmp(&b.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.0.cmp(&b.0))
+    });
+    ranked.truncate(k);
+    Ok(ranked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_rerank_candidates() {
+        let vs: Vec<Vec<f32>> = vec![vec![1., 0.], vec![0., 2.], vec![3., 0.], vec![1., 1.]];
+        let candidates: Vec<(u32, &[f32])> = vs
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i as u32 * 10, &v[..]))
+            .collect();
+        let q = [1., 0.];
+
+        let ids = |metric| -> Vec<u32> {
+            rerank_candidates(&q, &candidates, metric, 3)
+                .unwrap()
+                .into_iter()
+                .map(|(idx, _)| idx)
+                .collect()
+        };
+        assert_eq!(ids(Metric::L2), vec![0, 30, 20]);
+        // 0 and 20 point in the same direction, the tie is broken by id.
+        assert_eq!(ids(Metric::Cosine), vec![0, 20, 30]);
+        assert_eq!(ids(Metric::InnerProduct), vec![20, 0, 30]);
+        assert_eq!(ids(Metric::Jaccard), vec![0, 20, 30]);
+
+        let ranked = rerank_candidates(&q, &candidates, Metric::L2, 10).unwrap();
+        assert_eq!(ranked.len(), 4);
+        assert_eq!(ranked[0], (0, 0.));
+        assert!(rerank_candidates(&q, &[], Metric::L2, 1)
+            .unwrap()
+            .is_empty());
+
+        let short = [1.];
+        assert!(matches!(
+            rerank_candidates(&q, &[(0, &short[..])], Metric::L2, 1),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! gRPC serving layer. Only available with the `serve` feature.
+//!
+//! [LshService](struct.LshService.html) exposes an [LSH](../struct.LSH.html) instance with
+//! `f32` data points through the service defined in `proto/lsh.proto`. See the `serve_server`
+//! and `serve_client` examples.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use lsh_rs::prelude::LshMem;
+//! use lsh_rs::serve::LshService;
+//!
+//! let lsh = LshMem::<_, f32, i32>::new(9, 10, 3).l2(4.)?;
+//! tonic::transport::Server::builder()
+//!     .add_service(LshService::new(lsh).into_server())
+//!     .serve("[::1]:50051".parse()?)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::data::Integer;
+use crate::prelude::{Error, HashTables, VecHash, LSH};
+use std::sync::{Arc, RwLock};
+use tonic::{Request, Response, Status};
+
+/// Generated protobuf messages, client and server.
+pub mod proto {
+    tonic::include_proto!("lsh");
+}
+
+use proto::lsh_server::{Lsh, LshServer};
+use proto::{
+    Neighbor, QueryRequest, QueryResponse, StoreRequest, StoreResponse, TopKRequest, TopKResponse,
+};
+
+fn to_status(e: Error) -> Status {
+    match e {
+        Error::DimensionMismatch { .. } | Error::Batch { .. } => {
+            Status::invalid_argument(e.to_string())
+        }
+        Error::NotFound => Status::not_found(e.to_string()),
+        Error::ReadOnly => Status::failed_precondition(e.to_string()),
+        Error::NotImplemented => Status::unimplemented(e.to_string()),
+        e => Status::internal(e.to_string()),
+    }
+}
+
+fn missing_vector() -> Status {
+    Status::invalid_argument("Missing query vector")
+}
+
+/// gRPC service backed by an [LSH](../struct.LSH.html) instance.
+///
+/// Queries take a read lock and run concurrently, stores take a write lock. The requests run on
+/// the blocking thread pool of tokio, so waiting for the lock and hashing don't block the async
+/// executor. The backend therefore needs to be `Send + Sync`, which rules out the SQLite
+/// backends.
+pub struct LshService<H, T, K>
+where
+    H: VecHash<f32, K>,
+    T: HashTables<f32, K>,
+    K: Integer,
+{
+    lsh: Arc<RwLock<LSH<H, f32, T, K>>>,
+}
+
+impl<H, T, K> LshService<H, T, K>
+where
+    H: VecHash<f32, K>,
+    T: HashTables<f32, K>,
+    K: Integer,
+{
+    pub fn new(lsh: LSH<H, f32, T, K>) -> Self {
+        Self::from_shared(Arc::new(RwLock::new(lsh)))
+    }
+
+    /// Serve an index that is also used outside of the service, e.g. to
+    /// [dump](../struct.LSH.html#method.dump) it periodically.
+    pub fn from_shared(lsh: Arc<RwLock<LSH<H, f32, T, K>>>) -> Self {
+        LshService { lsh }
+    }
+
+    /// Shared handle to the served index.
+    pub fn lsh(&self) -> Arc<RwLock<LSH<H, f32, T, K>>> {
+        self.lsh.clone()
+    }
+
+    pub fn into_server(self) -> LshServer<Self>
+    where
+        Self: Lsh,
+    {
+        LshServer::new(self)
+    }
+}
+
+impl<H, T, K> LshService<H, T, K>
+where
+    H: VecHash<f32, K> + Send + Sync + 'static,
+    T: HashTables<f32, K> + Send + Sync + 'static,
+    K: Integer + Send + Sync + 'static,
+{
+    /// Run `f` with a read lock on the index, on the blocking thread pool.
+    async fn read<R, F>(&self, f: F) -> Result<R, Status>
+    where
+        R: Send + 'static,
+        F: FnOnce(&LSH<H, f32, T, K>) -> crate::prelude::Result<R> + Send + 'static,
+    {
+        let lsh = self.lsh.clone();
+        tokio::task::spawn_blocking(move || {
+            let lsh = lsh.read().map_err(|e| Error::Failed(e.to_string()))?;
+            f(&lsh)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(to_status)
+    }
+
+    /// Run `f` with a write lock on the index, on the blocking thread pool.
+    async fn write<R, F>(&self, f: F) -> Result<R, Status>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut LSH<H, f32, T, K>) -> crate::prelude::Result<R> + Send + 'static,
+    {
+        let lsh = self.lsh.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut lsh = lsh.write().map_err(|e| Error::Failed(e.to_string()))?;
+            f(&mut lsh)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(to_status)
+    }
+}
+
+#[tonic::async_trait]
+impl<H, T, K> Lsh for LshService<H, T, K>
+where
+    H: VecHash<f32, K> + Send + Sync + 'static,
+    T: HashTables<f32, K, Id = u32> + Send + Sync + 'static,
+    K: Integer + Send + Sync + 'static,
+{
+    async fn store(
+        &self,
+        request: Request<StoreRequest>,
+    ) -> Result<Response<StoreResponse>, Status> {
+        let vs: Vec<Vec<f32>> = request
+            .into_inner()
+            .vectors
+            .into_iter()
+            .map(|v| v.values)
+            .collect();
+        let ids = self.write(move |lsh| lsh.store_vecs(&vs)).await?;
+        Ok(Response::new(StoreResponse { ids }))
+    }
+
+    async fn query(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<QueryResponse>, Status> {
+        let v = request
+            .into_inner()
+            .vector
+            .ok_or_else(missing_vector)?
+            .values;
+        let ids = self.read(move |lsh| lsh.query_bucket_ids(&v)).await?;
+        Ok(Response::new(QueryResponse { ids }))
+    }
+
+    /// The candidates are ranked by the [distance](../trait.VecHash.html#method.distance) of
+    /// the hasher, see [query_top_k](../struct.LSH.html#method.query_top_k).
+    async fn top_k(&self, request: Request<TopKRequest>) -> Result<Response<TopKResponse>, Status> {
+        let request = request.into_inner();
+        let v = request.vector.ok_or_else(missing_vector)?.values;
+        let k = request.k as usize;
+        let neighbors = self
+            .read(move |lsh| lsh.query_top_k(&v, k))
+            .await?
+            .into_iter()
+            .map(|(id, distance)| Neighbor {
+                id,
+                distance: distance as f32,
+            })
+            .collect();
+        Ok(Response::new(TopKResponse { neighbors }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use proto::Vector;
+
+    fn vector(values: &[f32]) -> Option<Vector> {
+        Some(Vector {
+            values: values.to_vec(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let lsh = LshMem::<_, f32>::new(5, 10, 3).seed(1).srp().unwrap();
+        let service = LshService::new(lsh);
+        let vectors = [[1., 1., 1.], [1., 1., 1.1], [-1., -1., -1.]]
+            .iter()
+            .map(|v| Vector { values: v.to_vec() })
+            .collect();
+        let ids = service
+            .store(Request::new(StoreRequest { vectors }))
+            .await
+            .unwrap()
+            .into_inner()
+            .ids;
+        assert_eq!(ids, vec![0, 1, 2]);
+
+        let ids = service
+            .query(Request::new(QueryRequest {
+                vector: vector(&[1., 1., 1.]),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .ids;
+        assert!(ids.contains(&0) && !ids.contains(&2));
+
+        // the neighbors are ranked by the cosine distance of SRP.
+        let neighbors = service
+            .top_k(Request::new(TopKRequest {
+                vector: vector(&[2., 2., 2.]),
+                k: 1,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .neighbors;
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].id, 0);
+        assert!(neighbors[0].distance.abs() < 1e-6);
+
+        let status = service
+            .query(Request::new(QueryRequest {
+                vector: vector(&[1., 1.]),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
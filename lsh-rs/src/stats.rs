@@ -1,13 +1,20 @@
 //! Some utilities to help choose LSH parameters.
+use crate::constants::ESTIMATE_R_PAIRS;
+use crate::data::{Idx, Integer, MaybeSerde, Numeric};
 use crate::dist::l2_norm;
 use crate::prelude::*;
+use crate::utils::create_rng;
 use fnv::FnvHashSet;
 use ndarray::aview1;
+use rand::Rng;
 use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use statrs::{
     consts::SQRT_2PI,
     distribution::{Normal, Univariate},
 };
+use std::cmp::Ordering;
 use std::f64::consts::PI;
 use std::time::Instant;
 
@@ -53,10 +60,10 @@ pub struct OptRes {
     pub min_len: usize,
     pub max_len: usize,
     pub avg_len: f32,
-    pub unique_hash_values: FnvHashSet<i32>,
+    pub unique_hash_values: FnvHashSet<u64>,
 }
 
-fn lsh_to_result<H: 'static + VecHash<f32, i8> + Send + Sync + Clone>(
+fn lsh_to_result<H: 'static + VecHash<f32, i8> + MaybeSerde + Send + Sync + Clone>(
     lsh: LshMem<H, f32, i8>,
     vs: &[Vec<f32>],
     k: usize,
@@ -144,10 +151,38 @@ pub fn optimize_l2_params(
     dim: usize,
     k: &[usize],
     vs: &[Vec<f32>],
+) -> Result<Vec<OptRes>> {
+    optimize_l2(delta, 4., l2_ph(4., 1.), dim, k, vs)
+}
+
+/// Same as [optimize_l2_params](fn.optimize_l2_params.html), with the bucket width `r` of
+/// [estimate_r](fn.estimate_r.html). *L* is determined by the collision probability at the
+/// target distance, so the data points don't have to be normalized.
+///
+/// # Arguments
+/// * `delta` - Probability of not returning NN. P(NN) = 1 - δ
+/// * `estimate` - Result of [estimate_r](fn.estimate_r.html).
+/// * `dim` - Dimension of the data points.
+/// * `vs` - Data points.
+pub fn optimize_l2_params_with_r(
+    delta: f64,
+    estimate: &REstimate,
+    dim: usize,
+    k: &[usize],
+    vs: &[Vec<f32>],
+) -> Result<Vec<OptRes>> {
+    optimize_l2(delta, estimate.r, estimate.p1, dim, k, vs)
+}
+
+fn optimize_l2(
+    delta: f64,
+    r: f32,
+    p1: f64,
+    dim: usize,
+    k: &[usize],
+    vs: &[Vec<f32>],
 ) -> Result<Vec<OptRes>> {
     let mut params = vec![];
-    let r = 4.0;
-    let p1 = l2_ph(r as f64, 1.);
     for _k in k {
         let l = estimate_l(delta, p1, *_k as usize);
         params.push((r, *_k, l))
@@ -155,13 +190,736 @@ pub fn optimize_l2_params(
     let result = params
         .par_iter()
         .map(|&(r, k, l)| {
-            let lsh = LshMem::new(k, l, dim).l2(r as f32)?;
+            let lsh = LshMem::new(k, l, dim).l2(r)?;
             lsh_to_result(lsh, vs, k, l)
         })
         .collect();
     result
 }
 
+/// Result of [estimate_r](fn.estimate_r.html).
+#[derive(Debug, Clone)]
+pub struct REstimate {
+    /// Suggested bucket width of the L2 hash function, in the units of the data points.
+    pub r: f32,
+    /// Collision probability of a single hash function for data points at the target distance.
+    pub p1: f64,
+    /// Collision probability of a single hash function for data points at the median distance.
+    pub p2: f64,
+    /// `ln(1/p1) / ln(1/p2)`. The lower, the better `r` separates near from typical pairs.
+    pub rho: f64,
+    /// Median distance of the sampled pairs.
+    pub median_distance: f64,
+}
+
+/// Suggest the bucket width `r` of the L2 hash function for a sample of the data points, like
+/// E2LSH does. Pairwise distances are sampled and the median is taken as the distance of a
+/// typical (far) pair. Of a grid of widths, the width is chosen that separates pairs at
+/// `target_distance` best from typical pairs, i.e. that minimizes `rho`. Use the result in
+/// [optimize_l2_params_with_r](fn.optimize_l2_params_with_r.html) to choose *K* and *L*.
+///
+/// All pairs are used for small samples. Otherwise `ESTIMATE_R_PAIRS` pairs are drawn with a
+/// fixed seed, so that the estimate is reproducible.
+///
+/// # Arguments
+/// * `sample` - Sample of the data points.
+/// * `target_distance` - Distance within which the nearest neighbor should be found, *R* in
+///   literature.
+///
+/// # Examples
+/// ```
+/// use lsh_rs::stats::estimate_r;
+/// let sample: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, (i % 3) as f32]).collect();
+/// let est = estimate_r(&sample, 1.).unwrap();
+/// assert!(est.p1 > est.p2);
+/// ```
+pub fn estimate_r<N: Numeric>(sample: &[Vec<N>], target_distance: f64) -> Result<REstimate> {
+    if sample.len() < 2 {
+        return Err(Error::Failed(
+            "sample should hold at least 2 data points".to_string(),
+        ));
+    }
+    if target_distance <= 0. {
+        return Err(Error::Failed(
+            "target_distance should be larger than 0".to_string(),
+        ));
+    }
+    let n = sample.len();
+    let pairs: Vec<(usize, usize)> = if n * (n - 1) / 2 <= ESTIMATE_R_PAIRS {
+        (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .collect()
+    } else {
+        let mut rng = create_rng(1);
+        (0..ESTIMATE_R_PAIRS)
+            .map(|_| {
+                let i = rng.gen_range(0, n);
+                let j = (i + rng.gen_range(1, n)) % n;
+                (i, j)
+            })
+            .collect()
+    };
+    let mut distances: Vec<f64> = pairs
+        .par_iter()
+        .map(|&(i, j)| {
+            sample[i]
+                .iter()
+                .zip(&sample[j])
+                .map(|(a, b)| (a.to_f64().unwrap() - b.to_f64().unwrap()).powi(2))
+                .sum::<f64>()
+                .sqrt()
+        })
+        .collect();
+    distances.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let median_distance = percentile(&distances, 0.5);
+    if median_distance <= target_distance {
+        return Err(Error::Failed(format!(
+            "median distance {} of the sample isn't larger than target_distance {}",
+            median_distance, target_distance
+        )));
+    }
+
+    // l2_ph only depends on the ratio of the bucket width and the distance.
+    let best = (1..=64)
+        .map(|i| {
+            let r = target_distance * i as f64 / 4.;
+            let p1 = l2_ph(r, target_distance);
+            let p2 = l2_ph(r, median_distance);
+            REstimate {
+                r: r as f32,
+                p1,
+                p2,
+                rho: p1.ln() / p2.ln(),
+                median_distance,
+            }
+        })
+        .min_by(|a, b| a.rho.partial_cmp(&b.rho).unwrap());
+    Ok(best.unwrap())
+}
+
+/// Result of [suggest_mips_params](fn.suggest_mips_params.html).
+#[derive(Debug, Clone)]
+pub struct MipsParams {
+    pub r: f32,
+    pub U: f32,
+    pub m: usize,
+    /// Fraction of the queries for which the true maximum inner product was a candidate.
+    pub recall: f64,
+    /// Average number of candidates per query. Proxy for the query cost.
+    pub avg_candidates: f32,
+}
+
+/// Grid search over the MIPS parameters `r`, `U` and `m`. Every data point in `sample` is used
+/// as query and its true maximum inner product neighbor (ground truth) is computed by brute force.
+///
+/// Returns the parameters with the least candidates per query that reach `target_recall`.
+/// If no parameters reach the target, the parameters with the highest recall are returned.
+///
+/// # Arguments
+/// * `sample` - Sample of the data points.
+/// * `target_recall` - Minimal fraction of queries that should find their true neighbor.
+/// * `n_projections` - Number of projections (hash length).
+/// * `n_hash_tables` - Number of hash tables.
+/// * `seed` - Seed for the hashers, the same for every grid point. If 0, randomness is seeded
+///   from the os.
+pub fn suggest_mips_params(
+    sample: &[Vec<f32>],
+    target_recall: f64,
+    n_projections: usize,
+    n_hash_tables: usize,
+    seed: u64,
+) -> Result<MipsParams> {
+    if sample.is_empty() {
+        return Err(Error::Failed("sample is empty".to_string()));
+    }
+    if sample.iter().flatten().any(|x| !x.is_finite()) {
+        return Err(Error::Failed(
+            "sample has values that aren't finite".to_string(),
+        ));
+    }
+    let dim = sample[0].len();
+    let ground_truth: Vec<u32> = sample
+        .par_iter()
+        .map(|q| {
+            let q = aview1(q);
+            sample
+                .iter()
+                .map(|p| q.dot(&aview1(p)))
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+                .map(|(i, _)| i as u32)
+                .unwrap()
+        })
+        .collect();
+
+    let mut params = vec![];
+    for &r in &[1., 2., 2.5, 3., 4.] {
+        for &U in &[0.75, 0.83, 0.9] {
+            for &m in &[2, 3, 4] {
+                params.push((r, U, m))
+            }
+        }
+    }
+    let results = params
+        .par_iter()
+        .map(|&(r, U, m)| {
+            let mut lsh: LshMem<_, f32, i32> = LshMem::new(n_projections, n_hash_tables, dim)
+                .seed(seed)
+                .mips(r, U, m)?;
+            lsh.fit(sample)?;
+            lsh.store_vecs(sample)?;
+
+            let mut hits = 0;
+            let mut n_candidates = 0;
+            for (q, truth) in sample.iter().zip(&ground_truth) {
+                let candidates = lsh.query_bucket_ids(q)?;
+                n_candidates += candidates.len();
+                if candidates.contains(truth) {
+                    hits += 1
+                }
+            }
+            Ok(MipsParams {
+                r,
+                U,
+                m,
+                recall: hits as f64 / sample.len() as f64,
+                avg_candidates: n_candidates as f32 / sample.len() as f32,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let best = results
+        .iter()
+        .filter(|p| p.recall >= target_recall)
+        .min_by(|a, b| {
+            a.avg_candidates
+                .partial_cmp(&b.avg_candidates)
+                .unwrap_or(Ordering::Equal)
+        })
+        .or_else(|| {
+            results
+                .iter()
+                .max_by(|a, b| a.recall.partial_cmp(&b.recall).unwrap_or(Ordering::Equal))
+        });
+    Ok(best.unwrap().clone())
+}
+
+/// Search space of [fit_params](fn.fit_params.html).
+#[derive(Debug, Clone)]
+pub struct ParamGrid {
+    /// Values for the number of projections `K`.
+    pub n_projections: Vec<usize>,
+    /// Values for the number of hash tables `L`.
+    pub n_hash_tables: Vec<usize>,
+    /// Values for the multi-probe budget. A budget of 0 disables multi-probing.
+    pub multi_probe_budget: Vec<usize>,
+}
+
+/// Result of [fit_params](fn.fit_params.html).
+#[derive(Debug, Clone)]
+pub struct LshParams {
+    pub n_projections: usize,
+    pub n_hash_tables: usize,
+    /// Multi-probe budget, `None` if multi-probing is disabled.
+    pub multi_probe_budget: Option<usize>,
+    /// Fraction of the true neighbors that were returned as candidate.
+    pub recall: f64,
+    /// Average number of candidates per query.
+    pub avg_candidates: f32,
+    /// Estimated query cost: the number of bucket lookups plus the number of candidates that
+    /// need to be re-ranked.
+    pub cost: f32,
+}
+
+impl LshParams {
+    /// Create an [LSH](../struct.LSH.html) builder with these parameters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lsh_rs::prelude::*;
+    /// use lsh_rs::stats::LshParams;
+    /// # let params = LshParams { n_projections: 5, n_hash_tables: 10, multi_probe_budget: Some(4),
+    /// #     recall: 1., avg_candidates: 1., cost: 1. };
+    /// let lsh: LshMem<_, f32> = params.builder(3).srp().unwrap();
+    /// ```
+    pub fn builder<H, N, T, K>(&self, dim: usize) -> LSH<H, N, T, K>
+    where
+        H: VecHash<N, K>,
+        N: Numeric,
+        T: HashTables<N, K>,
+        K: Integer,
+    {
+        let mut lsh = LSH::new(self.n_projections, self.n_hash_tables, dim);
+        if let Some(budget) = self.multi_probe_budget {
+            lsh.multi_probe(budget);
+        }
+        lsh
+    }
+}
+
+/// Grid search over `K`, `L` and the multi-probe budget driven by ground truth. For every
+/// combination in `grid` an in memory index of `data` is created with `build` and queried with
+/// `queries`.
+///
+/// Returns the parameters with the lowest estimated cost that reach `target_recall`.
+/// If no parameters reach the target, the parameters with the highest recall are returned.
+///
+/// # Arguments
+/// * `data` - Data points to index. Their indexes are `0..data.len()`.
+/// * `queries` - Labeled sample of queries.
+/// * `neighbors` - True neighbors (indexes in `data`) of every query.
+/// * `target_recall` - Minimal fraction of the true neighbors that should be returned.
+/// * `grid` - Parameter values to search.
+/// * `build` - Finish the builder, e.g. `|lsh| lsh.seed(1).srp()`.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::prelude::*;
+/// use lsh_rs::stats::{fit_params, ParamGrid};
+/// let data = vec![vec![2., 3., 4.], vec![-1., 2., 5.], vec![8., -3., 1.]];
+/// let queries = vec![vec![2., 3.1, 4.]];
+/// let neighbors = vec![vec![0]];
+/// let grid = ParamGrid {
+///     n_projections: vec![4, 8],
+///     n_hash_tables: vec![5, 10],
+///     multi_probe_budget: vec![0, 4],
+/// };
+/// let params = fit_params(&data, &queries, &neighbors, 0.9, &grid, |lsh| lsh.seed(1).srp()).unwrap();
+/// let mut lsh: LshMem<_, f32> = params.builder(3).seed(1).srp().unwrap();
+/// ```
+pub fn fit_params<H, K, F>(
+    data: &[Vec<f32>],
+    queries: &[Vec<f32>],
+    neighbors: &[Vec<u32>],
+    target_recall: f64,
+    grid: &ParamGrid,
+    build: F,
+) -> Result<LshParams>
+where
+    H: VecHash<f32, K> + MaybeSerde + Send + Sync,
+    K: Integer,
+    F: Fn(&mut LshMem<H, f32, K>) -> Result<LshMem<H, f32, K>> + Sync,
+{
+    if data.is_empty() || queries.is_empty() {
+        return Err(Error::Failed(
+            "data and queries should not be empty".to_string(),
+        ));
+    }
+    if queries.len() != neighbors.len() {
+        return Err(Error::Failed(
+            "every query should have a set of neighbors".to_string(),
+        ));
+    }
+    let dim = data[0].len();
+    let n_neighbors: usize = neighbors.iter().map(|n| n.len()).sum();
+
+    let mut params = vec![];
+    for &k in &grid.n_projections {
+        for &l in &grid.n_hash_tables {
+            for &budget in &grid.multi_probe_budget {
+                params.push((k, l, budget))
+            }
+        }
+    }
+    let results = params
+        .par_iter()
+        .map(|&(k, l, budget)| {
+            let p = LshParams {
+                n_projections: k,
+                n_hash_tables: l,
+                multi_probe_budget: if budget > 0 { Some(budget) } else { None },
+                recall: 0.,
+                avg_candidates: 0.,
+                cost: 0.,
+            };
+            let mut lsh = build(&mut p.builder(dim))?;
+            lsh.store_vecs(data)?;
+
+            let mut hits = 0;
+            let mut n_candidates = 0;
+            for (q, truth) in queries.iter().zip(neighbors) {
+                let candidates = lsh
+                    .query_bucket_ids(q)?
+                    .into_iter()
+                    .collect::<FnvHashSet<_>>();
+                n_candidates += candidates.len();
+                hits += truth.iter().filter(|idx| candidates.contains(idx)).count();
+            }
+            let avg_candidates = n_candidates as f32 / queries.len() as f32;
+            Ok(LshParams {
+                recall: hits as f64 / n_neighbors.max(1) as f64,
+                avg_candidates,
+                cost: (l * (budget + 1)) as f32 + avg_candidates,
+                ..p
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let best = results
+        .iter()
+        .filter(|p| p.recall >= target_recall)
+        .min_by(|a, b| a.cost.partial_cmp(&b.cost).unwrap())
+        .or_else(|| {
+            results
+                .iter()
+                .max_by(|a, b| a.recall.partial_cmp(&b.recall).unwrap())
+        });
+    best.cloned()
+        .ok_or_else(|| Error::Failed("parameter grid is empty".to_string()))
+}
+
+/// Result of [benchmark_queries](fn.benchmark_queries.html). Latencies are in seconds.
+#[derive(Debug, Clone)]
+pub struct QueryBenchmark {
+    pub n_queries: usize,
+    pub p50_latency: f64,
+    pub p95_latency: f64,
+    pub mean_latency: f64,
+    /// Average number of bucket lookups per query.
+    pub avg_probes: f32,
+    /// Average number of candidates per query.
+    pub avg_candidates: f32,
+}
+
+impl std::fmt::Display for QueryBenchmark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Query benchmark ({} queries):\nlatency p50: {:.1} µs\nlatency p95: {:.1} µs\n\
+             latency mean: {:.1} µs\navg probes: {}\navg candidates: {}",
+            self.n_queries,
+            self.p50_latency * 1e6,
+            self.p95_latency * 1e6,
+            self.mean_latency * 1e6,
+            self.avg_probes,
+            self.avg_candidates
+        )
+    }
+}
+
+/// The `p` percentile of the non empty, sorted `values`.
+fn percentile(values: &[f64], p: f64) -> f64 {
+    values[((p * values.len() as f64).ceil() as usize).max(1) - 1]
+}
+
+/// Self-benchmark of an index for capacity planning. Random stored data points are used as
+/// queries, with the current query settings (e.g. multi-probing).
+///
+/// # Arguments
+/// * `lsh` - Index with stored data points.
+/// * `n_queries` - Number of queries.
+/// * `seed` - Seed for sampling the queries. If 0, randomness is seeded from the os.
+pub fn benchmark_queries<H, N, K, I>(
+    lsh: &LSH<H, N, MemoryTable<N, K, I>, K>,
+    n_queries: usize,
+    seed: u64,
+) -> Result<QueryBenchmark>
+where
+    H: VecHash<N, K>,
+    N: Numeric,
+    K: Integer,
+    I: Idx,
+{
+    let vec_store = &lsh.hash_tables.as_ref().unwrap().vec_store;
+    if vec_store.is_empty() || n_queries == 0 {
+        return Err(Error::Failed(
+            "benchmark needs stored data points and at least one query".to_string(),
+        ));
+    }
+    let mut rng = create_rng(seed);
+    let mut latencies = Vec::with_capacity(n_queries);
+    let mut n_probes = 0;
+    let mut n_candidates = 0;
+    for _ in 0..n_queries {
+        let q = vec_store.get(rng.gen_range(0, vec_store.len()));
+        let t0 = Instant::now();
+        n_candidates += lsh.query_bucket_ids(q)?.len();
+        latencies.push(t0.elapsed().as_secs_f64());
+
+        n_probes += match lsh.multi_probe_budget() {
+            Some(_) => lsh
+                .probing_sequence(q)?
+                .iter()
+                .map(|seq| seq.probes.len())
+                .sum(),
+            None => lsh.n_hash_tables,
+        };
+    }
+    latencies.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    Ok(QueryBenchmark {
+        n_queries,
+        p50_latency: percentile(&latencies, 0.5),
+        p95_latency: percentile(&latencies, 0.95),
+        mean_latency: latencies.iter().sum::<f64>() / n_queries as f64,
+        avg_probes: n_probes as f32 / n_queries as f32,
+        avg_candidates: n_candidates as f32 / n_queries as f32,
+    })
+}
+
+/// Number of buckets with `bucket_len` data points.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HistogramBin {
+    pub bucket_len: usize,
+    pub n_buckets: usize,
+}
+
+/// Bucket statistics of a single hash table.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HashTableStats {
+    /// Index of the hash table.
+    pub table: usize,
+    /// Number of unique hashes (non empty buckets).
+    pub n_buckets: usize,
+    /// Number of data point indexes over all buckets.
+    pub n_entries: usize,
+    /// Bucket length histogram, sorted by bucket length.
+    pub histogram: Vec<HistogramBin>,
+}
+
+impl HashTableStats {
+    /// Collect the statistics from the lengths of the buckets in hash table `table`.
+    pub(crate) fn from_lengths<I: Iterator<Item = usize>>(table: usize, lengths: I) -> Self {
+        let mut counts = std::collections::BTreeMap::new();
+        for len in lengths.filter(|&len| len > 0) {
+            *counts.entry(len).or_insert(0) += 1;
+        }
+        Self::from_histogram(
+            table,
+            counts
+                .into_iter()
+                .map(|(bucket_len, n_buckets)| HistogramBin {
+                    bucket_len,
+                    n_buckets,
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn from_histogram(table: usize, histogram: Vec<HistogramBin>) -> Self {
+        HashTableStats {
+            table,
+            n_buckets: histogram.iter().map(|bin| bin.n_buckets).sum(),
+            n_entries: histogram
+                .iter()
+                .map(|bin| bin.bucket_len * bin.n_buckets)
+                .sum(),
+            histogram,
+        }
+    }
+}
+
+/// Bucket length summary of a single hash table, see [TableStats](struct.TableStats.html).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BucketStats {
+    /// Index of the hash table.
+    pub table: usize,
+    /// Number of unique hashes (non empty buckets).
+    pub n_buckets: usize,
+    pub avg: f64,
+    pub std_dev: f64,
+    pub min: usize,
+    pub max: usize,
+}
+
+impl BucketStats {
+    /// Summarize the bucket length `histogram` of hash table `table`. All statistics are 0 for
+    /// an empty histogram.
+    pub(crate) fn from_histogram(table: usize, histogram: &[HistogramBin]) -> Self {
+        let n: usize = histogram.iter().map(|bin| bin.n_buckets).sum();
+        if n == 0 {
+            return BucketStats {
+                table,
+                n_buckets: 0,
+                avg: 0.,
+                std_dev: 0.,
+                min: 0,
+                max: 0,
+            };
+        }
+        let moment = |p: i32| {
+            histogram
+                .iter()
+                .map(|bin| (bin.bucket_len as f64).powi(p) * bin.n_buckets as f64)
+                .sum::<f64>()
+                / n as f64
+        };
+        let avg = moment(1);
+        BucketStats {
+            table,
+            n_buckets: n,
+            avg,
+            std_dev: (moment(2) - avg * avg).max(0.).sqrt(),
+            min: histogram[0].bucket_len,
+            max: histogram[histogram.len() - 1].bucket_len,
+        }
+    }
+}
+
+/// Statistics of all hash tables of an index, see
+/// [LSH::stats](../struct.LSH.html#method.stats).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TableStats {
+    pub n_hash_tables: usize,
+    /// Number of ids over all buckets of all hash tables.
+    pub n_entries: usize,
+    /// Approximate memory or disk usage in bytes. `None` if the backend doesn't report it.
+    pub approximate_bytes: Option<usize>,
+    /// Number of distinct values in the hashes of a sample of the buckets.
+    pub unique_hash_values: usize,
+    /// Bucket length summary of every hash table.
+    pub tables: Vec<BucketStats>,
+}
+
+/// File format of [export_stats](../struct.LSH.html#method.export_stats).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    /// A list with the [HashTableStats](struct.HashTableStats.html) of every hash table.
+    /// Requires the "serde" feature.
+    #[cfg(feature = "serde")]
+    Json,
+    /// One row per histogram bin with the columns
+    /// `table,n_buckets,n_entries,bucket_len,bucket_count`.
+    Csv,
+}
+
+/// Write the statistics of all hash tables in `format`.
+pub(crate) fn write_stats<W: std::io::Write>(
+    stats: &[HashTableStats],
+    format: StatsFormat,
+    mut w: W,
+) -> Result<()> {
+    match format {
+        #[cfg(feature = "serde")]
+        StatsFormat::Json => serde_json::to_writer_pretty(&mut w, stats)?,
+        StatsFormat::Csv => {
+            writeln!(w, "table,n_buckets,n_entries,bucket_len,bucket_count")?;
+            for s in stats {
+                for bin in &s.histogram {
+                    writeln!(
+                        w,
+                        "{},{},{},{},{}",
+                        s.table, s.n_buckets, s.n_entries, bin.bucket_len, bin.n_buckets
+                    )?;
+                }
+            }
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Result of [evaluate_recall](fn.evaluate_recall.html). Latencies are in seconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RecallEvaluation {
+    pub n_queries: usize,
+    pub k: usize,
+    /// Fraction of the `k` true neighbors that were returned as candidate.
+    pub recall: f64,
+    /// Fraction of the candidates that are one of the `k` true neighbors.
+    pub precision: f64,
+    /// Average number of candidates per query.
+    pub avg_candidates: f64,
+    pub p50_latency: f64,
+    pub p95_latency: f64,
+    pub mean_latency: f64,
+}
+
+impl std::fmt::Display for RecallEvaluation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Recall evaluation ({} queries):\nrecall@{}: {:.4}\nprecision: {:.4}\n\
+             avg candidates: {:.1}\nlatency p50: {:.1} µs\nlatency p95: {:.1} µs\n\
+             latency mean: {:.1} µs",
+            self.n_queries,
+            self.k,
+            self.recall,
+            self.precision,
+            self.avg_candidates,
+            self.p50_latency * 1e6,
+            self.p95_latency * 1e6,
+            self.mean_latency * 1e6
+        )
+    }
+}
+
+/// Measure the recall of an index against ground truth. Every query is answered with
+/// [query_bucket_ids](../struct.LSH.html#method.query_bucket_ids), with the current query
+/// settings of `lsh` (e.g. multi-probing).
+///
+/// # Arguments
+/// * `lsh` - Index with stored data points.
+/// * `queries` - Query data points.
+/// * `ground_truth` - Indexes of the true neighbors of every query, nearest first. Only the
+///   first `k` are used.
+/// * `k` - Number of true neighbors per query.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::prelude::*;
+/// use lsh_rs::stats::evaluate_recall;
+/// let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+/// lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., 2., 5.], vec![8., -3., 1.]]).unwrap();
+/// let eval = evaluate_recall(&lsh, &[vec![2., 3.1, 4.]], &[vec![0, 1]], 1).unwrap();
+/// assert_eq!(eval.recall, 1.);
+/// ```
+pub fn evaluate_recall<H, N, T, K>(
+    lsh: &LSH<H, N, T, K>,
+    queries: &[Vec<N>],
+    ground_truth: &[Vec<T::Id>],
+    k: usize,
+) -> Result<RecallEvaluation>
+where
+    H: VecHash<N, K>,
+    N: Numeric,
+    T: HashTables<N, K>,
+    K: Integer,
+{
+    if queries.is_empty() || k == 0 {
+        return Err(Error::Failed(
+            "evaluation needs at least one query and k > 0".to_string(),
+        ));
+    }
+    if queries.len() != ground_truth.len() {
+        return Err(Error::Failed(
+            "every query should have ground truth".to_string(),
+        ));
+    }
+    let mut latencies = Vec::with_capacity(queries.len());
+    let (mut hits, mut n_truth, mut n_candidates) = (0, 0, 0);
+    for (q, truth) in queries.iter().zip(ground_truth) {
+        let t0 = Instant::now();
+        let candidates = lsh.query_bucket_ids(q)?;
+        latencies.push(t0.elapsed().as_secs_f64());
+
+        let candidates: FnvHashSet<T::Id> = candidates.into_iter().collect();
+        let truth = &truth[..k.min(truth.len())];
+        hits += truth.iter().filter(|idx| candidates.contains(idx)).count();
+        n_truth += truth.len();
+        n_candidates += candidates.len();
+    }
+    let n_queries = queries.len();
+    latencies.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    Ok(RecallEvaluation {
+        n_queries,
+        k,
+        recall: hits as f64 / n_truth.max(1) as f64,
+        precision: hits as f64 / n_candidates.max(1) as f64,
+        avg_candidates: n_candidates as f64 / n_queries as f64,
+        p50_latency: percentile(&latencies, 0.5),
+        p95_latency: percentile(&latencies, 0.95),
+        mean_latency: latencies.iter().sum::<f64>() / n_queries as f64,
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -174,6 +932,140 @@ mod test {
         assert_eq!(0.609548422215397, l2_ph(r, c) as f32);
     }
 
+    #[test]
+    fn test_estimate_r() {
+        use crate::utils::rand_unit_vec;
+        use rand::{rngs::SmallRng, SeedableRng};
+        let vs: Vec<_> = (0..30)
+            .map(|i| rand_unit_vec(8, SmallRng::seed_from_u64(i)))
+            .collect();
+        let est = estimate_r(&vs, 0.2).unwrap();
+        assert!(est.median_distance > 0.2);
+        assert!(est.p1 > est.p2);
+        assert!((est.p1 - l2_ph(est.r as f64, 0.2)).abs() < 1e-6);
+        assert!(est.rho < 1.);
+        // a width that isn't chosen separates the pairs worse.
+        let p1 = l2_ph(est.r as f64 * 4., 0.2);
+        let p2 = l2_ph(est.r as f64 * 4., est.median_distance);
+        assert!(p1.ln() / p2.ln() > est.rho);
+
+        // many data points, the pairs are sampled.
+        let vs: Vec<_> = (0..200)
+            .map(|i| rand_unit_vec(8, SmallRng::seed_from_u64(i)))
+            .collect();
+        let est2 = estimate_r(&vs, 0.2).unwrap();
+        assert!((est2.median_distance / est.median_distance - 1.).abs() < 0.2);
+        assert_eq!(estimate_r(&vs, 0.2).unwrap().r, est2.r);
+        assert!(estimate_r(&vs, 10.).is_err());
+        assert!(estimate_r(&vs[..1], 0.2).is_err());
+
+        let res = optimize_l2_params_with_r(0.2, &est, 8, &[2, 4], &vs[..30]).unwrap();
+        assert_eq!(res[0].l, estimate_l(0.2, est.p1, 2));
+    }
+
+    #[test]
+    fn test_suggest_mips_params() {
+        use crate::utils::rand_unit_vec;
+        use rand::{rngs::SmallRng, SeedableRng};
+        let vs: Vec<_> = (0..50)
+            .map(|i| rand_unit_vec(5, SmallRng::seed_from_u64(i)))
+            .collect();
+        let p = suggest_mips_params(&vs, 0.8, 4, 5, 1).unwrap();
+        assert!(p.recall >= 0.8);
+        // without a target the cheapest parameters are returned.
+        let cheap = suggest_mips_params(&vs, 0., 4, 5, 1).unwrap();
+        assert!(cheap.avg_candidates <= p.avg_candidates);
+        // an unreachable target returns the parameters with the highest recall.
+        let best = suggest_mips_params(&vs, 1.1, 4, 5, 1).unwrap();
+        assert!(best.recall >= p.recall && best.recall >= cheap.recall);
+
+        let mut vs = vs;
+        vs[3][0] = f32::NAN;
+        assert!(suggest_mips_params(&vs, 0.8, 4, 5, 1).is_err());
+    }
+
+    #[test]
+    fn test_fit_params() {
+        use crate::utils::rand_unit_vec;
+        use rand::{rngs::SmallRng, SeedableRng};
+        let data: Vec<_> = (0..50)
+            .map(|i| rand_unit_vec(8, SmallRng::seed_from_u64(i)))
+            .collect();
+        // slightly perturbed data points, with the original as true neighbor.
+        let queries: Vec<Vec<f32>> = data[..10]
+            .iter()
+            .map(|v| v.iter().map(|x| x * 1.01).collect())
+            .collect();
+        let neighbors: Vec<Vec<u32>> = (0..10).map(|i| vec![i]).collect();
+        let grid = ParamGrid {
+            n_projections: vec![4, 8, 16],
+            n_hash_tables: vec![2, 8],
+            multi_probe_budget: vec![0, 4],
+        };
+        let p = fit_params(&data, &queries, &neighbors, 1., &grid, |lsh| {
+            lsh.seed(1).srp()
+        })
+        .unwrap();
+        assert_eq!(p.recall, 1.);
+        // scaling doesn't change the cosine, so the cheapest parameters reach full recall.
+        assert_eq!((p.n_hash_tables, p.multi_probe_budget), (2, None));
+        assert_eq!(p.n_projections, 16);
+    }
+
+    #[test]
+    fn test_benchmark_queries() {
+        use crate::utils::rand_unit_vec;
+        use rand::{rngs::SmallRng, SeedableRng};
+        let vs: Vec<_> = (0..50)
+            .map(|i| rand_unit_vec(8, SmallRng::seed_from_u64(i)))
+            .collect();
+        let mut lsh = LshMem::<_, f32, i32>::new(4, 5, 8).seed(1).l2(4.).unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let b = benchmark_queries(&lsh, 20, 1).unwrap();
+        assert_eq!(b.avg_probes, 5.);
+        // stored data points always find themselves.
+        assert!(b.avg_candidates >= 1.);
+        assert!(b.p50_latency <= b.p95_latency);
+
+        lsh.multi_probe(3);
+        let b = benchmark_queries(&lsh, 20, 1).unwrap();
+        assert_eq!(b.avg_probes, 5. * 4.);
+        let out = lsh.describe_with_benchmark(10).unwrap();
+        assert!(out.starts_with(&lsh.describe().unwrap()));
+        assert!(out.contains("Query benchmark (10 queries):"));
+        assert!(out.contains("avg probes: 20\n"));
+    }
+
+    #[test]
+    fn test_evaluate_recall() {
+        let data = vec![
+            vec![2., 3., 4.],
+            vec![2., 3., 4.2],
+            vec![-1., 2., 5.],
+            vec![8., -3., 1.],
+        ];
+        let mut lsh = LshMem::<_, f32, i32>::new(4, 5, 3).seed(1).l2(4.).unwrap();
+        lsh.store_vecs(&data).unwrap();
+        let queries = vec![data[0].clone(), data[3].clone()];
+        let truth = vec![vec![0, 1], vec![3, 2]];
+
+        let eval = evaluate_recall(&lsh, &queries, &truth, 1).unwrap();
+        // stored data points always find themselves.
+        assert_eq!(eval.recall, 1.);
+        let n_candidates: usize = queries
+            .iter()
+            .map(|q| lsh.query_bucket_ids(q).unwrap().len())
+            .sum();
+        assert_eq!(eval.avg_candidates, n_candidates as f64 / 2.);
+        assert_eq!(eval.precision, 2. / n_candidates as f64);
+        assert!(eval.p50_latency <= eval.p95_latency);
+
+        let eval = evaluate_recall(&lsh, &queries, &truth, 2).unwrap();
+        assert!(eval.recall >= 0.5 && eval.recall <= 1.);
+        assert!(evaluate_recall(&lsh, &queries, &truth[..1], 1).is_err());
+        assert!(evaluate_recall(&lsh, &queries, &truth, 0).is_err());
+    }
+
     #[test]
     fn test_estimate_l() {
         let delta = 0.2;
@@ -1,10 +1,17 @@
-use crate::data::Integer;
-use crate::{data::Numeric, prelude::*};
+use crate::data::{Idx, Integer, MaybeSerde};
+use crate::{
+    data::Numeric,
+    prelude::*,
+    stats::{HashTableStats, HistogramBin},
+};
 use fnv::{FnvHashSet as HashSet, FnvHashSet};
-use serde::{de::DeserializeOwned, Serialize};
 
 /// Bucket contains indexes to VecStore
-pub type Bucket = HashSet<u32>;
+pub type Bucket<I = u32> = HashSet<I>;
+
+/// Bytes attached to a data point, see
+/// [store_vec_with_payload](struct.LSH.html#method.store_vec_with_payload).
+pub type Payload = Vec<u8>;
 
 /// Hashtable consisting of `L` Hash tables.
 pub trait HashTables<N, K>
@@ -12,52 +19,241 @@ where
     N: Numeric,
     K: Integer,
 {
+    /// Id type of the data points, see [Idx](data/trait.Idx.html).
+    type Id: Idx;
+
     fn new(n_hash_tables: usize, only_index_storage: bool, db_path: &str) -> Result<Box<Self>>;
 
+    /// Open the existing hash tables at `db_path` without writing to them, see
+    /// [read_only](struct.LSH.html#method.read_only).
+    fn open_read_only(_n_hash_tables: usize, _db_path: &str) -> Result<Box<Self>> {
+        Err(Error::NotImplemented)
+    }
+
     /// # Arguments
     ///
     /// * `hash` - hashed vector.
     /// * `d` - Vector to store in the buckets.
     /// * `hash_table` - Number of the hash_table to store the vector. Ranging from 0 to L.
-    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<u32>;
+    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<Self::Id>;
+
+    /// Id that the next data point is stored under, so it can be written to the write-ahead
+    /// log before the data point is put.
+    fn next_id(&self) -> Result<Self::Id> {
+        Err(Error::NotImplemented)
+    }
 
     fn delete(&mut self, _hash: &[K], _d: &[N], _hash_table: usize) -> Result<()> {
         Err(Error::NotImplemented)
     }
 
+    /// Remove an id from a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Id of the data point.
+    /// * `hash` - Hash of the bucket that contains the id.
+    /// * `hash_table` - Number of the hash_table. Ranging from 0 to L.
+    fn remove_idx(&mut self, _idx: Self::Id, _hash: &[K], _hash_table: usize) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Remove an id from the buckets of every hash table, when its hashes are not known.
+    /// Backends may need to scan all buckets.
+    fn delete_by_idx(&mut self, _idx: Self::Id) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Bulk insert ids with precomputed hashes into a hash table. No data points are stored.
+    /// The id counter continues after the largest inserted id.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - `(hash, id)` pairs, preferably sorted by hash.
+    /// * `hash_table` - Number of the hash_table. Ranging from 0 to L.
+    fn insert_ids(&mut self, _entries: &[(Vec<K>, Self::Id)], _hash_table: usize) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
     fn update_by_idx(
         &mut self,
         _old_hash: &[K],
         _new_hash: Vec<K>,
-        _idx: u32,
+        _idx: Self::Id,
         _hash_table: usize,
     ) -> Result<()> {
         Err(Error::NotImplemented)
     }
 
+    /// Start an ingestion session. Data points stored during the session can be undone with
+    /// `rollback_ingest`.
+    fn begin_ingest(&mut self) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// End the ingestion session and keep the data points stored during the session.
+    fn commit_ingest(&mut self) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// End the ingestion session and remove the data points stored during the session.
+    fn rollback_ingest(&mut self) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Called after every chunk of [store_from_iter](struct.LSH.html#method.store_from_iter).
+    /// Backends can persist the chunk here, e.g. commit the running transaction.
+    fn end_chunk(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     /// Query the whole bucket
-    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket>;
+    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket<Self::Id>>;
+
+    /// Query the buckets of multiple hashes of one hash table, e.g. the probes of a multi probe
+    /// query. Returns a bucket per hash, which is empty if the hash isn't stored. Backends with
+    /// overhead per query can override this to query all buckets at once.
+    ///
+    /// # Arguments
+    /// * `hashes` - Hashes of the buckets.
+    /// * `hash_table` - Number of the hash_table. Ranging from 0 to L.
+    fn query_buckets(&self, hashes: &[Vec<K>], hash_table: usize) -> Result<Vec<Bucket<Self::Id>>> {
+        hashes
+            .iter()
+            .map(|hash| match self.query_bucket(hash, hash_table) {
+                Err(Error::NotFound) => Ok(Bucket::default()),
+                r => r,
+            })
+            .collect()
+    }
+
+    /// Number of data points in a bucket, 0 if the hash isn't stored. Backends can override
+    /// this to count the bucket without fetching the ids.
+    ///
+    /// # Arguments
+    /// * `hash` - Hash of the bucket.
+    /// * `hash_table` - Number of the hash_table. Ranging from 0 to L.
+    fn bucket_len(&self, hash: &[K], hash_table: usize) -> Result<usize> {
+        match self.query_bucket(hash, hash_table) {
+            Ok(bucket) => Ok(bucket.len()),
+            Err(Error::NotFound) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attach a payload to data point `idx`, replacing its previous payload.
+    fn put_payload(&mut self, _idx: Self::Id, _payload: &[u8]) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// The payload of data point `idx`, `None` if it has no payload.
+    fn payload(&self, _idx: Self::Id) -> Result<Option<Payload>> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Drop the payload of data point `idx`, if any. `delete_by_idx` already does this.
+    fn remove_payload(&mut self, _idx: Self::Id) -> Result<()> {
+        Ok(())
+    }
 
-    fn idx_to_datapoint(&self, _idx: u32) -> Result<&Vec<N>> {
+    fn idx_to_datapoint(&self, _idx: Self::Id) -> Result<&[N]> {
         Err(Error::NotImplemented)
     }
 
+    /// Call `f` with the data point `idx`. Backends that can't lend out a data point, e.g.
+    /// because it is behind a lock, override this instead of `idx_to_datapoint`.
+    fn with_datapoint<R, F: FnOnce(&[N]) -> R>(&self, idx: Self::Id, f: F) -> Result<R> {
+        Ok(f(self.idx_to_datapoint(idx)?))
+    }
+
     fn increase_storage(&mut self, _size: usize) {}
 
+    /// Store data points in a single aligned, row-major allocation. Backends that don't store
+    /// data points can ignore this.
+    fn flat_storage(&mut self, _dim: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Pack the hashes of the bucket keys in a compact layout, see
+    /// [packed_keys](struct.LSH.html#method.packed_keys). Backends that don't keep the keys in
+    /// memory can ignore this.
+    fn packed_keys(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Create an index on the hashes once a hash table exceeds `threshold` rows. `None` disables
+    /// automatic indexing. Backends that don't need an index can ignore this.
+    fn auto_index(&mut self, _threshold: Option<u32>) {}
+
     fn describe(&self) -> Result<String> {
         Err(Error::NotImplemented)
     }
 
+    /// Bucket length histogram and unique hash count of every hash table.
+    fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Number of ids over all buckets of all hash tables. Defaults to the sum of the
+    /// `n_entries` of [hash_table_stats](#method.hash_table_stats).
+    fn n_entries(&self) -> Result<usize> {
+        Ok(self.hash_table_stats()?.iter().map(|s| s.n_entries).sum())
+    }
+
+    /// Approximate memory or disk usage of the backend in bytes.
+    fn approximate_bytes(&self) -> Result<usize> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Bucket length histogram of hash table `hash_table`, sorted by bucket length. Backends
+    /// may compute it over only `max_buckets` buckets to bound the cost for large tables.
+    /// Defaults to the (complete) histogram of [hash_table_stats](#method.hash_table_stats).
+    fn bucket_histogram(
+        &self,
+        hash_table: usize,
+        _max_buckets: usize,
+    ) -> Result<Vec<HistogramBin>> {
+        self.hash_table_stats()?
+            .into_iter()
+            .nth(hash_table)
+            .map(|s| s.histogram)
+            .ok_or(Error::TableNotExist)
+    }
+
+    /// Pass the ids of every non empty bucket of hash table `hash_table` to `f`, sorted
+    /// ascending. Used to self-join the index, see
+    /// [candidate_pairs](struct.LSH.html#method.candidate_pairs).
+    fn for_each_bucket(
+        &self,
+        _hash_table: usize,
+        _f: &mut dyn FnMut(&[Self::Id]) -> Result<()>,
+    ) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Append the buckets, data points and payloads of `other`, which was hashed with the same
+    /// hashers. The ids of `other` are shifted by the returned offset, the counter of this table
+    /// before the merge.
+    fn merge(&mut self, _other: &Self) -> Result<Self::Id> {
+        Err(Error::NotImplemented)
+    }
+
     // Should fail if hashers already stored.
-    fn store_hashers<H: VecHash<N, K> + Serialize>(&mut self, _hashers: &[H]) -> Result<()> {
+    fn store_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, _hashers: &[H]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Replace the stored hashers, e.g. after they were fitted. Backends that don't store the
+    /// hashers do nothing.
+    fn update_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, _hashers: &[H]) -> Result<()> {
         Ok(())
     }
 
     // If store_hashers fails, load_hasher can be executed
-    fn load_hashers<H: VecHash<N, K> + DeserializeOwned>(&self) -> Result<Vec<H>> {
+    fn load_hashers<H: VecHash<N, K> + MaybeSerde>(&self) -> Result<Vec<H>> {
         // just chose an error to make a default trait implementation
         Err(Error::NotImplemented)
     }
 
-    fn get_unique_hash_int(&self) -> FnvHashSet<i32>;
+    fn get_unique_hash_int(&self) -> FnvHashSet<u64>;
 }
@@ -1,101 +1,628 @@
-use crate::data::Integer;
+use crate::data::{hash_word, Idx, Integer};
 use crate::{
     constants::DESCRIBE_MAX,
     data::Numeric,
     prelude::*,
+    stats::{HashTableStats, HistogramBin},
     table::general::{Bucket, HashTables},
     utils::{all_eq, increase_capacity},
 };
 use fnv::{FnvHashMap as HashMap, FnvHashSet};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::iter::FromIterator;
+use std::marker::PhantomData;
+
+/// Alignment in bytes of the rows in a flat [VecStore](enum.VecStore.html).
+/// This is the width of an `f32x8` SIMD register.
+const ROW_ALIGN: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(32))]
+struct Block([u8; ROW_ALIGN]);
+
+/// Row-major storage of equally sized data points in a single allocation.
+///
+/// Every row is padded to a multiple of 32 bytes and starts at a 32 byte boundary, so that
+/// rows can be loaded with aligned SIMD instructions during exact distance computation.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        from = "FlatRows<N>",
+        into = "FlatRows<N>",
+        bound(
+            serialize = "N: Numeric",
+            deserialize = "N: Numeric + Deserialize<'de>"
+        )
+    )
+)]
+pub struct FlatVecStore<N> {
+    blocks: Vec<Block>,
+    dim: usize,
+    stride: usize,
+    len: usize,
+    phantom: PhantomData<N>,
+}
+
+/// Serialized form of the `FlatVecStore`. The padding is not serialized.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+struct FlatRows<N> {
+    dim: usize,
+    data: Vec<N>,
+}
+
+impl<N: Numeric> FlatVecStore<N> {
+    pub fn new(dim: usize) -> Self {
+        // number of elements of N that fit in an aligned block.
+        let lanes = ROW_ALIGN / std::mem::size_of::<N>();
+        let stride = dim.div_ceil(lanes) * lanes;
+        FlatVecStore {
+            blocks: vec![],
+            dim,
+            stride,
+            len: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Number of blocks that are needed to store `n_rows`.
+    fn n_blocks(&self, n_rows: usize) -> usize {
+        n_rows * self.stride * std::mem::size_of::<N>() / ROW_ALIGN
+    }
+
+    fn as_ptr(&self) -> *const N {
+        self.blocks.as_ptr() as *const N
+    }
+
+    pub fn push(&mut self, d: &[N]) -> usize {
+        assert_eq!(d.len(), self.dim, "data point has wrong dimension");
+        let n_blocks = self.n_blocks(self.len + 1);
+        self.blocks.resize(n_blocks, Block([0; ROW_ALIGN]));
+        // The buffer is large enough for the new row and N is a primitive numeric type.
+        unsafe {
+            let row = (self.blocks.as_mut_ptr() as *mut N).add(self.len * self.stride);
+            std::ptr::copy_nonoverlapping(d.as_ptr(), row, self.dim);
+        }
+        self.len += 1;
+        self.len - 1
+    }
+
+    /// Get a data point. The slice does not contain the padding.
+    pub fn get(&self, idx: usize) -> &[N] {
+        assert!(idx < self.len, "index out of bounds");
+        unsafe { std::slice::from_raw_parts(self.as_ptr().add(idx * self.stride), self.dim) }
+    }
+
+    /// The complete padded buffer. Rows can be found at `idx * stride`.
+    pub fn as_slice(&self) -> &[N] {
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len * self.stride) }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[N]> {
+        (0..self.len).map(move |idx| self.get(idx))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Distance in elements between the starts of two consecutive rows.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    fn truncate(&mut self, n_rows: usize) {
+        if n_rows < self.len {
+            self.len = n_rows;
+            let n_blocks = self.n_blocks(n_rows);
+            self.blocks.truncate(n_blocks);
+        }
+    }
+
+    fn reserve(&mut self, n_rows: usize) {
+        let n_blocks = self.n_blocks(n_rows);
+        increase_capacity(n_blocks, &mut self.blocks);
+    }
+}
+
+impl<N: Numeric> From<FlatRows<N>> for FlatVecStore<N> {
+    fn from(rows: FlatRows<N>) -> Self {
+        let mut store = FlatVecStore::new(rows.dim);
+        if let Some(n_rows) = rows.data.len().checked_div(rows.dim) {
+            store.reserve(n_rows);
+            rows.data.chunks(rows.dim).for_each(|d| {
+                store.push(d);
+            });
+        }
+        store
+    }
+}
+
+impl<N: Numeric> From<FlatVecStore<N>> for FlatRows<N> {
+    fn from(store: FlatVecStore<N>) -> Self {
+        let mut data = Vec::with_capacity(store.len * store.dim);
+        store.rows().for_each(|d| data.extend_from_slice(d));
+        FlatRows {
+            dim: store.dim,
+            data,
+        }
+    }
+}
 
 /// Indexible vector storage.
 /// indexes will be stored in hashtables. The original vectors can be looked up in this data structure.
-#[derive(Debug, Deserialize, Serialize)]
-pub struct VecStore<N> {
-    pub map: Vec<Vec<N>>,
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "N: Numeric",
+        deserialize = "N: Numeric + Deserialize<'de>"
+    ))
+)]
+pub enum VecStore<N> {
+    /// Every data point in a separate allocation.
+    Nested(Vec<Vec<N>>),
+    /// All data points in one aligned, row-major allocation.
+    Flat(FlatVecStore<N>),
+}
+
+impl<N> Default for VecStore<N> {
+    fn default() -> Self {
+        VecStore::Nested(vec![])
+    }
 }
 
 impl<N: Numeric> VecStore<N> {
-    fn push(&mut self, d: Vec<N>) -> u32 {
-        self.map.push(d);
-        (self.map.len() - 1) as u32
+    pub(crate) fn push(&mut self, d: &[N]) -> usize {
+        match self {
+            VecStore::Nested(map) => {
+                map.push(d.to_vec());
+                map.len() - 1
+            }
+            VecStore::Flat(store) => store.push(d),
+        }
     }
 
-    fn position(&self, d: &[N]) -> Option<u32> {
-        self.map.iter().position(|x| all_eq(x, d)).map(|x| x as u32)
+    fn position(&self, d: &[N]) -> Option<usize> {
+        match self {
+            VecStore::Nested(map) => map.iter().position(|x| all_eq(x, d)),
+            VecStore::Flat(store) => store.rows().position(|x| all_eq(x, d)),
+        }
     }
 
-    fn get(&self, idx: u32) -> &Vec<N> {
-        &self.map[idx as usize]
+    pub fn get(&self, idx: usize) -> &[N] {
+        match self {
+            VecStore::Nested(map) => &map[idx],
+            VecStore::Flat(store) => store.get(idx),
+        }
     }
 
-    fn increase_storage(&mut self, size: usize) {
-        increase_capacity(size, &mut self.map);
+    pub fn len(&self) -> usize {
+        match self {
+            VecStore::Nested(map) => map.len(),
+            VecStore::Flat(store) => store.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn truncate(&mut self, len: usize) {
+        match self {
+            VecStore::Nested(map) => map.truncate(len),
+            VecStore::Flat(store) => store.truncate(len),
+        }
+    }
+
+    pub(crate) fn increase_storage(&mut self, size: usize) {
+        match self {
+            VecStore::Nested(map) => increase_capacity(size, map),
+            VecStore::Flat(store) => store.reserve(size),
+        }
+    }
+
+    /// Allocated bytes of the data points.
+    fn approximate_bytes(&self) -> usize {
+        match self {
+            VecStore::Nested(map) => map
+                .iter()
+                .map(|v| std::mem::size_of::<Vec<N>>() + v.capacity() * std::mem::size_of::<N>())
+                .sum(),
+            VecStore::Flat(store) => store.blocks.capacity() * ROW_ALIGN,
+        }
+    }
+}
+
+/// Number of bytes of hash values that a `PackedKey` stores inline.
+const INLINE_KEY_BYTES: usize = 22;
+
+/// Compact bucket key, see [packed_keys](struct.LSH.html#method.packed_keys).
+///
+/// Binary hashes (e.g. of sign random projections) are packed in one bit per projection.
+/// Other hashes are stored inline if they fit in `INLINE_KEY_BYTES`. Only longer hashes are
+/// allocated on the heap. A hash always packs to the same variant, so keys can be compared
+/// without decoding.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum PackedKey<K> {
+    /// Hash values in `{0, 1}`, or in `{-1, 1}` if `bipolar`.
+    Bits {
+        len: u8,
+        bipolar: bool,
+        bits: u64,
+    },
+    Inline {
+        len: u8,
+        bytes: [u8; INLINE_KEY_BYTES],
+    },
+    Heap(Box<[K]>),
+}
+
+impl<K: Integer> PackedKey<K> {
+    fn new(hash: &[K]) -> Self {
+        if let Some(key) = Self::pack_bits(hash) {
+            return key;
+        }
+        let size = std::mem::size_of::<K>();
+        if std::mem::size_of_val(hash) <= INLINE_KEY_BYTES {
+            let mut bytes = [0; INLINE_KEY_BYTES];
+            for (chunk, &v) in bytes.chunks_exact_mut(size).zip(hash) {
+                unsafe { std::ptr::write_unaligned(chunk.as_mut_ptr() as *mut K, v) }
+            }
+            return PackedKey::Inline {
+                len: hash.len() as u8,
+                bytes,
+            };
+        }
+        PackedKey::Heap(hash.into())
+    }
+
+    fn pack_bits(hash: &[K]) -> Option<Self> {
+        if hash.len() > 64 {
+            return None;
+        }
+        let minus_one = K::from_i8(-1);
+        let (mut bits, mut zero, mut bipolar) = (0u64, false, false);
+        for (i, &v) in hash.iter().enumerate() {
+            if v == K::one() {
+                bits |= 1 << i;
+            } else if v == K::zero() {
+                zero = true;
+            } else if Some(v) == minus_one {
+                bipolar = true;
+            } else {
+                return None;
+            }
+        }
+        if zero && bipolar {
+            return None;
+        }
+        Some(PackedKey::Bits {
+            len: hash.len() as u8,
+            bipolar,
+            bits,
+        })
+    }
+
+    fn to_vec(&self) -> Vec<K> {
+        match self {
+            PackedKey::Bits { len, bipolar, bits } => {
+                let off = if *bipolar {
+                    K::from_i8(-1).unwrap()
+                } else {
+                    K::zero()
+                };
+                (0..*len)
+                    .map(|i| if bits >> i & 1 == 1 { K::one() } else { off })
+                    .collect()
+            }
+            PackedKey::Inline { len, bytes } => bytes
+                .chunks_exact(std::mem::size_of::<K>())
+                .take(*len as usize)
+                .map(|c| unsafe { std::ptr::read_unaligned(c.as_ptr() as *const K) })
+                .collect(),
+            PackedKey::Heap(hash) => hash.to_vec(),
+        }
+    }
+
+    /// Allocated bytes outside of the key.
+    fn heap_bytes(&self) -> usize {
+        match self {
+            PackedKey::Heap(hash) => hash.len() * std::mem::size_of::<K>(),
+            _ => 0,
+        }
+    }
+}
+
+/// A bucket and its hash.
+type KeyedBucket<'a, K, I> = (Cow<'a, [K]>, &'a Bucket<I>);
+
+/// Buckets of a single hash table.
+#[derive(Clone)]
+enum BucketMap<K, I> {
+    /// Every hash in a separate allocation.
+    Nested(HashMap<Vec<K>, Bucket<I>>),
+    /// Hashes packed in compact keys.
+    Packed(HashMap<PackedKey<K>, Bucket<I>>),
+}
+
+impl<K: Integer, I: Idx> BucketMap<K, I> {
+    fn bucket_mut(&mut self, hash: Vec<K>) -> &mut Bucket<I> {
+        match self {
+            BucketMap::Nested(map) => map.entry(hash).or_default(),
+            BucketMap::Packed(map) => map.entry(PackedKey::new(&hash)).or_default(),
+        }
+    }
+
+    fn get(&self, hash: &[K]) -> Option<&Bucket<I>> {
+        match self {
+            BucketMap::Nested(map) => map.get(hash),
+            BucketMap::Packed(map) => map.get(&PackedKey::new(hash)),
+        }
+    }
+
+    fn get_mut(&mut self, hash: &[K]) -> Option<&mut Bucket<I>> {
+        match self {
+            BucketMap::Nested(map) => map.get_mut(hash),
+            BucketMap::Packed(map) => map.get_mut(&PackedKey::new(hash)),
+        }
+    }
+
+    /// Buckets as `(hash, ids)` pairs, in arbitrary order.
+    fn iter(&self) -> Box<dyn Iterator<Item = KeyedBucket<'_, K, I>> + '_> {
+        match self {
+            BucketMap::Nested(map) => Box::new(map.iter().map(|(k, b)| (Cow::from(&k[..]), b))),
+            BucketMap::Packed(map) => Box::new(map.iter().map(|(k, b)| (Cow::from(k.to_vec()), b))),
+        }
+    }
+
+    fn values(&self) -> Box<dyn Iterator<Item = &Bucket<I>> + '_> {
+        match self {
+            BucketMap::Nested(map) => Box::new(map.values()),
+            BucketMap::Packed(map) => Box::new(map.values()),
+        }
+    }
+
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut Bucket<I>> + '_> {
+        match self {
+            BucketMap::Nested(map) => Box::new(map.values_mut()),
+            BucketMap::Packed(map) => Box::new(map.values_mut()),
+        }
+    }
+
+    fn retain<F: FnMut(&mut Bucket<I>) -> bool>(&mut self, mut f: F) {
+        match self {
+            BucketMap::Nested(map) => map.retain(|_, b| f(b)),
+            BucketMap::Packed(map) => map.retain(|_, b| f(b)),
+        }
+    }
+
+    fn pack(&mut self) {
+        if let BucketMap::Nested(map) = self {
+            let packed = map
+                .drain()
+                .map(|(hash, bucket)| (PackedKey::new(&hash), bucket))
+                .collect();
+            *self = BucketMap::Packed(packed);
+        }
+    }
+
+    /// Counts the keys and the ids. The overhead of the hash map is estimated at one control
+    /// byte per slot.
+    fn approximate_bytes(&self) -> usize {
+        let bucket_bytes = |bucket: &Bucket<I>| bucket.capacity() * (std::mem::size_of::<I>() + 1);
+        let slot = std::mem::size_of::<Bucket<I>>() + 1;
+        match self {
+            BucketMap::Nested(map) => {
+                map.capacity() * (slot + std::mem::size_of::<Vec<K>>())
+                    + map
+                        .iter()
+                        .map(|(hash, bucket)| {
+                            hash.capacity() * std::mem::size_of::<K>() + bucket_bytes(bucket)
+                        })
+                        .sum::<usize>()
+            }
+            BucketMap::Packed(map) => {
+                map.capacity() * (slot + std::mem::size_of::<PackedKey<K>>())
+                    + map
+                        .iter()
+                        .map(|(hash, bucket)| hash.heap_bytes() + bucket_bytes(bucket))
+                        .sum::<usize>()
+            }
+        }
+    }
+}
+
+impl<K: Integer, I: Idx> std::fmt::Debug for BucketMap<K, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Serialized as a map from hash to bucket, regardless of the key layout.
+#[cfg(feature = "serde")]
+impl<K: Integer, I: Idx> Serialize for BucketMap<K, I> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, I> Deserialize<'de> for BucketMap<K, I>
+where
+    K: Integer + Deserialize<'de>,
+    I: Idx,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(BucketMap::Nested)
     }
 }
 
 /// In memory backend for [LSH](struct.LSH.html).
-#[derive(Deserialize, Serialize)]
-pub struct MemoryTable<N, K>
+///
+/// `MemoryTable` is `Send + Sync`, queries can be run concurrently from multiple threads.
+/// The ids are `u32` by default, set `I` to `u64` to store more than about 4.29 billion data
+/// points.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "N: Numeric, K: Integer, I: Idx",
+        deserialize = "N: Numeric, K: Integer + Deserialize<'de>, I: Idx"
+    ))
+)]
+pub struct MemoryTable<N, K, I = u32>
 where
     N: Numeric,
     K: Integer,
+    I: Idx,
 {
-    hash_tables: Vec<HashMap<Vec<K>, Bucket>>,
+    hash_tables: Vec<BucketMap<K, I>>,
     n_hash_tables: usize,
+    /// Serialized separately by [LSH::dump](struct.LSH.html#method.dump), so that the
+    /// hash tables can be loaded without the original vectors.
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub vec_store: VecStore<N>,
     only_index_storage: bool,
-    counter: u32,
+    counter: I,
+    /// First id of the running ingestion session.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ingest_start: Option<I>,
+    /// Serialized separately by [LSH::dump](struct.LSH.html#method.dump), like the vectors.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    payloads: HashMap<I, Box<[u8]>>,
 }
 
-impl<N, K> MemoryTable<N, K>
+impl<N, K, I> MemoryTable<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: Idx,
 {
-    fn remove_idx(&mut self, idx: u32, hash: &[K], hash_table: usize) -> Result<()> {
-        let tbl = &mut self.hash_tables[hash_table];
-        let bucket = tbl.get_mut(hash);
-        match bucket {
-            None => return Err(Error::NotFound),
-            Some(bucket) => {
-                bucket.remove(&idx);
-                Ok(())
+    /// Buckets of a hash table as `(hash, ids)` pairs, in arbitrary order.
+    pub(crate) fn buckets(
+        &self,
+        hash_table: usize,
+    ) -> impl Iterator<Item = (Cow<'_, [K]>, &Bucket<I>)> {
+        self.hash_tables[hash_table].iter()
+    }
+
+    /// Restore a table from the buckets (`(hash, ids)` pairs) of every hash table. Without
+    /// `vectors`, only the indexes are stored.
+    pub(crate) fn from_buckets(
+        buckets: Vec<Vec<(Vec<K>, Vec<I>)>>,
+        vectors: Option<Vec<Vec<N>>>,
+    ) -> Self {
+        let n_hash_tables = buckets.len();
+        let mut m = MemoryTable {
+            hash_tables: vec![BucketMap::Nested(HashMap::default()); n_hash_tables],
+            n_hash_tables,
+            vec_store: VecStore::Nested(vec![]),
+            only_index_storage: vectors.is_none(),
+            counter: I::zero(),
+            ingest_start: None,
+            payloads: HashMap::default(),
+        };
+        for (i, tbl) in buckets.into_iter().enumerate() {
+            for (hash, ids) in tbl {
+                for idx in ids {
+                    m.counter = m.counter.max(idx.succ());
+                    m.insert_idx(idx, hash.clone(), i);
+                }
             }
         }
+        if let Some(vectors) = vectors {
+            m.counter = m.counter.max(I::from_index(vectors.len()));
+            m.vec_store = VecStore::Nested(vectors);
+        }
+        m
     }
-    fn insert_idx(&mut self, idx: u32, hash: Vec<K>, hash_table: usize) {
-        debug_assert!(hash_table < self.n_hash_tables);
-        let tbl = unsafe { self.hash_tables.get_unchecked_mut(hash_table) };
-        let bucket = tbl.entry(hash).or_insert_with(|| FnvHashSet::default());
-        bucket.insert(idx);
+
+    /// The payloads as `(id, payload)` pairs, sorted by id.
+    pub(crate) fn payload_pairs(&self) -> Vec<(I, Vec<u8>)> {
+        let mut pairs: Vec<_> = self
+            .payloads
+            .iter()
+            .map(|(&idx, payload)| (idx, payload.to_vec()))
+            .collect();
+        pairs.sort_unstable_by_key(|&(idx, _)| idx);
+        pairs
+    }
+
+    pub(crate) fn set_payloads(&mut self, pairs: Vec<(I, Vec<u8>)>) {
+        self.payloads = pairs
+            .into_iter()
+            .map(|(idx, payload)| (idx, payload.into()))
+            .collect();
+    }
+
+    /// Append `n` empty hash tables, with the same key layout as the existing ones.
+    pub(crate) fn add_tables(&mut self, n: usize) {
+        let mut empty = BucketMap::Nested(HashMap::default());
+        if let Some(BucketMap::Packed(_)) = self.hash_tables.first() {
+            empty.pack();
+        }
+        self.hash_tables.resize(self.n_hash_tables + n, empty);
+        self.n_hash_tables += n;
+    }
+
+    /// Remove the last `n` hash tables.
+    pub(crate) fn drop_tables(&mut self, n: usize) {
+        self.n_hash_tables -= n;
+        self.hash_tables.truncate(self.n_hash_tables);
+        self.hash_tables.shrink_to_fit();
+    }
+
+    fn insert_idx(&mut self, idx: I, hash: Vec<K>, hash_table: usize) {
+        self.hash_tables[hash_table].bucket_mut(hash).insert(idx);
     }
 }
 
-impl<N, K> HashTables<N, K> for MemoryTable<N, K>
+impl<N, K, I> HashTables<N, K> for MemoryTable<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: Idx,
 {
+    type Id = I;
+
     fn new(n_hash_tables: usize, only_index_storage: bool, _: &str) -> Result<Box<Self>> {
         // TODO: Check the average number of vectors in the buckets.
         // this way the capacity can be approximated by the number of DataPoints that will
         // be stored.
-        let hash_tables = vec![HashMap::default(); n_hash_tables];
-        let vector_store = VecStore { map: vec![] };
+        let hash_tables = vec![BucketMap::Nested(HashMap::default()); n_hash_tables];
+        let vector_store = VecStore::Nested(vec![]);
         let m = MemoryTable {
             hash_tables,
             n_hash_tables,
             vec_store: vector_store,
             only_index_storage,
-            counter: 0,
+            counter: I::zero(),
+            ingest_start: None,
+            payloads: HashMap::default(),
         };
         Ok(Box::new(m))
     }
 
-    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<u32> {
+    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<I> {
         // Store hash and id/idx
         let idx = self.counter;
         self.insert_idx(idx, hash, hash_table);
@@ -104,19 +631,60 @@ where
         // the unique v hash_table 0 and increment the counter (the id)
         // after we've update the last (N) hash_table.
         if (hash_table == 0) && (!self.only_index_storage) {
-            self.vec_store.push(d.to_vec());
-        } else if hash_table == self.n_hash_tables - 1 {
-            self.counter += 1
+            self.vec_store.push(d);
+        }
+        if hash_table == self.n_hash_tables - 1 {
+            self.counter = self.counter.succ()
         }
         Ok(idx)
     }
 
+    fn next_id(&self) -> Result<I> {
+        Ok(self.counter)
+    }
+
+    fn remove_idx(&mut self, idx: I, hash: &[K], hash_table: usize) -> Result<()> {
+        let tbl = &mut self.hash_tables[hash_table];
+        let bucket = tbl.get_mut(hash);
+        match bucket {
+            None => return Err(Error::NotFound),
+            Some(bucket) => {
+                bucket.remove(&idx);
+                Ok(())
+            }
+        }
+    }
+
+    /// Scans the buckets of every hash table.
+    fn delete_by_idx(&mut self, idx: I) -> Result<()> {
+        let mut found = false;
+        for tbl in self.hash_tables.iter_mut() {
+            for bucket in tbl.values_mut() {
+                found |= bucket.remove(&idx);
+            }
+        }
+        self.payloads.remove(&idx);
+        if found {
+            Ok(())
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    fn insert_ids(&mut self, entries: &[(Vec<K>, I)], hash_table: usize) -> Result<()> {
+        for (hash, idx) in entries {
+            self.insert_idx(*idx, hash.clone(), hash_table);
+            self.counter = self.counter.max(idx.succ());
+        }
+        Ok(())
+    }
+
     /// Expensive operation we need to do a linear search over all datapoints
     fn delete(&mut self, hash: &[K], d: &[N], hash_table: usize) -> Result<()> {
         // First find the data point in the VecStore
         let idx = match self.vec_store.position(d) {
             None => return Ok(()),
-            Some(idx) => idx,
+            Some(idx) => I::from_index(idx),
         };
         // Note: data point remains in VecStore as shrinking the vector would mean we need to
         // re-hash all datapoints.
@@ -127,7 +695,7 @@ where
         &mut self,
         old_hash: &[K],
         new_hash: Vec<K>,
-        idx: u32,
+        idx: I,
         hash_table: usize,
     ) -> Result<()> {
         self.remove_idx(idx, old_hash, hash_table)?;
@@ -135,8 +703,38 @@ where
         Ok(())
     }
 
+    fn begin_ingest(&mut self) -> Result<()> {
+        if self.ingest_start.is_some() {
+            return Err(Error::Failed(
+                "an ingestion session is already running".to_string(),
+            ));
+        }
+        self.ingest_start = Some(self.counter);
+        Ok(())
+    }
+
+    fn commit_ingest(&mut self) -> Result<()> {
+        self.ingest_start.take().map(|_| ()).ok_or(Error::NotFound)
+    }
+
+    /// The ids stored during the session are removed from all buckets. This is a linear scan
+    /// over the hash tables.
+    fn rollback_ingest(&mut self) -> Result<()> {
+        let start = self.ingest_start.take().ok_or(Error::NotFound)?;
+        for tbl in self.hash_tables.iter_mut() {
+            tbl.retain(|bucket| {
+                bucket.retain(|&idx| idx < start);
+                !bucket.is_empty()
+            });
+        }
+        self.payloads.retain(|&idx, _| idx < start);
+        self.vec_store.truncate(start.index());
+        self.counter = start;
+        Ok(())
+    }
+
     /// Query the whole bucket
-    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket> {
+    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket<I>> {
         let tbl = &self.hash_tables[hash_table];
         match tbl.get(hash) {
             None => Err(Error::NotFound),
@@ -144,28 +742,68 @@ where
         }
     }
 
-    fn idx_to_datapoint(&self, idx: u32) -> Result<&Vec<N>> {
-        Ok(self.vec_store.get(idx))
+    fn bucket_len(&self, hash: &[K], hash_table: usize) -> Result<usize> {
+        Ok(self.hash_tables[hash_table]
+            .get(hash)
+            .map_or(0, |b| b.len()))
+    }
+
+    fn put_payload(&mut self, idx: I, payload: &[u8]) -> Result<()> {
+        if idx >= self.counter {
+            return Err(Error::NotFound);
+        }
+        self.payloads.insert(idx, payload.into());
+        Ok(())
+    }
+
+    fn payload(&self, idx: I) -> Result<Option<Vec<u8>>> {
+        Ok(self.payloads.get(&idx).map(|p| p.to_vec()))
+    }
+
+    fn remove_payload(&mut self, idx: I) -> Result<()> {
+        self.payloads.remove(&idx);
+        Ok(())
+    }
+
+    fn idx_to_datapoint(&self, idx: I) -> Result<&[N]> {
+        if idx.index() >= self.vec_store.len() {
+            return Err(Error::NotFound);
+        }
+        Ok(self.vec_store.get(idx.index()))
     }
 
     fn increase_storage(&mut self, size: usize) {
-        increase_capacity(size, &mut self.hash_tables);
         self.vec_store.increase_storage(size);
     }
 
+    fn packed_keys(&mut self) -> Result<()> {
+        self.hash_tables.iter_mut().for_each(BucketMap::pack);
+        Ok(())
+    }
+
+    fn flat_storage(&mut self, dim: usize) -> Result<()> {
+        if !self.vec_store.is_empty() {
+            return Err(Error::Failed(
+                "storage layout cannot be changed after storing data points".to_string(),
+            ));
+        }
+        self.vec_store = VecStore::Flat(FlatVecStore::new(dim));
+        Ok(())
+    }
+
     fn describe(&self) -> Result<String> {
         let mut lengths = vec![];
         let mut max_len = 0;
         let mut min_len = 1000000;
-        let mut set: FnvHashSet<i32> = FnvHashSet::default();
+        let mut set: FnvHashSet<u64> = FnvHashSet::default();
         // iterator over hash tables 0..L
         for map in self.hash_tables.iter() {
             // iterator over all hashes
             // zip to truncate at the describe maximum
             for ((k, v), _) in map.iter().zip(0..DESCRIBE_MAX) {
                 let len = v.len();
-                let hash_values: FnvHashSet<i32> =
-                    FnvHashSet::from_iter(k.iter().map(|&k| k.to_i32().unwrap()));
+                let hash_values: FnvHashSet<u64> =
+                    FnvHashSet::from_iter(k.iter().map(|&k| hash_word(k)));
                 set = set.union(&hash_values).copied().collect();
                 lengths.push(len);
                 if len > max_len {
@@ -196,13 +834,116 @@ where
         Ok(out)
     }
 
-    fn get_unique_hash_int(&self) -> FnvHashSet<i32> {
+    fn for_each_bucket(
+        &self,
+        hash_table: usize,
+        f: &mut dyn FnMut(&[I]) -> Result<()>,
+    ) -> Result<()> {
+        let tbl = self
+            .hash_tables
+            .get(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        let mut ids = vec![];
+        for bucket in tbl.values().filter(|b| !b.is_empty()) {
+            ids.clear();
+            ids.extend(bucket);
+            ids.sort_unstable();
+            f(&ids)?;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<I> {
+        if self.n_hash_tables != other.n_hash_tables
+            || self.only_index_storage != other.only_index_storage
+        {
+            return Err(Error::Failed(
+                "cannot merge tables with a different layout".to_string(),
+            ));
+        }
+        if self.ingest_start.is_some() {
+            return Err(Error::Failed(
+                "cannot merge during an ingestion session".to_string(),
+            ));
+        }
+        if !self.only_index_storage
+            && (self.vec_store.len() != self.counter.index()
+                || other.vec_store.len() != other.counter.index())
+        {
+            return Err(Error::Failed(
+                "cannot merge tables with missing data points".to_string(),
+            ));
+        }
+        let offset = self.counter;
+        let shift = |idx: I| I::from_index(idx.index() + offset.index());
+        for (tbl, other_tbl) in self.hash_tables.iter_mut().zip(&other.hash_tables) {
+            for (hash, bucket) in other_tbl.iter() {
+                tbl.bucket_mut(hash.into_owned())
+                    .extend(bucket.iter().map(|&idx| shift(idx)));
+            }
+        }
+        if !self.only_index_storage {
+            for i in 0..other.vec_store.len() {
+                self.vec_store.push(other.vec_store.get(i));
+            }
+        }
+        for (&idx, payload) in &other.payloads {
+            self.payloads.insert(shift(idx), payload.clone());
+        }
+        self.counter = shift(other.counter);
+        Ok(offset)
+    }
+
+    fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        Ok(self
+            .hash_tables
+            .iter()
+            .enumerate()
+            .map(|(i, map)| HashTableStats::from_lengths(i, map.values().map(|b| b.len())))
+            .collect())
+    }
+
+    fn n_entries(&self) -> Result<usize> {
+        Ok(self
+            .hash_tables
+            .iter()
+            .flat_map(|map| map.values())
+            .map(|b| b.len())
+            .sum())
+    }
+
+    /// Counts the keys, the ids, the data points and the payloads. The overhead of the hash maps
+    /// is estimated at one control byte per slot.
+    fn approximate_bytes(&self) -> Result<usize> {
+        let tables: usize = self
+            .hash_tables
+            .iter()
+            .map(BucketMap::approximate_bytes)
+            .sum();
+        let payloads: usize = self
+            .payloads
+            .values()
+            .map(|p| std::mem::size_of::<(I, Box<[u8]>)>() + 1 + p.len())
+            .sum();
+        Ok(tables + self.vec_store.approximate_bytes() + payloads)
+    }
+
+    fn bucket_histogram(&self, hash_table: usize, max_buckets: usize) -> Result<Vec<HistogramBin>> {
+        let map = self
+            .hash_tables
+            .get(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        let lengths = map.values().take(max_buckets).map(|b| b.len());
+        Ok(HashTableStats::from_lengths(hash_table, lengths).histogram)
+    }
+
+    fn get_unique_hash_int(&self) -> FnvHashSet<u64> {
         let mut hash_numbers = FnvHashSet::default();
 
         for ht in &self.hash_tables {
             for ((hash, _), _i) in ht.iter().zip(0..100) {
-                for &v in hash {
-                    hash_numbers.insert(v.to_i32().unwrap());
+                for &v in hash.iter() {
+                    hash_numbers.insert(hash_word(v));
                 }
             }
         }
@@ -210,10 +951,11 @@ where
     }
 }
 
-impl<N, K> std::fmt::Debug for MemoryTable<N, K>
+impl<N, K, I> std::fmt::Debug for MemoryTable<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: Idx,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "hash_tables:\nhash, \t buckets\n")?;
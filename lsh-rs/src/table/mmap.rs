@@ -0,0 +1,653 @@
+#![cfg(feature = "mmap")]
+use crate::constants::{HASH_FLAVOR, MMAP_INITIAL_LEN};
+use crate::data::{hash_word, Integer, MaybeSerde, Numeric};
+use crate::lsh::lsh::check_hash_flavor;
+use crate::prelude::*;
+use crate::stats::{HashTableStats, HistogramBin};
+use crate::table::general::{Bucket, HashTables};
+use fnv::{FnvHashMap as HashMap, FnvHashSet};
+use memmap2::MmapMut;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"LSHM";
+const VERSION: u32 = 2;
+const HEADER_LEN: usize = 64;
+const FLAVOR_OFFSET: usize = 16;
+const FLAVOR_LEN: usize = 16;
+/// Offset of the end of the last record in the header.
+const USED_OFFSET: usize = 32;
+/// tag, hash table, id, number of elements of the payload, crc32 and padding.
+const RECORD_HEADER_LEN: usize = 24;
+/// Offset of the crc32 in a record, which covers the preceding fields and the payload.
+const RECORD_CRC_OFFSET: usize = 16;
+
+/// Kind of a record in the log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    /// Add an id to a bucket. The payload is the hash.
+    Put = 1,
+    /// Remove an id from a bucket. The payload is the hash.
+    Remove = 2,
+    /// A data point. The payload are its values.
+    Vector = 3,
+    /// Remove an id from every bucket.
+    Delete = 4,
+    /// The serialized hashers.
+    Hashers = 5,
+}
+
+impl Tag {
+    fn from_u32(tag: u32) -> Option<Tag> {
+        match tag {
+            1 => Some(Tag::Put),
+            2 => Some(Tag::Remove),
+            3 => Some(Tag::Vector),
+            4 => Some(Tag::Delete),
+            5 => Some(Tag::Hashers),
+            _ => None,
+        }
+    }
+}
+
+fn as_bytes<T>(v: &[T]) -> &[u8] {
+    let data = v.as_ptr() as *const u8;
+    unsafe { std::slice::from_raw_parts(data, std::mem::size_of_val(v)) }
+}
+
+fn read_u32(map: &[u8], pos: usize) -> u32 {
+    u32::from_ne_bytes(map[pos..pos + 4].try_into().unwrap())
+}
+
+fn read_u64(map: &[u8], pos: usize) -> u64 {
+    u64::from_ne_bytes(map[pos..pos + 8].try_into().unwrap())
+}
+
+/// Records start at a multiple of 8 bytes, so the payloads can be read in place.
+fn align(pos: usize) -> usize {
+    (pos + 7) & !7
+}
+
+/// Append-only memory-mapped backend for [LSH](struct.LSH.html).
+///
+/// Every change is appended as a record to a log file, which is memory-mapped. The buckets are
+/// kept in memory and are rebuilt from the log when the file is opened again, the data points
+/// are read in place from the mapped file. Storing is a memory copy and queries run at the
+/// speed of the [MemoryTable](struct.MemoryTable.html), while the index survives restarts
+/// without [dump](struct.LSH.html#method.dump) and [load](struct.LSH.html#method.load).
+///
+/// Use it through [LshMmap](type.LshMmap.html) and set the file with
+/// [set_database_file](struct.LSH.html#method.set_database_file). The hashers are stored in the
+/// file as well, so reopening the file restores the whole index.
+///
+/// Removed ids stay in the log, so the file grows with every change. The log is written in
+/// native endianness and every record carries a crc32, so a record that was torn by a crash is
+/// dropped when the file is opened again, together with the records after it. Changes are
+/// durable after [flush](#method.flush), which is also called when the table is dropped. The
+/// file must not be opened by multiple `MmapTable`s or modified by other processes at the same
+/// time.
+pub struct MmapTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    file: File,
+    map: MmapMut,
+    /// End of the last record.
+    len: usize,
+    hash_tables: Vec<HashMap<Vec<K>, Bucket>>,
+    /// Position and number of values of the data points in the file, by id.
+    vectors: Vec<Option<(usize, usize)>>,
+    /// Position and length of the serialized hashers in the file.
+    hashers: Option<(usize, usize)>,
+    n_hash_tables: usize,
+    only_index_storage: bool,
+    counter: u32,
+    phantom: PhantomData<N>,
+}
+
+impl<N, K> MmapTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    /// Open the log at `path` and rebuild the buckets, or create a new log if the file doesn't
+    /// exist.
+    ///
+    /// # Arguments
+    /// * `n_hash_tables` - Number of hash tables.
+    /// * `only_index_storage` - Don't store the data points.
+    /// * `path` - Path of the log file.
+    pub fn open<P: AsRef<Path>>(
+        n_hash_tables: usize,
+        only_index_storage: bool,
+        path: P,
+    ) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let new = file.metadata()?.len() == 0;
+        if new {
+            file.set_len(MMAP_INITIAL_LEN as u64)?;
+        }
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        if new {
+            map[..4].copy_from_slice(MAGIC);
+            map[4..8].copy_from_slice(&VERSION.to_ne_bytes());
+            map[8..12].copy_from_slice(&(n_hash_tables as u32).to_ne_bytes());
+            map[12] = size_of::<N>() as u8;
+            map[13] = size_of::<K>() as u8;
+            map[FLAVOR_OFFSET..FLAVOR_OFFSET + HASH_FLAVOR.len()]
+                .copy_from_slice(HASH_FLAVOR.as_bytes());
+            map[USED_OFFSET..USED_OFFSET + 8].copy_from_slice(&(HEADER_LEN as u64).to_ne_bytes());
+        }
+        let mut tbl = MmapTable {
+            file,
+            map,
+            len: HEADER_LEN,
+            hash_tables: vec![HashMap::default(); n_hash_tables],
+            vectors: vec![],
+            hashers: None,
+            n_hash_tables,
+            only_index_storage,
+            counter: 0,
+            phantom: PhantomData,
+        };
+        tbl.check_header()?;
+        tbl.replay()?;
+        Ok(tbl)
+    }
+
+    fn check_header(&self) -> Result<()> {
+        let map = &self.map[..];
+        if map.len() < HEADER_LEN || &map[..4] != MAGIC {
+            return Err(Error::CorruptIndex {
+                what: "header",
+                expected: u32::from_le_bytes(*MAGIC) as u64,
+                found: map
+                    .get(..4)
+                    .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as u64),
+            });
+        }
+        let fail = |what: &str, found: usize, expected: usize| {
+            Err(Error::Failed(format!(
+                "log file has {} {}, expected {}",
+                what, found, expected
+            )))
+        };
+        let version = read_u32(map, 4) as usize;
+        if version != VERSION as usize {
+            return fail("version", version, VERSION as usize);
+        }
+        let n_hash_tables = read_u32(map, 8) as usize;
+        if n_hash_tables != self.n_hash_tables {
+            return fail("n_hash_tables", n_hash_tables, self.n_hash_tables);
+        }
+        if map[12] as usize != size_of::<N>() {
+            return fail(
+                "data point values of bytes",
+                map[12] as usize,
+                size_of::<N>(),
+            );
+        }
+        if map[13] as usize != size_of::<K>() {
+            return fail("hash values of bytes", map[13] as usize, size_of::<K>());
+        }
+        let flavor = &map[FLAVOR_OFFSET..FLAVOR_OFFSET + FLAVOR_LEN];
+        let flavor = String::from_utf8_lossy(flavor);
+        check_hash_flavor(Some(flavor.trim_end_matches('\0')))?;
+        let used = read_u64(map, USED_OFFSET);
+        if used > map.len() as u64 {
+            return Err(Error::CorruptIndex {
+                what: "length in bytes",
+                expected: used,
+                found: map.len() as u64,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rebuild the buckets, the positions of the data points and the id counter from the log.
+    /// Replay stops at the first incomplete or damaged record, which is what a crash during an
+    /// append leaves behind, and the log is truncated to the intact records.
+    fn replay(&mut self) -> Result<()> {
+        let used = read_u64(&self.map, USED_OFFSET) as usize;
+        let mut pos = HEADER_LEN;
+        while pos + RECORD_HEADER_LEN <= used {
+            let tag = match Tag::from_u32(read_u32(&self.map, pos)) {
+                Some(tag) => tag,
+                None => break,
+            };
+            let hash_table = read_u32(&self.map, pos + 4) as usize;
+            let idx = read_u32(&self.map, pos + 8);
+            let n = read_u32(&self.map, pos + 12) as usize;
+            let start = pos + RECORD_HEADER_LEN;
+            let end = start + n * Self::elem_size(tag);
+            if end > used || read_u32(&self.map, pos + RECORD_CRC_OFFSET) != self.crc(pos, end) {
+                break;
+            }
+            match tag {
+                Tag::Put => {
+                    let hash = self.slice::<K>(start, n).to_vec();
+                    self.insert_idx(idx, hash, hash_table)?;
+                }
+                Tag::Remove => {
+                    let hash = self.slice::<K>(start, n).to_vec();
+                    self.remove_from_bucket(idx, &hash, hash_table)?;
+                }
+                Tag::Vector => self.set_vector(idx, start, n),
+                Tag::Delete => {
+                    self.remove_from_buckets(idx);
+                }
+                Tag::Hashers => self.hashers = Some((start, n)),
+            }
+            // only the records of data points hold an id.
+            if matches!(tag, Tag::Put | Tag::Vector) {
+                self.counter = self.counter.max(idx + 1);
+            }
+            pos = align(end);
+        }
+        self.len = pos.min(used);
+        self.map[USED_OFFSET..USED_OFFSET + 8].copy_from_slice(&(self.len as u64).to_ne_bytes());
+        Ok(())
+    }
+
+    /// Checksum of the record at `pos` with its payload ending at `end`.
+    fn crc(&self, pos: usize, end: usize) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.map[pos..pos + RECORD_CRC_OFFSET]);
+        hasher.update(&self.map[pos + RECORD_HEADER_LEN..end]);
+        hasher.finalize()
+    }
+
+    fn elem_size(tag: Tag) -> usize {
+        match tag {
+            Tag::Put | Tag::Remove => size_of::<K>(),
+            Tag::Vector => size_of::<N>(),
+            Tag::Delete | Tag::Hashers => 1,
+        }
+    }
+
+    /// `n` values of type `T` at `pos` of the mapped file.
+    fn slice<T>(&self, pos: usize, n: usize) -> &[T] {
+        let bytes = &self.map[pos..pos + n * size_of::<T>()];
+        // records are aligned to 8 bytes and the mapping to a page.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, n) }
+    }
+
+    /// Append a record and return the position of its payload.
+    fn append(&mut self, tag: Tag, hash_table: usize, idx: u32, payload: &[u8]) -> Result<usize> {
+        let start = self.len + RECORD_HEADER_LEN;
+        let end = align(start + payload.len());
+        self.reserve(end)?;
+        let n = payload.len() / Self::elem_size(tag);
+        let pos = self.len;
+        for (i, v) in [tag as u32, hash_table as u32, idx, n as u32]
+            .iter()
+            .enumerate()
+        {
+            self.map[pos + 4 * i..pos + 4 * i + 4].copy_from_slice(&v.to_ne_bytes());
+        }
+        self.map[start..start + payload.len()].copy_from_slice(payload);
+        let crc = self.crc(pos, start + payload.len());
+        self.map[pos + RECORD_CRC_OFFSET..pos + RECORD_CRC_OFFSET + 4]
+            .copy_from_slice(&crc.to_ne_bytes());
+        // the record is only part of the log once the header points past it.
+        self.len = end;
+        self.map[USED_OFFSET..USED_OFFSET + 8].copy_from_slice(&(end as u64).to_ne_bytes());
+        Ok(start)
+    }
+
+    /// Grow the file and the mapping to at least `len` bytes.
+    fn reserve(&mut self, len: usize) -> Result<()> {
+        if len <= self.map.len() {
+            return Ok(());
+        }
+        let new_len = len.max(2 * self.map.len());
+        self.map.flush()?;
+        self.file.set_len(new_len as u64)?;
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+
+    fn insert_idx(&mut self, idx: u32, hash: Vec<K>, hash_table: usize) -> Result<()> {
+        let tbl = self
+            .hash_tables
+            .get_mut(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        tbl.entry(hash).or_default().insert(idx);
+        Ok(())
+    }
+
+    fn remove_from_bucket(&mut self, idx: u32, hash: &[K], hash_table: usize) -> Result<()> {
+        let tbl = self
+            .hash_tables
+            .get_mut(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        let bucket = tbl.get_mut(hash).ok_or(Error::NotFound)?;
+        if !bucket.remove(&idx) {
+            return Err(Error::NotFound);
+        }
+        if bucket.is_empty() {
+            tbl.remove(hash);
+        }
+        Ok(())
+    }
+
+    /// Remove `idx` from every bucket. Returns if it was found.
+    fn remove_from_buckets(&mut self, idx: u32) -> bool {
+        let mut found = false;
+        for tbl in &mut self.hash_tables {
+            tbl.retain(|_, bucket| {
+                found |= bucket.remove(&idx);
+                !bucket.is_empty()
+            });
+        }
+        found
+    }
+
+    fn set_vector(&mut self, idx: u32, pos: usize, n: usize) {
+        let idx = idx as usize;
+        if self.vectors.len() <= idx {
+            self.vectors.resize(idx + 1, None);
+        }
+        self.vectors[idx] = Some((pos, n));
+    }
+
+    /// Write the changes to the file.
+    pub fn flush(&self) -> Result<()> {
+        self.map.flush()?;
+        Ok(())
+    }
+
+    /// Size of the log in bytes.
+    pub fn log_len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<N, K> Drop for MmapTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    fn drop(&mut self) {
+        let _ = self.map.flush();
+    }
+}
+
+impl<N, K> HashTables<N, K> for MmapTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    /// The records of the log hold `u32` ids.
+    type Id = u32;
+
+    fn new(n_hash_tables: usize, only_index_storage: bool, db_path: &str) -> Result<Box<Self>> {
+        MmapTable::open(n_hash_tables, only_index_storage, db_path).map(Box::new)
+    }
+
+    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<u32> {
+        // Same protocol as the MemoryTable, the id is incremented after the last hash table.
+        let idx = self.counter;
+        if hash_table >= self.n_hash_tables {
+            return Err(Error::TableNotExist);
+        }
+        self.append(Tag::Put, hash_table, idx, as_bytes(&hash))?;
+        self.insert_idx(idx, hash, hash_table)?;
+
+        if (hash_table == 0) && (!self.only_index_storage) {
+            let pos = self.append(Tag::Vector, 0, idx, as_bytes(d))?;
+            self.set_vector(idx, pos, d.len());
+        }
+        if hash_table == self.n_hash_tables - 1 {
+            self.counter += 1
+        }
+        Ok(idx)
+    }
+
+    fn delete(&mut self, hash: &[K], d: &[N], hash_table: usize) -> Result<()> {
+        // The data points are scanned to find the id.
+        let idx =
+            (0..self.vectors.len() as u32).find(|&idx| self.idx_to_datapoint(idx).ok() == Some(d));
+        match idx {
+            None => Ok(()),
+            Some(idx) => self.remove_idx(idx, hash, hash_table),
+        }
+    }
+
+    fn remove_idx(&mut self, idx: u32, hash: &[K], hash_table: usize) -> Result<()> {
+        self.remove_from_bucket(idx, hash, hash_table)?;
+        self.append(Tag::Remove, hash_table, idx, as_bytes(hash))?;
+        Ok(())
+    }
+
+    fn delete_by_idx(&mut self, idx: u32) -> Result<()> {
+        if !self.remove_from_buckets(idx) {
+            return Err(Error::NotFound);
+        }
+        self.append(Tag::Delete, 0, idx, &[])?;
+        Ok(())
+    }
+
+    fn insert_ids(&mut self, entries: &[(Vec<K>, u32)], hash_table: usize) -> Result<()> {
+        for (hash, idx) in entries {
+            self.insert_idx(*idx, hash.clone(), hash_table)?;
+            self.append(Tag::Put, hash_table, *idx, as_bytes(hash))?;
+            self.counter = self.counter.max(idx + 1);
+        }
+        Ok(())
+    }
+
+    fn update_by_idx(
+        &mut self,
+        old_hash: &[K],
+        new_hash: Vec<K>,
+        idx: u32,
+        hash_table: usize,
+    ) -> Result<()> {
+        self.remove_idx(idx, old_hash, hash_table)?;
+        self.append(Tag::Put, hash_table, idx, as_bytes(&new_hash))?;
+        self.insert_idx(idx, new_hash, hash_table)
+    }
+
+    /// Writes the chunk to the file, without waiting for the write to complete.
+    fn end_chunk(&mut self) -> Result<()> {
+        self.map.flush_async()?;
+        Ok(())
+    }
+
+    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket> {
+        let tbl = self
+            .hash_tables
+            .get(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        match tbl.get(hash) {
+            None => Err(Error::NotFound),
+            Some(bucket) => Ok(bucket.clone()),
+        }
+    }
+
+    fn bucket_len(&self, hash: &[K], hash_table: usize) -> Result<usize> {
+        let tbl = self
+            .hash_tables
+            .get(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        Ok(tbl.get(hash).map_or(0, |b| b.len()))
+    }
+
+    fn idx_to_datapoint(&self, idx: u32) -> Result<&[N]> {
+        match self.vectors.get(idx as usize) {
+            Some(&Some((pos, n))) => Ok(self.slice(pos, n)),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn increase_storage(&mut self, size: usize) {
+        self.vectors.reserve(size)
+    }
+
+    fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        Ok(self
+            .hash_tables
+            .iter()
+            .enumerate()
+            .map(|(i, map)| HashTableStats::from_lengths(i, map.values().map(|b| b.len())))
+            .collect())
+    }
+
+    /// Size of the log file.
+    fn approximate_bytes(&self) -> Result<usize> {
+        Ok(self.len)
+    }
+
+    fn bucket_histogram(&self, hash_table: usize, max_buckets: usize) -> Result<Vec<HistogramBin>> {
+        let map = self
+            .hash_tables
+            .get(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        let lengths = map.values().take(max_buckets).map(|b| b.len());
+        Ok(HashTableStats::from_lengths(hash_table, lengths).histogram)
+    }
+
+    fn for_each_bucket(
+        &self,
+        hash_table: usize,
+        f: &mut dyn FnMut(&[u32]) -> Result<()>,
+    ) -> Result<()> {
+        let tbl = self
+            .hash_tables
+            .get(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        let mut ids = vec![];
+        for bucket in tbl.values().filter(|b| !b.is_empty()) {
+            ids.clear();
+            ids.extend(bucket);
+            ids.sort_unstable();
+            f(&ids)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the log already holds hashers.
+    fn store_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, hashers: &[H]) -> Result<()> {
+        if self.hashers.is_some() {
+            return Err(Error::Failed("the hashers are already stored".to_string()));
+        }
+        let buf = bincode::serialize(hashers)?;
+        let pos = self.append(Tag::Hashers, 0, 0, &buf)?;
+        self.hashers = Some((pos, buf.len()));
+        Ok(())
+    }
+
+    /// The new hashers are appended, the last hashers of the log are loaded.
+    fn update_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, hashers: &[H]) -> Result<()> {
+        let buf = bincode::serialize(hashers)?;
+        let pos = self.append(Tag::Hashers, 0, 0, &buf)?;
+        self.hashers = Some((pos, buf.len()));
+        Ok(())
+    }
+
+    fn load_hashers<H: VecHash<N, K> + MaybeSerde>(&self) -> Result<Vec<H>> {
+        let (pos, n) = self.hashers.ok_or(Error::NotFound)?;
+        Ok(bincode::deserialize(self.slice::<u8>(pos, n))?)
+    }
+
+    fn get_unique_hash_int(&self) -> FnvHashSet<u64> {
+        let mut hash_numbers = FnvHashSet::default();
+
+        for ht in &self.hash_tables {
+            for ((hash, _), _i) in ht.iter().zip(0..100) {
+                for &v in hash {
+                    hash_numbers.insert(hash_word(v));
+                }
+            }
+        }
+        hash_numbers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mmap_reopen() {
+        let p = std::env::temp_dir().join("lsh_reopen.mmap");
+        let p = p.as_path();
+        let _ = std::fs::remove_file(p);
+        {
+            let mut tbl = MmapTable::<f32, i8>::open(2, false, p).unwrap();
+            for ht in 0..2 {
+                tbl.put(vec![1, 2], &[1., 2.], ht).unwrap();
+            }
+            for ht in 0..2 {
+                tbl.put(vec![1, 2], &[3., 4.], ht).unwrap();
+            }
+            tbl.remove_idx(0, &[1, 2], 1).unwrap();
+            tbl.update_by_idx(&[1, 2], vec![5, 6], 1, 0).unwrap();
+            assert!(tbl.remove_idx(0, &[1, 2], 1).is_err());
+        }
+        let mut tbl = MmapTable::<f32, i8>::open(2, false, p).unwrap();
+        assert_eq!(tbl.query_bucket(&[1, 2], 0).unwrap().len(), 1);
+        assert!(tbl.query_bucket(&[5, 6], 0).unwrap().contains(&1));
+        assert_eq!(tbl.query_bucket(&[1, 2], 1).unwrap().len(), 1);
+        assert_eq!(tbl.idx_to_datapoint(1).unwrap(), &[3., 4.]);
+        // ids continue after the stored ones.
+        assert_eq!(tbl.put(vec![1, 2], &[5., 6.], 0).unwrap(), 2);
+
+        // the layout of the log has to match.
+        assert!(MmapTable::<f32, i8>::open(3, false, p).is_err());
+        assert!(MmapTable::<f64, i8>::open(2, false, p).is_err());
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_torn_record() {
+        let p = std::env::temp_dir().join("lsh_torn.mmap");
+        let p = p.as_path();
+        let _ = std::fs::remove_file(p);
+        let torn = {
+            let mut tbl = MmapTable::<f32, i8>::open(1, false, p).unwrap();
+            tbl.put(vec![1, 2], &[1., 2.], 0).unwrap();
+            let torn = tbl.log_len();
+            tbl.put(vec![1, 2], &[3., 4.], 0).unwrap();
+            // damage the payload of the second put.
+            tbl.map[torn + RECORD_HEADER_LEN] ^= 1;
+            torn
+        };
+        let mut tbl = MmapTable::<f32, i8>::open(1, false, p).unwrap();
+        // the damaged record and the records after it are dropped.
+        assert_eq!(tbl.log_len(), torn);
+        assert_eq!(tbl.query_bucket(&[1, 2], 0).unwrap().len(), 1);
+        assert!(tbl.idx_to_datapoint(1).is_err());
+        assert_eq!(tbl.put(vec![1, 2], &[5., 6.], 0).unwrap(), 1);
+        drop(tbl);
+        let tbl = MmapTable::<f32, i8>::open(1, false, p).unwrap();
+        assert_eq!(tbl.idx_to_datapoint(1).unwrap(), &[5., 6.]);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_grow() {
+        let p = std::env::temp_dir().join("lsh_grow.mmap");
+        let p = p.as_path();
+        let _ = std::fs::remove_file(p);
+        let v = vec![1.; 128];
+        let mut tbl = MmapTable::<f32, i32>::open(1, false, p).unwrap();
+        for i in 0..4000 {
+            tbl.put(vec![i % 10], &v, 0).unwrap();
+        }
+        assert!(tbl.log_len() > MMAP_INITIAL_LEN);
+        assert_eq!(tbl.idx_to_datapoint(3999).unwrap(), &v[..]);
+        assert_eq!(tbl.query_bucket(&[3], 0).unwrap().len(), 400);
+        std::fs::remove_file(p).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use crate::data::{hash_word, Integer};
+use crate::{
+    constants::N_SHARDS,
+    data::Numeric,
+    prelude::*,
+    stats::{HashTableStats, HistogramBin},
+    table::general::{Bucket, HashTables},
+};
+use fnv::{FnvHashMap as HashMap, FnvHashSet, FnvHasher};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+type Shard<K> = RwLock<HashMap<Vec<K>, Bucket>>;
+
+/// In memory backend that can be written and queried concurrently. Every hash table is split in
+/// shards with their own lock, so a writer only blocks the readers of the shards it writes to.
+///
+/// Use it through [LshShared](type.LshShared.html), which adds `&self` variants of the write
+/// methods, e.g. [store_vec_shared](struct.LSH.html#method.store_vec_shared). A data point that
+/// is being stored can be found in some hash tables before it is found in all of them.
+pub struct ShardedMemoryTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    /// `N_SHARDS` shards per hash table.
+    shards: Vec<Vec<Shard<K>>>,
+    n_hash_tables: usize,
+    vec_store: RwLock<VecStore<N>>,
+    only_index_storage: bool,
+    counter: AtomicU32,
+}
+
+// A poisoned lock only means that another thread panicked while holding it. The buckets are
+// updated with single inserts and removes, so they are still consistent.
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Index of the shard that holds the bucket of `hash`.
+fn shard_idx<K: Integer>(hash: &[K]) -> usize {
+    let mut h = FnvHasher::default();
+    hash.hash(&mut h);
+    h.finish() as usize % N_SHARDS
+}
+
+impl<N, K> ShardedMemoryTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    fn shard(&self, hash: &[K], hash_table: usize) -> Result<&Shard<K>> {
+        let tbl = self.shards.get(hash_table).ok_or(Error::TableNotExist)?;
+        Ok(&tbl[shard_idx(hash)])
+    }
+
+    /// Store a data point with its hash for every hash table and return its id.
+    pub(crate) fn put_shared(&self, hashes: Vec<Vec<K>>, d: &[N]) -> Result<u32> {
+        if hashes.len() != self.n_hash_tables {
+            return Err(Error::TableNotExist);
+        }
+        let idx = {
+            // the id is assigned while holding the lock, so it matches the data point.
+            let mut vec_store = write(&self.vec_store);
+            let idx = self.counter.fetch_add(1, Ordering::SeqCst);
+            if !self.only_index_storage {
+                vec_store.push(d);
+            }
+            idx
+        };
+        for (i, hash) in hashes.into_iter().enumerate() {
+            let shard = self.shard(&hash, i)?;
+            write(shard).entry(hash).or_default().insert(idx);
+        }
+        Ok(idx)
+    }
+
+    pub(crate) fn remove_idx_shared(&self, idx: u32, hash: &[K], hash_table: usize) -> Result<()> {
+        let shard = self.shard(hash, hash_table)?;
+        let mut map = write(shard);
+        let bucket = map.get_mut(hash).ok_or(Error::NotFound)?;
+        if !bucket.remove(&idx) {
+            return Err(Error::NotFound);
+        }
+        if bucket.is_empty() {
+            map.remove(hash);
+        }
+        Ok(())
+    }
+
+    /// Scans the buckets of every hash table.
+    pub(crate) fn delete_by_idx_shared(&self, idx: u32) -> Result<()> {
+        let mut found = false;
+        for shard in self.shards.iter().flatten() {
+            let mut map = write(shard);
+            map.retain(|_, bucket| {
+                found |= bucket.remove(&idx);
+                !bucket.is_empty()
+            });
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    /// Lengths of the buckets of a hash table.
+    fn bucket_lengths(&self, hash_table: usize) -> Result<Vec<usize>> {
+        let tbl = self.shards.get(hash_table).ok_or(Error::TableNotExist)?;
+        Ok(tbl
+            .iter()
+            .flat_map(|shard| read(shard).values().map(|b| b.len()).collect::<Vec<_>>())
+            .collect())
+    }
+}
+
+impl<N, K> HashTables<N, K> for ShardedMemoryTable<N, K>
+where
+    N: Numeric,
+    K: Integer,
+{
+    /// The ids are assigned by an atomic counter.
+    type Id = u32;
+
+    fn new(n_hash_tables: usize, only_index_storage: bool, _: &str) -> Result<Box<Self>> {
+        let shards = (0..n_hash_tables)
+            .map(|_| (0..N_SHARDS).map(|_| RwLock::default()).collect())
+            .collect();
+        Ok(Box::new(ShardedMemoryTable {
+            shards,
+            n_hash_tables,
+            vec_store: RwLock::new(VecStore::Nested(vec![])),
+            only_index_storage,
+            counter: AtomicU32::new(0),
+        }))
+    }
+
+    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<u32> {
+        // Same protocol as the MemoryTable, the id is incremented after the last hash table.
+        let idx = *self.counter.get_mut();
+        let tbl = self
+            .shards
+            .get_mut(hash_table)
+            .ok_or(Error::TableNotExist)?;
+        tbl[shard_idx(&hash)]
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(hash)
+            .or_default()
+            .insert(idx);
+
+        if (hash_table == 0) && (!self.only_index_storage) {
+            self.vec_store
+                .get_mut()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(d);
+        }
+        if hash_table == self.n_hash_tables - 1 {
+            *self.counter.get_mut() += 1
+        }
+        Ok(idx)
+    }
+
+    fn remove_idx(&mut self, idx: u32, hash: &[K], hash_table: usize) -> Result<()> {
+        self.remove_idx_shared(idx, hash, hash_table)
+    }
+
+    fn delete_by_idx(&mut self, idx: u32) -> Result<()> {
+        self.delete_by_idx_shared(idx)
+    }
+
+    fn insert_ids(&mut self, entries: &[(Vec<K>, u32)], hash_table: usize) -> Result<()> {
+        for (hash, idx) in entries {
+            let shard = self.shard(hash, hash_table)?;
+            write(shard).entry(hash.clone()).or_default().insert(*idx);
+            self.counter.fetch_max(idx + 1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket> {
+        let shard = self.shard(hash, hash_table)?;
+        match read(shard).get(hash) {
+            None => Err(Error::NotFound),
+            Some(bucket) => Ok(bucket.clone()),
+        }
+    }
+
+    fn bucket_len(&self, hash: &[K], hash_table: usize) -> Result<usize> {
+        let shard = self.shard(hash, hash_table)?;
+        Ok(read(shard).get(hash).map_or(0, |b| b.len()))
+    }
+
+    fn with_datapoint<R, F: FnOnce(&[N]) -> R>(&self, idx: u32, f: F) -> Result<R> {
+        let vec_store = read(&self.vec_store);
+        if idx as usize >= vec_store.len() {
+            return Err(Error::NotFound);
+        }
+        Ok(f(vec_store.get(idx as usize)))
+    }
+
+    fn increase_storage(&mut self, size: usize) {
+        self.vec_store
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .increase_storage(size);
+    }
+
+    fn flat_storage(&mut self, dim: usize) -> Result<()> {
+        let vec_store = self.vec_store.get_mut().unwrap_or_else(|e| e.into_inner());
+        if !vec_store.is_empty() {
+            return Err(Error::Failed(
+                "storage layout cannot be changed after storing data points".to_string(),
+            ));
+        }
+        *vec_store = VecStore::Flat(FlatVecStore::new(dim));
+        Ok(())
+    }
+
+    fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        (0..self.n_hash_tables)
+            .map(|i| {
+                let lengths = self.bucket_lengths(i)?;
+                Ok(HashTableStats::from_lengths(i, lengths.into_iter()))
+            })
+            .collect()
+    }
+
+    fn bucket_histogram(&self, hash_table: usize, max_buckets: usize) -> Result<Vec<HistogramBin>> {
+        let lengths = self.bucket_lengths(hash_table)?;
+        let lengths = lengths.into_iter().take(max_buckets);
+        Ok(HashTableStats::from_lengths(hash_table, lengths).histogram)
+    }
+
+    fn for_each_bucket(
+        &self,
+        hash_table: usize,
+        f: &mut dyn FnMut(&[u32]) -> Result<()>,
+    ) -> Result<()> {
+        let tbl = self.shards.get(hash_table).ok_or(Error::TableNotExist)?;
+        let mut ids = vec![];
+        for shard in tbl {
+            for bucket in read(shard).values().filter(|b| !b.is_empty()) {
+                ids.clear();
+                ids.extend(bucket);
+                ids.sort_unstable();
+                f(&ids)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_unique_hash_int(&self) -> FnvHashSet<u64> {
+        let mut hash_numbers = FnvHashSet::default();
+        for tbl in &self.shards {
+            // the hashes of the first 100 buckets of every hash table.
+            let mut n = 0;
+            for shard in tbl {
+                for (hash, _) in read(shard).iter().take(100 - n) {
+                    n += 1;
+                    for &v in hash {
+                        hash_numbers.insert(hash_word(v));
+                    }
+                }
+                if n == 100 {
+                    break;
+                }
+            }
+        }
+        hash_numbers
+    }
+}
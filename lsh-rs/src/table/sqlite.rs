@@ -1,14 +1,22 @@
 #![cfg(feature = "sqlite")]
 use super::general::Bucket;
-use crate::constants::DESCRIBE_MAX;
-use crate::data::{Integer, Numeric};
+use crate::constants::{AUTO_INDEX_THRESHOLD, DESCRIBE_MAX, HASH_FLAVOR, SQL_MAX_VARIABLES};
+use crate::data::{hash_word, Idx, Integer, MaybeSerde, Numeric};
+use crate::dist::Metric;
+use crate::lsh::lsh::check_hash_flavor;
 use crate::prelude::*;
-use fnv::FnvHashSet;
-use rusqlite::{params, Connection};
-use serde::de::DeserializeOwned;
-use std::marker::PhantomData;
-use serde::Serialize;
+use crate::stats::{HashTableStats, HistogramBin};
+use fnv::{FnvHashMap, FnvHashSet};
+use rusqlite::types::FromSql;
+use rusqlite::{
+    functions::FunctionFlags, params, params_from_iter, Connection, OpenFlags, OptionalExtension,
+    ToSql,
+};
 use std::cell::Cell;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::Duration;
 
 fn vec_to_blob<T>(hash: &[T]) -> &[u8] {
     let data = hash.as_ptr() as *const u8;
@@ -20,7 +28,16 @@ fn blob_to_vec<T>(blob: &[u8]) -> &[T] {
     unsafe { std::slice::from_raw_parts(data, blob.len() / std::mem::size_of::<T>()) }
 }
 
-fn query_bucket(blob: &[u8], table_name: &str, connection: &Connection) -> Result<Bucket> {
+/// Ids that can be stored in SQLite, i.e. `u32` and `u64`. Ids above `i64::MAX` can't be
+/// stored.
+pub trait SqlIdx: Idx + ToSql + FromSql {}
+impl<I: Idx + ToSql + FromSql> SqlIdx for I {}
+
+fn query_bucket<I: SqlIdx>(
+    blob: &[u8],
+    table_name: &str,
+    connection: &Connection,
+) -> Result<Bucket<I>> {
     let mut stmt = connection.prepare_cached(&format!(
         "
 SELECT (id) FROM {}
@@ -37,6 +54,55 @@ WHERE hash = ?
     Ok(bucket)
 }
 
+/// Count the rows of a bucket, without fetching the ids.
+fn count_bucket(blob: &[u8], table_name: &str, connection: &Connection) -> Result<usize> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+SELECT COUNT(*) FROM {}
+WHERE hash = ?
+        ",
+        table_name
+    ))?;
+    let n: i64 = stmt.query_row(params![blob], |row| row.get(0))?;
+    Ok(n as usize)
+}
+
+/// Query the buckets of multiple hashes with `WHERE hash IN (...)` statements, instead of a
+/// statement per hash.
+fn query_buckets<I: SqlIdx>(
+    blobs: &[&[u8]],
+    table_name: &str,
+    connection: &Connection,
+) -> Result<Vec<Bucket<I>>> {
+    // a hash may be probed more than once.
+    let mut positions: FnvHashMap<&[u8], Vec<usize>> = FnvHashMap::default();
+    for (i, &blob) in blobs.iter().enumerate() {
+        positions.entry(blob).or_default().push(i);
+    }
+    let unique: Vec<&[u8]> = positions.keys().copied().collect();
+
+    let mut buckets = vec![Bucket::default(); blobs.len()];
+    for chunk in unique.chunks(SQL_MAX_VARIABLES) {
+        let mut stmt = connection.prepare_cached(&format!(
+            "
+SELECT hash, id FROM {}
+WHERE hash IN ({})
+            ",
+            table_name,
+            vec!["?"; chunk.len()].join(", ")
+        ))?;
+        let mut rows = stmt.query(params_from_iter(chunk))?;
+        while let Some(row) = rows.next()? {
+            let hash: Vec<u8> = row.get(0)?;
+            let id: I = row.get(1)?;
+            for &i in &positions[&hash[..]] {
+                buckets[i].insert(id);
+            }
+        }
+    }
+    Ok(buckets)
+}
+
 fn make_table(table_name: &str, connection: &Connection) -> Result<()> {
     connection.execute_batch(&format!(
         "CREATE TABLE IF NOT EXISTS {} (
@@ -49,10 +115,10 @@ fn make_table(table_name: &str, connection: &Connection) -> Result<()> {
     Ok(())
 }
 
-fn insert_table<K>(
+fn insert_table<K, I: SqlIdx>(
     table_name: &str,
     hash: &Vec<K>,
-    idx: u32,
+    idx: I,
     connection: &Connection,
 ) -> Result<usize> {
     let blob = vec_to_blob(hash);
@@ -67,6 +133,41 @@ VALUES (?1, ?2)
     Ok(idx)
 }
 
+fn delete_from_table<K, I: SqlIdx>(
+    table_name: &str,
+    hash: &[K],
+    idx: I,
+    connection: &Connection,
+) -> Result<usize> {
+    let blob = vec_to_blob(hash);
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+DELETE FROM {}
+WHERE hash = ?1 AND id = ?2
+        ",
+        table_name
+    ))?;
+    let n = stmt.execute(params![blob, idx])?;
+    Ok(n)
+}
+
+fn contains_row<K, I: SqlIdx>(
+    table_name: &str,
+    hash: &[K],
+    idx: I,
+    connection: &Connection,
+) -> Result<bool> {
+    let blob = vec_to_blob(hash);
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+SELECT EXISTS (SELECT 1 FROM {} WHERE hash = ?1 AND id = ?2)
+        ",
+        table_name
+    ))?;
+    let exists: bool = stmt.query_row(params![blob, idx], |row| row.get(0))?;
+    Ok(exists)
+}
+
 fn hash_table_stats(
     table_name: &str,
     limit: u32,
@@ -100,21 +201,150 @@ FROM (
     Ok(out)
 }
 
+/// Bucket length histogram over at most `limit` buckets. A negative `limit` means no limit.
+fn bucket_histogram(table_name: &str, limit: i64, conn: &Connection) -> Result<Vec<HistogramBin>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "
+SELECT c, count(*)
+FROM (
+	SELECT count(id) as c
+	FROM {}
+	GROUP BY hash
+	LIMIT ?
+)
+GROUP BY c
+ORDER BY c;
+    ",
+        table_name
+    ))?;
+    let histogram = stmt
+        .query_map(params![limit], |row| {
+            let bucket_len: i64 = row.get(0)?;
+            let n_buckets: i64 = row.get(1)?;
+            Ok(HistogramBin {
+                bucket_len: bucket_len as usize,
+                n_buckets: n_buckets as usize,
+            })
+        })?
+        .collect::<std::result::Result<_, _>>()?;
+    Ok(histogram)
+}
+
+/// Handling of a database that is locked by another connection, e.g. another process that
+/// writes to it. See [set_busy_policy](struct.SqlTable.html#method.set_busy_policy).
+///
+/// A statement waits up to `timeout` for the lock. Queries and commits that still find the
+/// database locked are retried `retries` times, waiting `backoff` before the first retry and
+/// twice as long before every next one. After that they fail with `Error::Busy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusyPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for BusyPolicy {
+    /// The 5 second timeout of SQLite connections, with 2 retries.
+    fn default() -> Self {
+        BusyPolicy {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Fragmentation of a SQLite index, see
+/// [fragmentation_report](struct.SqlTable.html#method.fragmentation_report).
+#[derive(Debug, Clone)]
+pub struct FragmentationReport {
+    pub page_size: u64,
+    pub page_count: u64,
+    /// Unused pages in the database file. These are reclaimed by a vacuum.
+    pub freelist_count: u64,
+    /// Rows per hash table.
+    pub rows: Vec<u64>,
+    /// Deleted rows per hash table. Estimated from the gaps in the row ids, rows deleted from
+    /// the end of a table are not counted.
+    pub deleted_rows: Vec<u64>,
+}
+
+impl FragmentationReport {
+    /// Fraction of the pages that is unused.
+    pub fn free_ratio(&self) -> f64 {
+        if self.page_count == 0 {
+            0.
+        } else {
+            self.freelist_count as f64 / self.page_count as f64
+        }
+    }
+
+    /// Fraction of the inserted rows that is deleted, over all hash tables.
+    pub fn deleted_ratio(&self) -> f64 {
+        let deleted: u64 = self.deleted_rows.iter().sum();
+        let total = deleted + self.rows.iter().sum::<u64>();
+        if total == 0 {
+            0.
+        } else {
+            deleted as f64 / total as f64
+        }
+    }
+}
+
+impl std::fmt::Display for FragmentationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "pages:\t{} of {} bytes, {} free ({:.1}%)",
+            self.page_count,
+            self.page_size,
+            self.freelist_count,
+            self.free_ratio() * 100.
+        )?;
+        writeln!(f, "rows:\t{:?}", self.rows)?;
+        write!(
+            f,
+            "deleted rows:\t{:?} ({:.1}%)",
+            self.deleted_rows,
+            self.deleted_ratio() * 100.
+        )
+    }
+}
+
 /// Sqlite backend for [LSH](struct.LSH.html).
 ///
 /// State will be save during sessions. The database is automatically
 /// loaded if [LSH](struct.LSH.html) can find the database file (defaults to `./lsh.db3`.
-pub struct SqlTable<N, K>
+///
+/// `SqlTable` is `Send` but not `Sync`; the connection can be moved to another thread, but
+/// must not be used from multiple threads at the same time.
+///
+/// The ids are `u32` by default, set `I` to `u64` to store more data points.
+pub struct SqlTable<N, K, I = u32>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
     n_hash_tables: usize,
     only_index_storage: bool, // for now only supported
-    counter: u32,
+    counter: I,
     pub conn: Connection,
     table_names: Vec<String>,
     pub committed: Cell<bool>,
+    /// Create the hash index on commit if the tables exceed this number of rows.
+    auto_index: Option<u32>,
+    indexed: Cell<bool>,
+    read_only: bool,
+    /// First id of the running ingestion session.
+    ingest_start: Option<I>,
+    /// Store the data points for re-ranking in SQL, see
+    /// [enable_sql_distance](#method.enable_sql_distance).
+    sql_distance: bool,
+    busy_policy: BusyPolicy,
+    /// Read-only connections of the parallel queries, kept open for the next queries. See
+    /// [query_bucket_ids_batch_par_sql](struct.LSH.html#method.query_bucket_ids_batch_par_sql).
+    readers: Mutex<Vec<SqlTable<N, K, I>>>,
     phantom: PhantomData<(N, K)>,
 }
 
@@ -122,6 +352,27 @@ fn fmt_table_name(hash_table: usize) -> String {
     format!("hash_table_{}", hash_table)
 }
 
+fn has_table(conn: &Connection, name: &str) -> Result<bool> {
+    Ok(conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [name],
+        |row| row.get(0),
+    )?)
+}
+
+/// Read a value of the meta table. Databases from before the summation was recorded have no
+/// meta table.
+pub(crate) fn read_meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    if !has_table(conn, "meta")? {
+        return Ok(None);
+    }
+    Ok(conn
+        .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
 fn get_table_names(n_hash_tables: usize) -> Vec<String> {
     let mut table_names = Vec::with_capacity(n_hash_tables);
     for idx in 0..n_hash_tables {
@@ -131,7 +382,10 @@ fn get_table_names(n_hash_tables: usize) -> Vec<String> {
     table_names
 }
 
-fn get_unique_hash_int(n_hash_tables: usize, conn: &Connection) -> Result<FnvHashSet<i32>> {
+fn get_unique_hash_int<K: Integer>(
+    n_hash_tables: usize,
+    conn: &Connection,
+) -> Result<FnvHashSet<u64>> {
     let mut hash_numbers = FnvHashSet::default();
     for table_name in get_table_names(n_hash_tables) {
         let mut stmt = conn.prepare(&format!["SELECT hash FROM {} LIMIT 100;", table_name])?;
@@ -139,15 +393,72 @@ fn get_unique_hash_int(n_hash_tables: usize, conn: &Connection) -> Result<FnvHas
 
         while let Some(r) = rows.next()? {
             let blob: Vec<u8> = r.get(0)?;
-            let hash = blob_to_vec(&blob);
+            let hash = blob_to_vec_unaligned::<K>(&blob);
             hash.iter().for_each(|&v| {
-                hash_numbers.insert(v);
+                hash_numbers.insert(hash_word(v));
             })
         }
     }
     Ok(hash_numbers)
 }
 
+/// Decode a blob written by `vec_to_blob`. SQLite doesn't guarantee the alignment of a blob, so
+/// the values are read unaligned.
+fn blob_to_vec_unaligned<T: Copy>(blob: &[u8]) -> Vec<T> {
+    blob.chunks_exact(std::mem::size_of::<T>())
+        .map(|c| unsafe { std::ptr::read_unaligned(c.as_ptr() as *const T) })
+        .collect()
+}
+
+/// Distance functions that can be registered on a SQLite connection, see
+/// [enable_sql_distance](struct.SqlTable.html#method.enable_sql_distance).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqlDistance {
+    /// Euclidean distance, registered as `lsh_l2(a, b)`.
+    L2,
+    /// Cosine distance (`1 - cosine similarity`), registered as `lsh_cosine(a, b)`.
+    Cosine,
+}
+
+impl SqlDistance {
+    fn fn_name(self) -> &'static str {
+        match self {
+            SqlDistance::L2 => "lsh_l2",
+            SqlDistance::Cosine => "lsh_cosine",
+        }
+    }
+
+    fn metric(self) -> Metric {
+        match self {
+            SqlDistance::L2 => Metric::L2,
+            SqlDistance::Cosine => Metric::Cosine,
+        }
+    }
+
+    /// Register the distance as scalar function on `conn`. The arguments are vector blobs of `N`.
+    fn register<N: Numeric + 'static>(self, conn: &Connection) -> Result<()> {
+        conn.create_scalar_function(
+            self.fn_name(),
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            move |ctx| {
+                let mut args = [vec![], vec![]];
+                for (i, arg) in args.iter_mut().enumerate() {
+                    let blob = ctx
+                        .get_raw(i)
+                        .as_blob()
+                        .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+                    *arg = blob_to_vec_unaligned::<N>(blob);
+                }
+                self.metric()
+                    .distance(&args[0], &args[1])
+                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))
+            },
+        )?;
+        Ok(())
+    }
+}
+
 fn init_table(conn: &Connection, table_names: &[String]) -> Result<()> {
     for table_name in table_names {
         make_table(&table_name, &conn)?;
@@ -165,10 +476,11 @@ fn init_db_setttings(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
-impl<N, K> SqlTable<N, K>
+impl<N, K, I> SqlTable<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
     fn get_table_name_put(&self, hash_table: usize) -> Result<&str> {
         let opt = self.table_names.get(hash_table);
@@ -178,6 +490,32 @@ where
         }
     }
 
+    /// Write a value to the meta table, which is created by
+    /// [store_hashers](trait.HashTables.html#method.store_hashers).
+    pub(crate) fn write_meta(&self, key: &str, value: &str) -> Result<()> {
+        self.check_writable()?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Continue the ids after the largest stored id.
+    pub(crate) fn restore_counter(&mut self) -> Result<()> {
+        for table_name in &self.table_names {
+            let max: Option<I> =
+                self.conn
+                    .query_row(&format!("SELECT max(id) FROM {}", table_name), [], |row| {
+                        row.get(0)
+                    })?;
+            if let Some(max) = max {
+                self.counter = self.counter.max(max.succ());
+            }
+        }
+        Ok(())
+    }
+
     pub fn init_from_conn(
         n_hash_tables: usize,
         only_index_storage: bool,
@@ -186,29 +524,171 @@ where
         let table_names = get_table_names(n_hash_tables);
         init_db_setttings(&conn)?;
         init_table(&conn, &table_names)?;
-        let sql = SqlTable {
+        let mut sql = SqlTable {
             n_hash_tables,
             only_index_storage,
-            counter: 0,
+            counter: I::zero(),
             conn,
             table_names,
             committed: Cell::new(false),
+            auto_index: Some(AUTO_INDEX_THRESHOLD),
+            indexed: Cell::new(false),
+            read_only: false,
+            ingest_start: None,
+            sql_distance: false,
+            busy_policy: BusyPolicy::default(),
+            readers: Mutex::default(),
             phantom: PhantomData,
         };
+        // the ids of an existing database continue after the stored ids.
+        sql.restore_counter()?;
         sql.init_transaction()?;
         Ok(sql)
     }
 
+    /// Open an existing database in read-only mode. No tables are created, no transaction is
+    /// started and no settings are written, so the file may live on a read-only volume.
+    /// All operations that would write to the database return `Error::ReadOnly`.
+    ///
+    /// # Arguments
+    /// * `n_hash_tables` - Number of hash tables in the database.
+    /// * `db_path` - Path of the database file.
+    pub fn open_readonly<P: AsRef<std::path::Path>>(
+        n_hash_tables: usize,
+        db_path: P,
+    ) -> Result<Self> {
+        let conn = Connection::open_with_flags(
+            db_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        let table_names = get_table_names(n_hash_tables);
+        // fail fast if the tables don't exist.
+        for table_name in &table_names {
+            conn.prepare(&format!("SELECT hash, id FROM {} LIMIT 1", table_name))
+                .map_err(|e| match Error::from(e) {
+                    Error::Busy => Error::Busy,
+                    _ => Error::TableNotExist,
+                })?;
+        }
+        Ok(SqlTable {
+            n_hash_tables,
+            only_index_storage: true,
+            counter: I::zero(),
+            conn,
+            table_names,
+            // nothing to commit
+            committed: Cell::new(true),
+            auto_index: None,
+            indexed: Cell::new(false),
+            read_only: true,
+            ingest_start: None,
+            sql_distance: false,
+            busy_policy: BusyPolicy::default(),
+            readers: Mutex::default(),
+            phantom: PhantomData,
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Set how long to wait for and how often to retry a database that is locked by another
+    /// connection. See [BusyPolicy](struct.BusyPolicy.html).
+    pub fn set_busy_policy(&mut self, policy: BusyPolicy) -> Result<()> {
+        self.conn.busy_timeout(policy.timeout)?;
+        self.busy_policy = policy;
+        Ok(())
+    }
+
+    pub fn busy_policy(&self) -> BusyPolicy {
+        self.busy_policy
+    }
+
+    /// Take up to `n` pooled read-only connections of the parallel queries.
+    pub(crate) fn take_readers(&self, n: usize) -> Vec<Self> {
+        let mut readers = self.readers.lock().unwrap_or_else(|e| e.into_inner());
+        let at = readers.len().saturating_sub(n);
+        readers.split_off(at)
+    }
+
+    /// Return read-only connections to the pool, see `take_readers`.
+    pub(crate) fn put_readers<It: IntoIterator<Item = Self>>(&self, readers: It) {
+        self.readers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(readers);
+    }
+
+    /// Run `f` and retry it with backoff while the database is locked by another connection.
+    /// `f` should be a single statement, a failed statement has no effect and can be repeated.
+    fn retry_busy<T, F: FnMut() -> Result<T>>(&self, mut f: F) -> Result<T> {
+        let mut backoff = self.busy_policy.backoff;
+        for _ in 0..self.busy_policy.retries {
+            match f() {
+                Err(Error::Busy) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(?backoff, "database is busy, retrying");
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                r => return r,
+            }
+        }
+        f()
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Commit the running transaction. During an ingestion session this is deferred until the
+    /// session is committed, so the session can still be rolled back.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     pub fn commit(&self) -> Result<()> {
+        if self.ingest_start.is_some() {
+            return Ok(());
+        }
         if !self.committed.replace(true) {
-            self.conn.execute_batch("COMMIT TRANSACTION;")?;
+            let r = self.retry_busy(|| Ok(self.conn.execute_batch("COMMIT TRANSACTION;")?));
+            if let Err(e) = r {
+                // the transaction is still running.
+                self.committed.set(false);
+                return Err(e);
+            }
+            self.maybe_index_hash()?;
+        }
+        Ok(())
+    }
+
+    /// Create the hash index if automatic indexing is enabled and the threshold is exceeded.
+    fn maybe_index_hash(&self) -> Result<()> {
+        let threshold = match self.auto_index {
+            Some(threshold) if !self.indexed.get() => threshold,
+            _ => return Ok(()),
+        };
+        // rowid is monotonically increasing, so this is a cheap upper bound of the row count.
+        let n_rows: Option<u32> = self.conn.query_row(
+            &format!("SELECT max(rowid) FROM {}", fmt_table_name(0)),
+            [],
+            |row| row.get(0),
+        )?;
+        if n_rows.unwrap_or(0) >= threshold {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(n_rows, "creating the hash index");
+            self.index_hash()?;
         }
         Ok(())
     }
 
     pub fn init_transaction(&self) -> Result<()> {
+        self.check_writable()?;
+        self.retry_busy(|| Ok(self.conn.execute_batch("BEGIN TRANSACTION;")?))?;
         self.committed.set(false);
-        self.conn.execute_batch("BEGIN TRANSACTION;")?;
         Ok(())
     }
 
@@ -220,63 +700,404 @@ where
         }
         self.conn = new_con;
         self.committed.set(true);
+        if self.sql_distance {
+            SqlDistance::L2.register::<N>(&self.conn)?;
+            SqlDistance::Cosine.register::<N>(&self.conn)?;
+        }
         Ok(())
     }
 
+    /// Report the unused pages in the database file and the deleted rows per hash table.
+    /// Use it to decide when to [vacuum](#method.vacuum) after many deletes and updates.
+    pub fn fragmentation_report(&self) -> Result<FragmentationReport> {
+        let pragma = |name: &str| -> Result<u64> {
+            let n: i64 = self
+                .conn
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))?;
+            Ok(n as u64)
+        };
+        let mut rows = Vec::with_capacity(self.n_hash_tables);
+        let mut deleted_rows = Vec::with_capacity(self.n_hash_tables);
+        for table_name in &self.table_names {
+            let (n_rows, max_rowid): (i64, Option<i64>) = self.conn.query_row(
+                &format!("SELECT count(*), max(rowid) FROM {}", table_name),
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            rows.push(n_rows as u64);
+            deleted_rows.push((max_rowid.unwrap_or(0) - n_rows) as u64);
+        }
+        Ok(FragmentationReport {
+            page_size: pragma("page_size")?,
+            page_count: pragma("page_count")?,
+            freelist_count: pragma("freelist_count")?,
+            rows,
+            deleted_rows,
+        })
+    }
+
+    /// Rebuild the database file, reclaiming unused pages. Pending changes are committed first.
+    /// This rewrites the whole file, so it may take a while for large indexes.
+    pub fn vacuum(&self) -> Result<()> {
+        self.check_writable()?;
+        let in_transaction = !self.committed.get();
+        self.commit()?;
+        // VACUUM cannot run within a transaction.
+        self.conn.execute_batch("VACUUM;")?;
+        if in_transaction {
+            self.init_transaction()?;
+        }
+        Ok(())
+    }
+
+    /// End the savepoint of an ingestion session, restore the journal mode and continue
+    /// batching in a transaction.
+    fn end_ingest(&self, sql: &str) -> Result<()> {
+        self.conn.execute_batch(sql)?;
+        self.conn.execute_batch("PRAGMA journal_mode = OFF;")?;
+        self.init_transaction()
+    }
+
+    /// Store the data points in a `vectors` table and register the `lsh_l2` and `lsh_cosine`
+    /// distance functions on the connection, so that candidates can be re-ranked in SQL with
+    /// [query_top_k](#method.query_top_k). This is efficient for small dimensions (< 32),
+    /// as no data points have to be transferred out of SQLite.
+    ///
+    /// Only data points stored after enabling are stored, so enable it before storing. The
+    /// functions are registered per connection, so enable it again after reopening a database.
+    pub fn enable_sql_distance(&mut self) -> Result<()> {
+        if !self.read_only {
+            self.conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS vectors (
+                     id         INTEGER PRIMARY KEY,
+                     vector     BLOB
+                 )",
+            )?;
+        }
+        SqlDistance::L2.register::<N>(&self.conn)?;
+        SqlDistance::Cosine.register::<N>(&self.conn)?;
+        self.sql_distance = true;
+        Ok(())
+    }
+
+    /// Query the `k` nearest neighbors among the ids in the buckets of `hashes`, the query hash
+    /// of every hash table. The candidates are ranked in SQL by `distance` to `q`. Returns the
+    /// ids and distances sorted by ascending distance.
+    ///
+    /// Requires [enable_sql_distance](#method.enable_sql_distance).
+    pub fn query_top_k(
+        &self,
+        hashes: &[Vec<K>],
+        q: &[N],
+        k: usize,
+        distance: SqlDistance,
+    ) -> Result<Vec<(I, f64)>> {
+        if !self.sql_distance {
+            return Err(Error::Failed(
+                "the SQL distance functions are not enabled".to_string(),
+            ));
+        }
+        self.commit()?;
+        let candidates = (0..hashes.len())
+            .map(|i| {
+                format!(
+                    "SELECT id FROM {} WHERE hash = ?{}",
+                    fmt_table_name(i),
+                    i + 2
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" UNION ");
+        let mut stmt = self.retry_busy(|| {
+            Ok(self.conn.prepare_cached(&format!(
+                "
+SELECT id, {}(vector, ?1) AS dist
+FROM vectors
+WHERE id IN ({})
+ORDER BY dist, id
+LIMIT {}
+        ",
+                distance.fn_name(),
+                candidates,
+                k
+            ))?)
+        })?;
+        let mut blobs: Vec<&[u8]> = vec![vec_to_blob(q)];
+        blobs.extend(hashes.iter().map(|hash| vec_to_blob(hash)));
+        self.retry_busy(|| {
+            let neighbors = stmt
+                .query_map(rusqlite::params_from_iter(&blobs), |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<std::result::Result<_, _>>()?;
+            Ok(neighbors)
+        })
+    }
+
     pub fn index_hash(&self) -> Result<()> {
+        self.check_writable()?;
         self.commit()?;
         for tbl_name in get_table_names(self.n_hash_tables) {
             self.conn.execute_batch(&format!(
                 "
-                CREATE INDEX hash_index_{}
+                CREATE INDEX IF NOT EXISTS hash_index_{}
                 ON {} (hash);",
                 tbl_name, tbl_name
             ))?;
         }
+        self.indexed.set(true);
         Ok(())
     }
 }
 
-impl<N, K> HashTables<N, K> for SqlTable<N, K>
+impl<N, K, I> HashTables<N, K> for SqlTable<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
+    type Id = I;
+
     fn new(n_hash_tables: usize, only_index_storage: bool, db_path: &str) -> Result<Box<Self>> {
         let path = std::path::Path::new(db_path);
         let conn = Connection::open(path)?;
         SqlTable::init_from_conn(n_hash_tables, only_index_storage, conn).map(|tbl| Box::new(tbl))
     }
 
-    fn put(&mut self, hash: Vec<K>, _d: &[N], hash_table: usize) -> Result<u32> {
+    fn open_read_only(n_hash_tables: usize, db_path: &str) -> Result<Box<Self>> {
+        SqlTable::open_readonly(n_hash_tables, db_path).map(Box::new)
+    }
+
+    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<I> {
+        self.check_writable()?;
         // the unique id of the unique vector
         let idx = self.counter;
 
+        if self.sql_distance && hash_table == 0 {
+            let mut stmt = self
+                .conn
+                .prepare_cached("INSERT OR REPLACE INTO vectors (id, vector) VALUES (?1, ?2)")?;
+            stmt.execute(params![idx, vec_to_blob(d)])?;
+        }
+
         // Get the table name to store this id
         let table_name = self.get_table_name_put(hash_table)?;
-        let r = insert_table(&table_name, &hash, idx, &self.conn);
-
-        // Once we've traversed the last table we increment the id counter.
-        if hash_table == self.n_hash_tables - 1 {
-            self.counter += 1
-        };
-
-        match r {
+        let r = match self.retry_busy(|| insert_table(&table_name, &hash, idx, &self.conn)) {
             Ok(_) => Ok(idx),
+            Err(Error::Busy) => Err(Error::Busy),
             Err(Error::SqlFailure(_)) => Ok(idx), // duplicates
             Err(e) => Err(Error::Failed(format!("{:?}", e))),
+        };
+
+        // Once we've traversed the last table we increment the id counter. A failed put keeps
+        // the id, so the data point can be stored again under the same id.
+        if r.is_ok() && hash_table == self.n_hash_tables - 1 {
+            self.counter = self.counter.succ()
+        };
+        r
+    }
+
+    fn remove_idx(&mut self, idx: I, hash: &[K], hash_table: usize) -> Result<()> {
+        self.check_writable()?;
+        let table_name = self.get_table_name_put(hash_table)?;
+        match delete_from_table(table_name, hash, idx, &self.conn)? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_by_idx(&mut self, idx: I) -> Result<()> {
+        self.check_writable()?;
+        let mut n = 0;
+        for table_name in &self.table_names {
+            let mut stmt = self
+                .conn
+                .prepare_cached(&format!("DELETE FROM {} WHERE id = ?1", table_name))?;
+            n += stmt.execute(params![idx])?;
+        }
+        if self.sql_distance {
+            self.conn
+                .execute("DELETE FROM vectors WHERE id = ?1", params![idx])?;
+        }
+        self.remove_payload(idx)?;
+        match n {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// The ids are inserted and committed in a single transaction. During an ingestion session
+    /// they are part of the session instead.
+    fn insert_ids(&mut self, entries: &[(Vec<K>, I)], hash_table: usize) -> Result<()> {
+        self.check_writable()?;
+        let table_name = self.get_table_name_put(hash_table)?.to_string();
+        let in_transaction = !self.committed.get();
+        if self.ingest_start.is_none() {
+            self.commit()?;
+            self.init_transaction()?;
+        }
+        for (hash, idx) in entries {
+            insert_table(&table_name, hash, *idx, &self.conn)?;
+            self.counter = self.counter.max(idx.succ());
+        }
+        if self.ingest_start.is_none() {
+            self.commit()?;
+            if in_transaction {
+                self.init_transaction()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn update_by_idx(
+        &mut self,
+        old_hash: &[K],
+        new_hash: Vec<K>,
+        idx: I,
+        hash_table: usize,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let table_name = self.get_table_name_put(hash_table)?;
+        if !contains_row(table_name, old_hash, idx, &self.conn)? {
+            return Err(Error::NotFound);
+        }
+        if old_hash == &new_hash[..] {
+            return Ok(());
+        }
+        // The journal is off, so a rollback doesn't restore deleted rows. The new row is
+        // inserted first and the old row is deleted once the insert succeeded, so a failure
+        // never removes the id.
+        insert_table(table_name, &new_hash, idx, &self.conn)?;
+        if let Err(e) = delete_from_table(table_name, old_hash, idx, &self.conn) {
+            delete_from_table(table_name, &new_hash, idx, &self.conn).unwrap_or_default();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn auto_index(&mut self, threshold: Option<u32>) {
+        self.auto_index = threshold;
+    }
+
+    fn begin_ingest(&mut self) -> Result<()> {
+        self.check_writable()?;
+        if self.ingest_start.is_some() {
+            return Err(Error::Failed(
+                "an ingestion session is already running".to_string(),
+            ));
+        }
+        // The journal is disabled for speed, but a rollback needs it. The journal mode cannot
+        // be changed within a transaction, so the running transaction is committed first.
+        self.commit()?;
+        self.conn
+            .execute_batch("PRAGMA journal_mode = MEMORY; SAVEPOINT ingest;")?;
+        self.ingest_start = Some(self.counter);
+        Ok(())
+    }
+
+    fn commit_ingest(&mut self) -> Result<()> {
+        self.ingest_start.take().ok_or(Error::NotFound)?;
+        self.end_ingest("RELEASE ingest;")
+    }
+
+    fn rollback_ingest(&mut self) -> Result<()> {
+        let start = self.ingest_start.take().ok_or(Error::NotFound)?;
+        self.end_ingest("ROLLBACK TO ingest; RELEASE ingest;")?;
+        self.counter = start;
+        Ok(())
+    }
+
+    /// Commits the chunk and starts a new transaction. During an ingestion session the chunk
+    /// is part of the session instead.
+    fn end_chunk(&mut self) -> Result<()> {
+        if self.ingest_start.is_none() {
+            self.commit()?;
+            self.init_transaction()?;
         }
+        Ok(())
+    }
+
+    /// The payloads are stored in a `payloads` table, which is created by the first payload.
+    fn put_payload(&mut self, idx: I, payload: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        if idx >= self.counter {
+            return Err(Error::NotFound);
+        }
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS payloads (
+                 id         INTEGER PRIMARY KEY,
+                 payload    BLOB
+             )",
+        )?;
+        let mut stmt = self
+            .conn
+            .prepare_cached("INSERT OR REPLACE INTO payloads (id, payload) VALUES (?1, ?2)")?;
+        stmt.execute(params![idx, payload])?;
+        Ok(())
+    }
+
+    fn payload(&self, idx: I) -> Result<Option<Vec<u8>>> {
+        if !has_table(&self.conn, "payloads")? {
+            return Ok(None);
+        }
+        self.retry_busy(|| {
+            Ok(self
+                .conn
+                .query_row(
+                    "SELECT payload FROM payloads WHERE id = ?1",
+                    params![idx],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+    }
+
+    fn remove_payload(&mut self, idx: I) -> Result<()> {
+        self.check_writable()?;
+        if has_table(&self.conn, "payloads")? {
+            self.conn
+                .execute("DELETE FROM payloads WHERE id = ?1", params![idx])?;
+        }
+        Ok(())
     }
 
     /// Query the whole bucket
-    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket> {
+    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket<I>> {
         self.commit()?;
         let table_name = fmt_table_name(hash_table);
         let blob = vec_to_blob(hash);
-        let res = query_bucket(blob, &table_name, &self.conn);
+        let res = self.retry_busy(|| query_bucket(blob, &table_name, &self.conn));
 
         match res {
             Ok(bucket) => Ok(bucket),
+            Err(Error::Busy) => Err(Error::Busy),
+            Err(e) => Err(Error::Failed(format!("{:?}", e))),
+        }
+    }
+
+    /// Query the buckets of all hashes in one statement.
+    fn query_buckets(&self, hashes: &[Vec<K>], hash_table: usize) -> Result<Vec<Bucket<I>>> {
+        self.commit()?;
+        let table_name = fmt_table_name(hash_table);
+        let blobs: Vec<&[u8]> = hashes.iter().map(|hash| vec_to_blob(hash)).collect();
+        let res = self.retry_busy(|| query_buckets(&blobs, &table_name, &self.conn));
+
+        match res {
+            Ok(buckets) => Ok(buckets),
+            Err(Error::Busy) => Err(Error::Busy),
+            Err(e) => Err(Error::Failed(format!("{:?}", e))),
+        }
+    }
+
+    fn bucket_len(&self, hash: &[K], hash_table: usize) -> Result<usize> {
+        self.commit()?;
+        let table_name = fmt_table_name(hash_table);
+        let blob = vec_to_blob(hash);
+        let res = self.retry_busy(|| count_bucket(blob, &table_name, &self.conn));
+
+        match res {
+            Ok(n) => Ok(n),
+            Err(Error::Busy) => Err(Error::Busy),
             Err(e) => Err(Error::Failed(format!("{:?}", e))),
         }
     }
@@ -294,7 +1115,7 @@ WHERE type='table' AND type LIKE '%hash%';"#,
         let mut out = String::from(format!("No. of tables: {}\n", row));
 
         out.push_str("Unique hash values:\n");
-        let hv = get_unique_hash_int(self.n_hash_tables, &self.conn).unwrap();
+        let hv = get_unique_hash_int::<K>(self.n_hash_tables, &self.conn).unwrap();
         out.push_str(&format!("{:?}", hv));
 
         let tables = get_table_names(self.n_hash_tables);
@@ -320,14 +1141,156 @@ WHERE type='table' AND type LIKE '%hash%';"#,
         Ok(out)
     }
 
-    fn store_hashers<H: VecHash<N, K> + Serialize>(&mut self, hashers: &[H]) -> Result<()> {
+    fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        self.commit()?;
+        get_table_names(self.n_hash_tables)
+            .iter()
+            .enumerate()
+            .map(|(i, table_name)| {
+                let histogram = bucket_histogram(table_name, -1, &self.conn)?;
+                Ok(HashTableStats::from_histogram(i, histogram))
+            })
+            .collect()
+    }
+
+    fn n_entries(&self) -> Result<usize> {
+        self.commit()?;
+        let mut n = 0;
+        for table_name in &self.table_names {
+            let count: i64 = self.conn.query_row(
+                &format!("SELECT count(*) FROM {}", table_name),
+                [],
+                |row| row.get(0),
+            )?;
+            n += count as usize;
+        }
+        Ok(n)
+    }
+
+    /// Size of the database pages, including unused pages.
+    fn approximate_bytes(&self) -> Result<usize> {
+        let pragma = |name: &str| -> Result<i64> {
+            Ok(self
+                .conn
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))?)
+        };
+        Ok((pragma("page_count")? * pragma("page_size")?) as usize)
+    }
+
+    fn bucket_histogram(&self, hash_table: usize, max_buckets: usize) -> Result<Vec<HistogramBin>> {
+        self.commit()?;
+        let table_name = self.get_table_name_put(hash_table)?;
+        let limit = i64::try_from(max_buckets).unwrap_or(-1);
+        bucket_histogram(table_name, limit, &self.conn)
+    }
+
+    /// The rows are read ordered by hash, so only one bucket is held in memory at a time.
+    fn for_each_bucket(
+        &self,
+        hash_table: usize,
+        f: &mut dyn FnMut(&[I]) -> Result<()>,
+    ) -> Result<()> {
+        self.commit()?;
+        let table_name = self.get_table_name_put(hash_table)?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT hash, id FROM {} ORDER BY hash, id",
+            table_name
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut hash: Vec<u8> = vec![];
+        let mut ids = vec![];
+        while let Some(row) = rows.next()? {
+            let row_hash: Vec<u8> = row.get(0)?;
+            if row_hash != hash {
+                if !ids.is_empty() {
+                    f(&ids)?;
+                    ids.clear();
+                }
+                hash = row_hash;
+            }
+            ids.push(row.get(1)?);
+        }
+        if !ids.is_empty() {
+            f(&ids)?;
+        }
+        Ok(())
+    }
+
+    /// The rows of `other` are copied within the running transaction.
+    fn merge(&mut self, other: &Self) -> Result<I> {
+        self.check_writable()?;
+        if self.n_hash_tables != other.n_hash_tables {
+            return Err(Error::Failed(
+                "cannot merge tables with a different layout".to_string(),
+            ));
+        }
+        if self.ingest_start.is_some() {
+            return Err(Error::Failed(
+                "cannot merge during an ingestion session".to_string(),
+            ));
+        }
+        other.commit()?;
+        let offset = self.counter;
+        let shift = |idx: I| I::from_index(idx.index() + offset.index());
+        for table_name in &self.table_names {
+            let mut select = other
+                .conn
+                .prepare(&format!("SELECT hash, id FROM {}", table_name))?;
+            let mut insert = self.conn.prepare(&format!(
+                "INSERT INTO {} (hash, id) VALUES (?1, ?2)",
+                table_name
+            ))?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let hash: Vec<u8> = row.get(0)?;
+                insert.execute(params![hash, shift(row.get(1)?)])?;
+            }
+        }
+        if has_table(&other.conn, "payloads")? {
+            self.conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS payloads (
+                     id         INTEGER PRIMARY KEY,
+                     payload    BLOB
+                 )",
+            )?;
+            let mut select = other.conn.prepare("SELECT id, payload FROM payloads")?;
+            let mut insert = self
+                .conn
+                .prepare("INSERT OR REPLACE INTO payloads (id, payload) VALUES (?1, ?2)")?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let payload: Vec<u8> = row.get(1)?;
+                insert.execute(params![shift(row.get(0)?), payload])?;
+            }
+        }
+        if self.sql_distance && other.sql_distance {
+            let mut select = other.conn.prepare("SELECT id, vector FROM vectors")?;
+            let mut insert = self
+                .conn
+                .prepare("INSERT OR REPLACE INTO vectors (id, vector) VALUES (?1, ?2)")?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let vector: Vec<u8> = row.get(1)?;
+                insert.execute(params![shift(row.get(0)?), vector])?;
+            }
+        }
+        self.counter = shift(other.counter);
+        Ok(offset)
+    }
+
+    fn store_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, hashers: &[H]) -> Result<()> {
+        self.check_writable()?;
         let buf: Vec<u8> = bincode::serialize(hashers)?;
 
         // fails if already exists
         self.conn.execute_batch(
             "CREATE TABLE state (
             hashers     BLOB
-        )",
+        );
+        CREATE TABLE IF NOT EXISTS meta (
+            key         TEXT PRIMARY KEY,
+            value       TEXT
+        );",
         )?;
         let mut stmt = self
             .conn
@@ -336,11 +1299,26 @@ WHERE type='table' AND type LIKE '%hash%';"#,
         // unlock database by committing any running transaction.
         self.commit()?;
         stmt.execute(params![buf])?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('hash_flavor', ?1)",
+            params![HASH_FLAVOR],
+        )?;
         self.init_transaction()?;
         Ok(())
     }
 
-    fn load_hashers<H: VecHash<N, K> + DeserializeOwned>(&self) -> Result<Vec<H>> {
+    fn update_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, hashers: &[H]) -> Result<()> {
+        self.check_writable()?;
+        let buf: Vec<u8> = bincode::serialize(hashers)?;
+        self.conn
+            .execute("UPDATE state SET hashers = ?1", params![buf])?;
+        Ok(())
+    }
+
+    /// Fails if the hashes were computed with another summation, see
+    /// [HashFlavorMismatch](enum.Error.html#variant.HashFlavorMismatch).
+    fn load_hashers<H: VecHash<N, K> + MaybeSerde>(&self) -> Result<Vec<H>> {
+        check_hash_flavor(read_meta(&self.conn, "hash_flavor")?.as_deref())?;
         let mut stmt = self.conn.prepare("SELECT * FROM state;")?;
         let buf: Vec<u8> = stmt.query_row([], |row| {
             let v: Vec<u8> = row.get_unwrap(0);
@@ -350,8 +1328,8 @@ WHERE type='table' AND type LIKE '%hash%';"#,
         Ok(hashers)
     }
 
-    fn get_unique_hash_int(&self) -> FnvHashSet<i32> {
-        get_unique_hash_int(self.n_hash_tables, &self.conn).unwrap()
+    fn get_unique_hash_int(&self) -> FnvHashSet<u64> {
+        get_unique_hash_int::<K>(self.n_hash_tables, &self.conn).unwrap()
     }
 }
 
@@ -372,7 +1350,7 @@ mod test {
 
     #[test]
     fn test_sql_crud() {
-        let mut sql = *SqlTableMem::new(1, true, ".").unwrap();
+        let mut sql: SqlTableMem<f64, i32> = *SqlTableMem::new(1, true, ".").unwrap();
         let v = vec![1., 2.];
         for hash in &[vec![1, 2], vec![2, 3]] {
             sql.put(hash.clone(), &v, 0).unwrap();
@@ -388,6 +1366,169 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_query_buckets() {
+        let mut sql: SqlTableMem<f64, i32> = *SqlTableMem::new(1, true, ".").unwrap();
+        let v = vec![1., 2.];
+        for hash in &[vec![1, 2], vec![2, 3], vec![1, 2]] {
+            sql.put(hash.clone(), &v, 0).unwrap();
+        }
+        let hashes = vec![vec![2, 3], vec![9, 9], vec![1, 2], vec![2, 3]];
+        let buckets = sql.query_buckets(&hashes, 0).unwrap();
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(buckets[0], [1].iter().copied().collect());
+        assert!(buckets[1].is_empty());
+        assert_eq!(buckets[2], [0, 2].iter().copied().collect());
+        assert_eq!(buckets[3], buckets[0]);
+    }
+
+    #[test]
+    fn test_sql_delete_update() {
+        let mut sql = *SqlTableMem::<f32, i8>::new(1, true, ".").unwrap();
+        let v = vec![1., 2.];
+        sql.put(vec![1, 2], &v, 0).unwrap();
+        sql.put(vec![1, 2], &v, 0).unwrap();
+
+        sql.update_by_idx(&[1, 2], vec![3, 4], 0, 0).unwrap();
+        assert!(!sql.query_bucket(&[1, 2], 0).unwrap().contains(&0));
+        assert!(sql.query_bucket(&[3, 4], 0).unwrap().contains(&0));
+        // id is not in the old bucket anymore
+        assert!(sql.update_by_idx(&[1, 2], vec![3, 4], 0, 0).is_err());
+
+        sql.remove_idx(1, &[1, 2], 0).unwrap();
+        assert!(sql.query_bucket(&[1, 2], 0).unwrap().is_empty());
+        assert!(sql.remove_idx(1, &[1, 2], 0).is_err());
+    }
+
+    #[test]
+    fn test_sql_update_failed_insert() {
+        let mut sql = *SqlTableMem::<f32, i8>::new(1, true, ".").unwrap();
+        sql.put(vec![1, 2], &[1., 2.], 0).unwrap();
+        // make the insert of the new hash fail.
+        sql.conn
+            .execute_batch(&format!(
+                "CREATE TRIGGER fail_insert BEFORE INSERT ON {}
+                 BEGIN SELECT RAISE(ABORT, 'fail'); END;",
+                sql.table_names[0]
+            ))
+            .unwrap();
+        assert!(sql.update_by_idx(&[1, 2], vec![3, 4], 0, 0).is_err());
+        // the id is still in its old bucket.
+        assert!(sql.query_bucket(&[1, 2], 0).unwrap().contains(&0));
+        assert!(!sql.query_bucket(&[3, 4], 0).unwrap().contains(&0));
+    }
+
+    #[test]
+    fn test_auto_index() {
+        let n_indexes = |sql: &SqlTableMem<f32, i8>| -> u32 {
+            sql.conn
+                .query_row(
+                    "SELECT count(*) FROM sqlite_master WHERE type = 'index'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        for &(threshold, expected) in &[(Some(2), 1), (Some(3), 0), (None, 0)] {
+            let mut sql = *SqlTableMem::<f32, i8>::new(1, true, ".").unwrap();
+            sql.auto_index(threshold);
+            sql.put(vec![1, 2], &[1., 2.], 0).unwrap();
+            sql.put(vec![2, 3], &[1., 2.], 0).unwrap();
+            sql.commit().unwrap();
+            assert_eq!(n_indexes(&sql), expected);
+        }
+    }
+
+    #[test]
+    fn test_fragmentation_vacuum() {
+        let p = std::env::temp_dir().join("lsh_fragmentation.db3");
+        let p = p.to_str().unwrap();
+        let _ = std::fs::remove_file(p);
+        let mut sql = *SqlTable::<f32, i8>::new(2, true, p).unwrap();
+        let v = vec![1., 2.];
+        for i in 0..2000 {
+            for ht in 0..2 {
+                sql.put(vec![i as i8, (i / 256) as i8, 3, 4], &v, ht)
+                    .unwrap();
+            }
+        }
+        for i in 0..1000 {
+            for ht in 0..2 {
+                sql.remove_idx(i, &[i as i8, (i / 256) as i8, 3, 4], ht)
+                    .unwrap();
+            }
+        }
+        sql.commit().unwrap();
+        let report = sql.fragmentation_report().unwrap();
+        assert_eq!(report.rows, vec![1000, 1000]);
+        assert_eq!(report.deleted_rows, vec![1000, 1000]);
+        assert!(report.freelist_count > 0);
+        let out = report.to_string();
+        assert!(out.starts_with(&format!(
+            "pages:\t{} of {} bytes, {} free",
+            report.page_count, report.page_size, report.freelist_count
+        )));
+        assert!(out.contains("rows:\t[1000, 1000]\n"));
+        assert!(out.ends_with("deleted rows:\t[1000, 1000] (50.0%)"));
+
+        sql.init_transaction().unwrap();
+        sql.vacuum().unwrap();
+        assert!(!sql.committed.get());
+        let after = sql.fragmentation_report().unwrap();
+        assert_eq!(after.freelist_count, 0);
+        assert!(after.page_count < report.page_count);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_busy() {
+        let p = std::env::temp_dir().join("lsh_busy.db3");
+        let p = p.to_str().unwrap();
+        let _ = std::fs::remove_file(p);
+        {
+            let mut sql = *SqlTable::<f32, i8>::new(1, true, p).unwrap();
+            sql.put(vec![1, 2], &[1., 2.], 0).unwrap();
+            sql.commit().unwrap();
+        }
+        let mut sql = SqlTable::<f32, i8>::open_readonly(1, p).unwrap();
+        sql.set_busy_policy(BusyPolicy {
+            timeout: Duration::from_millis(10),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        })
+        .unwrap();
+
+        // another connection holds the lock.
+        let other = Connection::open(p).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE;").unwrap();
+        assert!(matches!(sql.query_bucket(&[1, 2], 0), Err(Error::Busy)));
+        other.execute_batch("COMMIT;").unwrap();
+        assert!(sql.query_bucket(&[1, 2], 0).unwrap().contains(&0));
+
+        // a put that stays busy doesn't use up the id.
+        drop(sql);
+        let mut sql = *SqlTable::<f32, i8>::new(1, true, p).unwrap();
+        // release the lock after the transaction, so the other connection can take it.
+        sql.conn
+            .execute_batch("PRAGMA main.locking_mode=NORMAL;")
+            .unwrap();
+        sql.commit().unwrap();
+        sql.set_busy_policy(BusyPolicy {
+            timeout: Duration::from_millis(10),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        })
+        .unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE;").unwrap();
+        assert!(matches!(
+            sql.put(vec![3, 4], &[3., 4.], 0),
+            Err(Error::Busy)
+        ));
+        other.execute_batch("COMMIT;").unwrap();
+        assert_eq!(sql.put(vec![3, 4], &[3., 4.], 0).unwrap(), 1);
+        std::fs::remove_file(p).unwrap();
+    }
+
     #[test]
     fn test_blob_hash_casting() {
         for hash in vec![
@@ -419,4 +1560,32 @@ mod test {
         assert_eq!(sql.query_bucket(&vec![1, 2], 0).unwrap().take(&0), Some(0));
         std::fs::remove_file(p).unwrap();
     }
+
+    #[test]
+    fn test_sql_distance() {
+        let mut sql = *SqlTableMem::<f32, i8>::new(2, false, ".").unwrap();
+        sql.enable_sql_distance().unwrap();
+        let vs = [vec![1., 0.], vec![3., 4.], vec![0., -2.]];
+        for v in &vs {
+            sql.put(vec![1], v, 0).unwrap();
+            sql.put(vec![1], v, 1).unwrap();
+        }
+        let hashes = [vec![1], vec![1]];
+        let top = sql
+            .query_top_k(&hashes, &[0., 0.], 2, SqlDistance::L2)
+            .unwrap();
+        assert_eq!(top, vec![(0, 1.), (2, 2.)]);
+
+        let top = sql
+            .query_top_k(&hashes, &[1., 0.], 3, SqlDistance::Cosine)
+            .unwrap();
+        assert_eq!(top.iter().map(|n| n.0).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert!((top[1].1 - 0.4).abs() < 1e-6);
+
+        // no candidates in an unknown bucket.
+        let top = sql
+            .query_top_k(&[vec![2], vec![2]], &[0., 0.], 2, SqlDistance::L2)
+            .unwrap();
+        assert!(top.is_empty());
+    }
 }
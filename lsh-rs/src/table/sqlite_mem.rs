@@ -1,25 +1,31 @@
 #![cfg(feature = "sqlite")]
-use super::sqlite::SqlTable;
-use crate::data::Integer;
+use super::sqlite::{SqlIdx, SqlTable};
+use crate::data::{Integer, MaybeSerde};
 use crate::prelude::*;
+use crate::stats::{HashTableStats, HistogramBin};
 use crate::{data::Numeric, table::general::Bucket, HashTables};
 use fnv::FnvHashSet;
+use rusqlite::{backup::Progress, DatabaseName};
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 
 /// In memory Sqlite backend for [LSH](struct.LSH.html).
-pub struct SqlTableMem<N, K>
+///
+/// Like [SqlTable](struct.SqlTable.html), `SqlTableMem` is `Send` but not `Sync`.
+pub struct SqlTableMem<N, K, I = u32>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
-    sql_table: SqlTable<N, K>,
+    sql_table: SqlTable<N, K, I>,
 }
 
-impl<N, K> SqlTableMem<N, K>
+impl<N, K, I> SqlTableMem<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
     pub fn to_db<P: AsRef<Path>>(&mut self, db_path: P) -> Result<()> {
         let mut new_con = rusqlite::Connection::open(db_path)?;
@@ -31,35 +37,68 @@ where
         self.committed.set(true);
         Ok(())
     }
+
+    /// Copy the database to a file. Unlike [to_db](#method.to_db), the table stays in memory.
+    ///
+    /// # Arguments
+    /// * `db_path` - Path of the file. An existing database is overwritten.
+    pub fn backup<P: AsRef<Path>>(&mut self, db_path: P) -> Result<()> {
+        // the backup only copies committed changes.
+        self.commit()?;
+        self.conn.backup(DatabaseName::Main, db_path, None)?;
+        self.init_transaction()
+    }
+
+    /// Load a database file, e.g. one written by [backup](#method.backup), in memory.
+    ///
+    /// # Arguments
+    /// * `n_hash_tables` - Number of hash tables in the database.
+    /// * `only_index_storage` - Don't store the data points.
+    /// * `db_path` - Path of the database file.
+    pub fn restore<P: AsRef<Path>>(
+        n_hash_tables: usize,
+        only_index_storage: bool,
+        db_path: P,
+    ) -> Result<Self> {
+        let mut conn = rusqlite::Connection::open_in_memory()?;
+        conn.restore(DatabaseName::Main, db_path, None::<fn(Progress)>)?;
+        let sql_table = SqlTable::init_from_conn(n_hash_tables, only_index_storage, conn)?;
+        Ok(SqlTableMem { sql_table })
+    }
 }
 
-impl<N, K> Deref for SqlTableMem<N, K>
+impl<N, K, I> Deref for SqlTableMem<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
-    type Target = SqlTable<N, K>;
+    type Target = SqlTable<N, K, I>;
 
-    fn deref(&self) -> &SqlTable<N, K> {
+    fn deref(&self) -> &SqlTable<N, K, I> {
         &self.sql_table
     }
 }
 
-impl<N, K> DerefMut for SqlTableMem<N, K>
+impl<N, K, I> DerefMut for SqlTableMem<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
-    fn deref_mut(&mut self) -> &mut SqlTable<N, K> {
+    fn deref_mut(&mut self) -> &mut SqlTable<N, K, I> {
         &mut self.sql_table
     }
 }
 
-impl<N, K> HashTables<N, K> for SqlTableMem<N, K>
+impl<N, K, I> HashTables<N, K> for SqlTableMem<N, K, I>
 where
     N: Numeric,
     K: Integer,
+    I: SqlIdx,
 {
+    type Id = I;
+
     fn new(n_hash_tables: usize, only_index_storage: bool, _db_path: &str) -> Result<Box<Self>> {
         let conn = rusqlite::Connection::open_in_memory()?;
         let sql_table = SqlTable::init_from_conn(n_hash_tables, only_index_storage, conn)?;
@@ -71,7 +110,7 @@ where
     /// * `hash` - hashed vector.
     /// * `d` - Vector to store in the buckets.
     /// * `hash_table` - Number of the hash_table to store the vector. Ranging from 0 to L.
-    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<u32> {
+    fn put(&mut self, hash: Vec<K>, d: &[N], hash_table: usize) -> Result<I> {
         self.sql_table.put(hash, d, hash_table)
     }
 
@@ -79,20 +118,123 @@ where
         self.sql_table.delete(hash, d, hash_table)
     }
 
+    fn remove_idx(&mut self, idx: I, hash: &[K], hash_table: usize) -> Result<()> {
+        self.sql_table.remove_idx(idx, hash, hash_table)
+    }
+
+    fn end_chunk(&mut self) -> Result<()> {
+        self.sql_table.end_chunk()
+    }
+
+    fn delete_by_idx(&mut self, idx: I) -> Result<()> {
+        self.sql_table.delete_by_idx(idx)
+    }
+
+    fn insert_ids(&mut self, entries: &[(Vec<K>, I)], hash_table: usize) -> Result<()> {
+        self.sql_table.insert_ids(entries, hash_table)
+    }
+
+    fn update_by_idx(
+        &mut self,
+        old_hash: &[K],
+        new_hash: Vec<K>,
+        idx: I,
+        hash_table: usize,
+    ) -> Result<()> {
+        self.sql_table
+            .update_by_idx(old_hash, new_hash, idx, hash_table)
+    }
+
+    fn begin_ingest(&mut self) -> Result<()> {
+        self.sql_table.begin_ingest()
+    }
+
+    fn commit_ingest(&mut self) -> Result<()> {
+        self.sql_table.commit_ingest()
+    }
+
+    fn rollback_ingest(&mut self) -> Result<()> {
+        self.sql_table.rollback_ingest()
+    }
+
     /// Query the whole bucket
-    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket> {
+    fn query_bucket(&self, hash: &[K], hash_table: usize) -> Result<Bucket<I>> {
         self.sql_table.query_bucket(hash, hash_table)
     }
 
-    fn idx_to_datapoint(&self, idx: u32) -> Result<&Vec<N>> {
+    fn query_buckets(&self, hashes: &[Vec<K>], hash_table: usize) -> Result<Vec<Bucket<I>>> {
+        self.sql_table.query_buckets(hashes, hash_table)
+    }
+
+    fn bucket_len(&self, hash: &[K], hash_table: usize) -> Result<usize> {
+        self.sql_table.bucket_len(hash, hash_table)
+    }
+
+    fn put_payload(&mut self, idx: I, payload: &[u8]) -> Result<()> {
+        self.sql_table.put_payload(idx, payload)
+    }
+
+    fn payload(&self, idx: I) -> Result<Option<Vec<u8>>> {
+        self.sql_table.payload(idx)
+    }
+
+    fn remove_payload(&mut self, idx: I) -> Result<()> {
+        self.sql_table.remove_payload(idx)
+    }
+
+    fn idx_to_datapoint(&self, idx: I) -> Result<&[N]> {
         self.sql_table.idx_to_datapoint(idx)
     }
 
+    fn auto_index(&mut self, threshold: Option<u32>) {
+        self.sql_table.auto_index(threshold)
+    }
+
     fn describe(&self) -> Result<String> {
         self.sql_table.describe()
     }
 
-    fn get_unique_hash_int(&self) -> FnvHashSet<i32> {
+    fn hash_table_stats(&self) -> Result<Vec<HashTableStats>> {
+        self.sql_table.hash_table_stats()
+    }
+
+    fn n_entries(&self) -> Result<usize> {
+        self.sql_table.n_entries()
+    }
+
+    fn approximate_bytes(&self) -> Result<usize> {
+        self.sql_table.approximate_bytes()
+    }
+
+    fn bucket_histogram(&self, hash_table: usize, max_buckets: usize) -> Result<Vec<HistogramBin>> {
+        self.sql_table.bucket_histogram(hash_table, max_buckets)
+    }
+
+    fn for_each_bucket(
+        &self,
+        hash_table: usize,
+        f: &mut dyn FnMut(&[I]) -> Result<()>,
+    ) -> Result<()> {
+        self.sql_table.for_each_bucket(hash_table, f)
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<I> {
+        self.sql_table.merge(&other.sql_table)
+    }
+
+    fn store_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, hashers: &[H]) -> Result<()> {
+        self.sql_table.store_hashers(hashers)
+    }
+
+    fn update_hashers<H: VecHash<N, K> + MaybeSerde>(&mut self, hashers: &[H]) -> Result<()> {
+        self.sql_table.update_hashers(hashers)
+    }
+
+    fn load_hashers<H: VecHash<N, K> + MaybeSerde>(&self) -> Result<Vec<H>> {
+        self.sql_table.load_hashers()
+    }
+
+    fn get_unique_hash_int(&self) -> FnvHashSet<u64> {
         self.sql_table.get_unique_hash_int()
     }
 }
@@ -4,8 +4,8 @@ use rand_distr::StandardNormal;
 
 pub fn increase_capacity<T>(size: usize, container: &mut Vec<T>) {
     if container.capacity() < size {
-        let diff = size - container.capacity();
-        container.reserve(diff)
+        // `reserve` counts from the length, not from the capacity.
+        container.reserve(size - container.len())
     }
 }
 
@@ -0,0 +1,56 @@
+//! Run the insert, expire, checkpoint and reload path of the `dedup_stream` example on a
+//! generated stream with known duplicates.
+#[allow(dead_code)]
+#[path = "../examples/dedup_stream.rs"]
+mod dedup_stream;
+
+use dedup_stream::Dedup;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+#[test]
+fn test_dedup_stream() {
+    let dim = 16;
+    let (threshold, ttl, checkpoint) = (0.5, 150, 100);
+    let path = std::env::temp_dir().join("lsh_test_dedup_stream.bincode");
+    let mut rng = SmallRng::seed_from_u64(1);
+    let base: Vec<Vec<f32>> = (0..200)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1., 1.)).collect())
+        .collect();
+    let near = |v: &[f32], rng: &mut SmallRng| -> Vec<f32> {
+        v.iter().map(|x| x + rng.gen_range(-0.01, 0.01)).collect()
+    };
+
+    // the unique embeddings 0..200, then near copies of 100..200, which are live, and near
+    // copies of 0..20, which have expired.
+    let mut stream: Vec<(Vec<f32>, Option<u64>)> = base.iter().map(|v| (v.clone(), None)).collect();
+    for (i, v) in base.iter().enumerate().skip(100) {
+        stream.push((near(v, &mut rng), Some(i as u64)));
+    }
+    for v in &base[..20] {
+        stream.push((near(v, &mut rng), None));
+    }
+
+    let mut dedup = Dedup::new(threshold, ttl);
+    for (i, (v, expected)) in stream.iter().enumerate() {
+        let duplicate = dedup.push(v).unwrap();
+        assert_eq!(duplicate.map(|(original, _)| original), *expected);
+        if let Some((_, dist)) = duplicate {
+            assert!(dist <= threshold);
+        }
+        if i % checkpoint == checkpoint - 1 {
+            dedup.checkpoint(&path).unwrap();
+            // the checkpoint frees the expired embeddings.
+            assert_eq!(dedup.n_stored(), dedup.n_live());
+        }
+    }
+    assert_eq!(dedup.n_embeddings(), 320);
+    assert_eq!(dedup.n_duplicates(), 100);
+    assert_eq!(dedup.n_live(), ttl);
+    assert!(dedup.n_stored() > ttl);
+
+    dedup.checkpoint(&path).unwrap();
+    assert_eq!(dedup.n_stored(), ttl);
+    assert_eq!(dedup.reload(&path).unwrap(), ttl);
+    std::fs::remove_file(&path).unwrap();
+}